@@ -45,32 +45,33 @@ impl BaseLayer {
         files: &BaseLayerFiles<F>,
     ) -> io::Result<Self> {
         let maps = files.map_all().await?;
-        Ok(Self::load(name, maps))
+        Self::load(name, maps)
     }
 
-    pub fn load(name: [u32; 5], maps: BaseLayerMaps) -> BaseLayer {
+    /// Load a base layer from its maps.
+    ///
+    /// Returns an error if the layer was written with a newer format version than this build
+    /// supports.
+    pub fn load(name: [u32; 5], maps: BaseLayerMaps) -> io::Result<BaseLayer> {
         let node_dictionary = PfcDict::parse(
             maps.node_dictionary_maps.blocks_map,
             maps.node_dictionary_maps.offsets_map,
-        )
-        .unwrap();
+        )?;
         let predicate_dictionary = PfcDict::parse(
             maps.predicate_dictionary_maps.blocks_map,
             maps.predicate_dictionary_maps.offsets_map,
-        )
-        .unwrap();
+        )?;
         let value_dictionary = PfcDict::parse(
             maps.value_dictionary_maps.blocks_map,
             maps.value_dictionary_maps.offsets_map,
-        )
-        .unwrap();
+        )?;
 
         let node_value_idmap = match maps.id_map_maps.node_value_idmap_maps {
             None => IdMap::default(),
             Some(maps) => IdMap::from_maps(
                 maps,
                 util::calculate_width((node_dictionary.len() + value_dictionary.len()) as u64),
-            ),
+            )?,
         };
 
         let predicate_idmap = match maps.id_map_maps.predicate_idmap_maps {
@@ -78,34 +79,40 @@ impl BaseLayer {
             Some(map) => IdMap::from_maps(
                 map,
                 util::calculate_width(predicate_dictionary.len() as u64),
-            ),
+            )?,
         };
 
-        let subjects = maps.subjects_map.map(|subjects_map| {
-            MonotonicLogArray::from_logarray(LogArray::parse(subjects_map).unwrap())
-        });
-        let objects = maps.objects_map.map(|objects_map| {
-            MonotonicLogArray::from_logarray(LogArray::parse(objects_map).unwrap())
-        });
+        let subjects = match maps.subjects_map {
+            Some(subjects_map) => Some(MonotonicLogArray::from_logarray(LogArray::parse(
+                subjects_map,
+            )?)),
+            None => None,
+        };
+        let objects = match maps.objects_map {
+            Some(objects_map) => Some(MonotonicLogArray::from_logarray(LogArray::parse(
+                objects_map,
+            )?)),
+            None => None,
+        };
 
         let s_p_adjacency_list = AdjacencyList::parse(
             maps.s_p_adjacency_list_maps.nums_map,
             maps.s_p_adjacency_list_maps.bitindex_maps.bits_map,
             maps.s_p_adjacency_list_maps.bitindex_maps.blocks_map,
             maps.s_p_adjacency_list_maps.bitindex_maps.sblocks_map,
-        );
+        )?;
         let sp_o_adjacency_list = AdjacencyList::parse(
             maps.sp_o_adjacency_list_maps.nums_map,
             maps.sp_o_adjacency_list_maps.bitindex_maps.bits_map,
             maps.sp_o_adjacency_list_maps.bitindex_maps.blocks_map,
             maps.sp_o_adjacency_list_maps.bitindex_maps.sblocks_map,
-        );
+        )?;
         let o_ps_adjacency_list = AdjacencyList::parse(
             maps.o_ps_adjacency_list_maps.nums_map,
             maps.o_ps_adjacency_list_maps.bitindex_maps.bits_map,
             maps.o_ps_adjacency_list_maps.bitindex_maps.blocks_map,
             maps.o_ps_adjacency_list_maps.bitindex_maps.sblocks_map,
-        );
+        )?;
 
         let predicate_wavelet_tree_width = s_p_adjacency_list.nums().width();
         let predicate_wavelet_tree = WaveletTree::from_parts(
@@ -113,11 +120,11 @@ impl BaseLayer {
                 maps.predicate_wavelet_tree_maps.bits_map,
                 maps.predicate_wavelet_tree_maps.blocks_map,
                 maps.predicate_wavelet_tree_maps.sblocks_map,
-            ),
+            )?,
             predicate_wavelet_tree_width,
         );
 
-        BaseLayer {
+        Ok(BaseLayer {
             name,
             node_dictionary,
             predicate_dictionary,
@@ -135,7 +142,7 @@ impl BaseLayer {
             o_ps_adjacency_list,
 
             predicate_wavelet_tree,
-        }
+        })
     }
 }
 
@@ -248,10 +255,18 @@ pub struct BaseLayerFileBuilder<F: 'static + FileLoad + FileStore> {
 impl<F: 'static + FileLoad + FileStore + Clone> BaseLayerFileBuilder<F> {
     /// Create the builder from the given files.
     pub fn from_files(files: &BaseLayerFiles<F>) -> Self {
-        let builder = DictionarySetFileBuilder::from_files(
+        Self::from_files_with_block_size(files, DEFAULT_BLOCK_SIZE)
+    }
+
+    /// Create the builder from the given files, building dictionaries with the given block size.
+    ///
+    /// See `PfcDictFileBuilder::with_block_size` for what the block size does.
+    pub fn from_files_with_block_size(files: &BaseLayerFiles<F>, block_size: usize) -> Self {
+        let builder = DictionarySetFileBuilder::from_files_with_block_size(
             files.node_dictionary_files.clone(),
             files.predicate_dictionary_files.clone(),
             files.value_dictionary_files.clone(),
+            block_size,
         );
 
         BaseLayerFileBuilder {
@@ -912,4 +927,37 @@ pub mod tests {
         assert_eq!(0, layer.triple_removal_count());
         assert_eq!(7, layer.triple_count());
     }
+
+    #[test]
+    fn loading_layer_with_newer_format_version_fails() {
+        let mut runtime = Runtime::new().unwrap();
+        let files = example_base_layer_files(runtime.handle());
+        let mut maps = runtime.block_on(files.map_all()).unwrap();
+
+        let mut bytes = maps.node_dictionary_maps.blocks_map.to_vec();
+        let len = bytes.len();
+        bytes[len - 6] = 2;
+        maps.node_dictionary_maps.blocks_map = bytes::Bytes::from(bytes);
+
+        let error = BaseLayer::load([1, 2, 3, 4, 5], maps).err().unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        assert_eq!(
+            "layer written with store format v2, this build supports up to v1",
+            error.to_string()
+        );
+
+        // the version of a log array is in its control word, at the end
+        let mut maps = runtime.block_on(files.map_all()).unwrap();
+        let mut bytes = maps.sp_o_adjacency_list_maps.nums_map.to_vec();
+        let len = bytes.len();
+        bytes[len - 3] = 2;
+        maps.sp_o_adjacency_list_maps.nums_map = bytes::Bytes::from(bytes);
+
+        let error = BaseLayer::load([1, 2, 3, 4, 5], maps).err().unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        assert_eq!(
+            "layer written with store format v2, this build supports up to v1",
+            error.to_string()
+        );
+    }
 }
@@ -20,17 +20,35 @@ impl<F: 'static + FileLoad + FileStore> DictionarySetFileBuilder<F> {
         predicate_files: DictionaryFiles<F>,
         value_files: DictionaryFiles<F>,
     ) -> Self {
-        let node_dictionary_builder = PfcDictFileBuilder::new(
+        Self::from_files_with_block_size(
+            node_files,
+            predicate_files,
+            value_files,
+            DEFAULT_BLOCK_SIZE,
+        )
+    }
+
+    /// Create the builder from the given files, using the given block size for all three dictionaries.
+    pub fn from_files_with_block_size(
+        node_files: DictionaryFiles<F>,
+        predicate_files: DictionaryFiles<F>,
+        value_files: DictionaryFiles<F>,
+        block_size: usize,
+    ) -> Self {
+        let node_dictionary_builder = PfcDictFileBuilder::with_block_size(
             node_files.blocks_file.open_write(),
             node_files.offsets_file.open_write(),
+            block_size,
         );
-        let predicate_dictionary_builder = PfcDictFileBuilder::new(
+        let predicate_dictionary_builder = PfcDictFileBuilder::with_block_size(
             predicate_files.blocks_file.open_write(),
             predicate_files.offsets_file.open_write(),
+            block_size,
         );
-        let value_dictionary_builder = PfcDictFileBuilder::new(
+        let value_dictionary_builder = PfcDictFileBuilder::with_block_size(
             value_files.blocks_file.open_write(),
             value_files.offsets_file.open_write(),
+            block_size,
         );
 
         Self {
@@ -301,13 +319,6 @@ pub async fn build_object_index<FLoad: 'static + FileLoad, F: 'static + FileLoad
     pairs.par_sort_unstable();
 
     let aj_width = util::calculate_width(greatest_sp);
-    let mut o_ps_adjacency_list_builder = AdjacencyListBuilder::new(
-        o_ps_files.bitindex_files.bits_file,
-        o_ps_files.bitindex_files.blocks_file.open_write(),
-        o_ps_files.bitindex_files.sblocks_file.open_write(),
-        o_ps_files.nums_file.open_write(),
-        aj_width,
-    );
 
     if build_sparse_index {
         // a sparse index compresses the adjacency list so that all objects in use are remapped to form a continuous range.
@@ -316,7 +327,8 @@ pub async fn build_object_index<FLoad: 'static + FileLoad, F: 'static + FileLoad
         let mut objects = Vec::new();
         let mut last_object = 0;
         let mut object_ix = 0;
-        for (object, sp) in pairs {
+        for pair in pairs.iter_mut() {
+            let object = pair.0;
             if object > last_object {
                 object_ix += 1;
                 last_object = object;
@@ -325,7 +337,7 @@ pub async fn build_object_index<FLoad: 'static + FileLoad, F: 'static + FileLoad
                 objects.push(object);
             }
 
-            o_ps_adjacency_list_builder.push(object_ix, sp).await?;
+            pair.0 = object_ix;
         }
         let objects_width = util::calculate_width(last_object);
 
@@ -336,13 +348,17 @@ pub async fn build_object_index<FLoad: 'static + FileLoad, F: 'static + FileLoad
             .push_all(util::stream_iter_ok(objects))
             .await?;
         objects_builder.finalize().await?;
-    } else {
-        o_ps_adjacency_list_builder
-            .push_all(util::stream_iter_ok(pairs))
-            .await?;
     }
 
-    o_ps_adjacency_list_builder.finalize().await
+    AdjacencyListBuilder::from_sorted_pairs(
+        o_ps_files.bitindex_files.bits_file,
+        o_ps_files.bitindex_files.blocks_file.open_write(),
+        o_ps_files.bitindex_files.sblocks_file.open_write(),
+        o_ps_files.nums_file.open_write(),
+        aj_width,
+        pairs,
+    )
+    .await
 }
 
 pub async fn build_predicate_index<FLoad: 'static + FileLoad, F: 'static + FileLoad + FileStore>(
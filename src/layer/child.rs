@@ -59,25 +59,30 @@ impl ChildLayer {
         files: &ChildLayerFiles<F>,
     ) -> io::Result<Self> {
         let maps = files.map_all().await?;
-        Ok(Self::load(name, parent, maps))
+        Self::load(name, parent, maps)
     }
 
-    pub fn load(name: [u32; 5], parent: Arc<InternalLayer>, maps: ChildLayerMaps) -> ChildLayer {
+    /// Load a child layer from its maps.
+    ///
+    /// Returns an error if the layer was written with a newer format version than this build
+    /// supports.
+    pub fn load(
+        name: [u32; 5],
+        parent: Arc<InternalLayer>,
+        maps: ChildLayerMaps,
+    ) -> io::Result<ChildLayer> {
         let node_dictionary = PfcDict::parse(
             maps.node_dictionary_maps.blocks_map,
             maps.node_dictionary_maps.offsets_map,
-        )
-        .unwrap();
+        )?;
         let predicate_dictionary = PfcDict::parse(
             maps.predicate_dictionary_maps.blocks_map,
             maps.predicate_dictionary_maps.offsets_map,
-        )
-        .unwrap();
+        )?;
         let value_dictionary = PfcDict::parse(
             maps.value_dictionary_maps.blocks_map,
             maps.value_dictionary_maps.offsets_map,
-        )
-        .unwrap();
+        )?;
 
         let parent_node_value_count = parent.node_and_value_count();
         let parent_predicate_count = parent.predicate_count();
@@ -87,7 +92,7 @@ impl ChildLayer {
             Some(maps) => IdMap::from_maps(
                 maps,
                 util::calculate_width((node_dictionary.len() + value_dictionary.len()) as u64),
-            ),
+            )?,
         };
 
         let predicate_idmap = match maps.id_map_maps.predicate_idmap_maps {
@@ -95,54 +100,52 @@ impl ChildLayer {
             Some(map) => IdMap::from_maps(
                 map,
                 util::calculate_width(predicate_dictionary.len() as u64),
-            ),
+            )?,
         };
 
         let pos_subjects =
-            MonotonicLogArray::from_logarray(LogArray::parse(maps.pos_subjects_map).unwrap());
-        let pos_objects =
-            MonotonicLogArray::from_logarray(LogArray::parse(maps.pos_objects_map).unwrap());
+            MonotonicLogArray::from_logarray(LogArray::parse(maps.pos_subjects_map)?);
+        let pos_objects = MonotonicLogArray::from_logarray(LogArray::parse(maps.pos_objects_map)?);
         let neg_subjects =
-            MonotonicLogArray::from_logarray(LogArray::parse(maps.neg_subjects_map).unwrap());
-        let neg_objects =
-            MonotonicLogArray::from_logarray(LogArray::parse(maps.neg_objects_map).unwrap());
+            MonotonicLogArray::from_logarray(LogArray::parse(maps.neg_subjects_map)?);
+        let neg_objects = MonotonicLogArray::from_logarray(LogArray::parse(maps.neg_objects_map)?);
 
         let pos_s_p_adjacency_list = AdjacencyList::parse(
             maps.pos_s_p_adjacency_list_maps.nums_map,
             maps.pos_s_p_adjacency_list_maps.bitindex_maps.bits_map,
             maps.pos_s_p_adjacency_list_maps.bitindex_maps.blocks_map,
             maps.pos_s_p_adjacency_list_maps.bitindex_maps.sblocks_map,
-        );
+        )?;
         let pos_sp_o_adjacency_list = AdjacencyList::parse(
             maps.pos_sp_o_adjacency_list_maps.nums_map,
             maps.pos_sp_o_adjacency_list_maps.bitindex_maps.bits_map,
             maps.pos_sp_o_adjacency_list_maps.bitindex_maps.blocks_map,
             maps.pos_sp_o_adjacency_list_maps.bitindex_maps.sblocks_map,
-        );
+        )?;
         let pos_o_ps_adjacency_list = AdjacencyList::parse(
             maps.pos_o_ps_adjacency_list_maps.nums_map,
             maps.pos_o_ps_adjacency_list_maps.bitindex_maps.bits_map,
             maps.pos_o_ps_adjacency_list_maps.bitindex_maps.blocks_map,
             maps.pos_o_ps_adjacency_list_maps.bitindex_maps.sblocks_map,
-        );
+        )?;
         let neg_s_p_adjacency_list = AdjacencyList::parse(
             maps.neg_s_p_adjacency_list_maps.nums_map,
             maps.neg_s_p_adjacency_list_maps.bitindex_maps.bits_map,
             maps.neg_s_p_adjacency_list_maps.bitindex_maps.blocks_map,
             maps.neg_s_p_adjacency_list_maps.bitindex_maps.sblocks_map,
-        );
+        )?;
         let neg_sp_o_adjacency_list = AdjacencyList::parse(
             maps.neg_sp_o_adjacency_list_maps.nums_map,
             maps.neg_sp_o_adjacency_list_maps.bitindex_maps.bits_map,
             maps.neg_sp_o_adjacency_list_maps.bitindex_maps.blocks_map,
             maps.neg_sp_o_adjacency_list_maps.bitindex_maps.sblocks_map,
-        );
+        )?;
         let neg_o_ps_adjacency_list = AdjacencyList::parse(
             maps.neg_o_ps_adjacency_list_maps.nums_map,
             maps.neg_o_ps_adjacency_list_maps.bitindex_maps.bits_map,
             maps.neg_o_ps_adjacency_list_maps.bitindex_maps.blocks_map,
             maps.neg_o_ps_adjacency_list_maps.bitindex_maps.sblocks_map,
-        );
+        )?;
 
         let pos_predicate_wavelet_tree_width = pos_s_p_adjacency_list.nums().width();
         let pos_predicate_wavelet_tree = WaveletTree::from_parts(
@@ -150,7 +153,7 @@ impl ChildLayer {
                 maps.pos_predicate_wavelet_tree_maps.bits_map,
                 maps.pos_predicate_wavelet_tree_maps.blocks_map,
                 maps.pos_predicate_wavelet_tree_maps.sblocks_map,
-            ),
+            )?,
             pos_predicate_wavelet_tree_width,
         );
 
@@ -160,11 +163,11 @@ impl ChildLayer {
                 maps.neg_predicate_wavelet_tree_maps.bits_map,
                 maps.neg_predicate_wavelet_tree_maps.blocks_map,
                 maps.neg_predicate_wavelet_tree_maps.sblocks_map,
-            ),
+            )?,
             neg_predicate_wavelet_tree_width,
         );
 
-        ChildLayer {
+        Ok(ChildLayer {
             name,
             parent: parent,
 
@@ -193,7 +196,7 @@ impl ChildLayer {
 
             pos_predicate_wavelet_tree,
             neg_predicate_wavelet_tree,
-        }
+        })
     }
 }
 
@@ -343,13 +346,16 @@ impl<F: 'static + FileLoad + FileStore + Clone + Send + Sync> ChildLayerFileBuil
         }
     }
 
-    /// Add a value string.
+    /// Add a value dictionary entry.
     ///
     /// Does nothing if the value already exists in the paretn, and
     /// panics if the given value string is not a lexical successor of
     /// the previous value string.
     pub async fn add_value(&mut self, value: &str) -> io::Result<u64> {
-        match self.parent.object_value_id(value) {
+        match self
+            .parent
+            .object_id_of(&ObjectType::from_value_dict_entry(value.to_owned()))
+        {
             None => self.builder.add_value(value).await,
             Some(id) => Ok(id),
         }
@@ -21,14 +21,16 @@ pub async fn dictionary_rollup<F: 'static + FileLoad + FileStore>(
         .immediate_layers()
         .into_iter()
         .map(|l| l.predicate_dictionary());
-    let value_dicts = layer
+    // value dictionaries may be of an older format, so their entries are upgraded as they are merged
+    let value_entries = layer
         .immediate_layers()
         .into_iter()
-        .map(|l| l.value_dictionary());
+        .map(|l| l.value_dict_entries())
+        .collect();
 
     merge_dictionaries(node_dicts, files.node_dictionary_files.clone()).await?;
     merge_dictionaries(predicate_dicts, files.predicate_dictionary_files.clone()).await?;
-    merge_dictionaries(value_dicts, files.value_dictionary_files.clone()).await?;
+    merge_dictionary_entries(value_entries, files.value_dictionary_files.clone()).await?;
 
     construct_idmaps(layer, files.id_map_files.clone()).await
 }
@@ -46,14 +48,15 @@ pub async fn dictionary_rollup_upto<F: 'static + FileLoad + FileStore>(
         .immediate_layers_upto(upto)
         .into_iter()
         .map(|l| l.predicate_dictionary());
-    let value_dicts = layer
+    let value_entries = layer
         .immediate_layers_upto(upto)
         .into_iter()
-        .map(|l| l.value_dictionary());
+        .map(|l| l.value_dict_entries())
+        .collect();
 
     merge_dictionaries(node_dicts, files.node_dictionary_files.clone()).await?;
     merge_dictionaries(predicate_dicts, files.predicate_dictionary_files.clone()).await?;
-    merge_dictionaries(value_dicts, files.value_dictionary_files.clone()).await?;
+    merge_dictionary_entries(value_entries, files.value_dictionary_files.clone()).await?;
 
     construct_idmaps_upto(layer, upto, files.id_map_files.clone()).await
 }
@@ -18,17 +18,21 @@ impl Default for IdMap {
 }
 
 impl IdMap {
-    pub fn from_maps(maps: BitIndexMaps, width: u8) -> Self {
-        let bitindex = BitIndex::from_maps(maps.bits_map, maps.blocks_map, maps.sblocks_map);
+    pub fn from_maps(maps: BitIndexMaps, width: u8) -> io::Result<Self> {
+        let bitindex = BitIndex::from_maps(maps.bits_map, maps.blocks_map, maps.sblocks_map)?;
         let id_wtree = WaveletTree::from_parts(bitindex, width);
 
-        Self::from_parts(Some(id_wtree))
+        Ok(Self::from_parts(Some(id_wtree)))
     }
 
     pub fn from_parts(id_wtree: Option<WaveletTree>) -> Self {
         IdMap { id_wtree }
     }
 
+    pub fn heap_size(&self) -> usize {
+        self.id_wtree.as_ref().map_or(0, |wtree| wtree.heap_size())
+    }
+
     pub fn outer_to_inner(&self, id: u64) -> u64 {
         self.id_wtree
             .as_ref()
@@ -6,8 +6,10 @@ use super::base::*;
 use super::child::*;
 use super::id_map::*;
 use super::layer::*;
+use super::rollup::*;
 use crate::structure::*;
 use std::convert::TryInto;
+use std::iter::Peekable;
 use std::ops::Deref;
 
 pub use object_iterator::*;
@@ -99,16 +101,31 @@ pub trait InternalLayerImpl {
         self.node_dictionary().len()
     }
 
-    fn value_dict_id(&self, value: &str) -> Option<u64> {
-        self.value_dictionary().id(value)
+    fn value_dict_id(&self, value: &ObjectType) -> Option<u64> {
+        let dict = self.value_dictionary();
+        value
+            .value_dict_entry_in(dict.format_version())
+            .and_then(|entry| dict.id(&entry))
     }
 
     fn value_dict_len(&self) -> usize {
         self.value_dictionary().len()
     }
 
-    fn value_dict_get(&self, id: usize) -> Option<String> {
-        self.value_dictionary().get(id)
+    fn value_dict_get(&self, id: usize) -> Option<ObjectType> {
+        let dict = self.value_dictionary();
+        dict.get(id)
+            .map(|entry| ObjectType::from_value_dict_entry_in(entry, dict.format_version()))
+    }
+
+    /// The entries of the value dictionary, as they would be stored in a value dictionary of the current format.
+    fn value_dict_entries(&self) -> Box<dyn Iterator<Item = PfcDictEntry> + Send> {
+        let format_version = self.value_dictionary().format_version();
+        Box::new(
+            self.value_dictionary()
+                .entries()
+                .map(move |e| upgrade_value_dict_entry(e, format_version)),
+        )
     }
 
     fn node_dict_entries_zero_index(&self) -> Box<dyn Iterator<Item = (u64, PfcDictEntry)> + Send> {
@@ -133,18 +150,13 @@ pub trait InternalLayerImpl {
         let parent_node_value_count = self.parent_node_value_count();
         let node_count = self.node_dict_len();
         let node_value_id_map = self.node_value_id_map().clone();
-        Box::new(
-            self.value_dictionary()
-                .entries()
-                .enumerate()
-                .map(move |(i, e)| {
-                    (
-                        node_value_id_map.inner_to_outer((i + node_count) as u64)
-                            + parent_node_value_count as u64,
-                        e,
-                    )
-                }),
-        )
+        Box::new(self.value_dict_entries().enumerate().map(move |(i, e)| {
+            (
+                node_value_id_map.inner_to_outer((i + node_count) as u64)
+                    + parent_node_value_count as u64,
+                e,
+            )
+        }))
     }
 
     fn predicate_dict_entries_zero_index(
@@ -337,7 +349,15 @@ impl<T: 'static + InternalLayerImpl + Send + Sync + Clone> Layer for T {
         id_option.map(|id| 1 + id + parent_option.map_or(0, |p| p.node_and_value_count() as u64))
     }
 
-    fn object_value_id<'a>(&'a self, object: &str) -> Option<u64> {
+    fn object_value_id(&self, object: &str) -> Option<u64> {
+        self.object_id_of(&ObjectType::Value(object.to_owned()))
+    }
+
+    fn object_id_of<'a>(&'a self, object: &ObjectType) -> Option<u64> {
+        if let ObjectType::Node(node) = object {
+            return self.object_node_id(node);
+        }
+
         let to_result = |layer: &'a dyn InternalLayerImpl| {
             (
                 layer.value_dict_id(object).map(|i| {
@@ -449,9 +469,7 @@ impl<T: 'static + InternalLayerImpl + Send + Sync + Clone> Layer for T {
             if corrected_id >= current_layer.node_dict_len() as u64 {
                 // object, if it exists, must be a value
                 corrected_id -= current_layer.node_dict_len() as u64;
-                return current_layer
-                    .value_dict_get(corrected_id.try_into().unwrap())
-                    .map(ObjectType::Value);
+                return current_layer.value_dict_get(corrected_id.try_into().unwrap());
             } else {
                 return current_layer
                     .node_dict_get(corrected_id.try_into().unwrap())
@@ -462,6 +480,145 @@ impl<T: 'static + InternalLayerImpl + Send + Sync + Clone> Layer for T {
         None
     }
 
+    fn object_type_of_id(&self, id: u64) -> Option<ObjectKind> {
+        if id == 0 || id > self.node_and_value_count() as u64 {
+            return None;
+        }
+        let mut corrected_id = id - 1;
+        let mut current_option: Option<&dyn InternalLayerImpl> = Some(self);
+        let mut parent_count = self.node_and_value_count() as u64;
+        while let Some(current_layer) = current_option {
+            if let Some(parent) = current_layer.immediate_parent() {
+                parent_count = parent_count
+                    - current_layer.node_dict_len() as u64
+                    - current_layer.value_dict_len() as u64;
+
+                if corrected_id >= parent_count {
+                    // object is in this layer
+                    corrected_id -= parent_count;
+                } else {
+                    current_option = Some(parent);
+                    continue;
+                }
+            }
+
+            corrected_id = current_layer
+                .node_value_id_map()
+                .outer_to_inner(corrected_id);
+
+            if corrected_id >= current_layer.node_dict_len() as u64 {
+                return Some(ObjectKind::Value);
+            } else {
+                return Some(ObjectKind::Node);
+            }
+        }
+
+        None
+    }
+
+    fn subjects_with_prefix(&self, prefix: &str) -> Box<dyn Iterator<Item = (u64, String)>> {
+        let mut iters = Vec::new();
+        let mut layer_option: Option<&dyn InternalLayerImpl> = Some(self);
+        while let Some(layer) = layer_option {
+            let parent_count = layer.parent_node_value_count() as u64;
+            let node_value_id_map = layer.node_value_id_map().clone();
+            iters.push(Box::new(
+                layer
+                    .node_dictionary()
+                    .strings_with_prefix(prefix)
+                    .map(move |(i, s)| (1 + node_value_id_map.inner_to_outer(i) + parent_count, s)),
+            ) as DictEntryIterator);
+
+            layer_option = layer
+                .immediate_parent()
+                .map(|p| p as &dyn InternalLayerImpl);
+        }
+
+        Box::new(SortedDictEntryMerge::new(iters))
+    }
+
+    fn iter_subject_dict(&self) -> Box<dyn Iterator<Item = (u64, String)> + Send> {
+        let mut iters = Vec::new();
+        let mut layer_option: Option<&dyn InternalLayerImpl> = Some(self);
+        while let Some(layer) = layer_option {
+            iters.push(dict_entries_in_id_order(
+                layer.node_dictionary(),
+                layer.node_value_id_map(),
+                0,
+                layer.parent_node_value_count() as u64,
+            ));
+
+            layer_option = layer
+                .immediate_parent()
+                .map(|p| p as &dyn InternalLayerImpl);
+        }
+
+        Box::new(iters.into_iter().rev().flatten())
+    }
+
+    fn iter_predicate_dict(&self) -> Box<dyn Iterator<Item = (u64, String)> + Send> {
+        let mut iters = Vec::new();
+        let mut layer_option: Option<&dyn InternalLayerImpl> = Some(self);
+        while let Some(layer) = layer_option {
+            iters.push(dict_entries_in_id_order(
+                layer.predicate_dictionary(),
+                layer.predicate_id_map(),
+                0,
+                layer.parent_predicate_count() as u64,
+            ));
+
+            layer_option = layer
+                .immediate_parent()
+                .map(|p| p as &dyn InternalLayerImpl);
+        }
+
+        Box::new(iters.into_iter().rev().flatten())
+    }
+
+    fn iter_value_dict(&self) -> Box<dyn Iterator<Item = (u64, ObjectType)> + Send> {
+        let mut iters = Vec::new();
+        let mut layer_option: Option<&dyn InternalLayerImpl> = Some(self);
+        while let Some(layer) = layer_option {
+            let format_version = layer.value_dictionary().format_version();
+            iters.push(
+                dict_entries_in_id_order(
+                    layer.value_dictionary(),
+                    layer.node_value_id_map(),
+                    layer.node_dict_len() as u64,
+                    layer.parent_node_value_count() as u64,
+                )
+                .map(move |(id, s)| (id, ObjectType::from_value_dict_entry_in(s, format_version))),
+            );
+
+            layer_option = layer
+                .immediate_parent()
+                .map(|p| p as &dyn InternalLayerImpl);
+        }
+
+        Box::new(iters.into_iter().rev().flatten())
+    }
+
+    fn predicates_with_prefix(&self, prefix: &str) -> Box<dyn Iterator<Item = (u64, String)>> {
+        let mut iters = Vec::new();
+        let mut layer_option: Option<&dyn InternalLayerImpl> = Some(self);
+        while let Some(layer) = layer_option {
+            let parent_count = layer.parent_predicate_count() as u64;
+            let predicate_id_map = layer.predicate_id_map().clone();
+            iters.push(Box::new(
+                layer
+                    .predicate_dictionary()
+                    .strings_with_prefix(prefix)
+                    .map(move |(i, s)| (1 + predicate_id_map.inner_to_outer(i) + parent_count, s)),
+            ) as DictEntryIterator);
+
+            layer_option = layer
+                .immediate_parent()
+                .map(|p| p as &dyn InternalLayerImpl);
+        }
+
+        Box::new(SortedDictEntryMerge::new(iters))
+    }
+
     fn subjects(&self) -> Box<dyn Iterator<Item = Box<dyn SubjectLookup>>> {
         let mut layers = Vec::new();
         layers.push((
@@ -878,6 +1035,32 @@ impl<T: 'static + InternalLayerImpl + Send + Sync + Clone> Layer for T {
         Box::new(self.clone())
     }
 
+    fn heap_size(&self) -> usize {
+        let optional_size = |list: Option<&AdjacencyList>| list.map_or(0, |l| l.heap_size());
+        let optional_array_size =
+            |array: Option<&MonotonicLogArray>| array.map_or(0, |a| a.heap_size());
+
+        self.node_dictionary().heap_size()
+            + self.predicate_dictionary().heap_size()
+            + self.value_dictionary().heap_size()
+            + self.node_value_id_map().heap_size()
+            + self.predicate_id_map().heap_size()
+            + self.pos_s_p_adjacency_list().heap_size()
+            + self.pos_sp_o_adjacency_list().heap_size()
+            + self.pos_o_ps_adjacency_list().heap_size()
+            + optional_size(self.neg_s_p_adjacency_list())
+            + optional_size(self.neg_sp_o_adjacency_list())
+            + optional_size(self.neg_o_ps_adjacency_list())
+            + self.pos_predicate_wavelet_tree().heap_size()
+            + self
+                .neg_predicate_wavelet_tree()
+                .map_or(0, |t| t.heap_size())
+            + optional_array_size(self.pos_subjects())
+            + optional_array_size(self.pos_objects())
+            + optional_array_size(self.neg_subjects())
+            + optional_array_size(self.neg_objects())
+    }
+
     fn triple_layer_addition_count(&self) -> usize {
         self.pos_sp_o_adjacency_list().right_count()
             - self
@@ -926,17 +1109,20 @@ impl<T: 'static + InternalLayerImpl + Send + Sync + Clone> Layer for T {
         let mut node_count = self.node_dict_len();
         let mut predicate_count = self.predicate_dict_len();
         let mut value_count = self.value_dict_len();
+        let mut chain_depth = 1;
         let mut parent_option = self.immediate_parent();
         while let Some(parent) = parent_option {
             node_count += parent.node_dict_len();
             predicate_count += parent.predicate_dict_len();
             value_count += parent.value_dict_len();
+            chain_depth += 1;
             parent_option = parent.immediate_parent();
         }
         LayerCounts {
             node_count,
             predicate_count,
             value_count,
+            chain_depth,
         }
     }
 
@@ -967,6 +1153,47 @@ impl<T: 'static + InternalLayerImpl + Send + Sync + Clone> Layer for T {
         }
     }
 
+    fn triples_exist(&self, triples: &[(u64, u64, u64)]) -> Vec<bool> {
+        // look up every distinct triple once, in sorted order, so
+        // each layer's structures are walked front to back
+        let mut probes: Vec<_> = triples
+            .iter()
+            .filter(|&&(s, p, o)| s != 0 && p != 0 && o != 0)
+            .cloned()
+            .collect();
+        probes.sort_unstable();
+        probes.dedup();
+
+        let mut found = vec![false; probes.len()];
+        let mut pending: Vec<usize> = (0..probes.len()).collect();
+        let mut resolve = |layer: &dyn Layer, pending: &mut Vec<usize>| {
+            pending.retain(|&i| {
+                let (s, p, o) = probes[i];
+                if layer.triple_addition_exists(s, p, o) {
+                    found[i] = true;
+                    false
+                } else {
+                    !layer.triple_removal_exists(s, p, o)
+                }
+            })
+        };
+
+        resolve(self, &mut pending);
+        let mut parent_opt = self.immediate_parent();
+        while let Some(parent) = parent_opt {
+            if pending.is_empty() {
+                break;
+            }
+            resolve(parent, &mut pending);
+            parent_opt = parent.immediate_parent();
+        }
+
+        triples
+            .iter()
+            .map(|t| probes.binary_search(t).map(|i| found[i]).unwrap_or(false))
+            .collect()
+    }
+
     fn triple_addition_exists(&self, subject: u64, predicate: u64, object: u64) -> bool {
         layer_triple_exists(
             self.pos_subjects(),
@@ -1082,6 +1309,176 @@ impl<T: 'static + InternalLayerImpl + Send + Sync + Clone> Layer for T {
         Box::new(InternalTriplePredicateIterator::from_layer(self, predicate))
     }
 
+    fn distinct_subjects(&self) -> usize {
+        if self.immediate_parent().is_none() {
+            // without removals, every subject in the index has triples
+            self.subject_additions().count()
+        } else {
+            self.subjects()
+                .filter(|lookup| lookup.triples().next().is_some())
+                .count()
+        }
+    }
+
+    fn distinct_objects(&self) -> usize {
+        if self.immediate_parent().is_none() {
+            self.object_additions().count()
+        } else {
+            self.objects()
+                .filter(|lookup| lookup.subject_predicate_pairs().next().is_some())
+                .count()
+        }
+    }
+
+    fn predicate_occurrence_count(&self, predicate: u64) -> usize {
+        // every addition is new to the stack and every removal removes
+        // an existing triple, so the counts per layer can just be summed
+        let mut additions = 0;
+        let mut removals = 0;
+        let mut layer_opt: Option<&dyn InternalLayerImpl> = Some(self);
+        while let Some(layer) = layer_opt {
+            additions += layer_predicate_occurrence_count(
+                layer.pos_predicate_wavelet_tree(),
+                layer.pos_sp_o_adjacency_list(),
+                predicate,
+            );
+            if let (Some(wavelet_tree), Some(sp_o_adjacency_list)) = (
+                layer.neg_predicate_wavelet_tree(),
+                layer.neg_sp_o_adjacency_list(),
+            ) {
+                removals +=
+                    layer_predicate_occurrence_count(wavelet_tree, sp_o_adjacency_list, predicate);
+            }
+
+            layer_opt = layer
+                .immediate_parent()
+                .map(|p| p as &dyn InternalLayerImpl);
+        }
+
+        additions - removals
+    }
+
+    fn nth_triple_with_predicate(&self, predicate: u64, mut n: usize) -> Option<IdTriple> {
+        if self.immediate_parent().is_none() {
+            return layer_nth_triple_with_predicate(
+                self.pos_subjects(),
+                self.pos_s_p_adjacency_list(),
+                self.pos_sp_o_adjacency_list(),
+                self.pos_predicate_wavelet_tree(),
+                predicate,
+                n,
+            );
+        }
+        if predicate == 0 {
+            return None;
+        }
+
+        let mut layers: Vec<&dyn InternalLayerImpl> = Vec::new();
+        let mut layer_opt: Option<&dyn InternalLayerImpl> = Some(self);
+        while let Some(layer) = layer_opt {
+            layers.push(layer);
+            layer_opt = layer
+                .immediate_parent()
+                .map(|p| p as &dyn InternalLayerImpl);
+        }
+
+        // Removals can shift positions, so we walk the subjects that
+        // were given this predicate in any layer, in order. For each of
+        // them, the amount of triples left over in the stack is just
+        // the sum of the additions minus the removals in every layer,
+        // so whole subjects can be skipped without looking at their
+        // objects.
+        let mut subject_iters: Vec<_> = layers
+            .iter()
+            .map(|layer| {
+                layer_predicate_subjects(
+                    layer.pos_subjects(),
+                    layer.pos_s_p_adjacency_list(),
+                    layer.pos_predicate_wavelet_tree(),
+                    predicate,
+                )
+                .peekable()
+            })
+            .collect();
+        loop {
+            let subject = subject_iters
+                .iter_mut()
+                .filter_map(|iter| iter.peek().cloned())
+                .min()?;
+            for iter in subject_iters.iter_mut() {
+                if iter.peek() == Some(&subject) {
+                    iter.next();
+                }
+            }
+
+            let mut additions = 0;
+            let mut removals = 0;
+            for layer in layers.iter() {
+                additions += layer_sp_object_count(
+                    layer.pos_subjects(),
+                    layer.pos_s_p_adjacency_list(),
+                    layer.pos_sp_o_adjacency_list(),
+                    subject,
+                    predicate,
+                );
+                if let (Some(s_p_adjacency_list), Some(sp_o_adjacency_list)) = (
+                    layer.neg_s_p_adjacency_list(),
+                    layer.neg_sp_o_adjacency_list(),
+                ) {
+                    removals += layer_sp_object_count(
+                        layer.neg_subjects(),
+                        s_p_adjacency_list,
+                        sp_o_adjacency_list,
+                        subject,
+                        predicate,
+                    );
+                }
+            }
+
+            let count = additions - removals;
+            if n < count {
+                return self.triples_sp(subject, predicate).nth(n);
+            }
+            n -= count;
+        }
+    }
+
+    fn triples_with_object_value_range(
+        &self,
+        low: &str,
+        high: &str,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        let objects = value_objects_in_entry_ranges(
+            self,
+            |format_version| ObjectType::value_dict_entry_ranges(low, high, format_version),
+            |object| match object {
+                ObjectType::Value(value) => low <= value.as_str() && value.as_str() < high,
+                _ => false,
+            },
+        );
+
+        let layer = self.clone();
+        Box::new(objects.into_iter().flat_map(move |id| layer.triples_o(id)))
+    }
+
+    fn triples_with_numeric_object_range(
+        &self,
+        low: Numeric,
+        high: Numeric,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        let objects = value_objects_in_entry_ranges(
+            self,
+            |format_version| ObjectType::numeric_dict_entry_range(low, high, format_version),
+            |object| match object {
+                ObjectType::Numeric(n) => low <= *n && *n < high,
+                _ => false,
+            },
+        );
+
+        let layer = self.clone();
+        Box::new(objects.into_iter().flat_map(move |id| layer.triples_o(id)))
+    }
+
     fn triple_additions_o(&self, object: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
         Box::new(
             self.internal_triple_additions_by_object()
@@ -1105,12 +1502,216 @@ impl<T: 'static + InternalLayerImpl + Send + Sync + Clone> Layer for T {
                 .take_while(move |t| t.object == object),
         )
     }
+
+    fn triples_by_object(&self) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        sort_object_groups(InternalTripleObjectIterator::from_layer(self))
+    }
+
+    fn out_neighbors(&self, subject: u64) -> Box<dyn Iterator<Item = (u64, u64)> + Send> {
+        if self.immediate_parent().is_some() {
+            // removals in this layer or its ancestors have to be
+            // taken into account, which the triple iterators do
+            return Box::new(self.triples_s(subject).map(|t| (t.predicate, t.object)));
+        }
+
+        let s_p_adjacency_list = self.pos_s_p_adjacency_list();
+        let index = match self.pos_subjects() {
+            None => subject,
+            Some(subjects) => match subjects.index_of(subject) {
+                Some(index) => index as u64 + 1,
+                None => return Box::new(std::iter::empty()),
+            },
+        };
+        if index == 0 || index > s_p_adjacency_list.left_count() as u64 {
+            return Box::new(std::iter::empty());
+        }
+
+        let offset = s_p_adjacency_list.offset_for(index);
+        let sp_o_adjacency_list = self.pos_sp_o_adjacency_list().clone();
+        Box::new(
+            s_p_adjacency_list
+                .get(index)
+                .iter()
+                .enumerate()
+                .filter(|(_, predicate)| *predicate != 0)
+                .flat_map(move |(i, predicate)| {
+                    sp_o_adjacency_list
+                        .get(offset + i as u64 + 1)
+                        .iter()
+                        .filter(|object| *object != 0)
+                        .map(move |object| (predicate, object))
+                }),
+        )
+    }
+
+    fn in_neighbors(&self, object: u64) -> Box<dyn Iterator<Item = (u64, u64)> + Send> {
+        if self.immediate_parent().is_some() {
+            return Box::new(self.triples_o(object).map(|t| (t.predicate, t.subject)));
+        }
+
+        let o_ps_adjacency_list = self.pos_o_ps_adjacency_list();
+        let index = match self.pos_objects() {
+            None => object,
+            Some(objects) => match objects.index_of(object) {
+                Some(index) => index as u64 + 1,
+                None => return Box::new(std::iter::empty()),
+            },
+        };
+        if index == 0 || index > o_ps_adjacency_list.left_count() as u64 {
+            return Box::new(std::iter::empty());
+        }
+
+        let s_p_adjacency_list = self.pos_s_p_adjacency_list().clone();
+        let subjects = self.pos_subjects().cloned();
+        Box::new(
+            o_ps_adjacency_list
+                .get(index)
+                .iter()
+                .filter(|sp_pair_num| *sp_pair_num != 0)
+                .map(move |sp_pair_num| {
+                    let (mapped_subject, predicate) =
+                        s_p_adjacency_list.pair_at_pos(sp_pair_num - 1);
+                    let subject = match subjects.as_ref() {
+                        Some(subjects) => subjects.entry(mapped_subject as usize - 1),
+                        None => mapped_subject,
+                    };
+
+                    (predicate, subject)
+                }),
+        )
+    }
+
+    fn triples_page(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        if self.immediate_parent().is_some() {
+            return Box::new(self.triples().skip(offset).take(limit));
+        }
+
+        let s_p_adjacency_list = self.pos_s_p_adjacency_list();
+        let sp_o_adjacency_list = self.pos_sp_o_adjacency_list();
+        let end = sp_o_adjacency_list.right_count() as u64;
+
+        // subjects without any triples take up a single position with
+        // predicate 0 in the s_p list, and one with object 0 in the
+        // sp_o list. These have to be accounted for when jumping.
+        let placeholders = self.pos_predicate_wavelet_tree().lookup(0);
+        let placeholders_before = |s_p_position: u64| match placeholders.as_ref() {
+            None => 0,
+            Some(lookup) => {
+                let (mut low, mut high) = (0, lookup.len());
+                while low < high {
+                    let mid = (low + high) / 2;
+                    if lookup.entry(mid) < s_p_position {
+                        low = mid + 1;
+                    } else {
+                        high = mid;
+                    }
+                }
+                low as u64
+            }
+        };
+
+        // find the first sp_o position that has exactly `offset` triples before it
+        let mut position = offset as u64;
+        while position < end {
+            let s_p_position = sp_o_adjacency_list.left_at_pos(position) - 1;
+            let next = offset as u64 + placeholders_before(s_p_position);
+            if next == position {
+                break;
+            }
+            position = next;
+        }
+
+        Box::new(
+            InternalLayerTripleSubjectIterator::new(
+                self.pos_subjects(),
+                s_p_adjacency_list,
+                sp_o_adjacency_list,
+            )
+            .seek_sp_o_pos(position)
+            .take(limit),
+        )
+    }
+}
+
+type DictEntryIterator = Box<dyn Iterator<Item = (u64, String)>>;
+
+/// The entries of a single layer's dictionary along with their numerical id, in id order.
+///
+/// `offset` is the position of the first dictionary entry in
+/// `id_map`, which is nonzero for the value dictionary. Without an id
+/// map, the dictionary order already is the id order. Otherwise the
+/// entries of this layer have to be sorted first.
+fn dict_entries_in_id_order(
+    dict: &PfcDict,
+    id_map: &IdMap,
+    offset: u64,
+    parent_count: u64,
+) -> Box<dyn Iterator<Item = (u64, String)> + Send> {
+    let is_identity = id_map.id_wtree.is_none();
+    let id_map = id_map.clone();
+    let entries = dict.strings().enumerate().map(move |(i, s)| {
+        (
+            1 + id_map.inner_to_outer(i as u64 + offset) + parent_count,
+            s,
+        )
+    });
+
+    if is_identity {
+        Box::new(entries)
+    } else {
+        let mut entries: Vec<_> = entries.collect();
+        entries.sort_unstable_by_key(|(id, _)| *id);
+        Box::new(entries.into_iter())
+    }
+}
+
+/// Merges the sorted dictionary entries of several layers into one sorted iterator.
+///
+/// Every string occurs in at most one layer of a stack, so there is
+/// nothing to deduplicate.
+struct SortedDictEntryMerge {
+    iters: Vec<Peekable<DictEntryIterator>>,
+}
+
+impl SortedDictEntryMerge {
+    fn new(iters: Vec<DictEntryIterator>) -> Self {
+        Self {
+            iters: iters.into_iter().map(|i| i.peekable()).collect(),
+        }
+    }
+}
+
+impl Iterator for SortedDictEntryMerge {
+    type Item = (u64, String);
+
+    fn next(&mut self) -> Option<(u64, String)> {
+        let mut min: Option<(usize, &str)> = None;
+        for (ix, iter) in self.iters.iter_mut().enumerate() {
+            if let Some((_, s)) = iter.peek() {
+                let smaller = match min {
+                    None => true,
+                    Some((_, m)) => s.as_str() < m,
+                };
+                if smaller {
+                    min = Some((ix, s.as_str()));
+                }
+            }
+        }
+
+        let ix = min.map(|(ix, _)| ix)?;
+        self.iters[ix].next()
+    }
 }
 
 #[derive(Clone)]
 pub enum InternalLayer {
     Base(BaseLayer),
     Child(ChildLayer),
+    Rollup(RollupLayer),
 }
 
 impl InternalLayer {
@@ -1118,6 +1719,22 @@ impl InternalLayer {
         match self {
             Self::Base(base) => base as &dyn Layer,
             Self::Child(child) => child as &dyn Layer,
+            Self::Rollup(rollup) => rollup as &dyn Layer,
+        }
+    }
+
+    pub fn is_rollup(&self) -> bool {
+        matches!(self, Self::Rollup(_))
+    }
+
+    /// The layer holding the additions and removals of this layer itself.
+    ///
+    /// This is the layer itself, except for a rollup, whose own
+    /// structures hold the contents of the entire layer stack.
+    pub fn changes_layer(&self) -> &InternalLayer {
+        match self {
+            Self::Rollup(rollup) => rollup.changes(),
+            _ => self,
         }
     }
 
@@ -1170,6 +1787,7 @@ impl Deref for InternalLayer {
         match self {
             Self::Base(base) => base as &Self::Target,
             Self::Child(child) => child as &Self::Target,
+            Self::Rollup(rollup) => rollup as &Self::Target,
         }
     }
 }
@@ -1186,6 +1804,12 @@ impl From<ChildLayer> for InternalLayer {
     }
 }
 
+impl From<RollupLayer> for InternalLayer {
+    fn from(layer: RollupLayer) -> InternalLayer {
+        InternalLayer::Rollup(layer)
+    }
+}
+
 impl InternalLayerImpl for InternalLayer {
     fn name(&self) -> [u32; 5] {
         InternalLayerImpl::name(&**self)
@@ -1470,6 +2094,147 @@ fn layer_triple_exists(
     return false;
 }
 
+// The amount of triples with the given predicate in a single layer.
+//
+// The wavelet tree selects the s_p positions for the predicate
+// directly, and the sp_o adjacency list tells us how many objects
+// there are for each of them.
+fn layer_predicate_occurrence_count(
+    predicate_wavelet_tree: &WaveletTree,
+    sp_o_adjacency_list: &AdjacencyList,
+    predicate: u64,
+) -> usize {
+    if predicate == 0 {
+        return 0;
+    }
+
+    match predicate_wavelet_tree.lookup(predicate) {
+        Some(lookup) => lookup
+            .iter()
+            .map(|pos| sp_o_adjacency_list.get(pos + 1).len())
+            .sum(),
+        None => 0,
+    }
+}
+
+// The ids of the values in the given layer stack whose dictionary
+// entries fall in the ranges for their dictionary's format version, and
+// that match once decoded, sorted and without duplicates.
+fn value_objects_in_entry_ranges<R: Fn(u8) -> Vec<(String, String)>, M: Fn(&ObjectType) -> bool>(
+    layer: &dyn InternalLayerImpl,
+    entry_ranges: R,
+    matches: M,
+) -> Vec<u64> {
+    let mut objects = Vec::new();
+    let mut layer_option = Some(layer);
+    while let Some(layer) = layer_option {
+        let parent_count = layer.parent_node_value_count() as u64;
+        let node_count = layer.node_dict_len() as u64;
+        let format_version = layer.value_dictionary().format_version();
+        for (entry_low, entry_high) in entry_ranges(format_version) {
+            for (i, entry) in layer
+                .value_dictionary()
+                .strings_in_range(&entry_low, &entry_high)
+            {
+                if matches(&ObjectType::from_value_dict_entry_in(entry, format_version)) {
+                    objects.push(
+                        1 + layer.node_value_id_map().inner_to_outer(i + node_count) + parent_count,
+                    );
+                }
+            }
+        }
+
+        layer_option = layer
+            .immediate_parent()
+            .map(|p| p as &dyn InternalLayerImpl);
+    }
+    objects.sort_unstable();
+    objects.dedup();
+
+    objects
+}
+
+// The subjects that have the given predicate in a single layer, in order.
+fn layer_predicate_subjects(
+    subjects: Option<&MonotonicLogArray>,
+    s_p_adjacency_list: &AdjacencyList,
+    predicate_wavelet_tree: &WaveletTree,
+    predicate: u64,
+) -> impl Iterator<Item = u64> {
+    let subjects = subjects.cloned();
+    let s_p_adjacency_list = s_p_adjacency_list.clone();
+    predicate_wavelet_tree
+        .lookup(predicate)
+        .into_iter()
+        .flat_map(|lookup| lookup.iter())
+        .map(move |pos| {
+            let s_position = s_p_adjacency_list.left_at_pos(pos) - 1;
+            match &subjects {
+                Some(subjects) => subjects.entry(s_position.try_into().unwrap()),
+                None => s_position + 1,
+            }
+        })
+}
+
+// The amount of objects for the given subject and predicate in a single layer.
+fn layer_sp_object_count(
+    subjects: Option<&MonotonicLogArray>,
+    s_p_adjacency_list: &AdjacencyList,
+    sp_o_adjacency_list: &AdjacencyList,
+    subject: u64,
+    predicate: u64,
+) -> usize {
+    let index = match subjects {
+        Some(subjects) => match subjects.index_of(subject) {
+            Some(index) => index as u64 + 1,
+            None => return 0,
+        },
+        None if subject >= 1 && subject <= s_p_adjacency_list.left_count() as u64 => subject,
+        None => return 0,
+    };
+
+    let offset = s_p_adjacency_list.offset_for(index);
+    match s_p_adjacency_list
+        .get(index)
+        .iter()
+        .position(|p| p == predicate)
+    {
+        Some(i) => sp_o_adjacency_list.get(offset + i as u64 + 1).len(),
+        None => 0,
+    }
+}
+
+fn layer_nth_triple_with_predicate(
+    subjects: Option<&MonotonicLogArray>,
+    s_p_adjacency_list: &AdjacencyList,
+    sp_o_adjacency_list: &AdjacencyList,
+    predicate_wavelet_tree: &WaveletTree,
+    predicate: u64,
+    mut n: usize,
+) -> Option<IdTriple> {
+    if predicate == 0 {
+        return None;
+    }
+
+    let lookup = predicate_wavelet_tree.lookup(predicate)?;
+    for pos in lookup.iter() {
+        let objects = sp_o_adjacency_list.get(pos + 1);
+        if n < objects.len() {
+            let s_position = s_p_adjacency_list.left_at_pos(pos) - 1;
+            let subject = match subjects {
+                Some(subjects) => subjects.entry(s_position.try_into().unwrap()),
+                None => s_position + 1,
+            };
+
+            return Some(IdTriple::new(subject, predicate, objects.entry(n)));
+        }
+
+        n -= objects.len();
+    }
+
+    None
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1555,4 +2320,363 @@ mod tests {
 
         assert_eq!(1, layer.triple_layer_addition_count());
     }
+
+    #[test]
+    fn nodes_and_predicates_with_prefix_over_layer_stack() {
+        let store = open_sync_memory_store();
+        let base_layer = create_base_layer(&store);
+        let builder = base_layer.open_write().unwrap();
+
+        builder
+            .add_string_triple(StringTriple::new_node("cat", "likes", "duck"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cat", "sees", "bird"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("dog", "likes", "cow"))
+            .unwrap();
+
+        let layer = builder.commit().unwrap();
+
+        let subjects: Vec<_> = layer.subjects_with_prefix("c").collect();
+        assert_eq!(
+            vec!["cat".to_string(), "cow".to_string()],
+            subjects.iter().map(|(_, s)| s.clone()).collect::<Vec<_>>()
+        );
+        for (id, s) in subjects {
+            assert_eq!(Some(id), layer.subject_id(&s));
+        }
+
+        let predicates: Vec<_> = layer.predicates_with_prefix("s").collect();
+        assert_eq!(
+            vec!["says".to_string(), "sees".to_string()],
+            predicates
+                .iter()
+                .map(|(_, s)| s.clone())
+                .collect::<Vec<_>>()
+        );
+        for (id, s) in predicates {
+            assert_eq!(Some(id), layer.predicate_id(&s));
+        }
+
+        let objects: Vec<_> = layer
+            .object_nodes_with_prefix("d")
+            .map(|(_, s)| s)
+            .collect();
+        assert_eq!(vec!["dog".to_string(), "duck".to_string()], objects);
+
+        assert_eq!(0, layer.subjects_with_prefix("x").count());
+    }
+
+    #[test]
+    fn predicate_occurrences_match_predicate_iterator() {
+        let store = open_sync_memory_store();
+        let builder = store.create_base_layer().unwrap();
+        for (subject, object) in &[
+            ("cow", "duck"),
+            ("cow", "pig"),
+            ("duck", "cow"),
+            ("horse", "cow"),
+            ("horse", "duck"),
+            ("horse", "pig"),
+        ] {
+            builder
+                .add_string_triple(StringTriple::new_node(subject, "likes", object))
+                .unwrap();
+        }
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let base_layer = builder.commit().unwrap();
+
+        let builder = base_layer.open_write().unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_node("horse", "likes", "duck"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("pig", "likes", "cow"))
+            .unwrap();
+        let child_layer = builder.commit().unwrap();
+
+        let builder = child_layer.open_write().unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_node("duck", "likes", "cow"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("horse", "likes", "duck"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("cow", "likes", "horse"))
+            .unwrap();
+        let grandchild_layer = builder.commit().unwrap();
+
+        for layer in &[&base_layer, &child_layer, &grandchild_layer] {
+            for predicate in 0..=layer.predicate_count() as u64 + 1 {
+                let triples: Vec<_> = layer.triples_p(predicate).collect();
+                assert_eq!(triples.len(), layer.predicate_occurrence_count(predicate));
+                for n in 0..=triples.len() {
+                    assert_eq!(
+                        triples.get(n).cloned(),
+                        layer.nth_triple_with_predicate(predicate, n)
+                    );
+                }
+            }
+        }
+
+        let likes = child_layer.predicate_id("likes").unwrap();
+        assert_eq!(6, child_layer.predicate_occurrence_count(likes));
+        assert_eq!(
+            Some(StringTriple::new_node("pig", "likes", "cow")),
+            child_layer
+                .nth_triple_with_predicate(likes, 5)
+                .and_then(|t| child_layer.id_triple_to_string(&t))
+        );
+    }
+
+    #[test]
+    fn dictionary_iterators_walk_ids_in_order() {
+        let store = open_sync_memory_store();
+        let base_layer = create_base_layer(&store);
+        let builder = base_layer.open_write().unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("cat", "likes", "bird"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cat", "says", "meow"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("bird", "sings", "tweet"))
+            .unwrap();
+        let child_layer = builder.commit().unwrap();
+        let rolled_up_layer = child_layer.rollup().unwrap();
+
+        for layer in &[&base_layer, &child_layer, &rolled_up_layer] {
+            let count = layer.node_and_value_count() as u64;
+            let expected_nodes: Vec<_> = (1..=count)
+                .filter_map(|id| layer.id_subject(id).map(|s| (id, s)))
+                .collect();
+            let expected_values: Vec<_> = (1..=count)
+                .filter_map(|id| match layer.id_object(id) {
+                    Some(ObjectType::Node(_)) | None => None,
+                    Some(value) => Some((id, value)),
+                })
+                .collect();
+            let expected_predicates: Vec<_> = (1..=layer.predicate_count() as u64)
+                .map(|id| (id, layer.id_predicate(id).unwrap()))
+                .collect();
+
+            assert_eq!(
+                expected_nodes,
+                layer.iter_subject_dict().collect::<Vec<_>>()
+            );
+            assert_eq!(expected_values, layer.iter_value_dict().collect::<Vec<_>>());
+            assert_eq!(
+                expected_predicates,
+                layer.iter_predicate_dict().collect::<Vec<_>>()
+            );
+        }
+
+        assert_eq!(4, child_layer.iter_subject_dict().count());
+        assert_eq!(4, child_layer.iter_value_dict().count());
+        assert_eq!(3, child_layer.iter_predicate_dict().count());
+    }
+
+    #[test]
+    fn distinct_counts_leave_out_unused_terms() {
+        let store = open_sync_memory_store();
+        let base_layer = create_base_layer(&store);
+
+        assert_eq!(2, base_layer.distinct_subjects());
+        assert_eq!(2, base_layer.distinct_predicates());
+        assert_eq!(3, base_layer.distinct_objects());
+
+        let builder = base_layer.open_write().unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_node("cow", "likes", "duck"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("horse", "says", "neigh"))
+            .unwrap();
+        let child_layer = builder.commit().unwrap();
+
+        // duck and likes are still in the dictionaries, but no longer used
+        assert_eq!(3, child_layer.all_counts().node_count);
+        assert_eq!(2, child_layer.predicate_count());
+        assert_eq!(2, child_layer.distinct_subjects());
+        assert_eq!(1, child_layer.distinct_predicates());
+        assert_eq!(2, child_layer.distinct_objects());
+
+        let rolled_up_layer = child_layer.rollup().unwrap();
+        assert_eq!(2, rolled_up_layer.distinct_subjects());
+        assert_eq!(1, rolled_up_layer.distinct_predicates());
+        assert_eq!(2, rolled_up_layer.distinct_objects());
+    }
+
+    #[test]
+    fn object_type_of_id_matches_id_object() {
+        let store = open_sync_memory_store();
+        let base_layer = create_base_layer(&store);
+        let builder = base_layer.open_write().unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("cat", "likes", "bird"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cat", "says", "meow"))
+            .unwrap();
+        let child_layer = builder.commit().unwrap();
+        let rolled_up_layer = child_layer.rollup().unwrap();
+
+        for layer in &[&base_layer, &child_layer, &rolled_up_layer] {
+            let count = layer.node_and_value_count() as u64;
+            for id in 1..=count {
+                let object = layer.id_object(id).unwrap();
+                let kind = match object {
+                    ObjectType::Node(_) => ObjectKind::Node,
+                    _ => ObjectKind::Value,
+                };
+                assert_eq!(Some(kind), layer.object_type_of_id(id));
+                assert_eq!(Some(id), layer.object_id_of(&object));
+            }
+
+            assert_eq!(None, layer.object_type_of_id(0));
+            assert_eq!(None, layer.object_type_of_id(count + 1));
+        }
+
+        assert_eq!(
+            child_layer.object_value_id("meow"),
+            child_layer.object_id("meow", ObjectKind::Value)
+        );
+        assert_eq!(None, child_layer.object_id("meow", ObjectKind::Node));
+    }
+
+    #[test]
+    fn triples_by_object_sorts_by_object_predicate_subject() {
+        let store = open_sync_memory_store();
+        let base_layer = create_base_layer(&store);
+        let builder = base_layer.open_write().unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("pig", "likes", "cow"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("cow", "hates", "cow"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "hates", "quack"))
+            .unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        let child_layer = builder.commit().unwrap();
+        let rolled_up_layer = child_layer.rollup().unwrap();
+
+        for layer in &[&base_layer, &child_layer, &rolled_up_layer] {
+            let by_object: Vec<_> = layer.triples_by_object().collect();
+            assert!(by_object.windows(2).all(|w| {
+                (w[0].object, w[0].predicate, w[0].subject)
+                    < (w[1].object, w[1].predicate, w[1].subject)
+            }));
+
+            let mut sorted = by_object.clone();
+            sorted.sort();
+            let mut expected: Vec<_> = layer.triples().collect();
+            expected.sort();
+            assert_eq!(expected, sorted);
+        }
+
+        // "likes" comes from the base layer, so it has a lower id than "hates"
+        assert_eq!(
+            vec![
+                StringTriple::new_node("pig", "likes", "cow"),
+                StringTriple::new_node("cow", "hates", "cow"),
+            ],
+            child_layer
+                .triples_by_object()
+                .map(|t| child_layer.id_triple_to_string(&t).unwrap())
+                .filter(|t| t.object == ObjectType::Node("cow".to_string()))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn neighbors_match_subject_and_object_triples() {
+        let store = open_sync_memory_store();
+        let base_layer = create_base_layer(&store);
+        let builder = base_layer.open_write().unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("cat", "likes", "bird"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cat", "says", "meow"))
+            .unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_node("cow", "likes", "duck"))
+            .unwrap();
+        let child_layer = builder.commit().unwrap();
+        let rolled_up_layer = child_layer.rollup().unwrap();
+
+        for layer in &[&base_layer, &child_layer, &rolled_up_layer] {
+            let count = layer.node_and_value_count() as u64;
+            for id in 0..=count + 1 {
+                let out: Vec<_> = layer
+                    .triples_s(id)
+                    .map(|t| (t.predicate, t.object))
+                    .collect();
+                assert_eq!(out, layer.out_neighbors(id).collect::<Vec<_>>());
+
+                let mut incoming: Vec<_> = layer
+                    .triples_o(id)
+                    .map(|t| (t.predicate, t.subject))
+                    .collect();
+                let mut in_neighbors: Vec<_> = layer.in_neighbors(id).collect();
+                incoming.sort();
+                in_neighbors.sort();
+                assert_eq!(incoming, in_neighbors);
+            }
+        }
+
+        let cat = child_layer.subject_id("cat").unwrap();
+        let likes = child_layer.predicate_id("likes").unwrap();
+        let bird = child_layer.object_node_id("bird").unwrap();
+        assert!(child_layer.out_neighbors(cat).any(|n| n == (likes, bird)));
+        assert_eq!(
+            vec![(likes, cat)],
+            child_layer.in_neighbors(bird).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn triples_page_matches_skipping_triples() {
+        let store = open_sync_memory_store();
+        let base_layer = create_base_layer(&store);
+        let builder = base_layer.open_write().unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("cat", "likes", "bird"))
+            .unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_node("cow", "likes", "duck"))
+            .unwrap();
+        let child_layer = builder.commit().unwrap();
+        let rolled_up_layer = child_layer.rollup().unwrap();
+        // bird is never a subject, so the rollup has a placeholder to jump over
+        let bird = rolled_up_layer.subject_id("bird").unwrap();
+        assert_eq!(0, rolled_up_layer.triples_s(bird).count());
+
+        for layer in &[&base_layer, &child_layer, &rolled_up_layer] {
+            let triples: Vec<_> = layer.triples().collect();
+            for offset in 0..=triples.len() + 1 {
+                for &limit in &[0, 1, 3, usize::MAX] {
+                    let expected: Vec<_> =
+                        triples.iter().cloned().skip(offset).take(limit).collect();
+                    assert_eq!(
+                        expected,
+                        layer.triples_page(offset, limit).collect::<Vec<_>>()
+                    );
+                }
+            }
+        }
+    }
 }
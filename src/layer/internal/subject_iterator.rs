@@ -117,6 +117,22 @@ impl InternalLayerTripleSubjectIterator {
         self.sp_o_position = self.sp_o_adjacency_list.offset_for(self.s_p_position + 1);
     }
 
+    /// Move to the given position in the sp_o adjacency list.
+    pub fn seek_sp_o_pos(mut self, pos: u64) -> Self {
+        self.peeked = None;
+
+        if pos >= self.sp_o_adjacency_list.right_count() as u64 {
+            self.sp_o_position = pos;
+            return self;
+        }
+
+        self.sp_o_position = pos;
+        self.s_p_position = self.sp_o_adjacency_list.left_at_pos(pos) - 1;
+        self.s_position = self.s_p_adjacency_list.left_at_pos(self.s_p_position) - 1;
+
+        self
+    }
+
     pub fn peek(&mut self) -> Option<&IdTriple> {
         self.peeked = self.next();
 
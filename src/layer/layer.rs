@@ -3,6 +3,8 @@ use std::collections::HashMap;
 use std::hash::Hash;
 use std::iter::Peekable;
 
+use crate::structure::PfcDictEntry;
+
 /// A layer containing dictionary entries and triples.
 ///
 /// A layer can be queried. To answer queries, layers will check their
@@ -22,18 +24,99 @@ pub trait Layer: Send + Sync {
 
     /// The numerical id of a subject, or None if the subject cannot be found.
     fn subject_id(&self, subject: &str) -> Option<u64>;
+    /// The numerical ids of all subjects that are equal to the given subject, ignoring case.
+    ///
+    /// Unlike `subject_id`, this cannot binary search the dictionary,
+    /// as it is sorted by the raw bytes of every entry. Instead, every
+    /// entry starting with an upper or lower case variant of the first
+    /// character of `subject` is scanned and compared after
+    /// lowercasing. This takes time proportional to the amount of such
+    /// entries, so it is a lot slower than an exact lookup when many
+    /// subjects share their first character.
+    fn subject_id_ci(&self, subject: &str) -> Vec<u64> {
+        let first = match subject.chars().next() {
+            Some(first) => first,
+            None => return self.subject_id(subject).into_iter().collect(),
+        };
+
+        let mut prefixes = vec![
+            first.to_string(),
+            first.to_lowercase().to_string(),
+            first.to_uppercase().to_string(),
+        ];
+        prefixes.sort();
+        prefixes.dedup();
+
+        let folded = subject.to_lowercase();
+        let mut result: Vec<u64> = prefixes
+            .iter()
+            .flat_map(|prefix| self.subjects_with_prefix(prefix))
+            .filter(|(_, candidate)| candidate.to_lowercase() == folded)
+            .map(|(id, _)| id)
+            .collect();
+        result.sort_unstable();
+        result.dedup();
+
+        result
+    }
+
     /// The numerical id of a predicate, or None if the predicate cannot be found.
     fn predicate_id(&self, predicate: &str) -> Option<u64>;
     /// The numerical id of a node object, or None if the node object cannot be found.
     fn object_node_id(&self, object: &str) -> Option<u64>;
-    /// The numerical id of a value object, or None if the value object cannot be found.
+    /// The numerical id of an untyped value object, or None if the value object cannot be found.
     fn object_value_id(&self, object: &str) -> Option<u64>;
+    /// The numerical id of an object of any type, or None if the object cannot be found.
+    fn object_id_of(&self, object: &ObjectType) -> Option<u64>;
     /// The subject corresponding to a numerical id, or None if it cannot be found.
     fn id_subject(&self, id: u64) -> Option<String>;
     /// The predicate corresponding to a numerical id, or None if it cannot be found.
     fn id_predicate(&self, id: u64) -> Option<String>;
     /// The object corresponding to a numerical id, or None if it cannot be found.
     fn id_object(&self, id: u64) -> Option<ObjectType>;
+    /// The numerical id of an object of the given kind, or None if it cannot be found.
+    fn object_id(&self, object: &str, kind: ObjectKind) -> Option<u64> {
+        match kind {
+            ObjectKind::Node => self.object_node_id(object),
+            ObjectKind::Value => self.object_value_id(object),
+        }
+    }
+    /// Whether the object with the given id is a node or a value, or None if it cannot be found.
+    ///
+    /// Nodes and values share a single id space, starting at 1. Each
+    /// layer continues where its parent left off, first numbering the
+    /// nodes it introduces and then the values it introduces. A rollup
+    /// keeps the ids of the layers it was made from, so nodes and
+    /// values are interleaved in the same way. Layers override this to
+    /// only check which of these ranges the id falls in, without
+    /// looking up the object itself.
+    fn object_type_of_id(&self, id: u64) -> Option<ObjectKind> {
+        self.id_object(id).map(|object| match object {
+            ObjectType::Node(_) => ObjectKind::Node,
+            _ => ObjectKind::Value,
+        })
+    }
+    /// Returns true if the node with the given id is a blank node.
+    ///
+    /// Subjects and node objects share their ids, so this works for
+    /// ids returned from both `subject_id` and `object_node_id`.
+    fn is_blank(&self, id: u64) -> bool {
+        self.id_subject(id)
+            .map(|node| is_blank_node(&node))
+            .unwrap_or(false)
+    }
+
+    /// All subjects starting with the given prefix, along with their numerical id, in sorted order.
+    fn subjects_with_prefix(&self, prefix: &str) -> Box<dyn Iterator<Item = (u64, String)>>;
+    /// All predicates starting with the given prefix, along with their numerical id, in sorted order.
+    fn predicates_with_prefix(&self, prefix: &str) -> Box<dyn Iterator<Item = (u64, String)>>;
+    /// All node objects starting with the given prefix, along with their numerical id, in sorted order.
+    ///
+    /// Subjects and node objects share a dictionary, so this returns
+    /// the same nodes as `subjects_with_prefix`.
+    fn object_nodes_with_prefix(&self, prefix: &str) -> Box<dyn Iterator<Item = (u64, String)>> {
+        self.subjects_with_prefix(prefix)
+    }
 
     /// Returns an iterator over all triple data known to this layer.
     ///
@@ -262,6 +345,18 @@ pub trait Layer: Send + Sync {
     /// Returns true if the given triple exists, and false otherwise.
     fn triple_exists(&self, subject: u64, predicate: u64, object: u64) -> bool;
 
+    /// Check the existence of many triples at once, given as (subject, predicate, object) ids.
+    ///
+    /// The result has an entry for every input triple, in the same
+    /// order. Implementations may process the triples in a different
+    /// order to avoid walking the layer stack for each of them.
+    fn triples_exist(&self, triples: &[(u64, u64, u64)]) -> Vec<bool> {
+        triples
+            .iter()
+            .map(|&(subject, predicate, object)| self.triple_exists(subject, predicate, object))
+            .collect()
+    }
+
     fn triple_addition_exists(&self, subject: u64, predicate: u64, object: u64) -> bool;
     fn triple_removal_exists(&self, subject: u64, predicate: u64, object: u64) -> bool;
 
@@ -308,11 +403,7 @@ pub trait Layer: Send + Sync {
     fn string_triple_to_id(&self, triple: &StringTriple) -> Option<IdTriple> {
         self.subject_id(&triple.subject).and_then(|subject| {
             self.predicate_id(&triple.predicate).and_then(|predicate| {
-                match &triple.object {
-                    ObjectType::Node(node) => self.object_node_id(&node),
-                    ObjectType::Value(value) => self.object_value_id(&value),
-                }
-                .map(|object| IdTriple {
+                self.object_id_of(&triple.object).map(|object| IdTriple {
                     subject,
                     predicate,
                     object,
@@ -325,10 +416,202 @@ pub trait Layer: Send + Sync {
     fn triple_removals_p(&self, predicate: u64) -> Box<dyn Iterator<Item = IdTriple> + Send>;
     fn triples_p(&self, predicate: u64) -> Box<dyn Iterator<Item = IdTriple> + Send>;
 
+    /// Returns the amount of triples with the given predicate in this layer and all its parents.
+    fn predicate_occurrence_count(&self, predicate: u64) -> usize {
+        self.triples_p(predicate).count()
+    }
+
+    /// Returns the triple at position `n` in `triples_p(predicate)`, if any.
+    ///
+    /// This is useful for paging through the triples for a predicate.
+    fn nth_triple_with_predicate(&self, predicate: u64, n: usize) -> Option<IdTriple> {
+        self.triples_p(predicate).nth(n)
+    }
+
+    /// Returns every predicate id together with the amount of triples using it, most used first.
+    ///
+    /// Predicates without any triples are left out. Predicates used
+    /// equally often are ordered by id.
+    fn predicate_histogram(&self) -> Vec<(u64, usize)> {
+        let mut histogram: Vec<_> = self
+            .predicates()
+            .map(|lookup| lookup.predicate())
+            .map(|predicate| (predicate, self.predicate_occurrence_count(predicate)))
+            .filter(|(_, count)| *count != 0)
+            .collect();
+        histogram.sort_by(|(p1, c1), (p2, c2)| c2.cmp(c1).then(p1.cmp(p2)));
+
+        histogram
+    }
+
+    /// The amount of distinct subjects that appear in the triples of this layer and its parents.
+    ///
+    /// Unlike the node count, this leaves out nodes that are only
+    /// used as an object, or whose triples were all removed. Only the
+    /// first triple of every subject is looked at.
+    fn distinct_subjects(&self) -> usize {
+        self.subjects()
+            .filter(|lookup| lookup.triples().next().is_some())
+            .count()
+    }
+
+    /// The amount of distinct predicates that appear in the triples of this layer and its parents.
+    ///
+    /// This is calculated from the predicate occurrence counts, so
+    /// no triples are looked at.
+    fn distinct_predicates(&self) -> usize {
+        (1..=self.predicate_count() as u64)
+            .filter(|&predicate| self.predicate_occurrence_count(predicate) != 0)
+            .count()
+    }
+
+    /// The amount of distinct objects, both nodes and values, that appear in the triples of this layer and its parents.
+    ///
+    /// Like `distinct_subjects`, this only looks at the first triple of every object.
+    fn distinct_objects(&self) -> usize {
+        self.objects()
+            .filter(|lookup| lookup.subject_predicate_pairs().next().is_some())
+            .count()
+    }
+
     fn triple_additions_o(&self, object: u64) -> Box<dyn Iterator<Item = IdTriple> + Send>;
     fn triple_removals_o(&self, object: u64) -> Box<dyn Iterator<Item = IdTriple> + Send>;
     fn triples_o(&self, object: u64) -> Box<dyn Iterator<Item = IdTriple> + Send>;
 
+    /// The triple additions in this layer with the given predicate and object.
+    ///
+    /// This looks up the object in the object index, and only keeps
+    /// the triples with the given predicate, so it never has to go
+    /// through all triples with that predicate. It does have to go
+    /// through all triples with that object in this layer though.
+    fn triple_additions_po(
+        &self,
+        predicate: u64,
+        object: u64,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        Box::new(
+            self.triple_additions_o(object)
+                .filter(move |t| t.predicate == predicate),
+        )
+    }
+
+    /// The triple removals in this layer with the given predicate and object.
+    fn triple_removals_po(
+        &self,
+        predicate: u64,
+        object: u64,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        Box::new(
+            self.triple_removals_o(object)
+                .filter(move |t| t.predicate == predicate),
+        )
+    }
+
+    /// All triples with the given predicate and object, in this layer and its parents.
+    ///
+    /// Like `triple_additions_po`, this goes through all triples with
+    /// the given object and keeps those with the given predicate. It
+    /// doesn't use the predicate index, so its cost is proportional
+    /// to the amount of triples with that object in the whole layer
+    /// stack, no matter how few of them have the predicate. For an
+    /// object used with many predicates, while the predicate is used
+    /// with only a few objects, going through `triples_p` and
+    /// filtering on the object is cheaper.
+    fn triples_po(&self, predicate: u64, object: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        Box::new(
+            self.triples_o(object)
+                .filter(move |t| t.predicate == predicate),
+        )
+    }
+
+    /// The (predicate, object) pairs of all triples with the given subject.
+    ///
+    /// This is meant for graph algorithms working in id space. Unlike
+    /// `triples_s`, which constructs a full triple for every edge,
+    /// this only returns the other ends of the outgoing edges. Layers
+    /// without a parent read these straight from their adjacency
+    /// lists.
+    fn out_neighbors(&self, subject: u64) -> Box<dyn Iterator<Item = (u64, u64)> + Send> {
+        Box::new(self.triples_s(subject).map(|t| (t.predicate, t.object)))
+    }
+
+    /// The (predicate, subject) pairs of all triples with the given object.
+    ///
+    /// This is the incoming counterpart of `out_neighbors`, read from
+    /// the object index rather than constructing full triples like
+    /// `triples_o` does.
+    fn in_neighbors(&self, object: u64) -> Box<dyn Iterator<Item = (u64, u64)> + Send> {
+        Box::new(self.triples_o(object).map(|t| (t.predicate, t.subject)))
+    }
+
+    /// At most `limit` triples of this layer, skipping the first `offset`.
+    ///
+    /// Triples are in the same order as `triples`. Layers without a
+    /// parent jump straight to the offset in their adjacency lists.
+    fn triples_page(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        Box::new(self.triples().skip(offset).take(limit))
+    }
+
+    /// All triples known to this layer, ordered by object, then predicate, then subject.
+    ///
+    /// This walks the object index, so only the triples of a single
+    /// object have to be sorted at a time.
+    fn triples_by_object(&self) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        let layer = self.clone_boxed();
+        let count = self.node_and_value_count() as u64;
+        sort_object_groups((1..=count).flat_map(move |object| layer.triples_o(object)))
+    }
+
+    /// All triples whose object is a value from `low` (inclusive) up to `high` (exclusive).
+    ///
+    /// Values are compared lexicographically. Only plain values are
+    /// matched, not typed values or language strings. Triples are
+    /// returned grouped by object id, and every triple is returned once.
+    fn triples_with_object_value_range(
+        &self,
+        low: &str,
+        high: &str,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        let mut objects: Vec<u64> = self.triples().map(|t| t.object).collect();
+        objects.sort_unstable();
+        objects.dedup();
+        objects.retain(|&id| match self.id_object(id) {
+            Some(ObjectType::Value(value)) => low <= value.as_str() && value.as_str() < high,
+            _ => false,
+        });
+
+        let layer = self.clone_boxed();
+        Box::new(objects.into_iter().flat_map(move |id| layer.triples_o(id)))
+    }
+
+    /// All triples whose object is a number from `low` (inclusive) up to `high` (exclusive).
+    ///
+    /// Numbers are compared the way `Numeric` orders them, so all
+    /// integers come before all floats. As numbers are stored in
+    /// numeric order, layers find them with a range lookup in their
+    /// value dictionaries. Triples are returned grouped by object id,
+    /// and every triple is returned once.
+    fn triples_with_numeric_object_range(
+        &self,
+        low: Numeric,
+        high: Numeric,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        let mut objects: Vec<u64> = self.triples().map(|t| t.object).collect();
+        objects.sort_unstable();
+        objects.dedup();
+        objects.retain(|&id| match self.id_object(id) {
+            Some(ObjectType::Numeric(n)) => low <= n && n < high,
+            _ => false,
+        });
+
+        let layer = self.clone_boxed();
+        Box::new(objects.into_iter().flat_map(move |id| layer.triples_o(id)))
+    }
+
     /// Convert all known strings in the given string triple to ids.
     fn string_triple_to_partially_resolved(&self, triple: StringTriple) -> PartiallyResolvedTriple {
         PartiallyResolvedTriple {
@@ -340,16 +623,10 @@ pub trait Layer: Send + Sync {
                 .predicate_id(&triple.predicate)
                 .map(|id| PossiblyResolved::Resolved(id))
                 .unwrap_or(PossiblyResolved::Unresolved(triple.predicate)),
-            object: match &triple.object {
-                ObjectType::Node(node) => self
-                    .object_node_id(&node)
-                    .map(|id| PossiblyResolved::Resolved(id))
-                    .unwrap_or(PossiblyResolved::Unresolved(triple.object)),
-                ObjectType::Value(value) => self
-                    .object_value_id(&value)
-                    .map(|id| PossiblyResolved::Resolved(id))
-                    .unwrap_or(PossiblyResolved::Unresolved(triple.object)),
-            },
+            object: self
+                .object_id_of(&triple.object)
+                .map(|id| PossiblyResolved::Resolved(id))
+                .unwrap_or(PossiblyResolved::Unresolved(triple.object)),
         }
     }
 
@@ -366,6 +643,85 @@ pub trait Layer: Send + Sync {
         })
     }
 
+    /// Convert a sequence of id triples to string triples.
+    ///
+    /// This returns the same triples as calling `id_triple_to_string`
+    /// on each of them, but consecutive triples usually share a
+    /// subject or predicate, so the last resolved subject and
+    /// predicate are reused rather than looked up again. Triples that
+    /// cannot be resolved are skipped.
+    fn id_triples_to_strings<'a, I: 'a + Iterator<Item = IdTriple>>(
+        &'a self,
+        triples: I,
+    ) -> Box<dyn Iterator<Item = StringTriple> + 'a>
+    where
+        Self: Sized,
+    {
+        let mut resolver = StringTripleResolver::default();
+
+        Box::new(triples.filter_map(move |triple| resolver.resolve(self, triple)))
+    }
+
+    /// Returns an iterator over all triples in this layer and its parents, as strings.
+    ///
+    /// Triples that cannot be resolved are skipped.
+    fn string_triples(&self) -> Box<dyn Iterator<Item = StringTriple> + Send> {
+        resolve_owned(self.clone_boxed(), self.triples())
+    }
+
+    /// Returns an iterator over the triples added in this layer, as strings.
+    ///
+    /// Triples that cannot be resolved are skipped.
+    fn string_triple_additions(&self) -> Box<dyn Iterator<Item = StringTriple> + Send> {
+        resolve_owned(self.clone_boxed(), self.triple_additions())
+    }
+
+    /// Returns an iterator over the triples removed in this layer, as strings.
+    ///
+    /// Triples that cannot be resolved are skipped.
+    fn string_triple_removals(&self) -> Box<dyn Iterator<Item = StringTriple> + Send> {
+        resolve_owned(self.clone_boxed(), self.triple_removals())
+    }
+
+    /// Returns an iterator over all nodes in this layer and its parents, along with their id, in id order.
+    ///
+    /// Subjects and node objects share one dictionary, so this
+    /// includes nodes that only ever occur as an object. Ids start at
+    /// 1 but are not contiguous: nodes and values share one id space,
+    /// and every layer's ids come after all the ids of its parent.
+    fn iter_subject_dict(&self) -> Box<dyn Iterator<Item = (u64, String)> + Send> {
+        let layer = self.clone_boxed();
+        let count = self.node_and_value_count() as u64;
+        Box::new((1..=count).filter_map(move |id| layer.id_subject(id).map(|s| (id, s))))
+    }
+
+    /// Returns an iterator over all predicates in this layer and its parents, along with their id, in id order.
+    ///
+    /// Predicate ids are contiguous, running from 1 up to and including `predicate_count()`.
+    fn iter_predicate_dict(&self) -> Box<dyn Iterator<Item = (u64, String)> + Send> {
+        let layer = self.clone_boxed();
+        let count = self.predicate_count() as u64;
+        Box::new((1..=count).filter_map(move |id| layer.id_predicate(id).map(|s| (id, s))))
+    }
+
+    /// Returns an iterator over all values in this layer and its parents, along with their id, in id order.
+    ///
+    /// Like the node ids from `iter_subject_dict`, value ids start at
+    /// 1 but are not contiguous.
+    fn iter_value_dict(&self) -> Box<dyn Iterator<Item = (u64, ObjectType)> + Send> {
+        let layer = self.clone_boxed();
+        let count = self.node_and_value_count() as u64;
+        Box::new((1..=count).filter_map(move |id| match layer.id_object(id) {
+            Some(ObjectType::Node(_)) | None => None,
+            Some(value) => Some((id, value)),
+        }))
+    }
+
+    /// Returns an estimate of the memory in bytes taken up by the data structures of this layer.
+    ///
+    /// This does not include the parent layers.
+    fn heap_size(&self) -> usize;
+
     /// Returns the total amount of triple additions in this layer and all its parents.
     fn triple_addition_count(&self) -> usize;
 
@@ -373,6 +729,10 @@ pub trait Layer: Send + Sync {
     fn triple_removal_count(&self) -> usize;
 
     /// Returns the total amount of triples in this layer and all its parents.
+    ///
+    /// This is calculated from the addition and removal counts of
+    /// every layer in the stack, so it takes time proportional to the
+    /// amount of layers rather than the amount of triples.
     fn triple_count(&self) -> usize {
         self.triple_addition_count() - self.triple_removal_count()
     }
@@ -387,6 +747,10 @@ pub struct LayerCounts {
     pub node_count: usize,
     pub predicate_count: usize,
     pub value_count: usize,
+    /// The amount of layers in the stack, including the layer itself.
+    ///
+    /// A rollup layer stands in for all the layers it rolled up.
+    pub chain_depth: usize,
 }
 
 /// The type of a layer - either base or child.
@@ -467,6 +831,51 @@ impl Iterator for GenericSubjectIterator {
     }
 }
 
+// Resolves id triples to string triples, reusing the last resolved
+// subject and predicate, as consecutive triples usually share them.
+#[derive(Default)]
+struct StringTripleResolver {
+    last_subject: Option<(u64, String)>,
+    last_predicate: Option<(u64, String)>,
+}
+
+impl StringTripleResolver {
+    fn resolve<L: Layer + ?Sized>(&mut self, layer: &L, triple: IdTriple) -> Option<StringTriple> {
+        let subject = match &self.last_subject {
+            Some((id, subject)) if *id == triple.subject => subject.clone(),
+            _ => {
+                let subject = layer.id_subject(triple.subject)?;
+                self.last_subject = Some((triple.subject, subject.clone()));
+                subject
+            }
+        };
+        let predicate = match &self.last_predicate {
+            Some((id, predicate)) if *id == triple.predicate => predicate.clone(),
+            _ => {
+                let predicate = layer.id_predicate(triple.predicate)?;
+                self.last_predicate = Some((triple.predicate, predicate.clone()));
+                predicate
+            }
+        };
+        let object = layer.id_object(triple.object)?;
+
+        Some(StringTriple {
+            subject,
+            predicate,
+            object,
+        })
+    }
+}
+
+fn resolve_owned(
+    layer: Box<dyn Layer>,
+    triples: Box<dyn Iterator<Item = IdTriple> + Send>,
+) -> Box<dyn Iterator<Item = StringTriple> + Send> {
+    let mut resolver = StringTripleResolver::default();
+
+    Box::new(triples.filter_map(move |triple| resolver.resolve(&*layer, triple)))
+}
+
 /// A trait that caches a lookup in a layer by subject, but only for that layer and not its parents.
 ///
 /// This is returned by `Layer::subjects` and
@@ -1316,6 +1725,67 @@ impl StringTriple {
         }
     }
 
+    /// Construct a triple with a typed value object.
+    ///
+    /// The datatype is usually an IRI, like
+    /// `http://www.w3.org/2001/XMLSchema#integer`. A typed value is
+    /// distinct from an untyped value with the same string, and from
+    /// the same string with a different datatype.
+    pub fn new_typed_value(
+        subject: &str,
+        predicate: &str,
+        object: &str,
+        datatype: &str,
+    ) -> StringTriple {
+        StringTriple {
+            subject: subject.to_owned(),
+            predicate: predicate.to_owned(),
+            object: ObjectType::TypedValue {
+                value: object.to_owned(),
+                datatype: datatype.to_owned(),
+            },
+        }
+    }
+
+    /// Construct a triple with a language-tagged string object.
+    ///
+    /// The language tag is something like `en` or `en-GB`. A
+    /// language-tagged string is distinct from the same string with
+    /// another tag, or without a tag.
+    pub fn new_lang_value(
+        subject: &str,
+        predicate: &str,
+        object: &str,
+        lang: &str,
+    ) -> StringTriple {
+        StringTriple {
+            subject: subject.to_owned(),
+            predicate: predicate.to_owned(),
+            object: ObjectType::LangString {
+                value: object.to_owned(),
+                lang: lang.to_owned(),
+            },
+        }
+    }
+
+    /// Construct a triple with a numeric value object.
+    ///
+    /// Numbers are stored so that they sort in numeric order, which
+    /// allows for range lookups with
+    /// `Layer::triples_with_numeric_object_range`. They are distinct
+    /// from untyped or typed values with the same string.
+    pub fn new_numeric_value<N: Into<Numeric>>(
+        subject: &str,
+        predicate: &str,
+        object: N,
+    ) -> StringTriple {
+        StringTriple {
+            subject: subject.to_owned(),
+            predicate: predicate.to_owned(),
+            object: ObjectType::Numeric(object.into()),
+        }
+    }
+
     /// Convert this triple to a `PartiallyResolvedTriple`, marking each field as unresolved.
     pub fn to_unresolved(self) -> PartiallyResolvedTriple {
         PartiallyResolvedTriple {
@@ -1393,7 +1863,9 @@ impl PartiallyResolvedTriple {
         };
         let object = match self.object.as_ref() {
             PossiblyResolved::Unresolved(ObjectType::Node(n)) => *node_map.get(n)?,
-            PossiblyResolved::Unresolved(ObjectType::Value(v)) => *value_map.get(v)?,
+            PossiblyResolved::Unresolved(object) => {
+                *value_map.get(&object.value_dict_entry().unwrap())?
+            }
             PossiblyResolved::Resolved(id) => id,
         };
 
@@ -1441,10 +1913,419 @@ impl PartiallyResolvedTriple {
 /// node or a value, and will return this information in queries. It
 /// is possible to have the same string appear both as a node and a
 /// value, without this leading to conflicts.
+///
+/// Values may optionally carry a datatype or a language tag. Values
+/// of different datatypes or languages are distinct from each other,
+/// even if their string representation is the same.
 #[derive(Debug, Clone, PartialOrd, PartialEq, Eq, Ord, Hash)]
 pub enum ObjectType {
     Node(String),
     Value(String),
+    TypedValue { value: String, datatype: String },
+    LangString { value: String, lang: String },
+    Numeric(Numeric),
+}
+
+/// Whether an object is a node or a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ObjectKind {
+    Node,
+    Value,
+}
+
+/// A numeric value, stored in a form that sorts by value.
+///
+/// Integers and floats are distinct from each other, so `Integer(1)`
+/// and `Float(1.0)` are different values. Within each kind, values
+/// are ordered numerically. All integers order before all floats.
+///
+/// In N-Triples, integers are `xsd:integer` literals and floats are
+/// `xsd:double` literals.
+#[derive(Debug, Clone, Copy)]
+pub enum Numeric {
+    Integer(i64),
+    Float(f64),
+}
+
+/// The datatype of integer literals.
+pub const XSD_INTEGER: &str = "http://www.w3.org/2001/XMLSchema#integer";
+/// The datatype of double precision float literals.
+pub const XSD_DOUBLE: &str = "http://www.w3.org/2001/XMLSchema#double";
+
+impl Numeric {
+    /// Parse a literal of the given datatype as a number.
+    ///
+    /// Returns None if the datatype is neither `XSD_INTEGER` nor
+    /// `XSD_DOUBLE`, if the literal isn't valid for its datatype, or
+    /// if it is an integer outside the range of an `i64`.
+    pub fn from_literal(value: &str, datatype: &str) -> Option<Numeric> {
+        let is_numeral = |allowed: &str| {
+            value.chars().any(|c| c.is_ascii_digit())
+                && value
+                    .chars()
+                    .all(|c| c.is_ascii_digit() || allowed.contains(c))
+        };
+        match datatype {
+            XSD_INTEGER if is_numeral("+-") => value.parse().ok().map(Numeric::Integer),
+            XSD_DOUBLE => match value {
+                "INF" | "+INF" => Some(Numeric::Float(f64::INFINITY)),
+                "-INF" => Some(Numeric::Float(f64::NEG_INFINITY)),
+                "NaN" => Some(Numeric::Float(f64::NAN)),
+                _ if is_numeral("+-.eE") => value.parse().ok().map(Numeric::Float),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// The datatype of the literal this number is written as.
+    pub fn datatype(&self) -> &'static str {
+        match self {
+            Numeric::Integer(_) => XSD_INTEGER,
+            Numeric::Float(_) => XSD_DOUBLE,
+        }
+    }
+
+    // an unsigned key with the same ordering as the value itself
+    fn key(&self) -> u64 {
+        match *self {
+            Numeric::Integer(n) => (n as u64) ^ (1 << 63),
+            Numeric::Float(f) => {
+                let bits = f.to_bits();
+                if bits >> 63 == 1 {
+                    !bits
+                } else {
+                    bits | (1 << 63)
+                }
+            }
+        }
+    }
+
+    fn kind(&self) -> u8 {
+        match self {
+            Numeric::Integer(_) => 0,
+            Numeric::Float(_) => 1,
+        }
+    }
+
+    fn from_key(kind: char, key: u64) -> Option<Numeric> {
+        match kind {
+            INTEGER_VALUE_MARKER => Some(Numeric::Integer((key ^ (1 << 63)) as i64)),
+            FLOAT_VALUE_MARKER => Some(Numeric::Float(f64::from_bits(if key >> 63 == 1 {
+                key & !(1 << 63)
+            } else {
+                !key
+            }))),
+            _ => None,
+        }
+    }
+}
+
+impl PartialEq for Numeric {
+    fn eq(&self, other: &Numeric) -> bool {
+        self.cmp(other) == std::cmp::Ordering::Equal
+    }
+}
+
+impl Eq for Numeric {}
+
+impl PartialOrd for Numeric {
+    fn partial_cmp(&self, other: &Numeric) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Numeric {
+    fn cmp(&self, other: &Numeric) -> std::cmp::Ordering {
+        (self.kind(), self.key()).cmp(&(other.kind(), other.key()))
+    }
+}
+
+impl std::hash::Hash for Numeric {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        (self.kind(), self.key()).hash(state)
+    }
+}
+
+impl std::fmt::Display for Numeric {
+    /// Writes the canonical `xsd:integer` or `xsd:double` form of this number.
+    ///
+    /// Floats are written in scientific notation with a single digit
+    /// before the decimal point, like `1.5E2`, or as `INF`, `-INF` or
+    /// `NaN`.
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Numeric::Integer(n) => write!(f, "{}", n),
+            Numeric::Float(n) if n.is_nan() => write!(f, "NaN"),
+            Numeric::Float(n) if n == f64::INFINITY => write!(f, "INF"),
+            Numeric::Float(n) if n == f64::NEG_INFINITY => write!(f, "-INF"),
+            Numeric::Float(n) => {
+                let formatted = format!("{:E}", n);
+                let (mantissa, exponent) = formatted.split_at(formatted.find('E').unwrap());
+                if mantissa.contains('.') {
+                    write!(f, "{}{}", mantissa, exponent)
+                } else {
+                    write!(f, "{}.0{}", mantissa, exponent)
+                }
+            }
+        }
+    }
+}
+
+impl From<i64> for Numeric {
+    fn from(n: i64) -> Numeric {
+        Numeric::Integer(n)
+    }
+}
+
+impl From<f64> for Numeric {
+    fn from(n: f64) -> Numeric {
+        Numeric::Float(n)
+    }
+}
+
+// All values, typed or not, are stored in the value dictionary as
+// plain strings. To tell them apart, the first few control characters
+// are reserved as markers at the start of a dictionary entry (the nul
+// character can't be used, as dictionary entries are nul-terminated).
+// Untyped values are stored as is, unless they happen to start with
+// one of these reserved characters, in which case they get escaped.
+// The escape marker is not used anywhere else, so an escaped value
+// can't be mistaken for the parts of a typed value.
+//
+// Value dictionaries written before this encoding was introduced have
+// an older format version, and hold nothing but untyped values stored
+// as is. Lookups and decoding check the format version of each value
+// dictionary, and rollups escape these values as they are merged.
+//
+// Typed values and language strings put their datatype or language
+// tag in front of the value, so that the dictionary's prefix
+// compression shares it between all values of the same type or
+// language.
+//
+// Numbers are stored as a fixed-width key, 6 bits per character, that
+// sorts the same way as the number itself. Integers sort before floats
+// as their marker is smaller, so a range of numbers is a range of
+// dictionary entries. Numerically close values share long prefixes in
+// the dictionary.
+const VALUE_ESCAPE_MARKER: char = '\u{6}';
+const TYPED_VALUE_MARKER: char = '\u{2}';
+const LANG_STRING_MARKER: char = '\u{3}';
+const INTEGER_VALUE_MARKER: char = '\u{4}';
+const FLOAT_VALUE_MARKER: char = '\u{5}';
+const NUMERIC_KEY_LEN: usize = 11;
+const FIRST_RESERVED_MARKER: char = '\u{1}';
+const LAST_RESERVED_MARKER: char = '\u{7}';
+const VALUE_PART_SEPARATOR: char = '\u{1}';
+const TYPED_VALUES_FORMAT_VERSION: u8 = 1;
+
+/// The prefix marking a node as a blank node.
+///
+/// Blank nodes are stored in the node dictionary like any other node,
+/// using a label like `_:b1`. They are only distinguished by this
+/// prefix, which can't occur in an IRI.
+pub const BLANK_NODE_PREFIX: &str = "_:";
+
+/// Returns true if the given node is a blank node.
+pub fn is_blank_node(node: &str) -> bool {
+    node.starts_with(BLANK_NODE_PREFIX)
+}
+
+/// Sorts every run of triples sharing an object by predicate and then subject.
+pub(crate) fn sort_object_groups<I: 'static + Iterator<Item = IdTriple> + Send>(
+    triples: I,
+) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+    let mut triples = triples.peekable();
+    Box::new(
+        std::iter::from_fn(move || {
+            let first = triples.next()?;
+            let mut group = vec![first];
+            while let Some(triple) = triples.peek() {
+                if triple.object != first.object {
+                    break;
+                }
+                group.push(*triple);
+                triples.next();
+            }
+            group.sort_unstable_by_key(|t| (t.predicate, t.subject));
+
+            Some(group)
+        })
+        .flatten(),
+    )
+}
+
+impl ObjectType {
+    /// Returns true if this object is a blank node.
+    pub fn is_blank_node(&self) -> bool {
+        match self {
+            ObjectType::Node(node) => is_blank_node(node),
+            _ => false,
+        }
+    }
+
+    /// Returns the string this object is stored as in the value dictionary, or None if this is a node.
+    pub fn value_dict_entry(&self) -> Option<String> {
+        match self {
+            ObjectType::Node(_) => None,
+            ObjectType::Value(value) => match value.chars().next() {
+                Some(c) if c >= FIRST_RESERVED_MARKER && c <= LAST_RESERVED_MARKER => {
+                    Some(format!("{}{}", VALUE_ESCAPE_MARKER, value))
+                }
+                _ => Some(value.clone()),
+            },
+            ObjectType::TypedValue { value, datatype } => Some(format!(
+                "{}{}{}{}",
+                TYPED_VALUE_MARKER, datatype, VALUE_PART_SEPARATOR, value
+            )),
+            ObjectType::LangString { value, lang } => Some(format!(
+                "{}{}{}{}",
+                LANG_STRING_MARKER, lang, VALUE_PART_SEPARATOR, value
+            )),
+            ObjectType::Numeric(n) => {
+                let marker = match n {
+                    Numeric::Integer(_) => INTEGER_VALUE_MARKER,
+                    Numeric::Float(_) => FLOAT_VALUE_MARKER,
+                };
+                let key = n.key();
+                let mut entry = String::with_capacity(1 + NUMERIC_KEY_LEN);
+                entry.push(marker);
+                entry.extend(
+                    (0..NUMERIC_KEY_LEN)
+                        .rev()
+                        .map(|i| (b'0' + ((key >> (i * 6)) & 0x3f) as u8) as char),
+                );
+
+                Some(entry)
+            }
+        }
+    }
+
+    /// Returns the string this object is stored as in a value dictionary of the given format version.
+    ///
+    /// Older value dictionaries only hold untyped values, so any other
+    /// object can't be in there, and None is returned for it.
+    pub(crate) fn value_dict_entry_in(&self, format_version: u8) -> Option<String> {
+        if format_version >= TYPED_VALUES_FORMAT_VERSION {
+            return self.value_dict_entry();
+        }
+
+        match self {
+            ObjectType::Value(value) => Some(value.clone()),
+            _ => None,
+        }
+    }
+
+    /// The ranges of entries in a value dictionary of the given format version that may contain the plain values from `low` up to `high`.
+    ///
+    /// Values starting with a reserved marker character are stored
+    /// escaped, so they need a separate range. Entries in these ranges
+    /// still have to be checked after decoding them.
+    pub(crate) fn value_dict_entry_ranges(
+        low: &str,
+        high: &str,
+        format_version: u8,
+    ) -> Vec<(String, String)> {
+        let mut ranges = vec![(low.to_owned(), high.to_owned())];
+        if format_version < TYPED_VALUES_FORMAT_VERSION {
+            return ranges;
+        }
+        let first_unreserved = ((LAST_RESERVED_MARKER as u8) + 1) as char;
+        if low < first_unreserved.to_string().as_str() {
+            let escaped_high = if high > first_unreserved.to_string().as_str() {
+                first_unreserved.to_string()
+            } else {
+                high.to_owned()
+            };
+            ranges.push((
+                format!("{}{}", VALUE_ESCAPE_MARKER, low),
+                format!("{}{}", VALUE_ESCAPE_MARKER, escaped_high),
+            ));
+        }
+
+        ranges
+    }
+
+    /// The range of entries in a value dictionary of the given format version holding the numbers from `low` up to `high`.
+    ///
+    /// Older value dictionaries hold no numbers, so there is no range
+    /// for them.
+    pub(crate) fn numeric_dict_entry_range(
+        low: Numeric,
+        high: Numeric,
+        format_version: u8,
+    ) -> Vec<(String, String)> {
+        if format_version < TYPED_VALUES_FORMAT_VERSION || low >= high {
+            return Vec::new();
+        }
+
+        vec![(
+            ObjectType::Numeric(low).value_dict_entry().unwrap(),
+            ObjectType::Numeric(high).value_dict_entry().unwrap(),
+        )]
+    }
+
+    /// Convert an entry from a value dictionary of the given format version back into a value object.
+    pub(crate) fn from_value_dict_entry_in(entry: String, format_version: u8) -> ObjectType {
+        if format_version < TYPED_VALUES_FORMAT_VERSION {
+            ObjectType::Value(entry)
+        } else {
+            Self::from_value_dict_entry(entry)
+        }
+    }
+
+    /// Convert an entry from the value dictionary back into a value object.
+    pub fn from_value_dict_entry(entry: String) -> ObjectType {
+        let mut chars = entry.chars();
+        match chars.next() {
+            Some(VALUE_ESCAPE_MARKER) => ObjectType::Value(chars.as_str().to_owned()),
+            Some(marker @ TYPED_VALUE_MARKER) | Some(marker @ LANG_STRING_MARKER) => {
+                let rest = chars.as_str();
+                match rest.find(VALUE_PART_SEPARATOR) {
+                    Some(pos) if marker == TYPED_VALUE_MARKER => ObjectType::TypedValue {
+                        datatype: rest[..pos].to_owned(),
+                        value: rest[pos + 1..].to_owned(),
+                    },
+                    Some(pos) => ObjectType::LangString {
+                        lang: rest[..pos].to_owned(),
+                        value: rest[pos + 1..].to_owned(),
+                    },
+                    // not something we wrote, so just hand it back as is
+                    None => ObjectType::Value(entry),
+                }
+            }
+            Some(marker @ INTEGER_VALUE_MARKER) | Some(marker @ FLOAT_VALUE_MARKER) => {
+                let rest = chars.as_str();
+                let key = if rest.len() == NUMERIC_KEY_LEN {
+                    rest.bytes().try_fold(0_u64, |key, b| match b {
+                        b'0'..=b'o' => Some((key << 6) | (b - b'0') as u64),
+                        _ => None,
+                    })
+                } else {
+                    None
+                };
+                match key.and_then(|key| Numeric::from_key(marker, key)) {
+                    Some(n) => ObjectType::Numeric(n),
+                    None => ObjectType::Value(entry),
+                }
+            }
+            _ => ObjectType::Value(entry),
+        }
+    }
+}
+
+/// Convert an entry from a value dictionary of the given format version into an entry of the current format.
+///
+/// The entries of a dictionary stay sorted, as all escaped values
+/// start with the same marker, and sort before unreserved values.
+pub(crate) fn upgrade_value_dict_entry(entry: PfcDictEntry, format_version: u8) -> PfcDictEntry {
+    let reserved = FIRST_RESERVED_MARKER as u8..=LAST_RESERVED_MARKER as u8;
+    match entry.first_byte() {
+        Some(b) if format_version < TYPED_VALUES_FORMAT_VERSION && reserved.contains(&b) => {
+            entry.with_prefix(bytes::Bytes::from_static(&[VALUE_ESCAPE_MARKER as u8]))
+        }
+        _ => entry,
+    }
 }
 
 #[cfg(test)]
@@ -1664,4 +2545,386 @@ mod tests {
 
         assert_eq!(vec![StringTriple::new_value("cow", "says", "moo")], triples);
     }
+
+    #[test]
+    fn typed_values_round_trip() {
+        let mut runtime = Runtime::new().unwrap();
+        let files = base_layer_files();
+        let mut builder = SimpleLayerBuilder::new([1, 2, 3, 4, 5], files.clone());
+
+        let integer = "http://www.w3.org/2001/XMLSchema#integer";
+        let string = "http://www.w3.org/2001/XMLSchema#string";
+        builder.add_string_triple(StringTriple::new_value("cow", "age", "42"));
+        builder.add_string_triple(StringTriple::new_typed_value("duck", "age", "42", integer));
+        builder.add_string_triple(StringTriple::new_typed_value("pig", "age", "42", string));
+        builder.add_string_triple(StringTriple::new_value("horse", "says", "\u{1}neigh"));
+
+        runtime.block_on(builder.commit()).unwrap();
+
+        let base: Arc<InternalLayer> = Arc::new(
+            runtime
+                .block_on(BaseLayer::load_from_files([1, 2, 3, 4, 5], &files))
+                .unwrap()
+                .into(),
+        );
+
+        let files = child_layer_files();
+        let mut builder =
+            SimpleLayerBuilder::from_parent([5, 4, 3, 2, 1], base.clone(), files.clone());
+        builder.add_string_triple(StringTriple::new_typed_value("sheep", "age", "42", integer));
+        builder.add_string_triple(StringTriple::new_typed_value(
+            "chicken", "age", "7", integer,
+        ));
+        runtime.block_on(builder.commit()).unwrap();
+
+        let child: Arc<InternalLayer> = Arc::new(
+            runtime
+                .block_on(ChildLayer::load_from_files([5, 4, 3, 2, 1], base, &files))
+                .unwrap()
+                .into(),
+        );
+
+        // the typed 42 in the child layer reuses the entry from the base layer
+        assert_eq!(5, child.all_counts().value_count);
+
+        let mut triples: Vec<_> = child
+            .triples()
+            .map(|t| child.id_triple_to_string(&t).unwrap())
+            .collect();
+        triples.sort();
+
+        let mut expected = vec![
+            StringTriple::new_value("cow", "age", "42"),
+            StringTriple::new_typed_value("duck", "age", "42", integer),
+            StringTriple::new_typed_value("pig", "age", "42", string),
+            StringTriple::new_value("horse", "says", "\u{1}neigh"),
+            StringTriple::new_typed_value("sheep", "age", "42", integer),
+            StringTriple::new_typed_value("chicken", "age", "7", integer),
+        ];
+        expected.sort();
+
+        assert_eq!(expected, triples);
+
+        let untyped = child.object_value_id("42").unwrap();
+        let typed = child
+            .object_id_of(&ObjectType::TypedValue {
+                value: "42".to_string(),
+                datatype: integer.to_string(),
+            })
+            .unwrap();
+        assert_ne!(untyped, typed);
+        assert_eq!(
+            Some(ObjectType::Value("42".to_string())),
+            child.id_object(untyped)
+        );
+
+        // values that get escaped can be looked up as they are
+        let escaped = child.object_value_id("\u{1}neigh").unwrap();
+        assert_eq!(
+            Some(ObjectType::Value("\u{1}neigh".to_string())),
+            child.id_object(escaped)
+        );
+    }
+
+    #[test]
+    fn lang_strings_round_trip() {
+        let mut runtime = Runtime::new().unwrap();
+        let files = base_layer_files();
+        let mut builder = SimpleLayerBuilder::new([1, 2, 3, 4, 5], files.clone());
+
+        builder.add_string_triple(StringTriple::new_value("red", "label", "colour"));
+        builder.add_string_triple(StringTriple::new_lang_value(
+            "red", "label", "colour", "en-GB",
+        ));
+        builder.add_string_triple(StringTriple::new_lang_value(
+            "red", "label", "color", "en-US",
+        ));
+        builder.add_string_triple(StringTriple::new_lang_value(
+            "red", "label", "colour", "en-AU",
+        ));
+
+        runtime.block_on(builder.commit()).unwrap();
+
+        let layer: Arc<InternalLayer> = Arc::new(
+            runtime
+                .block_on(BaseLayer::load_from_files([1, 2, 3, 4, 5], &files))
+                .unwrap()
+                .into(),
+        );
+
+        let british = ObjectType::LangString {
+            value: "colour".to_string(),
+            lang: "en-GB".to_string(),
+        };
+        let australian = ObjectType::LangString {
+            value: "colour".to_string(),
+            lang: "en-AU".to_string(),
+        };
+        let british_id = layer.object_id_of(&british).unwrap();
+        let australian_id = layer.object_id_of(&australian).unwrap();
+        let untyped_id = layer.object_value_id("colour").unwrap();
+        assert_ne!(british_id, australian_id);
+        assert_ne!(british_id, untyped_id);
+
+        let mut objects: Vec<_> = layer
+            .objects()
+            .map(|o| layer.id_object(o.object()).unwrap())
+            .collect();
+        objects.sort();
+
+        assert_eq!(
+            vec![
+                ObjectType::Value("colour".to_string()),
+                ObjectType::LangString {
+                    value: "color".to_string(),
+                    lang: "en-US".to_string()
+                },
+                australian,
+                british,
+            ],
+            objects
+        );
+    }
+
+    #[test]
+    fn numeric_values_round_trip() {
+        let mut runtime = Runtime::new().unwrap();
+        let files = base_layer_files();
+        let mut builder = SimpleLayerBuilder::new([1, 2, 3, 4, 5], files.clone());
+
+        builder.add_string_triple(StringTriple::new_value("cow", "age", "42"));
+        builder.add_string_triple(StringTriple::new_numeric_value("duck", "age", 42));
+        builder.add_string_triple(StringTriple::new_numeric_value("pig", "age", -3));
+        builder.add_string_triple(StringTriple::new_numeric_value("pig", "weight", 1.5));
+        builder.add_string_triple(StringTriple::new_numeric_value("horse", "weight", -0.25));
+        builder.add_string_triple(StringTriple::new_numeric_value("sheep", "count", i64::MIN));
+        builder.add_string_triple(StringTriple::new_numeric_value("sheep", "total", i64::MAX));
+
+        runtime.block_on(builder.commit()).unwrap();
+
+        let layer: Arc<InternalLayer> = Arc::new(
+            runtime
+                .block_on(BaseLayer::load_from_files([1, 2, 3, 4, 5], &files))
+                .unwrap()
+                .into(),
+        );
+
+        let numeric = ObjectType::Numeric(Numeric::Integer(42));
+        let numeric_id = layer.object_id_of(&numeric).unwrap();
+        assert_ne!(layer.object_value_id("42").unwrap(), numeric_id);
+        assert_eq!(Some(numeric), layer.id_object(numeric_id));
+
+        // the value dictionary stores numbers in numeric order
+        let objects: Vec<_> = (1..=layer.all_counts().value_count as u64)
+            .map(|i| layer.id_object(i + layer.all_counts().node_count as u64))
+            .collect();
+        assert_eq!(
+            vec![
+                Some(ObjectType::Numeric(Numeric::Integer(i64::MIN))),
+                Some(ObjectType::Numeric(Numeric::Integer(-3))),
+                Some(ObjectType::Numeric(Numeric::Integer(42))),
+                Some(ObjectType::Numeric(Numeric::Integer(i64::MAX))),
+                Some(ObjectType::Numeric(Numeric::Float(-0.25))),
+                Some(ObjectType::Numeric(Numeric::Float(1.5))),
+                Some(ObjectType::Value("42".to_string())),
+            ],
+            objects
+        );
+        assert_eq!("42", Numeric::Integer(42).to_string());
+    }
+
+    #[test]
+    fn numeric_dict_entries_sort_numerically() {
+        let mut floats = vec![
+            f64::NEG_INFINITY,
+            -1e300,
+            -2.5,
+            -1.0,
+            -1e-300,
+            0.0,
+            1e-300,
+            1.0,
+            2.5,
+            1e300,
+            f64::INFINITY,
+        ];
+        let entries: Vec<_> = floats
+            .iter()
+            .map(|&f| ObjectType::Numeric(f.into()).value_dict_entry().unwrap())
+            .collect();
+        let mut sorted = entries.clone();
+        sorted.sort();
+        assert_eq!(entries, sorted);
+        for (entry, f) in entries.into_iter().zip(floats.drain(..)) {
+            assert_eq!(
+                ObjectType::Numeric(Numeric::Float(f)),
+                ObjectType::from_value_dict_entry(entry)
+            );
+        }
+
+        let integers = [i64::MIN, -1000, -1, 0, 1, 63, 64, 1000, i64::MAX];
+        let entries: Vec<_> = integers
+            .iter()
+            .map(|&i| ObjectType::Numeric(i.into()).value_dict_entry().unwrap())
+            .collect();
+        let mut sorted = entries.clone();
+        sorted.sort();
+        assert_eq!(entries, sorted);
+        for (entry, &i) in entries.into_iter().zip(integers.iter()) {
+            assert_eq!(
+                ObjectType::Numeric(Numeric::Integer(i)),
+                ObjectType::from_value_dict_entry(entry)
+            );
+        }
+
+        // a value that happens to look like a numeric entry is escaped
+        let lookalike = ObjectType::Value(
+            ObjectType::Numeric(Numeric::Integer(1))
+                .value_dict_entry()
+                .unwrap(),
+        );
+        assert_eq!(
+            lookalike,
+            ObjectType::from_value_dict_entry(lookalike.value_dict_entry().unwrap())
+        );
+    }
+
+    #[test]
+    fn values_in_older_value_dictionaries_are_untyped() {
+        let mut runtime = Runtime::new().unwrap();
+        let files = base_layer_files();
+        let mut builder = crate::layer::BaseLayerFileBuilder::from_files(&files);
+        runtime
+            .block_on(async {
+                builder.add_node("cow").await?;
+                builder.add_predicate("says").await?;
+                builder.add_value("\u{2}moo").await?;
+                builder.add_value("moo").await?;
+                let mut builder = builder.into_phase2().await?;
+                builder.add_triple(1, 1, 2).await?;
+                builder.add_triple(1, 1, 3).await?;
+
+                builder.finalize().await
+            })
+            .unwrap();
+
+        // mark the value dictionary as written before typed values
+        let mut maps = runtime.block_on(files.map_all()).unwrap();
+        let mut bytes = maps.value_dictionary_maps.blocks_map.to_vec();
+        let len = bytes.len();
+        bytes[len - 6] = 0;
+        maps.value_dictionary_maps.blocks_map = bytes::Bytes::from(bytes);
+        let base: Arc<InternalLayer> =
+            Arc::new(BaseLayer::load([1, 2, 3, 4, 5], maps).unwrap().into());
+
+        let odd = ObjectType::Value("\u{2}moo".to_string());
+        assert_eq!(Some(2), base.object_value_id("\u{2}moo"));
+        assert_eq!(Some(odd.clone()), base.id_object(2));
+        assert_eq!(
+            vec![IdTriple::new(1, 1, 2)],
+            base.triples_with_object_value_range("\u{2}", "\u{3}")
+                .collect::<Vec<_>>()
+        );
+
+        let files = child_layer_files();
+        let mut builder =
+            SimpleLayerBuilder::from_parent([5, 4, 3, 2, 1], base.clone(), files.clone());
+        builder.add_string_triple(StringTriple::new_typed_value(
+            "cow",
+            "says",
+            "moo",
+            "http://www.w3.org/2001/XMLSchema#string",
+        ));
+        runtime.block_on(builder.commit()).unwrap();
+        let child: Arc<InternalLayer> = Arc::new(
+            runtime
+                .block_on(ChildLayer::load_from_files([5, 4, 3, 2, 1], base, &files))
+                .unwrap()
+                .into(),
+        );
+
+        let rollup_files = base_layer_files();
+        runtime
+            .block_on(crate::layer::delta_rollup(&child, rollup_files.clone()))
+            .unwrap();
+        let rollup: Arc<InternalLayer> = Arc::new(
+            runtime
+                .block_on(BaseLayer::load_from_files([1, 1, 1, 1, 1], &rollup_files))
+                .unwrap()
+                .into(),
+        );
+
+        for layer in &[child, rollup] {
+            let mut objects: Vec<_> = layer.iter_value_dict().map(|(_, o)| o).collect();
+            objects.sort();
+            assert_eq!(
+                vec![
+                    ObjectType::Value("\u{2}moo".to_string()),
+                    ObjectType::Value("moo".to_string()),
+                    ObjectType::TypedValue {
+                        value: "moo".to_string(),
+                        datatype: "http://www.w3.org/2001/XMLSchema#string".to_string(),
+                    },
+                ],
+                objects
+            );
+            let id = layer.object_id_of(&odd).unwrap();
+            assert_eq!(Some(odd.clone()), layer.id_object(id));
+        }
+    }
+
+    #[test]
+    fn numbers_display_in_canonical_form() {
+        assert_eq!("-42", Numeric::Integer(-42).to_string());
+        assert_eq!("-2.5E-1", Numeric::Float(-0.25).to_string());
+        assert_eq!("1.0E2", Numeric::Float(100.0).to_string());
+        assert_eq!("0.0E0", Numeric::Float(0.0).to_string());
+        assert_eq!("1.0E300", Numeric::Float(1e300).to_string());
+        assert_eq!("1.2345E-300", Numeric::Float(1.2345e-300).to_string());
+        assert_eq!("INF", Numeric::Float(f64::INFINITY).to_string());
+        assert_eq!("-INF", Numeric::Float(f64::NEG_INFINITY).to_string());
+        assert_eq!("NaN", Numeric::Float(f64::NAN).to_string());
+
+        for n in &[
+            Numeric::Integer(i64::MIN),
+            Numeric::Float(-0.25),
+            Numeric::Float(1e300),
+            Numeric::Float(f64::MIN_POSITIVE),
+            Numeric::Float(f64::NEG_INFINITY),
+            Numeric::Float(f64::NAN),
+        ] {
+            assert_eq!(
+                Some(*n),
+                Numeric::from_literal(&n.to_string(), n.datatype())
+            );
+        }
+    }
+
+    #[test]
+    fn numbers_from_literals() {
+        assert_eq!(
+            Some(Numeric::Integer(7)),
+            Numeric::from_literal("+007", XSD_INTEGER)
+        );
+        assert_eq!(
+            Some(Numeric::Float(0.5)),
+            Numeric::from_literal(".5", XSD_DOUBLE)
+        );
+        assert_eq!(
+            Some(Numeric::Float(1000.0)),
+            Numeric::from_literal("1e3", XSD_DOUBLE)
+        );
+        assert_eq!(None, Numeric::from_literal("1.5", XSD_INTEGER));
+        assert_eq!(
+            None,
+            Numeric::from_literal("99999999999999999999", XSD_INTEGER)
+        );
+        assert_eq!(None, Numeric::from_literal("inf", XSD_DOUBLE));
+        assert_eq!(None, Numeric::from_literal("1e", XSD_DOUBLE));
+        assert_eq!(None, Numeric::from_literal("", XSD_INTEGER));
+        assert_eq!(
+            None,
+            Numeric::from_literal("42", "http://www.w3.org/2001/XMLSchema#int")
+        );
+    }
 }
@@ -10,11 +10,16 @@ mod delta;
 mod id_map;
 mod internal;
 mod layer;
+mod ntriples;
+mod rollup;
 mod simple_builder;
 
 pub use base::*;
 pub use child::*;
+pub(crate) use delta::*;
 pub use id_map::*;
 pub use internal::*;
 pub use layer::*;
+pub(crate) use ntriples::*;
+pub use rollup::*;
 pub use simple_builder::*;
@@ -0,0 +1,406 @@
+//! Conversion between string triples and the N-Triples format.
+//!
+//! Nodes are written as IRIs, except for blank nodes, which use the
+//! `_:label` syntax. Values are written as literals. Typed values and
+//! language strings use the standard `^^<datatype>` and `@lang`
+//! suffixes. Numeric values are written as `xsd:integer` or
+//! `xsd:double` typed literals in their canonical form. Literals of
+//! these two datatypes are read as numeric values, unless they aren't
+//! valid numbers, in which case they are read as typed values.
+use super::layer::*;
+
+use std::io::{self, Write};
+
+fn write_iri<W: Write>(writer: &mut W, iri: &str) -> io::Result<()> {
+    writer.write_all(b"<")?;
+    for c in iri.chars() {
+        match c {
+            '\u{0}'..='\u{20}' | '<' | '>' | '"' | '{' | '}' | '|' | '^' | '`' | '\\' => {
+                write!(writer, "\\u{:04X}", c as u32)?
+            }
+            c => write!(writer, "{}", c)?,
+        }
+    }
+    writer.write_all(b">")
+}
+
+fn write_node<W: Write>(writer: &mut W, node: &str) -> io::Result<()> {
+    match node.strip_prefix(BLANK_NODE_PREFIX) {
+        Some(label) if is_valid_blank_node_label(label) => write!(writer, "{}", node),
+        _ => write_iri(writer, node),
+    }
+}
+
+fn is_valid_blank_node_label(label: &str) -> bool {
+    !label.is_empty()
+        && label
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn write_literal<W: Write>(writer: &mut W, literal: &str) -> io::Result<()> {
+    writer.write_all(b"\"")?;
+    for c in literal.chars() {
+        match c {
+            '"' => writer.write_all(b"\\\"")?,
+            '\\' => writer.write_all(b"\\\\")?,
+            '\n' => writer.write_all(b"\\n")?,
+            '\r' => writer.write_all(b"\\r")?,
+            c => write!(writer, "{}", c)?,
+        }
+    }
+    writer.write_all(b"\"")
+}
+
+/// Write a triple as a single N-Triples line.
+pub(crate) fn write_ntriple<W: Write>(writer: &mut W, triple: &StringTriple) -> io::Result<()> {
+    write_node(writer, &triple.subject)?;
+    writer.write_all(b" ")?;
+    write_iri(writer, &triple.predicate)?;
+    writer.write_all(b" ")?;
+    match &triple.object {
+        ObjectType::Node(node) => write_node(writer, node)?,
+        ObjectType::Value(value) => write_literal(writer, value)?,
+        ObjectType::TypedValue { value, datatype } => {
+            write_literal(writer, value)?;
+            writer.write_all(b"^^")?;
+            write_iri(writer, datatype)?;
+        }
+        ObjectType::LangString { value, lang } => {
+            write_literal(writer, value)?;
+            write!(writer, "@{}", lang)?;
+        }
+        ObjectType::Numeric(n) => {
+            write_literal(writer, &n.to_string())?;
+            writer.write_all(b"^^")?;
+            write_iri(writer, n.datatype())?;
+        }
+    }
+    writer.write_all(b" .\n")
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        self.rest = self.rest.trim_start_matches([' ', '\t']);
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let mut chars = self.rest.chars();
+        let c = chars.next();
+        self.rest = chars.as_str();
+
+        c
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.next_char() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(format!("expected '{}' but found '{}'", expected, c)),
+            None => Err(format!("expected '{}' but found end of line", expected)),
+        }
+    }
+
+    fn unicode_escape(&mut self, len: usize) -> Result<char, String> {
+        if self.rest.len() < len || !self.rest.is_char_boundary(len) {
+            return Err("truncated unicode escape".to_string());
+        }
+        let (hex, rest) = self.rest.split_at(len);
+        self.rest = rest;
+
+        Some(hex)
+            .filter(|hex| hex.chars().all(|c| c.is_ascii_hexdigit()))
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .and_then(std::char::from_u32)
+            .ok_or_else(|| format!("invalid unicode escape '{}'", hex))
+    }
+
+    fn iri(&mut self) -> Result<String, String> {
+        self.expect('<')?;
+        let mut result = String::new();
+        loop {
+            match self.next_char() {
+                Some('>') => return Ok(result),
+                Some('\\') => match self.next_char() {
+                    Some('u') => result.push(self.unicode_escape(4)?),
+                    Some('U') => result.push(self.unicode_escape(8)?),
+                    _ => return Err("invalid escape in IRI".to_string()),
+                },
+                Some(c) => result.push(c),
+                None => return Err("unterminated IRI".to_string()),
+            }
+        }
+    }
+
+    fn node(&mut self) -> Result<String, String> {
+        if !self.rest.starts_with(BLANK_NODE_PREFIX) {
+            return self.iri();
+        }
+
+        let end = self.rest[BLANK_NODE_PREFIX.len()..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+            .map(|pos| pos + BLANK_NODE_PREFIX.len())
+            .unwrap_or(self.rest.len());
+        if end == BLANK_NODE_PREFIX.len() {
+            return Err("empty blank node label".to_string());
+        }
+        let node = self.rest[..end].to_string();
+        self.rest = &self.rest[end..];
+
+        Ok(node)
+    }
+
+    fn literal(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut result = String::new();
+        loop {
+            match self.next_char() {
+                Some('"') => return Ok(result),
+                Some('\\') => match self.next_char() {
+                    Some('t') => result.push('\t'),
+                    Some('b') => result.push('\u{8}'),
+                    Some('n') => result.push('\n'),
+                    Some('r') => result.push('\r'),
+                    Some('f') => result.push('\u{c}'),
+                    Some('"') => result.push('"'),
+                    Some('\'') => result.push('\''),
+                    Some('\\') => result.push('\\'),
+                    Some('u') => result.push(self.unicode_escape(4)?),
+                    Some('U') => result.push(self.unicode_escape(8)?),
+                    _ => return Err("invalid escape in literal".to_string()),
+                },
+                Some(c) => result.push(c),
+                None => return Err("unterminated literal".to_string()),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<ObjectType, String> {
+        if !self.rest.starts_with('"') {
+            return Ok(ObjectType::Node(self.node()?));
+        }
+
+        let value = self.literal()?;
+        if self.rest.starts_with("^^") {
+            self.rest = &self.rest[2..];
+            let datatype = self.iri()?;
+
+            match Numeric::from_literal(&value, &datatype) {
+                Some(n) => Ok(ObjectType::Numeric(n)),
+                None => Ok(ObjectType::TypedValue { value, datatype }),
+            }
+        } else if self.rest.starts_with('@') {
+            self.rest = &self.rest[1..];
+            let end = self
+                .rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-'))
+                .unwrap_or(self.rest.len());
+            if end == 0 {
+                return Err("empty language tag".to_string());
+            }
+            let lang = self.rest[..end].to_string();
+            self.rest = &self.rest[end..];
+
+            Ok(ObjectType::LangString { value, lang })
+        } else {
+            Ok(ObjectType::Value(value))
+        }
+    }
+}
+
+/// Parse a single N-Triples line.
+///
+/// Returns None for blank lines and comment lines, and a description
+/// of the problem if the line is not a valid triple.
+pub(crate) fn parse_ntriple(line: &str) -> Result<Option<StringTriple>, String> {
+    let mut parser = Parser { rest: line };
+    parser.skip_whitespace();
+    if parser.rest.is_empty() || parser.rest.starts_with('#') {
+        return Ok(None);
+    }
+
+    let subject = parser.node()?;
+    parser.skip_whitespace();
+    let predicate = parser.iri()?;
+    parser.skip_whitespace();
+    let object = parser.object()?;
+    parser.skip_whitespace();
+    parser.expect('.')?;
+    parser.skip_whitespace();
+    if !(parser.rest.is_empty() || parser.rest.starts_with('#')) {
+        return Err(format!("unexpected '{}' after triple", parser.rest));
+    }
+
+    Ok(Some(StringTriple {
+        subject,
+        predicate,
+        object,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_ntriple(triple: &StringTriple) -> String {
+        let mut result = Vec::new();
+        write_ntriple(&mut result, triple).unwrap();
+        String::from_utf8(result).unwrap()
+    }
+
+    #[test]
+    fn write_node_and_value_triples() {
+        assert_eq!(
+            "<http://a> <http://b> <http://c> .\n",
+            to_ntriple(&StringTriple::new_node("http://a", "http://b", "http://c"))
+        );
+        assert_eq!(
+            "<http://a> <http://b> \"say \\\"hi\\\"\\n\\\\\" .\n",
+            to_ntriple(&StringTriple::new_value(
+                "http://a",
+                "http://b",
+                "say \"hi\"\n\\"
+            ))
+        );
+    }
+
+    #[test]
+    fn write_typed_and_lang_triples() {
+        assert_eq!(
+            "<a> <b> \"42\"^^<http://www.w3.org/2001/XMLSchema#integer> .\n",
+            to_ntriple(&StringTriple::new_typed_value(
+                "a",
+                "b",
+                "42",
+                "http://www.w3.org/2001/XMLSchema#integer"
+            ))
+        );
+        assert_eq!(
+            "<a> <b> \"colour\"@en-GB .\n",
+            to_ntriple(&StringTriple::new_lang_value("a", "b", "colour", "en-GB"))
+        );
+    }
+
+    #[test]
+    fn write_numeric_triples() {
+        assert_eq!(
+            "<a> <b> \"-42\"^^<http://www.w3.org/2001/XMLSchema#integer> .\n",
+            to_ntriple(&StringTriple::new_numeric_value("a", "b", -42))
+        );
+        assert_eq!(
+            "<a> <b> \"1.5E0\"^^<http://www.w3.org/2001/XMLSchema#double> .\n",
+            to_ntriple(&StringTriple::new_numeric_value("a", "b", 1.5))
+        );
+        assert_eq!(
+            "<a> <b> \"-INF\"^^<http://www.w3.org/2001/XMLSchema#double> .\n",
+            to_ntriple(&StringTriple::new_numeric_value(
+                "a",
+                "b",
+                f64::NEG_INFINITY
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_numeric_literals() {
+        assert_eq!(
+            Ok(Some(StringTriple::new_numeric_value("a", "b", 42))),
+            parse_ntriple("<a> <b> \"042\"^^<http://www.w3.org/2001/XMLSchema#integer> .")
+        );
+        assert_eq!(
+            Ok(Some(StringTriple::new_numeric_value("a", "b", 0.25))),
+            parse_ntriple("<a> <b> \"0.25\"^^<http://www.w3.org/2001/XMLSchema#double> .")
+        );
+        assert_eq!(
+            Ok(Some(StringTriple::new_typed_value(
+                "a",
+                "b",
+                "lots",
+                "http://www.w3.org/2001/XMLSchema#integer"
+            ))),
+            parse_ntriple("<a> <b> \"lots\"^^<http://www.w3.org/2001/XMLSchema#integer> .")
+        );
+    }
+
+    #[test]
+    fn write_escapes_invalid_iri_characters() {
+        assert_eq!(
+            "<a\\u0020b> <c\\u003Ed> <e\\u005Cf> .\n",
+            to_ntriple(&StringTriple::new_node("a b", "c>d", "e\\f"))
+        );
+    }
+
+    #[test]
+    fn parse_skips_blank_and_comment_lines() {
+        assert_eq!(Ok(None), parse_ntriple(""));
+        assert_eq!(Ok(None), parse_ntriple("   \t"));
+        assert_eq!(Ok(None), parse_ntriple("# <a> <b> <c> ."));
+    }
+
+    #[test]
+    fn parse_node_and_literal_triples() {
+        assert_eq!(
+            Ok(Some(StringTriple::new_node("a", "b", "c"))),
+            parse_ntriple("<a> <b> <c> . # trailing comment")
+        );
+        assert_eq!(
+            Ok(Some(StringTriple::new_value("a", "b", "c\u{e9}\t"))),
+            parse_ntriple("<a>\t<b> \"c\\u00E9\\t\".")
+        );
+        assert_eq!(
+            Ok(Some(StringTriple::new_typed_value(
+                "a",
+                "b",
+                "42",
+                "xsd:integer"
+            ))),
+            parse_ntriple("<a> <b> \"42\"^^<xsd:integer> .")
+        );
+        assert_eq!(
+            Ok(Some(StringTriple::new_lang_value(
+                "a", "b", "colour", "en-GB"
+            ))),
+            parse_ntriple("<a> <b> \"colour\"@en-GB .")
+        );
+    }
+
+    #[test]
+    fn parse_rejects_malformed_triples() {
+        assert!(parse_ntriple("<a> <b> .").is_err());
+        assert!(parse_ntriple("<a> <b> <c>").is_err());
+        assert!(parse_ntriple("<a> <b> \"c .").is_err());
+        assert!(parse_ntriple("<a> <b> <c> . <d>").is_err());
+        assert!(parse_ntriple("a <b> <c> .").is_err());
+    }
+
+    #[test]
+    fn parse_is_inverse_of_write() {
+        let triples = vec![
+            StringTriple::new_node("a b", "c>d", "e\\f\u{7f}"),
+            StringTriple::new_value("a", "b", "\"quoted\"\r\n\\ and more"),
+            StringTriple::new_typed_value("a", "b", "1\n2", "http://x/y z"),
+            StringTriple::new_lang_value("a", "b", "\\", "nl-BE"),
+            StringTriple::new_numeric_value("a", "b", i64::MIN),
+            StringTriple::new_numeric_value("a", "b", -1.25e-7),
+            StringTriple::new_numeric_value("a", "b", f64::INFINITY),
+        ];
+
+        for triple in triples {
+            let line = to_ntriple(&triple);
+            assert_eq!(Ok(Some(triple)), parse_ntriple(line.trim_end()));
+        }
+    }
+
+    #[test]
+    fn blank_nodes_round_trip() {
+        let triple = StringTriple::new_node("_:b1", "b", "_:b2");
+        assert_eq!("_:b1 <b> _:b2 .\n", to_ntriple(&triple));
+        assert_eq!(Ok(Some(triple)), parse_ntriple("_:b1 <b> _:b2 ."));
+
+        assert!(parse_ntriple("_: <b> <c> .").is_err());
+        assert!(parse_ntriple("<a> _:b <c> .").is_err());
+    }
+}
@@ -0,0 +1,150 @@
+//! Rollup layer implementation.
+//!
+//! A rollup layer is a base layer which materializes an entire layer
+//! stack. It is served under the name and parent of the layer it was
+//! rolled up from, so that it can stand in for that layer. The
+//! original layer is kept alongside it for its own additions and
+//! removals.
+use super::id_map::*;
+use super::internal::*;
+use super::layer::*;
+use crate::structure::*;
+
+use std::sync::Arc;
+
+/// A rollup layer.
+///
+/// The structures of a rollup layer are those of the wrapped base
+/// layer, but `name` and `parent_name` report the original layer. As
+/// the wrapped layer has no parent, its structures hold the full
+/// contents of the layer stack. The additions and removals of the
+/// original layer itself are available through `changes`.
+#[derive(Clone)]
+pub struct RollupLayer {
+    internal: Arc<InternalLayer>,
+    changes: Arc<InternalLayer>,
+    original: [u32; 5],
+    original_parent: Option<[u32; 5]>,
+}
+
+impl RollupLayer {
+    pub fn from_base_layer(
+        layer: Arc<InternalLayer>,
+        changes: Arc<InternalLayer>,
+        original: [u32; 5],
+        original_parent: Option<[u32; 5]>,
+    ) -> Self {
+        RollupLayer {
+            internal: layer,
+            changes,
+            original,
+            original_parent,
+        }
+    }
+
+    /// The original layer, loaded without its ancestors.
+    ///
+    /// Only its layer-level additions and removals are meaningful.
+    pub fn changes(&self) -> &InternalLayer {
+        &self.changes
+    }
+
+    /// The name of the layer the rollup was stored under.
+    pub fn rollup_name(&self) -> [u32; 5] {
+        InternalLayerImpl::name(&*self.internal)
+    }
+}
+
+impl InternalLayerImpl for RollupLayer {
+    fn name(&self) -> [u32; 5] {
+        self.original
+    }
+
+    fn layer_type(&self) -> LayerType {
+        self.internal.layer_type()
+    }
+
+    fn parent_name(&self) -> Option<[u32; 5]> {
+        self.original_parent
+    }
+
+    fn immediate_parent(&self) -> Option<&InternalLayer> {
+        self.internal.immediate_parent()
+    }
+
+    fn node_dictionary(&self) -> &PfcDict {
+        self.internal.node_dictionary()
+    }
+
+    fn predicate_dictionary(&self) -> &PfcDict {
+        self.internal.predicate_dictionary()
+    }
+
+    fn value_dictionary(&self) -> &PfcDict {
+        self.internal.value_dictionary()
+    }
+
+    fn node_value_id_map(&self) -> &IdMap {
+        self.internal.node_value_id_map()
+    }
+
+    fn predicate_id_map(&self) -> &IdMap {
+        self.internal.predicate_id_map()
+    }
+
+    fn parent_node_value_count(&self) -> usize {
+        self.internal.parent_node_value_count()
+    }
+
+    fn parent_predicate_count(&self) -> usize {
+        self.internal.parent_predicate_count()
+    }
+
+    fn pos_s_p_adjacency_list(&self) -> &AdjacencyList {
+        self.internal.pos_s_p_adjacency_list()
+    }
+
+    fn pos_sp_o_adjacency_list(&self) -> &AdjacencyList {
+        self.internal.pos_sp_o_adjacency_list()
+    }
+
+    fn pos_o_ps_adjacency_list(&self) -> &AdjacencyList {
+        self.internal.pos_o_ps_adjacency_list()
+    }
+
+    fn neg_s_p_adjacency_list(&self) -> Option<&AdjacencyList> {
+        self.internal.neg_s_p_adjacency_list()
+    }
+
+    fn neg_sp_o_adjacency_list(&self) -> Option<&AdjacencyList> {
+        self.internal.neg_sp_o_adjacency_list()
+    }
+
+    fn neg_o_ps_adjacency_list(&self) -> Option<&AdjacencyList> {
+        self.internal.neg_o_ps_adjacency_list()
+    }
+
+    fn pos_predicate_wavelet_tree(&self) -> &WaveletTree {
+        self.internal.pos_predicate_wavelet_tree()
+    }
+
+    fn neg_predicate_wavelet_tree(&self) -> Option<&WaveletTree> {
+        self.internal.neg_predicate_wavelet_tree()
+    }
+
+    fn pos_subjects(&self) -> Option<&MonotonicLogArray> {
+        self.internal.pos_subjects()
+    }
+
+    fn pos_objects(&self) -> Option<&MonotonicLogArray> {
+        self.internal.pos_objects()
+    }
+
+    fn neg_subjects(&self) -> Option<&MonotonicLogArray> {
+        self.internal.neg_subjects()
+    }
+
+    fn neg_objects(&self) -> Option<&MonotonicLogArray> {
+        self.internal.neg_objects()
+    }
+}
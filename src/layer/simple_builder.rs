@@ -13,6 +13,7 @@ use super::base::*;
 use super::child::*;
 use super::layer::*;
 use crate::storage::*;
+use crate::structure::{DEFAULT_BLOCK_SIZE, MAX_BLOCK_SIZE};
 use std::collections::{HashMap, HashSet};
 use std::io;
 use std::pin::Pin;
@@ -34,44 +35,256 @@ pub trait LayerBuilder: Send + Sync {
     fn parent(&self) -> Option<Arc<dyn Layer>>;
     /// Add a string triple
     fn add_string_triple(&mut self, triple: StringTriple);
+    /// Add a list of string triples that is already sorted and free of duplicates
+    ///
+    /// Builders may use this to skip sorting these triples on
+    /// commit. Passing in triples that are not sorted results in a
+    /// broken layer.
+    fn add_sorted_string_triples(&mut self, triples: Vec<StringTriple>) {
+        for triple in triples {
+            self.add_string_triple(triple);
+        }
+    }
     /// Add an id triple
     fn add_id_triple(&mut self, triple: IdTriple);
     /// Remove a string triple
     fn remove_string_triple(&mut self, triple: StringTriple);
     /// Remove an id triple
     fn remove_id_triple(&mut self, triple: IdTriple);
+    /// Add a string triple, returning whether it is new
+    ///
+    /// Like `HashSet::insert`, this returns true if the triple would
+    /// not have been in the committed layer before, but is now. The
+    /// addition is recorded either way. The default implementation
+    /// only checks the parent.
+    fn insert_string_triple(&mut self, triple: StringTriple) -> bool {
+        let new = match self.parent() {
+            None => true,
+            Some(parent) => !parent.string_triple_exists(&triple),
+        };
+        self.add_string_triple(triple);
+
+        new
+    }
+    /// Remove an id triple, returning whether this is a new removal
+    ///
+    /// Like `HashSet::remove`, this returns true if the triple would
+    /// have been in the committed layer before, but no longer is. The
+    /// removal is recorded either way. The default implementation
+    /// only checks the parent.
+    fn delete_id_triple(&mut self, triple: IdTriple) -> bool {
+        let new = match self.parent() {
+            None => false,
+            Some(parent) => parent.id_triple_exists(triple),
+        };
+        self.remove_id_triple(triple);
+
+        new
+    }
+    /// Returns true if committing this builder would not change anything relative to the parent
+    ///
+    /// For a base layer, this means nothing was added. For a child
+    /// layer, every addition has to be in the parent already, and
+    /// every removal has to be absent from it. Adding and removing the
+    /// same triple cancels out.
+    ///
+    /// This is meant to be a cheap check. The default implementation
+    /// can't tell, and returns false.
+    fn is_empty(&self) -> bool {
+        false
+    }
+    /// Returns the triples committing this builder would add to and remove from the parent
+    ///
+    /// Both lists are sorted and free of duplicates. Triples that
+    /// are both added and removed, additions that are already in the
+    /// parent and removals that aren't in it are left out, so two
+    /// builders making the same change return the same lists.
+    /// Returns `None` if the builder can't list its changes, which
+    /// is what the default implementation does.
+    fn net_changes(&self) -> Option<(Vec<StringTriple>, Vec<StringTriple>)> {
+        None
+    }
     /// Commit the layer to storage
     fn commit(self) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>>;
     /// Commit a boxed layer to storage
     fn commit_boxed(self: Box<Self>) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>>;
 }
 
+/// Options for building a layer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LayerBuilderOptions {
+    /// The amount of strings in every block of the layer's dictionaries.
+    ///
+    /// Strings are front-coded against the string before them, with
+    /// only the first string of a block stored in full. A larger block
+    /// size takes less space, especially for strings sharing long
+    /// prefixes like deep IRIs, while a smaller block size makes
+    /// looking up individual strings faster. This has to be between 1
+    /// and `MAX_BLOCK_SIZE`, and defaults to `DEFAULT_BLOCK_SIZE`.
+    pub dict_block_size: usize,
+}
+
+impl Default for LayerBuilderOptions {
+    fn default() -> Self {
+        Self {
+            dict_block_size: DEFAULT_BLOCK_SIZE,
+        }
+    }
+}
+
+impl LayerBuilderOptions {
+    /// Returns an `InvalidInput` error if these options can't be used to build a layer.
+    pub fn validate(&self) -> io::Result<()> {
+        if self.dict_block_size == 0 || self.dict_block_size > MAX_BLOCK_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "dictionary block size must be between 1 and {}, got {}",
+                    MAX_BLOCK_SIZE, self.dict_block_size
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+// A triple as recorded by a builder, with strings resolved to ids wherever the parent knows them
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum TrackedTriple {
+    Id(IdTriple),
+    String(StringTriple),
+}
+
+#[derive(Clone, Copy, Default)]
+struct TrackedChange {
+    in_parent: bool,
+    added: bool,
+    removed: bool,
+}
+
+impl TrackedChange {
+    // Adding and removing the same triple cancels out, and so do
+    // additions of triples that are already in the parent and
+    // removals of triples that aren't.
+    fn is_net_change(&self) -> bool {
+        self.added != self.removed && self.added != self.in_parent
+    }
+
+    // Whether the triple would be in the committed layer
+    fn is_present(&self) -> bool {
+        if self.is_net_change() {
+            self.added
+        } else {
+            self.in_parent
+        }
+    }
+}
+
+// The net change every distinct triple recorded by a builder makes
+// to the parent, kept up to date as triples are recorded.
+#[derive(Clone, Default)]
+struct NetChanges {
+    triples: HashMap<TrackedTriple, TrackedChange>,
+    count: usize,
+}
+
+impl NetChanges {
+    // Record an addition or removal, returning whether the triple
+    // would be in the committed layer before and after.
+    fn record(
+        &mut self,
+        parent: Option<&dyn Layer>,
+        triple: TrackedTriple,
+        addition: bool,
+    ) -> (bool, bool) {
+        let triple = match (parent, triple) {
+            (Some(parent), TrackedTriple::String(t)) => {
+                let ids = parent.subject_id(&t.subject).and_then(|subject| {
+                    let predicate = parent.predicate_id(&t.predicate)?;
+                    let object = parent.object_id_of(&t.object)?;
+                    Some(IdTriple::new(subject, predicate, object))
+                });
+                match ids {
+                    Some(ids) => TrackedTriple::Id(ids),
+                    None => TrackedTriple::String(t),
+                }
+            }
+            (_, triple) => triple,
+        };
+        let change = self.triples.entry(triple).or_insert_with_key(|triple| {
+            let in_parent = match (parent, triple) {
+                (Some(parent), TrackedTriple::Id(ids)) => parent.id_triple_exists(*ids),
+                _ => false,
+            };
+            TrackedChange {
+                in_parent,
+                ..Default::default()
+            }
+        });
+
+        let before = *change;
+        if addition {
+            change.added = true;
+        } else {
+            change.removed = true;
+        }
+        match (before.is_net_change(), change.is_net_change()) {
+            (false, true) => self.count += 1,
+            (true, false) => self.count -= 1,
+            _ => {}
+        }
+
+        (before.is_present(), change.is_present())
+    }
+}
+
 /// A layer builder
 ///
 /// `SimpleLayerBuilder` provides methods for adding and removing
 /// triples, and for committing the layer builder to storage.
+///
+/// A builder for a child layer keeps track of the net change every
+/// recorded triple makes to the parent, which takes a lookup in the
+/// parent for every distinct triple. A builder for a base layer only
+/// starts doing so once `insert_string_triple` is first called.
 #[derive(Clone)]
 pub struct SimpleLayerBuilder<F: 'static + FileLoad + FileStore + Clone> {
     name: [u32; 5],
     parent: Option<Arc<dyn Layer>>,
     files: LayerFiles<F>,
     additions: Vec<StringTriple>,
+    additions_sorted: bool,
     id_additions: Vec<IdTriple>,
     removals: Vec<StringTriple>,
     id_removals: Vec<IdTriple>,
+    net_changes: Option<NetChanges>,
+    options: LayerBuilderOptions,
 }
 
 impl<F: 'static + FileLoad + FileStore + Clone> SimpleLayerBuilder<F> {
     /// Construct a layer builder for a base layer
     pub fn new(name: [u32; 5], files: BaseLayerFiles<F>) -> Self {
+        Self::new_with_options(name, files, LayerBuilderOptions::default())
+    }
+
+    /// Construct a layer builder for a base layer with the given options
+    pub fn new_with_options(
+        name: [u32; 5],
+        files: BaseLayerFiles<F>,
+        options: LayerBuilderOptions,
+    ) -> Self {
         Self {
             name,
             parent: None,
             files: LayerFiles::Base(files),
             additions: Vec::new(),
+            additions_sorted: false,
             id_additions: Vec::with_capacity(0),
             removals: Vec::new(),
             id_removals: Vec::with_capacity(0),
+            net_changes: None,
+            options,
         }
     }
 
@@ -82,10 +295,46 @@ impl<F: 'static + FileLoad + FileStore + Clone> SimpleLayerBuilder<F> {
             parent: Some(parent),
             files: LayerFiles::Child(files),
             additions: Vec::new(),
+            additions_sorted: false,
             id_additions: Vec::new(),
             removals: Vec::new(),
             id_removals: Vec::new(),
+            net_changes: Some(NetChanges::default()),
+            options: LayerBuilderOptions::default(),
+        }
+    }
+
+    // Record a change if net changes are being tracked, returning
+    // whether the triple would be in the committed layer before and
+    // after.
+    fn track(&mut self, triple: TrackedTriple, addition: bool) -> Option<(bool, bool)> {
+        let parent = self.parent.as_deref();
+        self.net_changes
+            .as_mut()
+            .map(|net_changes| net_changes.record(parent, triple, addition))
+    }
+
+    // Start tracking net changes in a base layer builder, catching up on what was recorded so far
+    fn start_tracking(&mut self) {
+        if self.net_changes.is_some() {
+            return;
+        }
+
+        let mut net_changes = NetChanges::default();
+        for triple in self.additions.iter() {
+            net_changes.record(None, TrackedTriple::String(triple.clone()), true);
+        }
+        for triple in self.id_additions.iter() {
+            net_changes.record(None, TrackedTriple::Id(*triple), true);
+        }
+        for triple in self.removals.iter() {
+            net_changes.record(None, TrackedTriple::String(triple.clone()), false);
+        }
+        for triple in self.id_removals.iter() {
+            net_changes.record(None, TrackedTriple::Id(*triple), false);
         }
+
+        self.net_changes = Some(net_changes);
     }
 }
 
@@ -99,32 +348,132 @@ impl<F: 'static + FileLoad + FileStore + Clone> LayerBuilder for SimpleLayerBuil
     }
 
     fn add_string_triple(&mut self, triple: StringTriple) {
+        if self.net_changes.is_some() {
+            self.track(TrackedTriple::String(triple.clone()), true);
+        }
         self.additions.push(triple);
+        self.additions_sorted = false;
+    }
+
+    fn add_sorted_string_triples(&mut self, triples: Vec<StringTriple>) {
+        debug_assert!(triples.windows(2).all(|w| w[0] < w[1]));
+        if self.net_changes.is_some() {
+            for triple in triples.iter() {
+                self.track(TrackedTriple::String(triple.clone()), true);
+            }
+        }
+        if self.additions.is_empty() {
+            self.additions = triples;
+            self.additions_sorted = true;
+        } else {
+            self.additions.extend(triples);
+            self.additions_sorted = false;
+        }
     }
 
     fn add_id_triple(&mut self, triple: IdTriple) {
+        self.track(TrackedTriple::Id(triple), true);
         self.id_additions.push(triple);
     }
 
     fn remove_string_triple(&mut self, triple: StringTriple) {
+        if self.net_changes.is_some() {
+            self.track(TrackedTriple::String(triple.clone()), false);
+        }
         self.removals.push(triple);
     }
 
     fn remove_id_triple(&mut self, triple: IdTriple) {
+        self.track(TrackedTriple::Id(triple), false);
         self.id_removals.push(triple);
     }
 
+    fn insert_string_triple(&mut self, triple: StringTriple) -> bool {
+        self.start_tracking();
+        let (before, after) = self
+            .track(TrackedTriple::String(triple.clone()), true)
+            .unwrap();
+        self.additions.push(triple);
+        self.additions_sorted = false;
+
+        !before && after
+    }
+
+    fn delete_id_triple(&mut self, triple: IdTriple) -> bool {
+        let change = self.track(TrackedTriple::Id(triple), false);
+        self.id_removals.push(triple);
+
+        match change {
+            Some((before, after)) => before && !after,
+            // a base layer builder that isn't tracking has nothing to remove
+            None => false,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self.net_changes.as_ref() {
+            Some(net_changes) => net_changes.count == 0,
+            None => self.additions.is_empty() && self.id_additions.is_empty(),
+        }
+    }
+
+    fn net_changes(&self) -> Option<(Vec<StringTriple>, Vec<StringTriple>)> {
+        let parent = self.parent.as_ref();
+        let to_strings = |triples: &[StringTriple], id_triples: &[IdTriple]| {
+            let mut triples = triples.to_vec();
+            for triple in id_triples {
+                triples.push(parent?.id_triple_to_string(triple)?);
+            }
+            triples.par_sort_unstable();
+            triples.dedup();
+
+            Some(triples)
+        };
+        let additions = to_strings(&self.additions, &self.id_additions)?;
+        let removals = to_strings(&self.removals, &self.id_removals)?;
+
+        // adding and removing the same triple in one builder is a no-op
+        let net_additions = additions
+            .par_iter()
+            .filter(|triple| removals.binary_search(triple).is_err())
+            .filter(|triple| match parent {
+                None => true,
+                Some(parent) => !parent.string_triple_exists(triple),
+            })
+            .cloned()
+            .collect();
+        let net_removals = match parent {
+            None => Vec::new(),
+            Some(parent) => removals
+                .par_iter()
+                .filter(|triple| additions.binary_search(triple).is_err())
+                .filter(|triple| parent.string_triple_exists(triple))
+                .cloned()
+                .collect(),
+        };
+
+        Some((net_additions, net_removals))
+    }
+
     fn commit(self) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
         let SimpleLayerBuilder {
             name: _,
             parent,
             files,
             additions,
+            additions_sorted,
             id_additions,
             removals,
             id_removals,
+            net_changes: _,
+            options,
         } = self;
 
+        // unresolved triples sort the same way as the string triples
+        // they came from, so presorted additions for a base layer can
+        // be used as is.
+        let additions_sorted = additions_sorted && id_additions.is_empty() && parent.is_none();
+
         let (mut additions, mut removals) = rayon::join(
             || {
                 let mut additions: Vec<_> = match parent.as_ref() {
@@ -139,8 +488,10 @@ impl<F: 'static + FileLoad + FileStore + Clone> LayerBuilder for SimpleLayerBuil
                 };
 
                 additions.extend(id_additions.into_iter().map(|triple| triple.to_resolved()));
-                additions.par_sort_unstable();
-                additions.dedup();
+                if !additions_sorted {
+                    additions.par_sort_unstable();
+                    additions.dedup();
+                }
 
                 additions
             },
@@ -188,7 +539,7 @@ impl<F: 'static + FileLoad + FileStore + Clone> LayerBuilder for SimpleLayerBuil
             collect_unresolved_strings(&additions);
 
         // time to build things
-        Box::pin(async {
+        Box::pin(async move {
             match parent {
                 Some(parent) => {
                     let files = files.into_child();
@@ -239,7 +590,10 @@ impl<F: 'static + FileLoad + FileStore + Clone> LayerBuilder for SimpleLayerBuil
                 None => {
                     // TODO almost same as above, should be more generic
                     let files = files.into_base();
-                    let mut builder = BaseLayerFileBuilder::from_files(&files);
+                    let mut builder = BaseLayerFileBuilder::from_files_with_block_size(
+                        &files,
+                        options.dict_block_size,
+                    );
 
                     let node_ids = builder.add_nodes(unresolved_nodes.clone()).await?;
                     let predicate_ids = builder
@@ -379,10 +733,11 @@ fn collect_unresolved_strings(
                         .par_iter()
                         .filter_map(|triple| match triple.object.is_resolved() {
                             true => None,
-                            false => match triple.object.as_ref().unwrap_unresolved() {
-                                ObjectType::Value(value) => Some(value.to_owned()),
-                                _ => None,
-                            },
+                            false => triple
+                                .object
+                                .as_ref()
+                                .unwrap_unresolved()
+                                .value_dict_entry(),
                         })
                         .collect();
                     let mut unresolved_values: Vec<_> = unresolved_values_set.into_iter().collect();
@@ -761,4 +1116,91 @@ mod tests {
 
         assert!(child_layer.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
     }
+
+    #[test]
+    fn net_changes_leave_out_changes_that_do_nothing() {
+        let runtime = Runtime::new().unwrap();
+        let base_layer = example_base_layer(runtime.handle());
+        let node_id = base_layer.subject_id("pig").unwrap();
+        let predicate_id = base_layer.predicate_id("says").unwrap();
+        let value_id = base_layer.object_value_id("oink").unwrap();
+        let mut builder =
+            SimpleLayerBuilder::from_parent([0, 0, 0, 0, 0], base_layer, new_child_files());
+
+        builder.add_string_triple(StringTriple::new_value("horse", "says", "neigh"));
+        builder.add_string_triple(StringTriple::new_value("horse", "says", "neigh"));
+        builder.add_string_triple(StringTriple::new_value("cow", "says", "moo"));
+        builder.add_string_triple(StringTriple::new_value("sheep", "says", "baa"));
+        builder.remove_string_triple(StringTriple::new_value("sheep", "says", "baa"));
+        builder.remove_string_triple(StringTriple::new_value("cat", "says", "meow"));
+        builder.remove_id_triple(IdTriple::new(node_id, predicate_id, value_id));
+        builder.remove_string_triple(StringTriple::new_value("duck", "says", "quack"));
+
+        assert_eq!(
+            Some((
+                vec![StringTriple::new_value("horse", "says", "neigh")],
+                vec![
+                    StringTriple::new_value("duck", "says", "quack"),
+                    StringTriple::new_value("pig", "says", "oink")
+                ]
+            )),
+            builder.net_changes()
+        );
+
+        let mut builder = SimpleLayerBuilder::new([0, 0, 0, 0, 1], new_base_files());
+        builder.add_string_triple(StringTriple::new_value("cow", "says", "moo"));
+        builder.remove_string_triple(StringTriple::new_value("pig", "says", "oink"));
+        assert_eq!(
+            Some((vec![StringTriple::new_value("cow", "says", "moo")], vec![])),
+            builder.net_changes()
+        );
+        builder.add_id_triple(IdTriple::new(1, 1, 1));
+        assert_eq!(None, builder.net_changes());
+    }
+
+    #[test]
+    fn delete_id_triple_reports_only_new_removals() {
+        let runtime = Runtime::new().unwrap();
+        let base_layer = example_base_layer(runtime.handle());
+        let files = new_child_files();
+        let mut builder =
+            SimpleLayerBuilder::from_parent([0, 0, 0, 0, 1], base_layer.clone(), files);
+
+        let oink = base_layer
+            .string_triple_to_id(&StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        assert!(builder.delete_id_triple(oink));
+        assert!(!builder.delete_id_triple(oink));
+        assert!(!builder.delete_id_triple(IdTriple::new(1000, 1000, 1000)));
+
+        // adding a removed triple back cancels the removal
+        let moo = base_layer
+            .string_triple_to_id(&StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder.add_string_triple(StringTriple::new_value("cow", "says", "moo"));
+        assert!(!builder.delete_id_triple(moo));
+        assert_eq!(1, builder.net_changes.unwrap().count);
+    }
+
+    #[test]
+    fn insert_string_triple_reports_only_new_additions() {
+        let runtime = Runtime::new().unwrap();
+        let mut builder = SimpleLayerBuilder::new([0, 0, 0, 0, 1], new_base_files());
+        builder.add_string_triple(StringTriple::new_value("cow", "says", "moo"));
+
+        assert!(!builder.insert_string_triple(StringTriple::new_value("cow", "says", "moo")));
+        assert!(builder.insert_string_triple(StringTriple::new_value("pig", "says", "oink")));
+        assert!(!builder.insert_string_triple(StringTriple::new_value("pig", "says", "oink")));
+
+        let base_layer = example_base_layer(runtime.handle());
+        let mut builder =
+            SimpleLayerBuilder::from_parent([0, 0, 0, 0, 2], base_layer, new_child_files());
+        assert!(!builder.insert_string_triple(StringTriple::new_value("cow", "says", "moo")));
+        assert!(builder.insert_string_triple(StringTriple::new_value("cow", "says", "oink")));
+        assert!(!builder.insert_string_triple(StringTriple::new_value("cow", "says", "oink")));
+
+        // adding a removed triple back puts it back
+        builder.remove_string_triple(StringTriple::new_value("pig", "says", "oink"));
+        assert!(builder.insert_string_triple(StringTriple::new_value("pig", "says", "oink")));
+    }
 }
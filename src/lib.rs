@@ -38,5 +38,11 @@ pub mod store;
 pub mod structure;
 
 pub use layer::Layer;
-pub use store::sync::{open_sync_directory_store, open_sync_memory_store};
-pub use store::{open_directory_store, open_memory_store};
+pub use store::sync::{
+    open_sync_directory_store, open_sync_directory_store_readonly, open_sync_directory_store_with,
+    open_sync_memory_store,
+};
+pub use store::{
+    open_directory_store, open_directory_store_readonly, open_directory_store_with,
+    open_memory_store, StoreError,
+};
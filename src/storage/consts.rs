@@ -83,6 +83,9 @@ pub struct Filenames {
     pub neg_predicate_wavelet_tree_bit_index_sblocks: &'static str,
 
     pub parent: &'static str,
+    pub rollup: &'static str,
+    pub checksums: &'static str,
+    pub incomplete: &'static str,
 }
 
 pub const FILENAMES: Filenames = Filenames {
@@ -178,4 +181,7 @@ pub const FILENAMES: Filenames = Filenames {
         "neg_predicate_wavelet_tree_bit_index_sblocks.logarray",
 
     parent: "parent.hex",
+    rollup: "rollup.hex",
+    checksums: "checksums.sha256",
+    incomplete: "incomplete.marker",
 };
@@ -7,6 +7,7 @@ use flate2::Compression;
 use futures::stream::TryStreamExt;
 use futures::{future, Future};
 use locking::*;
+use sha2::{Digest, Sha256};
 use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
 use std::io::{self, Read, Seek, SeekFrom};
@@ -16,10 +17,14 @@ use tar::Archive;
 use tokio::fs::{self, *};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
+use super::consts::FILENAMES;
 use super::*;
 
 const PREFIX_DIR_SIZE: usize = 3;
 
+// The first entry of a pack, listing the ids of the layers it contains.
+const PACK_MANIFEST: &str = "manifest";
+
 #[derive(Clone)]
 pub struct FileBackedStore {
     path: PathBuf,
@@ -90,32 +95,293 @@ impl FileStore for FileBackedStore {
     }
 }
 
+/// Options for a store kept in a directory
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DirectoryStoreOptions {
+    /// Sync layer files and label files to disk before a commit or a
+    /// label change returns.
+    ///
+    /// This is off by default, leaving it to the operating system to
+    /// decide when data reaches the disk. A crash may then lose
+    /// recently committed layers and label changes. When this is off,
+    /// `Store::flush` syncs everything at once, which is a lot faster
+    /// than syncing on every commit when loading many layers.
+    pub sync_on_commit: bool,
+}
+
 #[derive(Clone)]
 pub struct DirectoryLayerStore {
     path: PathBuf,
+    pins: LayerPins,
+    readonly: bool,
+    options: DirectoryStoreOptions,
+    name_generator: LayerNameGenerator,
 }
 
 impl DirectoryLayerStore {
     pub fn new<P: Into<PathBuf>>(path: P) -> DirectoryLayerStore {
-        DirectoryLayerStore { path: path.into() }
+        Self::new_with_options(path, DirectoryStoreOptions::default())
+    }
+
+    /// Open a layer store with the given options
+    pub fn new_with_options<P: Into<PathBuf>>(
+        path: P,
+        options: DirectoryStoreOptions,
+    ) -> DirectoryLayerStore {
+        DirectoryLayerStore {
+            path: path.into(),
+            pins: LayerPins::new(),
+            readonly: false,
+            options,
+            name_generator: random_layer_name_generator(),
+        }
+    }
+
+    /// Open a layer store which refuses to create, import or delete layers
+    pub fn new_readonly<P: Into<PathBuf>>(path: P) -> DirectoryLayerStore {
+        DirectoryLayerStore {
+            readonly: true,
+            ..Self::new(path)
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn with_name_generator(self, name_generator: LayerNameGenerator) -> Self {
+        DirectoryLayerStore {
+            name_generator,
+            ..self
+        }
+    }
+
+    fn unpack_layers(
+        &self,
+        reader: &mut dyn io::Read,
+        layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
+    ) -> io::Result<()> {
+        // Every layer is unpacked under an incomplete marker, so that
+        // a layer cut short, for example by a cancelled import, is
+        // never mistaken for a complete one. If anything goes wrong,
+        // the layers unpacked so far are removed again.
+        let mut unpacked = HashSet::new();
+        match self.unpack_pack_entries(reader, layer_ids, &mut unpacked) {
+            Ok(()) => {
+                for layer_path in unpacked {
+                    std::fs::remove_file(layer_path.join(FILENAMES.incomplete))?;
+                }
+
+                Ok(())
+            }
+            Err(e) => {
+                for layer_path in unpacked {
+                    // the error that got us here is more useful than any cleanup error
+                    let _ = std::fs::remove_dir_all(layer_path);
+                }
+
+                Err(e)
+            }
+        }
+    }
+
+    fn unpack_pack_entries(
+        &self,
+        reader: &mut dyn io::Read,
+        layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
+        unpacked: &mut HashSet<PathBuf>,
+    ) -> io::Result<()> {
+        let tar = GzDecoder::new(reader);
+        let mut archive = Archive::new(tar);
+
+        // collect layer ids into a set
+        let layer_id_set: HashSet<String> = layer_ids.map(name_to_string).collect();
+        // packs without a manifest were written before it was added
+        let mut manifest: Option<Vec<String>> = None;
+        let mut pack_ids: HashSet<String> = HashSet::new();
+
+        // Layers we already have completely are not extracted again.
+        // Their structure files in the pack are still checked against
+        // the files we have by size, and against the checksums we
+        // recorded for them if there are any, to catch the same id
+        // being used for different content. The rollup and checksum
+        // files are store metadata which may legitimately differ.
+        let mut local_checksums: HashMap<String, HashMap<String, String>> = HashMap::new();
+        let existing_ids: HashSet<&str> = layer_id_set
+            .iter()
+            .map(|id| id.as_str())
+            .filter(|id| {
+                let mut layer_path: PathBuf = (&self.path).into();
+                layer_path.push(&id[0..PREFIX_DIR_SIZE]);
+                layer_path.push(id);
+                layer_path.is_dir() && !layer_path.join(FILENAMES.incomplete).exists()
+            })
+            .collect();
+
+        // TODO we actually need to validate that these layers, when extracted, will make for a valid store.
+        // In terminus-server we are currently already doing this validation. Due to time constraints, we're not implementing it here.
+        //
+        // This should definitely be done in the future though, to make this part of the library independently usable in a safe manner.
+        for e in archive.entries()? {
+            let mut entry = e?;
+            let path = entry.path()?.into_owned();
+            if path.as_os_str() == PACK_MANIFEST {
+                let mut contents = String::new();
+                entry.read_to_string(&mut contents)?;
+                manifest = Some(contents.lines().map(|id| id.to_owned()).collect());
+                continue;
+            }
+
+            // check if entry is prefixed with a layer id we are interested in
+            let layer_id = path
+                .iter()
+                .next()
+                .and_then(|p| p.to_str())
+                .unwrap_or("")
+                .to_owned();
+            pack_ids.insert(layer_id.clone());
+            if existing_ids.contains(layer_id.as_str()) {
+                let file_name = path
+                    .file_name()
+                    .and_then(|f| f.to_str())
+                    .unwrap_or("")
+                    .to_owned();
+                if entry.header().entry_type().is_file() && !is_layer_metadata_file(&file_name) {
+                    let mut local_path: PathBuf = (&self.path).into();
+                    local_path.push(&layer_id[0..PREFIX_DIR_SIZE]);
+                    local_path.push(&path);
+                    let local_size = std::fs::metadata(&local_path).map(|m| m.len()).ok();
+                    let mut matches = local_size == Some(entry.header().size()?);
+                    if matches {
+                        if !local_checksums.contains_key(&layer_id) {
+                            let checksums = self.read_local_checksums(&layer_id)?;
+                            local_checksums.insert(layer_id.clone(), checksums);
+                        }
+                        if let Some(expected) = local_checksums[&layer_id].get(&file_name) {
+                            let mut data = Vec::new();
+                            entry.read_to_end(&mut data)?;
+                            matches = *expected == format!("{:x}", Sha256::digest(&data));
+                        }
+                    }
+
+                    if !matches {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "layer {} already exists with different content than in the pack",
+                                layer_id
+                            ),
+                        ));
+                    }
+                }
+            } else if layer_id_set.contains(&layer_id) {
+                let mut path: PathBuf = (&self.path).into();
+                let prefix = &layer_id[0..PREFIX_DIR_SIZE];
+                path.push(prefix);
+
+                let layer_path = path.join(&layer_id);
+                if !unpacked.contains(&layer_path) {
+                    std::fs::create_dir_all(&layer_path)?;
+                    unpacked.insert(layer_path.clone());
+                    std::fs::File::create(layer_path.join(FILENAMES.incomplete))?;
+                }
+
+                // extract!
+                entry.unpack_in(path)?;
+            }
+        }
+
+        if let Some(missing) = manifest.iter().flatten().find(|id| !pack_ids.contains(*id)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "pack is incomplete: layer {} is in its manifest but not in the pack",
+                    missing
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn read_local_checksums(&self, layer_id: &str) -> io::Result<HashMap<String, String>> {
+        let mut path: PathBuf = (&self.path).into();
+        path.push(&layer_id[0..PREFIX_DIR_SIZE]);
+        path.push(layer_id);
+        path.push(FILENAMES.checksums);
+
+        match std::fs::read(path) {
+            Ok(data) => Ok(parse_checksum_file(&data)?.into_iter().collect()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HashMap::new()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Whether the given file in a layer directory is store metadata rather than part of the layer's structure
+fn is_layer_metadata_file(file_name: &str) -> bool {
+    file_name == FILENAMES.rollup
+        || file_name == FILENAMES.checksums
+        || file_name == FILENAMES.incomplete
+}
+
+fn readonly_error() -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, "store is read-only")
+}
+
+/// Sync a file or directory to disk.
+///
+/// Syncing a directory makes the creation and removal of its entries
+/// durable. Directories can't be opened as files on every platform,
+/// so they are only synced where they can be.
+async fn sync_path(path: PathBuf) -> io::Result<()> {
+    match File::open(&path).await {
+        Ok(mut file) => file.sync_all().await,
+        Err(_) if path.is_dir() => Ok(()),
+        Err(e) => Err(e),
     }
 }
 
+async fn sync_layer_directory(root: PathBuf, name: [u32; 5]) -> io::Result<()> {
+    let name = name_to_string(name);
+    let prefix = root.join(&name[0..PREFIX_DIR_SIZE]);
+    let dir = prefix.join(name);
+
+    let mut stream = fs::read_dir(&dir).await?;
+    while let Some(direntry) = stream.try_next().await? {
+        if direntry.file_type().await?.is_file() {
+            sync_path(direntry.path()).await?;
+        }
+    }
+    sync_path(dir).await?;
+    sync_path(prefix).await?;
+    sync_path(root).await
+}
+
 impl PersistentLayerStore for DirectoryLayerStore {
     type File = FileBackedStore;
+    fn layer_pins(&self) -> &LayerPins {
+        &self.pins
+    }
     fn directories(&self) -> Pin<Box<dyn Future<Output = io::Result<Vec<[u32; 5]>>> + Send>> {
         let path = self.path.clone();
         Box::pin(async move {
             let mut stream = fs::read_dir(path).await?;
             let mut result = Vec::new();
-            while let Some(direntry) = stream.try_next().await? {
-                if direntry.file_type().await?.is_dir() {
-                    let os_name = direntry.file_name();
-                    let name = os_name.to_str().ok_or(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "unexpected non-utf8 directory name",
-                    ))?;
-                    result.push(string_to_name(name)?);
+            // layers are stored in a prefix directory, so we need to look two levels deep
+            while let Some(prefix_entry) = stream.try_next().await? {
+                if !prefix_entry.file_type().await?.is_dir()
+                    || prefix_entry.file_name().len() != PREFIX_DIR_SIZE
+                {
+                    continue;
+                }
+                let mut prefix_stream = fs::read_dir(prefix_entry.path()).await?;
+                while let Some(direntry) = prefix_stream.try_next().await? {
+                    if direntry.file_type().await?.is_dir() {
+                        let os_name = direntry.file_name();
+                        let name = os_name.to_str().ok_or(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "unexpected non-utf8 directory name",
+                        ))?;
+                        result.push(string_to_name(name)?);
+                    }
                 }
             }
 
@@ -124,19 +390,81 @@ impl PersistentLayerStore for DirectoryLayerStore {
     }
 
     fn create_directory(&self) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
-        let name = rand::random();
-        let mut p = self.path.clone();
-        let name_str = name_to_string(name);
-        p.push(&name_str[0..PREFIX_DIR_SIZE]);
-        p.push(name_str);
+        if self.readonly {
+            return Box::pin(future::err(readonly_error()));
+        }
+        let path = self.path.clone();
+        let name_generator = self.name_generator.clone();
 
         Box::pin(async move {
-            fs::create_dir_all(p).await?;
+            for _ in 0..LAYER_NAME_ATTEMPTS {
+                let name = name_generator();
+                let name_str = name_to_string(name);
+                let prefix = path.join(&name_str[0..PREFIX_DIR_SIZE]);
+                fs::create_dir_all(&prefix).await?;
+                // unlike create_dir_all, this fails if the layer already exists
+                match fs::create_dir(prefix.join(name_str)).await {
+                    Ok(()) => return Ok(name),
+                    Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                    Err(e) => return Err(e),
+                }
+            }
 
-            Ok(name)
+            Err(layer_name_collision_error())
         })
     }
 
+    fn create_named_directory(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        if self.readonly {
+            return Box::pin(future::err(readonly_error()));
+        }
+        let mut prefix = self.path.clone();
+        let name_str = name_to_string(name);
+        prefix.push(&name_str[0..PREFIX_DIR_SIZE]);
+        let p = prefix.join(name_str);
+
+        Box::pin(async move {
+            fs::create_dir_all(prefix).await?;
+            // unlike create_dir_all, this fails if the directory already exists
+            fs::create_dir(p).await
+        })
+    }
+
+    fn delete_directory(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        if self.readonly {
+            return Box::pin(future::err(readonly_error()));
+        }
+        let mut p = self.path.clone();
+        let name = name_to_string(name);
+        p.push(&name[0..PREFIX_DIR_SIZE]);
+        p.push(name);
+
+        Box::pin(async move { fs::remove_dir_all(p).await })
+    }
+
+    fn delete_file(
+        &self,
+        directory: [u32; 5],
+        name: &str,
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        if self.readonly {
+            return Box::pin(future::err(readonly_error()));
+        }
+        let mut p = self.path.clone();
+        let dir_name = name_to_string(directory);
+        p.push(&dir_name[0..PREFIX_DIR_SIZE]);
+        p.push(dir_name);
+        p.push(name);
+
+        Box::pin(async move { fs::remove_file(p).await })
+    }
+
     fn directory_exists(
         &self,
         name: [u32; 5],
@@ -186,70 +514,191 @@ impl PersistentLayerStore for DirectoryLayerStore {
         })
     }
 
-    fn export_layers(&self, layer_ids: Box<dyn Iterator<Item = [u32; 5]>>) -> Vec<u8> {
-        let path = &self.path;
-        let mut enc = GzEncoder::new(Vec::new(), Compression::default());
-        {
-            let mut tar = tar::Builder::new(&mut enc);
-            for id in layer_ids {
-                let id_string = name_to_string(id);
-                let mut layer_path: PathBuf = path.into();
-                let layer_id_prefix_dir = &id_string[0..PREFIX_DIR_SIZE];
-                layer_path.push(layer_id_prefix_dir);
-                layer_path.push(&id_string);
-
-                let mut tar_path = PathBuf::new();
-                tar_path.push(&id_string);
-                tar.append_dir_all(tar_path, layer_path).unwrap();
+    fn sync_on_commit(&self) -> bool {
+        self.options.sync_on_commit
+    }
+
+    fn sync_directory(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        Box::pin(sync_layer_directory(self.path.clone(), name))
+    }
+
+    fn sync_all(&self) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        let path = self.path.clone();
+        let directories = self.directories();
+        Box::pin(async move {
+            for name in directories.await? {
+                sync_layer_directory(path.clone(), name).await?;
+            }
+
+            sync_path(path).await
+        })
+    }
+
+    fn directory_files(
+        &self,
+        directory: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Vec<String>>> + Send>> {
+        let mut p = self.path.clone();
+        let dir_name = name_to_string(directory);
+        p.push(&dir_name[0..PREFIX_DIR_SIZE]);
+        p.push(dir_name);
+
+        Box::pin(async move {
+            let mut stream = fs::read_dir(p).await?;
+            let mut result = Vec::new();
+            while let Some(direntry) = stream.try_next().await? {
+                if direntry.file_type().await?.is_file() {
+                    let os_name = direntry.file_name();
+                    let name = os_name.to_str().ok_or(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "unexpected non-utf8 file name",
+                    ))?;
+                    result.push(name.to_owned());
+                }
             }
+
+            Ok(result)
+        })
+    }
+
+    fn export_layers(&self, layer_ids: Box<dyn Iterator<Item = [u32; 5]>>) -> io::Result<Vec<u8>> {
+        let mut result = Vec::new();
+        PersistentLayerStore::export_layers_to(self, &mut result, layer_ids)?;
+
+        Ok(result)
+    }
+    fn export_layers_to(
+        &self,
+        writer: &mut dyn io::Write,
+        layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
+    ) -> Result<(), io::Error> {
+        let path = &self.path;
+        // The tar builder streams every file straight into the
+        // encoder, so at no point do we need to hold the full pack in
+        // memory.
+        let enc = GzEncoder::new(writer, Compression::default());
+        let mut tar = tar::Builder::new(enc);
+
+        let layer_ids: Vec<String> = layer_ids.map(name_to_string).collect();
+        let mut manifest = String::new();
+        for id_string in layer_ids.iter() {
+            manifest.push_str(id_string);
+            manifest.push('\n');
         }
-        // TODO: Proper error handling
-        enc.finish().unwrap()
+        let mut header = tar::Header::new_gnu();
+        header.set_size(manifest.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append_data(&mut header, PACK_MANIFEST, manifest.as_bytes())?;
+
+        for id_string in layer_ids {
+            let mut layer_path: PathBuf = path.into();
+            let layer_id_prefix_dir = &id_string[0..PREFIX_DIR_SIZE];
+            layer_path.push(layer_id_prefix_dir);
+            layer_path.push(&id_string);
+
+            let mut tar_path = PathBuf::new();
+            tar_path.push(&id_string);
+            tar.append_dir_all(tar_path, layer_path)?;
+        }
+
+        let enc = tar.into_inner()?;
+        let writer = enc.finish()?;
+        writer.flush()
     }
     fn import_layers(
         &self,
         pack: &[u8],
         layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
     ) -> Result<(), io::Error> {
-        let cursor = io::Cursor::new(pack);
-        let tar = GzDecoder::new(cursor);
-        let mut archive = Archive::new(tar);
-
-        // collect layer ids into a set
-        let layer_id_set: HashSet<String> = layer_ids.map(name_to_string).collect();
-
-        // TODO we actually need to validate that these layers, when extracted, will make for a valid store.
-        // In terminus-server we are currently already doing this validation. Due to time constraints, we're not implementing it here.
-        //
-        // This should definitely be done in the future though, to make this part of the library independently usable in a safe manner.
-        for e in archive.entries()? {
-            let mut entry = e?;
-            let path = entry.path()?;
-
-            // check if entry is prefixed with a layer id we are interested in
-            let layer_id = path.iter().next().and_then(|p| p.to_str()).unwrap_or("");
-            if layer_id_set.contains(layer_id) {
-                let mut path: PathBuf = (&self.path).into();
-                let prefix = &layer_id[0..PREFIX_DIR_SIZE];
-                path.push(prefix);
-
-                // extract!
-                entry.unpack_in(path)?;
+        if self.readonly {
+            return Err(readonly_error());
+        }
+        // layer ids that are not in the pack are ignored
+        self.unpack_layers(&mut io::Cursor::new(pack), layer_ids)
+    }
+    fn import_layers_from(
+        &self,
+        reader: &mut dyn io::Read,
+        layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
+    ) -> Result<(), io::Error> {
+        if self.readonly {
+            return Err(readonly_error());
+        }
+        let layer_ids: Vec<[u32; 5]> = layer_ids.collect();
+
+        // Everything read while looking at the manifest is kept, so
+        // that the pack can be unpacked from the start afterwards.
+        let mut peeked = Vec::new();
+        let manifest = read_pack_manifest(TeeReader {
+            inner: &mut *reader,
+            copy: &mut peeked,
+        })?;
+        let pack_ids: HashSet<[u32; 5]> = match manifest {
+            Some(ids) => ids,
+            None => {
+                // Packs without a manifest were written before it was
+                // added. Those were always built in memory, so reading
+                // the rest of the pack to find its layers is fine.
+                reader.read_to_end(&mut peeked)?;
+                pack_layer_parents(&peeked[..])
+                    .map_err(|e| {
+                        io::Error::new(io::ErrorKind::InvalidData, format!("malformed pack: {}", e))
+                    })?
+                    .into_keys()
+                    .collect()
             }
+        };
+
+        // nothing is written unless every layer is in the pack
+        if let Some(missing) = layer_ids.iter().find(|id| !pack_ids.contains(*id)) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "layer {} was not found in the pack",
+                    name_to_string(*missing)
+                ),
+            ));
         }
 
-        Ok(())
+        let mut replay = Read::chain(io::Cursor::new(peeked), reader);
+        self.unpack_layers(&mut replay, Box::new(layer_ids.into_iter()))
     }
 }
 
 #[derive(Clone)]
 pub struct DirectoryLabelStore {
     path: PathBuf,
+    readonly: bool,
+    options: DirectoryStoreOptions,
 }
 
 impl DirectoryLabelStore {
     pub fn new<P: Into<PathBuf>>(path: P) -> DirectoryLabelStore {
-        DirectoryLabelStore { path: path.into() }
+        Self::new_with_options(path, DirectoryStoreOptions::default())
+    }
+
+    /// Open a label store with the given options
+    pub fn new_with_options<P: Into<PathBuf>>(
+        path: P,
+        options: DirectoryStoreOptions,
+    ) -> DirectoryLabelStore {
+        DirectoryLabelStore {
+            path: path.into(),
+            readonly: false,
+            options,
+        }
+    }
+
+    /// Open a label store which refuses to create or change labels
+    pub fn new_readonly<P: Into<PathBuf>>(path: P) -> DirectoryLabelStore {
+        DirectoryLabelStore {
+            readonly: true,
+            ..Self::new(path)
+        }
     }
 }
 
@@ -261,7 +710,17 @@ async fn get_label_from_file<P: Into<PathBuf>>(path: P) -> io::Result<Label> {
     let mut data = Vec::new();
     file.read_to_end(&mut data).await?;
 
-    let s = String::from_utf8_lossy(&data);
+    // create_label creates the file before it can lock and write it,
+    // so an empty file is a label that is still being created
+    if data.is_empty() {
+        return Ok(Label::new_empty(&label));
+    }
+
+    parse_label_file(label, &data)
+}
+
+fn parse_label_file(label: String, data: &[u8]) -> io::Result<Label> {
+    let s = String::from_utf8_lossy(data);
     let lines: Vec<&str> = s.lines().collect();
     if lines.len() != 2 {
         return Err(io::Error::new(
@@ -312,26 +771,34 @@ impl LabelStore for DirectoryLabelStore {
             while let Some(direntry) = stream.try_next().await? {
                 if direntry.file_type().await?.is_file() {
                     let os_name = direntry.file_name();
-                    let name = os_name.to_str().ok_or(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "unexpected non-utf8 directory name",
-                    ))?;
-                    if name.ends_with(".label") {
-                        let label = get_label_from_file(name).await?;
+                    // files that aren't valid utf8 can't be labels we created, so skip them
+                    let name = match os_name.to_str() {
+                        Some(name) => name,
+                        None => continue,
+                    };
+                    if name.ends_with(".label") && name.len() > ".label".len() {
+                        let label = get_label_from_file(direntry.path()).await?;
                         result.push(label);
                     }
                 }
             }
 
+            result.sort_by(|l1, l2| l1.name.cmp(&l2.name));
+
             Ok(result)
         })
     }
 
     fn create_label(&self, label: &str) -> Pin<Box<dyn Future<Output = io::Result<Label>> + Send>> {
+        if self.readonly {
+            return Box::pin(future::err(readonly_error()));
+        }
+        let dir = self.path.clone();
         let mut p = self.path.clone();
         let label = label.to_owned();
         p.push(format!("{}.label", label));
         let contents = format!("0\n\n").into_bytes();
+        let sync = self.options.sync_on_commit;
         Box::pin(async move {
             match fs::metadata(&p).await {
                 Ok(_) => Err(io::Error::new(
@@ -343,6 +810,10 @@ impl LabelStore for DirectoryLabelStore {
                         let mut file = ExclusiveLockedFile::create_and_open(p).await?;
                         file.write_all(&contents).await?;
                         file.flush().await?;
+                        if sync {
+                            file.sync_all().await?;
+                            sync_path(dir).await?;
+                        }
 
                         Ok(Label::new_empty(&label))
                     }
@@ -376,6 +847,9 @@ impl LabelStore for DirectoryLabelStore {
         label: &Label,
         layer: Option<[u32; 5]>,
     ) -> Pin<Box<dyn Future<Output = io::Result<Option<Label>>> + Send>> {
+        if self.readonly {
+            return Box::pin(future::err(readonly_error()));
+        }
         let mut p = self.path.clone();
         p.push(format!("{}.label", label.name));
 
@@ -389,6 +863,7 @@ impl LabelStore for DirectoryLabelStore {
         };
 
         let get_label = self.get_label(&label.name);
+        let sync = self.options.sync_on_commit;
         Box::pin(async move {
             let retrieved_label = get_label.await?;
             if retrieved_label == Some(old_label) {
@@ -396,12 +871,78 @@ impl LabelStore for DirectoryLabelStore {
                 let mut file = ExclusiveLockedFile::open(p).await?;
                 file.write_all(&contents).await?;
                 file.flush().await?;
+                if sync {
+                    file.sync_all().await?;
+                }
                 Ok(Some(new_label))
             } else {
                 Ok(None)
             }
         })
     }
+
+    fn cas_label(
+        &self,
+        name: &str,
+        expected: Option<[u32; 5]>,
+        new: Option<[u32; 5]>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<bool>> + Send>> {
+        if self.readonly {
+            return Box::pin(future::err(readonly_error()));
+        }
+        let mut p = self.path.clone();
+        p.push(format!("{}.label", name));
+        let name = name.to_owned();
+        let sync = self.options.sync_on_commit;
+
+        Box::pin(async move {
+            // we hold an exclusive lock for the whole read-compare-write cycle
+            let mut file = ExclusiveLockedFile::open(p).await?;
+            let mut data = Vec::new();
+            file.read_to_end(&mut data).await?;
+            let label = parse_label_file(name, &data)?;
+
+            if label.layer != expected {
+                return Ok(false);
+            }
+
+            let new_label = label.with_updated_layer(new);
+            let contents = match new_label.layer {
+                None => format!("{}\n\n", new_label.version).into_bytes(),
+                Some(layer) => format!("{}\n{}\n", new_label.version, layer::name_to_string(layer))
+                    .into_bytes(),
+            };
+
+            file.seek(SeekFrom::Start(0)).await?;
+            file.write_all(&contents).await?;
+            file.truncate().await?;
+            file.flush().await?;
+            if sync {
+                file.sync_all().await?;
+            }
+
+            Ok(true)
+        })
+    }
+
+    fn flush(&self) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        let path = self.path.clone();
+        Box::pin(async move {
+            let mut stream = fs::read_dir(&path).await?;
+            while let Some(direntry) = stream.try_next().await? {
+                let is_label = direntry
+                    .file_name()
+                    .to_str()
+                    .map(|name| name.ends_with(".label"))
+                    .unwrap_or(false);
+                if is_label && direntry.file_type().await?.is_file() {
+                    sync_path(direntry.path()).await?;
+                }
+            }
+
+            sync_path(path).await
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -428,6 +969,47 @@ impl From<std::str::Utf8Error> for PackError {
     }
 }
 
+/// A reader that keeps a copy of everything read through it
+struct TeeReader<'a, R> {
+    inner: R,
+    copy: &'a mut Vec<u8>,
+}
+
+impl<'a, R: io::Read> io::Read for TeeReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.copy.extend_from_slice(&buf[..count]);
+
+        Ok(count)
+    }
+}
+
+/// Read the layer ids from the manifest of a pack
+///
+/// The manifest is the first entry of a pack, so only that entry is
+/// read. Packs written before the manifest was added have none.
+fn read_pack_manifest<R: io::Read>(readable: R) -> io::Result<Option<HashSet<[u32; 5]>>> {
+    let tar = GzDecoder::new(readable);
+    let mut archive = Archive::new(tar);
+
+    let mut entry = match archive.entries()?.next() {
+        Some(e) => e?,
+        None => return Ok(None),
+    };
+    if entry.path()?.as_os_str() != PACK_MANIFEST {
+        return Ok(None);
+    }
+
+    let mut contents = String::new();
+    entry.read_to_string(&mut contents)?;
+    let ids = contents
+        .lines()
+        .map(string_to_name)
+        .collect::<io::Result<_>>()?;
+
+    Ok(Some(ids))
+}
+
 pub fn pack_layer_parents<'a, R: io::Read>(
     readable: R,
 ) -> Result<HashMap<[u32; 5], Option<[u32; 5]>>, PackError> {
@@ -440,16 +1022,21 @@ pub fn pack_layer_parents<'a, R: io::Read>(
     for e in archive.entries()? {
         let mut entry = e?;
         let path = entry.path()?;
+        if path.as_os_str() == PACK_MANIFEST {
+            continue;
+        }
 
+        // packs may come from untrusted sources, so malformed paths are errors rather than panics
+        let malformed_path =
+            || io::Error::new(io::ErrorKind::InvalidData, "malformed path in pack");
         let id = string_to_name(
             path.iter()
                 .next()
-                .expect("expected path to have at least one component")
-                .to_str()
-                .expect("expected proper unicode path"),
+                .and_then(|component| component.to_str())
+                .ok_or_else(malformed_path)?,
         )?;
 
-        if path.file_name().expect("expected path to have a filename") == "parent.hex" {
+        if path.file_name().ok_or_else(malformed_path)? == "parent.hex" {
             // this is an element we want to know the parent of
             // lets read it
             let mut parent_id_bytes = [0u8; 40];
@@ -658,4 +1245,27 @@ mod tests {
         let file = FileBackedStore::new("asdfasfopivbuzxcvopiuvpoawehkafpouzvxv");
         assert!(!file.exists());
     }
+
+    #[test]
+    fn layer_creation_retries_on_name_collision() {
+        let mut runtime = Runtime::new().unwrap();
+        let dir = tempdir().unwrap();
+        let first = [1, 2, 3, 4, 5];
+        let second = [6, 7, 8, 9, 10];
+        let store = DirectoryLayerStore::new(dir.path())
+            .with_name_generator(fixed_layer_name_generator(vec![first, first, second]));
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        assert_eq!(first, builder.name());
+        runtime.block_on(builder.commit_boxed()).unwrap();
+
+        // the generator hands out the first name again, which is taken
+        let builder = runtime.block_on(store.create_child_layer(first)).unwrap();
+        assert_eq!(second, builder.name());
+        runtime.block_on(builder.commit_boxed()).unwrap();
+
+        // from now on only names that are in use are generated
+        let err = runtime.block_on(store.create_base_layer()).err().unwrap();
+        assert_eq!(io::ErrorKind::AlreadyExists, err.kind());
+    }
 }
@@ -1,4 +1,4 @@
-use futures::future::Future;
+use futures::future::{self, Future};
 use std::io;
 use std::pin::Pin;
 
@@ -61,4 +61,23 @@ pub trait LabelStore: Send + Sync {
     ) -> Pin<Box<dyn Future<Output = io::Result<Option<Label>>> + Send>> {
         self.set_label_option(label, None)
     }
+
+    /// Atomically point the label at `new`, but only if it currently points at `expected`
+    ///
+    /// Returns true if the label was updated, and false if it was
+    /// pointing at something other than `expected`.
+    fn cas_label(
+        &self,
+        name: &str,
+        expected: Option<[u32; 5]>,
+        new: Option<[u32; 5]>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<bool>> + Send>>;
+
+    /// Make sure all labels have been written to disk
+    ///
+    /// Stores that don't keep labels in files have nothing to do
+    /// here, which is what the default implementation does.
+    fn flush(&self) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        Box::pin(future::ok(()))
+    }
 }
@@ -1,21 +1,33 @@
 use super::consts::FILENAMES;
 use super::file::*;
+use super::memory::base_layer_memory_files;
+use super::pin::*;
 use crate::layer::{
-    BaseLayer, ChildLayer, InternalLayer, Layer, LayerBuilder, LayerType, SimpleLayerBuilder,
+    delta_rollup, BaseLayer, ChildLayer, IdTriple, InternalLayer, Layer, LayerBuilder,
+    LayerBuilderOptions, LayerType, RollupLayer, SimpleLayerBuilder, StringTriple,
 };
 use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Weak};
 
 use futures::future::{self, Future};
+use futures::stream::{self, StreamExt, TryStreamExt};
 use std::sync::RwLock;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
+use sha2::{Digest, Sha256};
+
 use std::collections::HashMap;
 use std::pin::Pin;
 
 pub trait LayerCache: 'static + Send + Sync {
     fn get_layer_from_cache(&self, name: [u32; 5]) -> Option<Arc<InternalLayer>>;
     fn cache_layer(&self, layer: Arc<InternalLayer>);
+    fn invalidate(&self, name: [u32; 5]);
+    /// The amount of layers currently held by the cache
+    fn cached_layer_count(&self) -> usize;
+    /// The amount of layers the cache has evicted so far
+    fn eviction_count(&self) -> u64;
 }
 
 pub struct NoCache;
@@ -26,12 +38,54 @@ impl LayerCache for NoCache {
     }
 
     fn cache_layer(&self, _layer: Arc<InternalLayer>) {}
+
+    fn invalidate(&self, _name: [u32; 5]) {}
+
+    fn cached_layer_count(&self) -> usize {
+        0
+    }
+
+    fn eviction_count(&self) -> u64 {
+        0
+    }
 }
 
 lazy_static! {
     static ref NOCACHE: Arc<dyn LayerCache> = Arc::new(NoCache);
 }
 
+/// The outcome of checking a layer against the checksums recorded for it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayerVerification {
+    /// All files of the layer match their checksums
+    Intact,
+    /// A file of the layer is missing or doesn't match its checksum
+    Corrupt,
+    /// No checksums were recorded for the layer, so it could not be checked
+    Unverifiable,
+}
+
+/// Parse the contents of a checksum file into (file name, checksum) pairs
+pub(crate) fn parse_checksum_file(data: &[u8]) -> io::Result<Vec<(String, String)>> {
+    let contents =
+        std::str::from_utf8(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let mut result = Vec::new();
+    for line in contents.lines() {
+        match line.find("  ") {
+            Some(pos) => result.push((line[pos + 2..].to_owned(), line[..pos].to_owned())),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "malformed checksum file",
+                ))
+            }
+        }
+    }
+
+    Ok(result)
+}
+
 pub trait LayerStore: 'static + Send + Sync {
     fn layers(&self) -> Pin<Box<dyn Future<Output = io::Result<Vec<[u32; 5]>>> + Send>>;
     fn get_layer_with_cache(
@@ -46,8 +100,48 @@ pub trait LayerStore: 'static + Send + Sync {
         self.get_layer_with_cache(name, NOCACHE.clone())
     }
 
+    /// Load a layer, reading the files of its ancestors concurrently
+    ///
+    /// At most `concurrency` layers are read at the same time. Stores
+    /// that don't load their layers from files just use
+    /// `get_layer_with_cache`.
+    fn get_layer_eager_with_cache(
+        &self,
+        name: [u32; 5],
+        cache: Arc<dyn LayerCache>,
+        _concurrency: usize,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<Arc<InternalLayer>>>> + Send>> {
+        self.get_layer_with_cache(name, cache)
+    }
+    fn get_layer_eager(
+        &self,
+        name: [u32; 5],
+        concurrency: usize,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<Arc<InternalLayer>>>> + Send>> {
+        self.get_layer_eager_with_cache(name, NOCACHE.clone(), concurrency)
+    }
+
+    /// Returns true if a complete layer with the given name is in the store
+    ///
+    /// Unlike `get_layer`, this doesn't load the layer. The default
+    /// implementation does, so stores should override it with a
+    /// cheaper check.
+    fn layer_exists(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<bool>> + Send>> {
+        let get_layer = self.get_layer(name);
+        Box::pin(async move { Ok(get_layer.await?.is_some()) })
+    }
+
     fn create_base_layer(
         &self,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>> {
+        self.create_base_layer_with_options(LayerBuilderOptions::default())
+    }
+    fn create_base_layer_with_options(
+        &self,
+        options: LayerBuilderOptions,
     ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>>;
     fn create_child_layer_with_cache(
         &self,
@@ -61,31 +155,159 @@ pub trait LayerStore: 'static + Send + Sync {
         self.create_child_layer_with_cache(parent, NOCACHE.clone())
     }
 
-    fn export_layers(&self, layer_ids: Box<dyn Iterator<Item = [u32; 5]>>) -> Vec<u8>;
+    /// Create a builder for a base layer with the given name
+    ///
+    /// This fails with `AlreadyExists` if a layer with that name is
+    /// already in the store.
+    fn create_named_base_layer(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>>;
+    fn create_named_child_layer_with_cache(
+        &self,
+        parent: [u32; 5],
+        name: [u32; 5],
+        cache: Arc<dyn LayerCache>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>>;
+    /// Create a builder for a child layer with the given name
+    ///
+    /// This fails with `AlreadyExists` if a layer with that name is
+    /// already in the store.
+    fn create_named_child_layer(
+        &self,
+        parent: [u32; 5],
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>> {
+        self.create_named_child_layer_with_cache(parent, name, NOCACHE.clone())
+    }
+
+    fn export_layers(&self, layer_ids: Box<dyn Iterator<Item = [u32; 5]>>) -> io::Result<Vec<u8>>;
+    fn export_layers_to(
+        &self,
+        writer: &mut dyn io::Write,
+        layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
+    ) -> Result<(), io::Error>;
     fn import_layers(
         &self,
         pack: &[u8],
         layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
     ) -> Result<(), io::Error>;
+    fn import_layers_from(
+        &self,
+        reader: &mut dyn io::Read,
+        layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
+    ) -> Result<(), io::Error>;
 
     fn layer_is_ancestor_of(
         &self,
         descendant: [u32; 5],
         ancestor: [u32; 5],
     ) -> Pin<Box<dyn Future<Output = io::Result<bool>> + Send>>;
+
+    /// Remove a layer from storage
+    ///
+    /// This does not check whether other layers still depend on the
+    /// layer being removed. It is up to the caller to ensure this is
+    /// safe.
+    fn delete_layer(&self, name: [u32; 5]) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>>;
+
+    /// Store a rollup of the given layer
+    ///
+    /// A rollup is a base layer containing all triples of the given
+    /// layer. Once it exists, retrieving the original layer will
+    /// serve it from the rollup instead of loading the entire stack.
+    /// Returns the name the rollup was stored under.
+    fn rollup(
+        &self,
+        layer: Arc<InternalLayer>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>>;
+
+    /// Returns the name of the rollup for the given layer, if it has one
+    fn get_rollup(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<[u32; 5]>>> + Send>>;
+
+    /// Record checksums for all files of the given layer, so it can be verified later
+    ///
+    /// Stores that don't keep layers in files don't need to record
+    /// anything.
+    fn write_checksums(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>>;
+
+    /// Make sure all layers have been written to disk
+    ///
+    /// Stores that don't keep layers in files have nothing to do
+    /// here, which is what the default implementation does.
+    fn flush(&self) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        Box::pin(future::ok(()))
+    }
+
+    /// Check the files of the given layer against their recorded checksums
+    fn verify_layer(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<LayerVerification>> + Send>>;
+
+    /// The amount of bytes the given layer takes up in storage, not counting its ancestors
+    ///
+    /// Stores that don't keep layers in files return an estimate of
+    /// the memory used by the layer instead.
+    fn layer_disk_size(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<u64>> + Send>>;
+
+    /// Pin a layer, keeping it and its ancestors safe from garbage collection
+    ///
+    /// Pins are counted, so a layer stays pinned until it has been
+    /// unpinned as many times as it was pinned.
+    fn pin_layer(&self, name: [u32; 5]);
+    /// Release a pin on a layer
+    fn unpin_layer(&self, name: [u32; 5]);
+    /// Returns all pinned layers, sorted by name
+    fn pinned_layers(&self) -> Vec<[u32; 5]>;
 }
 
 pub trait PersistentLayerStore: 'static + Send + Sync + Clone {
     type File: FileLoad + FileStore + Clone;
+    fn layer_pins(&self) -> &LayerPins;
     fn directories(&self) -> Pin<Box<dyn Future<Output = io::Result<Vec<[u32; 5]>>> + Send>>;
     fn create_directory(&self) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>>;
-    fn export_layers(&self, layer_ids: Box<dyn Iterator<Item = [u32; 5]>>) -> Vec<u8>;
+    /// Create a directory with the given name, failing with `AlreadyExists` if it already exists
+    fn create_named_directory(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>>;
+    fn export_layers(&self, layer_ids: Box<dyn Iterator<Item = [u32; 5]>>) -> io::Result<Vec<u8>>;
+    fn export_layers_to(
+        &self,
+        writer: &mut dyn io::Write,
+        layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
+    ) -> Result<(), io::Error>;
     fn import_layers(
         &self,
         pack: &[u8],
         layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
     ) -> Result<(), io::Error>;
+    fn import_layers_from(
+        &self,
+        reader: &mut dyn io::Read,
+        layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
+    ) -> Result<(), io::Error>;
 
+    fn delete_directory(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>>;
+    /// Remove a single file from the given directory
+    fn delete_file(
+        &self,
+        directory: [u32; 5],
+        name: &str,
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>>;
     fn directory_exists(
         &self,
         name: [u32; 5],
@@ -100,6 +322,29 @@ pub trait PersistentLayerStore: 'static + Send + Sync + Clone {
         directory: [u32; 5],
         file: &str,
     ) -> Pin<Box<dyn Future<Output = io::Result<bool>> + Send>>;
+    /// The names of all files in the given directory
+    fn directory_files(
+        &self,
+        directory: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Vec<String>>> + Send>>;
+
+    /// Whether layers have to be synced to disk before a commit returns
+    fn sync_on_commit(&self) -> bool {
+        false
+    }
+
+    /// Make sure the given directory and all its files have been written to disk
+    fn sync_directory(
+        &self,
+        _name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        Box::pin(future::ok(()))
+    }
+
+    /// Make sure all directories and their files have been written to disk
+    fn sync_all(&self) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        Box::pin(future::ok(()))
+    }
 
     fn layer_type(
         &self,
@@ -395,6 +640,32 @@ pub trait PersistentLayerStore: 'static + Send + Sync + Clone {
         })
     }
 
+    // A layer directory is marked incomplete before anything else is
+    // written to it, and the marker is only removed once the layer has
+    // been fully committed. A layer that still has this marker was
+    // never finished, for example because the process crashed while
+    // committing it, and is treated as if it doesn't exist.
+    fn write_incomplete_marker(
+        &self,
+        dir_name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        let get_file = self.get_file(dir_name, FILENAMES.incomplete);
+        Box::pin(async move {
+            let file = get_file.await?;
+            let mut writer = file.open_write();
+            writer.flush().await?;
+
+            Ok(())
+        })
+    }
+
+    fn is_incomplete(
+        &self,
+        dir_name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<bool>> + Send>> {
+        self.file_exists(dir_name, FILENAMES.incomplete)
+    }
+
     fn read_parent_file(
         &self,
         dir_name: [u32; 5],
@@ -402,6 +673,13 @@ pub trait PersistentLayerStore: 'static + Send + Sync + Clone {
         let get_file = self.get_file(dir_name, FILENAMES.parent);
         Box::pin(async move {
             let file = get_file.await?;
+            // base layers have no parent file
+            if !file.exists() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "layer has no parent file",
+                ));
+            }
             let mut reader = file.open_read();
 
             let mut buf = [0; 40];
@@ -411,6 +689,111 @@ pub trait PersistentLayerStore: 'static + Send + Sync + Clone {
         })
     }
 
+    fn write_rollup_file(
+        &self,
+        dir_name: [u32; 5],
+        rollup_name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        let rollup_string = name_to_string(rollup_name);
+
+        let get_file = self.get_file(dir_name, FILENAMES.rollup);
+        Box::pin(async move {
+            let file = get_file.await?;
+            let mut writer = file.open_write();
+
+            writer.write_all(rollup_string.as_bytes()).await?;
+
+            Ok(())
+        })
+    }
+
+    fn read_rollup_file(
+        &self,
+        dir_name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<[u32; 5]>>> + Send>> {
+        let self_ = self.clone();
+        Box::pin(async move {
+            if !self_.file_exists(dir_name, FILENAMES.rollup).await? {
+                return Ok(None);
+            }
+
+            let file = self_.get_file(dir_name, FILENAMES.rollup).await?;
+            let mut reader = file.open_read();
+
+            let mut buf = [0; 40];
+            reader.read_exact(&mut buf).await?;
+
+            let rollup_name = bytes_to_name(&buf)?;
+
+            // a rollup file may have been copied over without the
+            // rollup itself, for example through a pack export.
+            if self_.directory_exists(rollup_name).await? {
+                Ok(Some(rollup_name))
+            } else {
+                Ok(None)
+            }
+        })
+    }
+
+    fn file_checksum(
+        &self,
+        dir_name: [u32; 5],
+        file_name: &str,
+    ) -> Pin<Box<dyn Future<Output = io::Result<String>> + Send>> {
+        let get_file = self.get_file(dir_name, file_name);
+        Box::pin(async move {
+            let data = get_file.await?.map().await?;
+
+            Ok(format!("{:x}", Sha256::digest(&data)))
+        })
+    }
+
+    fn write_checksum_file(
+        &self,
+        dir_name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        let self_ = self.clone();
+        Box::pin(async move {
+            let mut files = self_.directory_files(dir_name).await?;
+            files.retain(|f| f != FILENAMES.checksums && f != FILENAMES.rollup);
+            files.sort();
+
+            let mut contents = String::new();
+            for file in files {
+                let checksum = self_.file_checksum(dir_name, &file).await?;
+                contents.push_str(&format!("{}  {}\n", checksum, file));
+            }
+
+            let file = self_.get_file(dir_name, FILENAMES.checksums).await?;
+            let mut writer = file.open_write();
+            writer.write_all(contents.as_bytes()).await?;
+            writer.flush().await?;
+
+            if self_.sync_on_commit() {
+                self_.sync_directory(dir_name).await?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn read_checksum_file(
+        &self,
+        dir_name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<Vec<(String, String)>>>> + Send>> {
+        let self_ = self.clone();
+        Box::pin(async move {
+            if !self_.file_exists(dir_name, FILENAMES.checksums).await? {
+                return Ok(None);
+            }
+
+            let file = self_.get_file(dir_name, FILENAMES.checksums).await?;
+            let data = file.map().await?;
+
+            Ok(Some(parse_checksum_file(&data)?))
+        })
+    }
+
     fn retrieve_layer_stack_names(
         &self,
         name: [u32; 5],
@@ -437,6 +820,41 @@ pub trait PersistentLayerStore: 'static + Send + Sync + Clone {
     }
 }
 
+/// Generates the names of new layers
+pub(crate) type LayerNameGenerator = Arc<dyn Fn() -> [u32; 5] + Send + Sync>;
+
+/// How many names are tried when creating a layer before giving up
+///
+/// As names are random, even a single collision with an existing
+/// layer is very unlikely. Several in a row point at a broken
+/// generator rather than bad luck.
+pub(crate) const LAYER_NAME_ATTEMPTS: usize = 8;
+
+pub(crate) fn random_layer_name_generator() -> LayerNameGenerator {
+    Arc::new(rand::random)
+}
+
+/// A generator handing out the given names in order, and then repeating the last one
+#[cfg(test)]
+pub(crate) fn fixed_layer_name_generator(names: Vec<[u32; 5]>) -> LayerNameGenerator {
+    let position = std::sync::Mutex::new(0);
+    Arc::new(move || {
+        let mut position = position.lock().unwrap();
+        let name = names[(*position).min(names.len() - 1)];
+        *position += 1;
+
+        name
+    })
+}
+
+pub(crate) fn layer_name_collision_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        "could not find an unused layer name",
+    )
+}
+
+/// The canonical representation of a layer name, as 40 lowercase hex digits.
 pub fn name_to_string(name: [u32; 5]) -> String {
     format!(
         "{:08x}{:08x}{:08x}{:08x}{:08x}",
@@ -444,22 +862,32 @@ pub fn name_to_string(name: [u32; 5]) -> String {
     )
 }
 
+/// Parse a layer name from its 40 hex digit representation, as returned by `name_to_string`.
 pub fn string_to_name(string: &str) -> Result<[u32; 5], std::io::Error> {
     if string.len() != 40 {
-        return Err(io::Error::new(io::ErrorKind::Other, "string not len 40"));
-    }
-    let n1 = u32::from_str_radix(&string[..8], 16)
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-    let n2 = u32::from_str_radix(&string[8..16], 16)
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-    let n3 = u32::from_str_radix(&string[16..24], 16)
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-    let n4 = u32::from_str_radix(&string[24..32], 16)
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-    let n5 = u32::from_str_radix(&string[32..40], 16)
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-
-    Ok([n1, n2, n3, n4, n5])
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "layer name {:?} is {} bytes long instead of 40",
+                string,
+                string.len()
+            ),
+        ));
+    }
+    if let Some(c) = string.chars().find(|c| !c.is_ascii_hexdigit()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("layer name {:?} contains non-hex character {:?}", string, c),
+        ));
+    }
+
+    let mut name = [0; 5];
+    for (i, part) in name.iter_mut().enumerate() {
+        *part = u32::from_str_radix(&string[i * 8..(i + 1) * 8], 16)
+            .expect("8 hex digits should always parse as u32");
+    }
+
+    Ok(name)
 }
 
 pub fn bytes_to_name(bytes: &[u8]) -> Result<[u32; 5], std::io::Error> {
@@ -472,133 +900,375 @@ pub fn bytes_to_name(bytes: &[u8]) -> Result<[u32; 5], std::io::Error> {
     }
 }
 
-impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStore<File = F>>
-    LayerStore for T
+/// A layer builder that removes the incomplete marker of its layer once it is committed
+struct MarkedLayerBuilder<F: 'static + FileLoad + FileStore + Clone, T: PersistentLayerStore> {
+    inner: SimpleLayerBuilder<F>,
+    store: T,
+}
+
+impl<F: 'static + FileLoad + FileStore + Clone, T: PersistentLayerStore> LayerBuilder
+    for MarkedLayerBuilder<F, T>
 {
-    fn layers(&self) -> Pin<Box<dyn Future<Output = io::Result<Vec<[u32; 5]>>> + Send>> {
-        self.directories()
+    fn name(&self) -> [u32; 5] {
+        self.inner.name()
     }
 
-    fn get_layer_with_cache(
-        &self,
-        name: [u32; 5],
-        cache: Arc<dyn LayerCache>,
-    ) -> Pin<Box<dyn Future<Output = io::Result<Option<Arc<InternalLayer>>>> + Send>> {
-        if let Some(layer) = cache.get_layer_from_cache(name) {
-            return Box::pin(future::ok(Some(layer)));
-        }
+    fn parent(&self) -> Option<Arc<dyn Layer>> {
+        self.inner.parent()
+    }
 
-        let mut layers_to_load = Vec::new();
-        layers_to_load.push(name);
-        let self_ = self.clone();
-        Box::pin(async move {
-            if !self_.directory_exists(name).await? {
-                return Ok(None);
-            }
+    fn add_string_triple(&mut self, triple: StringTriple) {
+        self.inner.add_string_triple(triple)
+    }
 
-            // find an ancestor in cache
-            let mut ancestor = None;
-            loop {
-                match cache.get_layer_from_cache(*layers_to_load.last().unwrap()) {
-                    Some(layer) => {
-                        // remove found cached layer from ids to retrieve
-                        layers_to_load.pop().unwrap();
-                        ancestor = Some(layer);
-                        break;
-                    }
-                    None => {
-                        match self_.layer_type(*layers_to_load.last().unwrap()).await? {
-                            LayerType::Base => break, // we got all the way to the base layer without finding a cached version
-                            LayerType::Child => {
-                                let parent = self_
-                                    .read_parent_file(*layers_to_load.last().unwrap())
-                                    .await?;
-                                layers_to_load.push(parent);
-                            }
-                        }
-                    }
-                }
-            }
+    fn add_sorted_string_triples(&mut self, triples: Vec<StringTriple>) {
+        self.inner.add_sorted_string_triples(triples)
+    }
 
-            if ancestor.is_none() {
-                // load the base layer
-                let base_id = layers_to_load.pop().unwrap();
-                let files = self_.base_layer_files(base_id).await?;
-                let base_layer: Arc<InternalLayer> =
-                    Arc::new(BaseLayer::load_from_files(base_id, &files).await?.into());
+    fn add_id_triple(&mut self, triple: IdTriple) {
+        self.inner.add_id_triple(triple)
+    }
 
-                cache.cache_layer(base_layer.clone());
-                ancestor = Some(base_layer);
-            }
+    fn remove_string_triple(&mut self, triple: StringTriple) {
+        self.inner.remove_string_triple(triple)
+    }
 
-            let mut ancestor = ancestor.unwrap();
-            layers_to_load.reverse();
+    fn remove_id_triple(&mut self, triple: IdTriple) {
+        self.inner.remove_id_triple(triple)
+    }
 
-            for layer_id in layers_to_load {
-                let files = self_.child_layer_files(layer_id).await?;
-                let child_layer: Arc<InternalLayer> = Arc::new(
-                    ChildLayer::load_from_files(layer_id, ancestor, &files)
-                        .await?
-                        .into(),
-                );
+    fn insert_string_triple(&mut self, triple: StringTriple) -> bool {
+        self.inner.insert_string_triple(triple)
+    }
 
-                cache.cache_layer(child_layer.clone());
-                ancestor = child_layer;
-            }
+    fn delete_id_triple(&mut self, triple: IdTriple) -> bool {
+        self.inner.delete_id_triple(triple)
+    }
 
-            Ok(Some(ancestor))
-        })
+    fn is_empty(&self) -> bool {
+        self.inner.is_empty()
     }
 
-    fn create_base_layer(
-        &self,
-    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>> {
-        let self_ = self.clone();
-        Box::pin(async move {
-            let dir_name = self_.create_directory().await?;
-            let files = self_.base_layer_files(dir_name).await?;
-            Ok(Box::new(SimpleLayerBuilder::new(dir_name, files)) as Box<dyn LayerBuilder>)
-        })
+    fn net_changes(&self) -> Option<(Vec<StringTriple>, Vec<StringTriple>)> {
+        self.inner.net_changes()
     }
 
-    fn create_child_layer_with_cache(
-        &self,
-        parent: [u32; 5],
-        cache: Arc<dyn LayerCache>,
-    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>> {
-        let self_ = self.clone();
+    fn commit(self) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        let name = self.inner.name();
+        let commit = self.inner.commit();
+        let store = self.store;
         Box::pin(async move {
-            let parent_layer = match self_.get_layer_with_cache(parent, cache).await? {
-                None => {
-                    return Err(io::Error::new(
-                        io::ErrorKind::NotFound,
-                        "parent layer not found",
-                    ))
-                }
-                Some(parent_layer) => Ok::<_, io::Error>(parent_layer),
-            }?;
-
-            let layer_dir = self_.create_directory().await?;
-            self_.write_parent_file(layer_dir, parent).await?;
-            let child_layer_files = self_.child_layer_files(layer_dir).await?;
-            Ok(Box::new(SimpleLayerBuilder::from_parent(
-                layer_dir,
-                parent_layer,
-                child_layer_files,
-            )) as Box<dyn LayerBuilder>)
+            commit.await?;
+            if store.sync_on_commit() {
+                // the layer files have to be on disk before the
+                // marker is removed, and the removal itself has to be
+                // on disk before the layer can be used.
+                store.sync_directory(name).await?;
+                store.delete_file(name, FILENAMES.incomplete).await?;
+                store.sync_directory(name).await
+            } else {
+                store.delete_file(name, FILENAMES.incomplete).await
+            }
         })
     }
 
-    fn export_layers(&self, layer_ids: Box<dyn Iterator<Item = [u32; 5]>>) -> Vec<u8> {
-        Self::export_layers(self, layer_ids)
+    fn commit_boxed(self: Box<Self>) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        let builder = *self;
+        builder.commit()
     }
-    fn import_layers(
-        &self,
-        pack: &[u8],
-        layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
+}
+
+// An empty base layer in memory, to load child layers on when their actual parent is not available
+pub(crate) async fn stand_in_parent_layer() -> io::Result<Arc<InternalLayer>> {
+    let name = [0; 5];
+    let files = base_layer_memory_files();
+    SimpleLayerBuilder::new(name, files.clone())
+        .commit()
+        .await?;
+    let layer = BaseLayer::load_from_files(name, &files).await?;
+
+    Ok(Arc::new(layer.into()))
+}
+
+// Load a layer without its ancestors, for the additions and removals
+// of a rolled up layer. A child layer is built on an empty stand-in
+// parent, so only its layer-level additions and removals are
+// meaningful.
+pub(crate) async fn load_layer_changes<F: 'static + FileLoad + FileStore + Clone>(
+    name: [u32; 5],
+    files: LayerFiles<F>,
+) -> io::Result<Arc<InternalLayer>> {
+    let layer: InternalLayer = match files {
+        LayerFiles::Base(files) => BaseLayer::load_from_files(name, &files).await?.into(),
+        LayerFiles::Child(files) => {
+            ChildLayer::load_from_files(name, stand_in_parent_layer().await?, &files)
+                .await?
+                .into()
+        }
+    };
+
+    Ok(Arc::new(layer))
+}
+
+// the maps of a layer that was read ahead of constructing it
+enum PrefetchedLayerMaps {
+    Base(Box<BaseLayerMaps>),
+    Child(Box<ChildLayerMaps>),
+}
+
+// Load a layer along with all its ancestors that aren't cached
+//
+// The layer stack is walked up to a cached layer, a rolled up layer
+// or the base layer. The files of up to `concurrency` layers of the
+// stack are then read at the same time, and the layers are built on
+// top of each other in order.
+async fn load_layer_stack<T: PersistentLayerStore>(
+    store: T,
+    name: [u32; 5],
+    cache: Arc<dyn LayerCache>,
+    concurrency: usize,
+) -> io::Result<Option<Arc<InternalLayer>>> {
+    if !store.directory_exists(name).await? || store.is_incomplete(name).await? {
+        return Ok(None);
+    }
+
+    let mut layers_to_load = Vec::new();
+    let mut ancestor = None;
+    let mut current = name;
+    loop {
+        if current != name {
+            if let Some(layer) = cache.get_layer_from_cache(current) {
+                ancestor = Some(layer);
+                break;
+            }
+            // a complete layer can't be built on an incomplete one
+            if store.is_incomplete(current).await? {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("ancestor layer {} is incomplete", name_to_string(current)),
+                ));
+            }
+        }
+
+        let layer_type = store.layer_type(current).await?;
+        let parent = match layer_type {
+            LayerType::Base => None,
+            LayerType::Child => Some(store.read_parent_file(current).await?),
+        };
+        if let Some(rollup) = store.read_rollup_file(current).await? {
+            // serve this layer from its rollup instead of loading its ancestors
+            let files = store.base_layer_files(rollup).await?;
+            let rollup_layer: Arc<InternalLayer> =
+                Arc::new(BaseLayer::load_from_files(rollup, &files).await?.into());
+            let files = match layer_type {
+                LayerType::Base => LayerFiles::Base(store.base_layer_files(current).await?),
+                LayerType::Child => LayerFiles::Child(store.child_layer_files(current).await?),
+            };
+            let changes = load_layer_changes(current, files).await?;
+            let layer: Arc<InternalLayer> = Arc::new(
+                RollupLayer::from_base_layer(rollup_layer, changes, current, parent).into(),
+            );
+
+            cache.cache_layer(layer.clone());
+            ancestor = Some(layer);
+            break;
+        }
+
+        layers_to_load.push((current, layer_type));
+        match parent {
+            None => break, // we got all the way to the base layer without finding a cached version
+            Some(parent) => current = parent,
+        }
+    }
+    layers_to_load.reverse();
+
+    let loads = layers_to_load.into_iter().map(|(layer_id, layer_type)| {
+        let store = store.clone();
+        async move {
+            let layer_maps = match layer_type {
+                LayerType::Base => PrefetchedLayerMaps::Base(Box::new(
+                    store.base_layer_files(layer_id).await?.map_all().await?,
+                )),
+                LayerType::Child => PrefetchedLayerMaps::Child(Box::new(
+                    store.child_layer_files(layer_id).await?.map_all().await?,
+                )),
+            };
+
+            io::Result::Ok((layer_id, layer_maps))
+        }
+    });
+    let mut maps = stream::iter(loads).buffered(concurrency.max(1));
+
+    while let Some((layer_id, layer_maps)) = maps.try_next().await? {
+        let layer: Arc<InternalLayer> = match (ancestor, layer_maps) {
+            (None, PrefetchedLayerMaps::Base(base_maps)) => {
+                Arc::new(BaseLayer::load(layer_id, *base_maps)?.into())
+            }
+            (Some(parent), PrefetchedLayerMaps::Child(child_maps)) => {
+                Arc::new(ChildLayer::load(layer_id, parent, *child_maps)?.into())
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "layer stack does not start with a base layer",
+                ))
+            }
+        };
+
+        cache.cache_layer(layer.clone());
+        ancestor = Some(layer);
+    }
+
+    Ok(ancestor)
+}
+
+impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStore<File = F>>
+    LayerStore for T
+{
+    fn layers(&self) -> Pin<Box<dyn Future<Output = io::Result<Vec<[u32; 5]>>> + Send>> {
+        self.directories()
+    }
+
+    fn layer_exists(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<bool>> + Send>> {
+        let self_ = self.clone();
+        Box::pin(async move {
+            Ok(self_.directory_exists(name).await? && !self_.is_incomplete(name).await?)
+        })
+    }
+
+    fn get_layer_with_cache(
+        &self,
+        name: [u32; 5],
+        cache: Arc<dyn LayerCache>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<Arc<InternalLayer>>>> + Send>> {
+        self.get_layer_eager_with_cache(name, cache, 1)
+    }
+
+    fn get_layer_eager_with_cache(
+        &self,
+        name: [u32; 5],
+        cache: Arc<dyn LayerCache>,
+        concurrency: usize,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<Arc<InternalLayer>>>> + Send>> {
+        if let Some(layer) = cache.get_layer_from_cache(name) {
+            return Box::pin(future::ok(Some(layer)));
+        }
+
+        Box::pin(load_layer_stack(self.clone(), name, cache, concurrency))
+    }
+
+    fn create_base_layer_with_options(
+        &self,
+        options: LayerBuilderOptions,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>> {
+        let self_ = self.clone();
+        Box::pin(async move {
+            options.validate()?;
+            let dir_name = self_.create_directory().await?;
+            self_.write_incomplete_marker(dir_name).await?;
+            let files = self_.base_layer_files(dir_name).await?;
+            Ok(Box::new(MarkedLayerBuilder {
+                inner: SimpleLayerBuilder::new_with_options(dir_name, files, options),
+                store: self_,
+            }) as Box<dyn LayerBuilder>)
+        })
+    }
+
+    fn create_child_layer_with_cache(
+        &self,
+        parent: [u32; 5],
+        cache: Arc<dyn LayerCache>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>> {
+        let self_ = self.clone();
+        Box::pin(async move {
+            let parent_layer = match self_.get_layer_with_cache(parent, cache).await? {
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        "parent layer not found",
+                    ))
+                }
+                Some(parent_layer) => Ok::<_, io::Error>(parent_layer),
+            }?;
+
+            let layer_dir = self_.create_directory().await?;
+            self_.write_incomplete_marker(layer_dir).await?;
+            self_.write_parent_file(layer_dir, parent).await?;
+            let child_layer_files = self_.child_layer_files(layer_dir).await?;
+            Ok(Box::new(MarkedLayerBuilder {
+                inner: SimpleLayerBuilder::from_parent(layer_dir, parent_layer, child_layer_files),
+                store: self_,
+            }) as Box<dyn LayerBuilder>)
+        })
+    }
+
+    fn create_named_base_layer(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>> {
+        let self_ = self.clone();
+        Box::pin(async move {
+            self_.create_named_directory(name).await?;
+            self_.write_incomplete_marker(name).await?;
+            let files = self_.base_layer_files(name).await?;
+            Ok(Box::new(MarkedLayerBuilder {
+                inner: SimpleLayerBuilder::new(name, files),
+                store: self_,
+            }) as Box<dyn LayerBuilder>)
+        })
+    }
+
+    fn create_named_child_layer_with_cache(
+        &self,
+        parent: [u32; 5],
+        name: [u32; 5],
+        cache: Arc<dyn LayerCache>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>> {
+        let self_ = self.clone();
+        Box::pin(async move {
+            let parent_layer = self_
+                .get_layer_with_cache(parent, cache)
+                .await?
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "parent layer not found"))?;
+
+            self_.create_named_directory(name).await?;
+            self_.write_incomplete_marker(name).await?;
+            self_.write_parent_file(name, parent).await?;
+            let child_layer_files = self_.child_layer_files(name).await?;
+            Ok(Box::new(MarkedLayerBuilder {
+                inner: SimpleLayerBuilder::from_parent(name, parent_layer, child_layer_files),
+                store: self_,
+            }) as Box<dyn LayerBuilder>)
+        })
+    }
+
+    fn export_layers(&self, layer_ids: Box<dyn Iterator<Item = [u32; 5]>>) -> io::Result<Vec<u8>> {
+        Self::export_layers(self, layer_ids)
+    }
+    fn export_layers_to(
+        &self,
+        writer: &mut dyn io::Write,
+        layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
+    ) -> Result<(), io::Error> {
+        Self::export_layers_to(self, writer, layer_ids)
+    }
+    fn import_layers(
+        &self,
+        pack: &[u8],
+        layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
     ) -> Result<(), io::Error> {
         Self::import_layers(self, pack, layer_ids)
     }
+    fn import_layers_from(
+        &self,
+        reader: &mut dyn io::Read,
+        layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
+    ) -> Result<(), io::Error> {
+        Self::import_layers_from(self, reader, layer_ids)
+    }
 
     fn layer_is_ancestor_of(
         &self,
@@ -626,19 +1296,175 @@ impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStor
             }
         })
     }
+
+    fn delete_layer(&self, name: [u32; 5]) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        self.delete_directory(name)
+    }
+
+    fn rollup(
+        &self,
+        layer: Arc<InternalLayer>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
+        let self_ = self.clone();
+        Box::pin(async move {
+            let dir_name = self_.create_directory().await?;
+            self_.write_incomplete_marker(dir_name).await?;
+            let files = self_.base_layer_files(dir_name).await?;
+            delta_rollup(&layer, files).await?;
+            self_.delete_file(dir_name, FILENAMES.incomplete).await?;
+            self_.write_checksum_file(dir_name).await?;
+            self_.write_rollup_file(layer.name(), dir_name).await?;
+
+            Ok(dir_name)
+        })
+    }
+
+    fn get_rollup(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<[u32; 5]>>> + Send>> {
+        self.read_rollup_file(name)
+    }
+
+    fn write_checksums(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        self.write_checksum_file(name)
+    }
+
+    fn flush(&self) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        self.sync_all()
+    }
+
+    fn layer_disk_size(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<u64>> + Send>> {
+        let self_ = self.clone();
+        Box::pin(async move {
+            if !self_.directory_exists(name).await? {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "layer not found"));
+            }
+
+            let mut size = 0;
+            for file in self_.directory_files(name).await? {
+                size += self_.get_file(name, &file).await?.size() as u64;
+            }
+
+            Ok(size)
+        })
+    }
+
+    fn verify_layer(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<LayerVerification>> + Send>> {
+        let self_ = self.clone();
+        Box::pin(async move {
+            if !self_.directory_exists(name).await? {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "layer not found"));
+            }
+
+            let checksums = match self_.read_checksum_file(name).await? {
+                None => return Ok(LayerVerification::Unverifiable),
+                Some(checksums) => checksums,
+            };
+            for (file, checksum) in checksums {
+                if !self_.file_exists(name, &file).await?
+                    || self_.file_checksum(name, &file).await? != checksum
+                {
+                    return Ok(LayerVerification::Corrupt);
+                }
+            }
+
+            Ok(LayerVerification::Intact)
+        })
+    }
+
+    fn pin_layer(&self, name: [u32; 5]) {
+        self.layer_pins().pin(name)
+    }
+
+    fn unpin_layer(&self, name: [u32; 5]) {
+        self.layer_pins().unpin(name)
+    }
+
+    fn pinned_layers(&self) -> Vec<[u32; 5]> {
+        self.layer_pins().pinned()
+    }
 }
 
 // locking isn't really ideal but the lock window will be relatively small so it shouldn't hurt performance too much except on heavy updates.
 // ideally we should be using some concurrent hashmap implementation instead.
-// furthermore, there should be some logic to remove stale entries, like a periodic pass. right now, there isn't.
+// an unbounded cache only holds weak references, and stale entries are removed when they are looked up.
 pub struct LockingHashMapLayerCache {
-    cache: RwLock<HashMap<[u32; 5], Weak<InternalLayer>>>,
+    cache: RwLock<HashMap<[u32; 5], CacheEntry>>,
+    capacity: Option<usize>,
+    clock: AtomicU64,
+    evictions: AtomicU64,
+}
+
+enum CachedLayer {
+    Weak(Weak<InternalLayer>),
+    Strong(Arc<InternalLayer>),
+}
+
+struct CacheEntry {
+    layer: CachedLayer,
+    last_access: AtomicU64,
 }
 
 impl LockingHashMapLayerCache {
     pub fn new() -> Self {
         Self {
             cache: RwLock::new(HashMap::new()),
+            capacity: None,
+            clock: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    /// Create a cache that keeps up to `capacity` layers loaded
+    ///
+    /// Unlike the unbounded cache, which only remembers layers for as
+    /// long as they are in use elsewhere, this cache keeps layers
+    /// alive itself. Once it holds more than `capacity` layers, the
+    /// least recently accessed layer is evicted. Layers that are
+    /// still referenced outside of the cache, for example as the
+    /// parent of another cached layer, are never evicted, so the
+    /// cache may temporarily exceed its capacity.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            cache: RwLock::new(HashMap::new()),
+            capacity: Some(capacity),
+            clock: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn evict(&self, cache: &mut HashMap<[u32; 5], CacheEntry>, capacity: usize) {
+        while cache.len() > capacity {
+            let candidate = cache
+                .iter()
+                .filter(|(_, entry)| match &entry.layer {
+                    CachedLayer::Weak(weak) => weak.strong_count() == 0,
+                    CachedLayer::Strong(layer) => Arc::strong_count(layer) == 1,
+                })
+                .min_by_key(|(_, entry)| entry.last_access.load(Ordering::Relaxed))
+                .map(|(name, _)| *name);
+
+            match candidate {
+                Some(name) => {
+                    cache.remove(&name);
+                    self.evictions.fetch_add(1, Ordering::Relaxed);
+                }
+                None => break, // everything left is still in use
+            }
         }
     }
 }
@@ -650,21 +1476,25 @@ impl LayerCache for LockingHashMapLayerCache {
             .read()
             .expect("rwlock read should always succeed");
 
-        let result = cache.get(&name).map(|c| c.to_owned());
+        let result = cache.get(&name).map(|entry| {
+            entry.last_access.store(self.tick(), Ordering::Relaxed);
+            match &entry.layer {
+                CachedLayer::Weak(weak) => weak.upgrade(),
+                CachedLayer::Strong(layer) => Some(layer.clone()),
+            }
+        });
         std::mem::drop(cache);
 
         match result {
             None => None,
-            Some(weak) => match weak.upgrade() {
-                None => {
-                    self.cache
-                        .write()
-                        .expect("rwlock write should always succeed")
-                        .remove(&name);
-                    None
-                }
-                Some(result) => Some(result),
-            },
+            Some(None) => {
+                self.cache
+                    .write()
+                    .expect("rwlock write should always succeed")
+                    .remove(&name);
+                None
+            }
+            Some(Some(result)) => Some(result),
         }
     }
 
@@ -673,14 +1503,78 @@ impl LayerCache for LockingHashMapLayerCache {
             .cache
             .write()
             .expect("rwlock write should always succeed");
-        cache.insert(layer.name(), Arc::downgrade(&layer));
+        let name = layer.name();
+        let entry = match self.capacity {
+            None => CachedLayer::Weak(Arc::downgrade(&layer)),
+            Some(_) => CachedLayer::Strong(layer),
+        };
+        cache.insert(
+            name,
+            CacheEntry {
+                layer: entry,
+                last_access: AtomicU64::new(self.tick()),
+            },
+        );
+
+        if let Some(capacity) = self.capacity {
+            self.evict(&mut cache, capacity);
+        }
+    }
+
+    fn invalidate(&self, name: [u32; 5]) {
+        let mut cache = self
+            .cache
+            .write()
+            .expect("rwlock write should always succeed");
+        cache.remove(&name);
     }
+
+    fn cached_layer_count(&self) -> usize {
+        let cache = self
+            .cache
+            .read()
+            .expect("rwlock read should always succeed");
+
+        cache
+            .values()
+            .filter(|entry| match &entry.layer {
+                CachedLayer::Weak(weak) => weak.strong_count() != 0,
+                CachedLayer::Strong(_) => true,
+            })
+            .count()
+    }
+
+    fn eviction_count(&self) -> u64 {
+        self.evictions.load(Ordering::Relaxed)
+    }
+}
+
+/// Statistics on how well a `CachedLayerStore` is doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    /// The amount of layer retrievals that were served from the cache
+    pub hits: u64,
+    /// The amount of layer retrievals that had to go to the underlying store
+    pub misses: u64,
+    /// The amount of layers the cache evicted
+    pub evictions: u64,
+    /// The amount of layers currently in the cache
+    pub len: usize,
+}
+
+#[derive(Default)]
+struct CacheCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    // evictions are counted by the cache, this is where we last reset
+    evictions_offset: AtomicU64,
 }
 
 #[derive(Clone)]
 pub struct CachedLayerStore {
     inner: Arc<dyn LayerStore>,
     cache: Arc<dyn LayerCache>,
+    counters: Arc<CacheCounters>,
 }
 
 impl CachedLayerStore {
@@ -688,8 +1582,29 @@ impl CachedLayerStore {
         CachedLayerStore {
             inner: Arc::new(inner),
             cache: Arc::new(cache),
+            counters: Arc::new(CacheCounters::default()),
+        }
+    }
+
+    /// Returns the cache statistics gathered since creation or the last `reset_stats`
+    pub fn cache_stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.counters.hits.load(Ordering::Relaxed),
+            misses: self.counters.misses.load(Ordering::Relaxed),
+            evictions: self.cache.eviction_count()
+                - self.counters.evictions_offset.load(Ordering::Relaxed),
+            len: self.cache.cached_layer_count(),
         }
     }
+
+    /// Reset the hit, miss and eviction counters to 0
+    pub fn reset_stats(&self) {
+        self.counters.hits.store(0, Ordering::Relaxed);
+        self.counters.misses.store(0, Ordering::Relaxed);
+        self.counters
+            .evictions_offset
+            .store(self.cache.eviction_count(), Ordering::Relaxed);
+    }
 }
 
 impl LayerStore for CachedLayerStore {
@@ -697,10 +1612,23 @@ impl LayerStore for CachedLayerStore {
         self.inner.layers()
     }
 
+    fn layer_exists(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<bool>> + Send>> {
+        self.inner.layer_exists(name)
+    }
+
     fn get_layer(
         &self,
         name: [u32; 5],
     ) -> Pin<Box<dyn Future<Output = io::Result<Option<Arc<InternalLayer>>>> + Send>> {
+        if let Some(layer) = self.cache.get_layer_from_cache(name) {
+            self.counters.hits.fetch_add(1, Ordering::Relaxed);
+            return Box::pin(future::ok(Some(layer)));
+        }
+
+        self.counters.misses.fetch_add(1, Ordering::Relaxed);
         self.inner.get_layer_with_cache(name, self.cache.clone())
     }
 
@@ -712,12 +1640,44 @@ impl LayerStore for CachedLayerStore {
         self.inner.get_layer_with_cache(name, cache)
     }
 
+    fn get_layer_eager(
+        &self,
+        name: [u32; 5],
+        concurrency: usize,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<Arc<InternalLayer>>>> + Send>> {
+        if let Some(layer) = self.cache.get_layer_from_cache(name) {
+            self.counters.hits.fetch_add(1, Ordering::Relaxed);
+            return Box::pin(future::ok(Some(layer)));
+        }
+
+        self.counters.misses.fetch_add(1, Ordering::Relaxed);
+        self.inner
+            .get_layer_eager_with_cache(name, self.cache.clone(), concurrency)
+    }
+
+    fn get_layer_eager_with_cache(
+        &self,
+        name: [u32; 5],
+        cache: Arc<dyn LayerCache>,
+        concurrency: usize,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<Arc<InternalLayer>>>> + Send>> {
+        self.inner
+            .get_layer_eager_with_cache(name, cache, concurrency)
+    }
+
     fn create_base_layer(
         &self,
     ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>> {
         self.inner.create_base_layer()
     }
 
+    fn create_base_layer_with_options(
+        &self,
+        options: LayerBuilderOptions,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>> {
+        self.inner.create_base_layer_with_options(options)
+    }
+
     fn create_child_layer(
         &self,
         parent: [u32; 5],
@@ -734,9 +1694,42 @@ impl LayerStore for CachedLayerStore {
         self.inner.create_child_layer_with_cache(parent, cache)
     }
 
-    fn export_layers(&self, layer_ids: Box<dyn Iterator<Item = [u32; 5]>>) -> Vec<u8> {
+    fn create_named_base_layer(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>> {
+        self.inner.create_named_base_layer(name)
+    }
+
+    fn create_named_child_layer(
+        &self,
+        parent: [u32; 5],
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>> {
+        self.inner
+            .create_named_child_layer_with_cache(parent, name, self.cache.clone())
+    }
+
+    fn create_named_child_layer_with_cache(
+        &self,
+        parent: [u32; 5],
+        name: [u32; 5],
+        cache: Arc<dyn LayerCache>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>> {
+        self.inner
+            .create_named_child_layer_with_cache(parent, name, cache)
+    }
+
+    fn export_layers(&self, layer_ids: Box<dyn Iterator<Item = [u32; 5]>>) -> io::Result<Vec<u8>> {
         self.inner.export_layers(layer_ids)
     }
+    fn export_layers_to(
+        &self,
+        writer: &mut dyn io::Write,
+        layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
+    ) -> Result<(), io::Error> {
+        self.inner.export_layers_to(writer, layer_ids)
+    }
     fn import_layers(
         &self,
         pack: &[u8],
@@ -744,6 +1737,13 @@ impl LayerStore for CachedLayerStore {
     ) -> Result<(), io::Error> {
         self.inner.import_layers(pack, layer_ids)
     }
+    fn import_layers_from(
+        &self,
+        reader: &mut dyn io::Read,
+        layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
+    ) -> Result<(), io::Error> {
+        self.inner.import_layers_from(reader, layer_ids)
+    }
 
     fn layer_is_ancestor_of(
         &self,
@@ -752,6 +1752,71 @@ impl LayerStore for CachedLayerStore {
     ) -> Pin<Box<dyn Future<Output = io::Result<bool>> + Send>> {
         self.inner.layer_is_ancestor_of(descendant, ancestor)
     }
+
+    fn delete_layer(&self, name: [u32; 5]) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        self.cache.invalidate(name);
+        self.inner.delete_layer(name)
+    }
+
+    fn rollup(
+        &self,
+        layer: Arc<InternalLayer>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
+        let name = layer.name();
+        let cache = self.cache.clone();
+        let rollup = self.inner.rollup(layer);
+        Box::pin(async move {
+            let result = rollup.await?;
+            // drop the cached stack so the next retrieval picks up the rollup
+            cache.invalidate(name);
+
+            Ok(result)
+        })
+    }
+
+    fn get_rollup(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<[u32; 5]>>> + Send>> {
+        self.inner.get_rollup(name)
+    }
+
+    fn write_checksums(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        self.inner.write_checksums(name)
+    }
+
+    fn flush(&self) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        self.inner.flush()
+    }
+
+    fn layer_disk_size(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<u64>> + Send>> {
+        self.inner.layer_disk_size(name)
+    }
+
+    fn verify_layer(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<LayerVerification>> + Send>> {
+        self.inner.verify_layer(name)
+    }
+
+    fn pin_layer(&self, name: [u32; 5]) {
+        self.inner.pin_layer(name)
+    }
+
+    fn unpin_layer(&self, name: [u32; 5]) {
+        self.inner.unpin_layer(name)
+    }
+
+    fn pinned_layers(&self) -> Vec<[u32; 5]> {
+        self.inner.pinned_layers()
+    }
 }
 
 #[cfg(test)]
@@ -899,9 +1964,143 @@ pub mod tests {
         assert_eq!(1, Arc::weak_count(&layer));
     }
 
+    #[test]
+    fn bounded_cache_evicts_least_recently_used_layer() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = CachedLayerStore::new(
+            MemoryLayerStore::new(),
+            LockingHashMapLayerCache::with_capacity(2),
+        );
+
+        let mut names = Vec::new();
+        for value in &["moo", "oink", "quack"] {
+            let mut builder = runtime.block_on(store.create_base_layer()).unwrap();
+            builder.add_string_triple(StringTriple::new_value("animal", "says", value));
+            names.push(builder.name());
+            runtime.block_on(builder.commit_boxed()).unwrap();
+        }
+
+        let first = runtime
+            .block_on(store.get_layer(names[0]))
+            .unwrap()
+            .unwrap();
+        let first_weak = Arc::downgrade(&first);
+        std::mem::drop(first);
+
+        // the cache keeps the layer around by itself
+        assert!(first_weak.upgrade().is_some());
+
+        let second_weak = Arc::downgrade(
+            &runtime
+                .block_on(store.get_layer(names[1]))
+                .unwrap()
+                .unwrap(),
+        );
+
+        // this is still in use while the third layer gets loaded
+        let first = runtime
+            .block_on(store.get_layer(names[0]))
+            .unwrap()
+            .unwrap();
+        let third_weak = Arc::downgrade(
+            &runtime
+                .block_on(store.get_layer(names[2]))
+                .unwrap()
+                .unwrap(),
+        );
+
+        // the second layer was least recently used and not in use elsewhere
+        assert!(second_weak.upgrade().is_none());
+        assert!(third_weak.upgrade().is_some());
+        std::mem::drop(first);
+        assert!(first_weak.upgrade().is_some());
+    }
+
+    #[test]
+    fn cached_layer_store_counts_hits_and_misses() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = CachedLayerStore::new(MemoryLayerStore::new(), LockingHashMapLayerCache::new());
+        let mut builder = runtime.block_on(store.create_base_layer()).unwrap();
+        let base_name = builder.name();
+        builder.add_string_triple(StringTriple::new_value("cow", "says", "moo"));
+        runtime.block_on(builder.commit_boxed()).unwrap();
+
+        assert_eq!(CacheStats::default(), store.cache_stats());
+
+        let _layer1 = runtime
+            .block_on(store.get_layer(base_name))
+            .unwrap()
+            .unwrap();
+        let _layer2 = runtime
+            .block_on(store.get_layer(base_name))
+            .unwrap()
+            .unwrap();
+
+        let stats = store.cache_stats();
+        assert_eq!(1, stats.hits);
+        assert_eq!(1, stats.misses);
+        assert_eq!(0, stats.evictions);
+        assert_eq!(1, stats.len);
+
+        store.reset_stats();
+        let stats = store.cache_stats();
+        assert_eq!(0, stats.hits);
+        assert_eq!(0, stats.misses);
+        assert_eq!(1, stats.len);
+    }
+
     #[test]
     fn retrieve_layer_stack_names_retrieves_correctly() {
         //let store = CachedLayerStore::new(MemoryLayerStore::new());
         //let builder = store.create_base_layer().wait().unwrap();
     }
+
+    #[test]
+    fn incomplete_directory_layers_are_absent() {
+        let mut runtime = Runtime::new().unwrap();
+        let dir = tempdir().unwrap();
+        let store = DirectoryLayerStore::new(dir.path());
+
+        let mut builder = runtime.block_on(store.create_base_layer()).unwrap();
+        let base_name = builder.name();
+        builder.add_string_triple(StringTriple::new_value("cow", "says", "moo"));
+        runtime.block_on(builder.commit_boxed()).unwrap();
+        assert!(!runtime
+            .block_on(store.file_exists(base_name, FILENAMES.incomplete))
+            .unwrap());
+        assert!(runtime
+            .block_on(store.get_layer(base_name))
+            .unwrap()
+            .is_some());
+
+        // a builder that never got to commit leaves an incomplete layer behind
+        let mut builder = runtime
+            .block_on(store.create_child_layer(base_name))
+            .unwrap();
+        let child_name = builder.name();
+        builder.add_string_triple(StringTriple::new_value("pig", "says", "oink"));
+        drop(builder);
+        assert!(runtime
+            .block_on(store.get_layer(child_name))
+            .unwrap()
+            .is_none());
+
+        // simulate a crash halfway through writing the layer files
+        runtime
+            .block_on(store.write_incomplete_marker(base_name))
+            .unwrap();
+        assert!(runtime
+            .block_on(store.get_layer(base_name))
+            .unwrap()
+            .is_none());
+
+        // layers without a marker, like those written before markers existed, are complete
+        runtime
+            .block_on(store.delete_file(base_name, FILENAMES.incomplete))
+            .unwrap();
+        assert!(runtime
+            .block_on(store.get_layer(base_name))
+            .unwrap()
+            .is_some());
+    }
 }
@@ -199,6 +199,17 @@ impl ExclusiveLockedFile {
         })
     }
 
+    pub async fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let file = self.file.as_mut().expect("tried to seek in a dropped file");
+        file.seek(pos).await
+    }
+
+    /// Make sure everything written to this file has reached the disk.
+    pub async fn sync_all(&mut self) -> io::Result<()> {
+        let file = self.file.as_mut().expect("tried to sync a dropped file");
+        file.sync_all().await
+    }
+
     pub async fn truncate(&mut self) -> io::Result<()> {
         let file = self
             .file
@@ -11,7 +11,10 @@ use std::sync::{self, Arc, RwLock};
 use tokio::prelude::*;
 
 use super::*;
-use crate::layer::{BaseLayer, ChildLayer, InternalLayer, LayerBuilder, SimpleLayerBuilder};
+use crate::layer::{
+    delta_rollup, BaseLayer, ChildLayer, InternalLayer, Layer, LayerBuilder, LayerBuilderOptions,
+    RollupLayer, SimpleLayerBuilder,
+};
 
 pub struct MemoryBackedStoreWriter {
     vec: Arc<sync::RwLock<Vec<u8>>>,
@@ -150,16 +153,41 @@ impl FileLoad for MemoryBackedStore {
 pub struct MemoryLayerStore {
     layers:
         futures_locks::RwLock<HashMap<[u32; 5], (Option<[u32; 5]>, LayerFiles<MemoryBackedStore>)>>,
+    rollups: futures_locks::RwLock<HashMap<[u32; 5], [u32; 5]>>,
+    pins: LayerPins,
+    name_generator: LayerNameGenerator,
 }
 
 impl MemoryLayerStore {
     pub fn new() -> MemoryLayerStore {
         MemoryLayerStore {
             layers: futures_locks::RwLock::new(HashMap::new()),
+            rollups: futures_locks::RwLock::new(HashMap::new()),
+            pins: LayerPins::new(),
+            name_generator: random_layer_name_generator(),
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn with_name_generator(self, name_generator: LayerNameGenerator) -> Self {
+        MemoryLayerStore {
+            name_generator,
+            ..self
         }
     }
 }
 
+// Pick a name for a new layer that is not in use yet
+fn unused_layer_name<T>(
+    layers: &HashMap<[u32; 5], T>,
+    name_generator: &LayerNameGenerator,
+) -> io::Result<[u32; 5]> {
+    (0..LAYER_NAME_ATTEMPTS)
+        .map(|_| name_generator())
+        .find(|name| !layers.contains_key(name))
+        .ok_or_else(layer_name_collision_error)
+}
+
 pub fn base_layer_memory_files() -> BaseLayerFiles<MemoryBackedStore> {
     BaseLayerFiles {
         node_dictionary_files: DictionaryFiles {
@@ -317,6 +345,14 @@ pub fn child_layer_memory_files() -> ChildLayerFiles<MemoryBackedStore> {
     }
 }
 
+// memory layers aren't kept as files, so there is nothing to pack
+fn packs_unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the memory layer store does not support layer packs",
+    )
+}
+
 impl LayerStore for MemoryLayerStore {
     fn layers(&self) -> Pin<Box<dyn Future<Output = io::Result<Vec<[u32; 5]>>> + Send>> {
         let guard = self.layers.read();
@@ -326,6 +362,14 @@ impl LayerStore for MemoryLayerStore {
         })
     }
 
+    fn layer_exists(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<bool>> + Send>> {
+        let guard = self.layers.read();
+        Box::pin(async move { Ok(guard.await.contains_key(&name)) })
+    }
+
     fn get_layer_with_cache(
         &self,
         name: [u32; 5],
@@ -336,17 +380,51 @@ impl LayerStore for MemoryLayerStore {
         }
 
         let guard = self.layers.read();
+        let rollups_lock = self.rollups.clone();
         Box::pin(async move {
             let layers = guard.await;
+            let rollups = rollups_lock.read().await;
 
             let mut ids = Vec::new();
             // collect ids until we get a cache hit
             let mut id = name;
             let mut first = true;
             let mut cached = None;
+            // rollups that were deleted after they were recorded
+            let mut stale_rollups = Vec::new();
             loop {
                 match cache.get_layer_from_cache(id) {
                     None => {
+                        if let (Some(rollup), Some((parent, files))) =
+                            (rollups.get(&id), layers.get(&id))
+                        {
+                            match layers.get(rollup) {
+                                Some((_, rollup_files)) => {
+                                    // serve this layer from its rollup instead of loading its ancestors
+                                    let rollup_layer = BaseLayer::load_from_files(
+                                        *rollup,
+                                        &rollup_files.clone().into_base(),
+                                    )
+                                    .await?;
+                                    let changes = load_layer_changes(id, files.clone()).await?;
+                                    let layer = Arc::new(
+                                        RollupLayer::from_base_layer(
+                                            Arc::new(rollup_layer.into()),
+                                            changes,
+                                            id,
+                                            *parent,
+                                        )
+                                        .into(),
+                                    )
+                                        as Arc<InternalLayer>;
+                                    cache.cache_layer(layer.clone());
+                                    cached = Some(layer);
+                                    break;
+                                }
+                                None => stale_rollups.push((id, *rollup)),
+                            }
+                        }
+
                         ids.push(id);
                         if let Some((parent, _)) = layers.get(&id) {
                             first = false;
@@ -372,6 +450,17 @@ impl LayerStore for MemoryLayerStore {
                 }
             }
 
+            std::mem::drop(rollups);
+            if !stale_rollups.is_empty() {
+                // fall back to the layer stack, and forget the missing rollups
+                let mut rollups = rollups_lock.write().await;
+                for (id, rollup) in stale_rollups {
+                    if rollups.get(&id) == Some(&rollup) && !layers.contains_key(&rollup) {
+                        rollups.remove(&id);
+                    }
+                }
+            }
+
             // at this point we have a list of layer ids, and optionally, we have a cached layer
             // starting with the cached layer, we need to construct child layers iteratively.
             // lacking a cached layer, the very last item in the vec is a base layer and that is our starting point.
@@ -407,17 +496,25 @@ impl LayerStore for MemoryLayerStore {
         })
     }
 
-    fn create_base_layer(
+    fn create_base_layer_with_options(
         &self,
+        options: LayerBuilderOptions,
     ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>> {
-        let name = rand::random();
+        if let Err(e) = options.validate() {
+            return Box::pin(future::err(e));
+        }
         let blf = base_layer_memory_files();
 
         let guard = self.layers.write();
+        let name_generator = self.name_generator.clone();
         Box::pin(async move {
             let mut layers = guard.await;
+            let name = unused_layer_name(&layers, &name_generator)?;
             layers.insert(name, (None, LayerFiles::Base(blf.clone())));
-            Ok(Box::new(SimpleLayerBuilder::new(name, blf)) as Box<dyn LayerBuilder>)
+            Ok(
+                Box::new(SimpleLayerBuilder::new_with_options(name, blf, options))
+                    as Box<dyn LayerBuilder>,
+            )
         })
     }
 
@@ -427,6 +524,7 @@ impl LayerStore for MemoryLayerStore {
         cache: Arc<dyn LayerCache>,
     ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>> {
         let layers = self.layers.clone();
+        let name_generator = self.name_generator.clone();
         let get_layer_with_cache = self.get_layer_with_cache(parent, cache);
         Box::pin(async move {
             let parent_layer_opt = get_layer_with_cache.await?;
@@ -438,13 +536,60 @@ impl LayerStore for MemoryLayerStore {
                 Some(parent_layer) => Ok::<_, io::Error>(parent_layer),
             }?;
 
-            let name = rand::random();
             let clf = child_layer_memory_files();
 
-            layers
-                .write()
-                .await
-                .insert(name, (Some(parent), LayerFiles::Child(clf.clone())));
+            let mut layers = layers.write().await;
+            let name = unused_layer_name(&layers, &name_generator)?;
+            layers.insert(name, (Some(parent), LayerFiles::Child(clf.clone())));
+            Ok(
+                Box::new(SimpleLayerBuilder::from_parent(name, parent_layer, clf))
+                    as Box<dyn LayerBuilder>,
+            )
+        })
+    }
+
+    fn create_named_base_layer(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>> {
+        let blf = base_layer_memory_files();
+
+        let guard = self.layers.write();
+        Box::pin(async move {
+            let mut layers = guard.await;
+            if layers.contains_key(&name) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "layer already exists",
+                ));
+            }
+            layers.insert(name, (None, LayerFiles::Base(blf.clone())));
+            Ok(Box::new(SimpleLayerBuilder::new(name, blf)) as Box<dyn LayerBuilder>)
+        })
+    }
+
+    fn create_named_child_layer_with_cache(
+        &self,
+        parent: [u32; 5],
+        name: [u32; 5],
+        cache: Arc<dyn LayerCache>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>> {
+        let layers = self.layers.clone();
+        let get_layer_with_cache = self.get_layer_with_cache(parent, cache);
+        Box::pin(async move {
+            let parent_layer = get_layer_with_cache
+                .await?
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "parent layer not found"))?;
+
+            let clf = child_layer_memory_files();
+            let mut layers = layers.write().await;
+            if layers.contains_key(&name) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "layer already exists",
+                ));
+            }
+            layers.insert(name, (Some(parent), LayerFiles::Child(clf.clone())));
             Ok(
                 Box::new(SimpleLayerBuilder::from_parent(name, parent_layer, clf))
                     as Box<dyn LayerBuilder>,
@@ -452,15 +597,29 @@ impl LayerStore for MemoryLayerStore {
         })
     }
 
-    fn export_layers(&self, _layer_ids: Box<dyn Iterator<Item = [u32; 5]>>) -> Vec<u8> {
-        unimplemented!();
+    fn export_layers(&self, _layer_ids: Box<dyn Iterator<Item = [u32; 5]>>) -> io::Result<Vec<u8>> {
+        Err(packs_unsupported())
+    }
+    fn export_layers_to(
+        &self,
+        _writer: &mut dyn std::io::Write,
+        _layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
+    ) -> Result<(), io::Error> {
+        Err(packs_unsupported())
     }
     fn import_layers(
         &self,
         _pack: &[u8],
         _layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
     ) -> Result<(), io::Error> {
-        unimplemented!();
+        Err(packs_unsupported())
+    }
+    fn import_layers_from(
+        &self,
+        _reader: &mut dyn std::io::Read,
+        _layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
+    ) -> Result<(), io::Error> {
+        Err(packs_unsupported())
     }
 
     fn layer_is_ancestor_of(
@@ -485,6 +644,112 @@ impl LayerStore for MemoryLayerStore {
             }
         })
     }
+
+    fn delete_layer(&self, name: [u32; 5]) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        let guard = self.layers.write();
+        let rollups_guard = self.rollups.write();
+        Box::pin(async move {
+            let mut layers = guard.await;
+            rollups_guard.await.remove(&name);
+            match layers.remove(&name) {
+                Some(_) => Ok(()),
+                None => Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "layer to delete not found",
+                )),
+            }
+        })
+    }
+
+    fn rollup(
+        &self,
+        layer: Arc<InternalLayer>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
+        let blf = base_layer_memory_files();
+
+        let layers = self.layers.clone();
+        let rollups = self.rollups.clone();
+        let name_generator = self.name_generator.clone();
+        Box::pin(async move {
+            delta_rollup(&layer, blf.clone()).await?;
+
+            let name = {
+                let mut layers = layers.write().await;
+                let name = unused_layer_name(&layers, &name_generator)?;
+                layers.insert(name, (None, LayerFiles::Base(blf)));
+
+                name
+            };
+            rollups.write().await.insert(layer.name(), name);
+
+            Ok(name)
+        })
+    }
+
+    fn get_rollup(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<[u32; 5]>>> + Send>> {
+        let guard = self.layers.read();
+        let rollups_guard = self.rollups.read();
+        Box::pin(async move {
+            let layers = guard.await;
+            // a rollup may have been deleted after it was recorded
+            Ok(rollups_guard
+                .await
+                .get(&name)
+                .filter(|rollup| layers.contains_key(*rollup))
+                .cloned())
+        })
+    }
+
+    fn write_checksums(
+        &self,
+        _name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        // memory layers can't get corrupted on disk
+        Box::pin(future::ok(()))
+    }
+
+    fn layer_disk_size(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<u64>> + Send>> {
+        // nothing is on disk, so estimate the memory used instead
+        let get_layer = self.get_layer(name);
+        Box::pin(async move {
+            match get_layer.await? {
+                Some(layer) => Ok(layer.heap_size() as u64),
+                None => Err(io::Error::new(io::ErrorKind::NotFound, "layer not found")),
+            }
+        })
+    }
+
+    fn verify_layer(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<LayerVerification>> + Send>> {
+        let guard = self.layers.read();
+        Box::pin(async move {
+            if guard.await.contains_key(&name) {
+                Ok(LayerVerification::Unverifiable)
+            } else {
+                Err(io::Error::new(io::ErrorKind::NotFound, "layer not found"))
+            }
+        })
+    }
+
+    fn pin_layer(&self, name: [u32; 5]) {
+        self.pins.pin(name)
+    }
+
+    fn unpin_layer(&self, name: [u32; 5]) {
+        self.pins.unpin(name)
+    }
+
+    fn pinned_layers(&self) -> Vec<[u32; 5]> {
+        self.pins.pinned()
+    }
 }
 
 #[derive(Clone)]
@@ -505,7 +770,10 @@ impl LabelStore for MemoryLabelStore {
         let guard = self.labels.read();
         Box::pin(async move {
             let labels = guard.await;
-            Ok(labels.values().map(|v| v.clone()).collect())
+            let mut result: Vec<Label> = labels.values().map(|v| v.clone()).collect();
+            result.sort_by(|l1, l2| l1.name.cmp(&l2.name));
+
+            Ok(result)
         })
     }
 
@@ -567,6 +835,36 @@ impl LabelStore for MemoryLabelStore {
             }
         })
     }
+
+    fn cas_label(
+        &self,
+        name: &str,
+        expected: Option<[u32; 5]>,
+        new: Option<[u32; 5]>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<bool>> + Send>> {
+        let name = name.to_owned();
+        let guard = self.labels.write();
+        Box::pin(async move {
+            let mut labels = guard.await;
+
+            match labels.get(&name) {
+                None => Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "label does not exist",
+                )),
+                Some(old_label) => {
+                    if old_label.layer != expected {
+                        Ok(false)
+                    } else {
+                        let new_label = old_label.with_updated_layer(new);
+                        labels.insert(name, new_label);
+
+                        Ok(true)
+                    }
+                }
+            }
+        })
+    }
 }
 
 #[cfg(test)]
@@ -711,4 +1009,110 @@ mod tests {
             .unwrap()
             .is_some());
     }
+
+    #[test]
+    fn layer_creation_retries_on_name_collision() {
+        let mut runtime = Runtime::new().unwrap();
+        let first = [1, 2, 3, 4, 5];
+        let second = [6, 7, 8, 9, 10];
+        let store = MemoryLayerStore::new()
+            .with_name_generator(fixed_layer_name_generator(vec![first, first, second]));
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        assert_eq!(first, builder.name());
+        runtime.block_on(builder.commit_boxed()).unwrap();
+
+        // the generator hands out the first name again, which is taken
+        let builder = runtime.block_on(store.create_child_layer(first)).unwrap();
+        assert_eq!(second, builder.name());
+        runtime.block_on(builder.commit_boxed()).unwrap();
+
+        // from now on only names that are in use are generated
+        let err = runtime.block_on(store.create_base_layer()).err().unwrap();
+        assert_eq!(io::ErrorKind::AlreadyExists, err.kind());
+    }
+
+    #[test]
+    fn exporting_layers_is_unsupported() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = MemoryLayerStore::new();
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        let name = builder.name();
+        runtime.block_on(builder.commit_boxed()).unwrap();
+
+        let err = LayerStore::export_layers(&store, Box::new(vec![name].into_iter()))
+            .err()
+            .unwrap();
+        assert_eq!(io::ErrorKind::Unsupported, err.kind());
+
+        let mut buf = Vec::new();
+        let err = store
+            .export_layers_to(&mut buf, Box::new(vec![name].into_iter()))
+            .err()
+            .unwrap();
+        assert_eq!(io::ErrorKind::Unsupported, err.kind());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn importing_layers_is_unsupported() {
+        let store = MemoryLayerStore::new();
+        let name = [1, 2, 3, 4, 5];
+
+        let err = LayerStore::import_layers(&store, &[], Box::new(vec![name].into_iter()))
+            .err()
+            .unwrap();
+        assert_eq!(io::ErrorKind::Unsupported, err.kind());
+
+        let err = store
+            .import_layers_from(&mut std::io::empty(), Box::new(vec![name].into_iter()))
+            .err()
+            .unwrap();
+        assert_eq!(io::ErrorKind::Unsupported, err.kind());
+    }
+
+    #[test]
+    fn layer_with_deleted_rollup_is_loaded_from_its_stack() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = MemoryLayerStore::new();
+        let mut builder = runtime.block_on(store.create_base_layer()).unwrap();
+        let base_name = builder.name();
+        builder.add_string_triple(StringTriple::new_value("cow", "says", "moo"));
+        runtime.block_on(builder.commit_boxed()).unwrap();
+
+        builder = runtime
+            .block_on(store.create_child_layer(base_name))
+            .unwrap();
+        let child_name = builder.name();
+        builder.add_string_triple(StringTriple::new_value("pig", "says", "oink"));
+        runtime.block_on(builder.commit_boxed()).unwrap();
+
+        let child = runtime
+            .block_on(store.get_layer(child_name))
+            .unwrap()
+            .unwrap();
+        let rollup_name = runtime.block_on(store.rollup(child)).unwrap();
+        assert!(runtime
+            .block_on(store.get_layer(child_name))
+            .unwrap()
+            .unwrap()
+            .is_rollup());
+
+        runtime.block_on(store.delete_layer(rollup_name)).unwrap();
+        assert_eq!(
+            None,
+            runtime.block_on(store.get_rollup(child_name)).unwrap()
+        );
+
+        let layer = runtime
+            .block_on(store.get_layer(child_name))
+            .unwrap()
+            .unwrap();
+        assert!(!layer.is_rollup());
+        assert!(layer.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+        assert!(layer.string_triple_exists(&StringTriple::new_value("pig", "says", "oink")));
+        assert!(!runtime
+            .block_on(store.rollups.read())
+            .contains_key(&child_name));
+    }
 }
@@ -29,7 +29,9 @@ mod label;
 mod layer;
 mod locking;
 pub mod memory;
+mod pin;
 
 pub use file::*;
 pub use label::*;
 pub use layer::*;
+pub use pin::*;
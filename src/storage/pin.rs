@@ -0,0 +1,50 @@
+//! A shared set of pinned layers.
+//!
+//! Pinned layers are kept around by garbage collection, even if no
+//! label points at them.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// A reference-counted set of pinned layer names.
+///
+/// A layer stays pinned until it has been unpinned as many times as
+/// it was pinned. Clones share the same set.
+#[derive(Clone, Default)]
+pub struct LayerPins {
+    pins: Arc<Mutex<HashMap<[u32; 5], usize>>>,
+}
+
+impl LayerPins {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn pin(&self, name: [u32; 5]) {
+        let mut pins = self.pins.lock().expect("mutex lock should always succeed");
+        *pins.entry(name).or_insert(0) += 1;
+    }
+
+    pub fn unpin(&self, name: [u32; 5]) {
+        let mut pins = self.pins.lock().expect("mutex lock should always succeed");
+        if let Some(count) = pins.get_mut(&name) {
+            *count -= 1;
+            if *count == 0 {
+                pins.remove(&name);
+            }
+        }
+    }
+
+    pub fn is_pinned(&self, name: [u32; 5]) -> bool {
+        let pins = self.pins.lock().expect("mutex lock should always succeed");
+        pins.contains_key(&name)
+    }
+
+    /// Returns all pinned layers, sorted by name
+    pub fn pinned(&self) -> Vec<[u32; 5]> {
+        let pins = self.pins.lock().expect("mutex lock should always succeed");
+        let mut result: Vec<_> = pins.keys().cloned().collect();
+        result.sort();
+
+        result
+    }
+}
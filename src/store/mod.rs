@@ -3,27 +3,251 @@
 //! It is expected that most users of this library will work exclusively with the types contained in this module.
 pub mod sync;
 
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::path::PathBuf;
-use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 
 use crate::layer::{
-    IdTriple, Layer, LayerBuilder, LayerCounts, LayerObjectLookup, LayerPredicateLookup,
-    LayerSubjectLookup, ObjectLookup, ObjectType, PredicateLookup, StringTriple, SubjectLookup,
+    is_blank_node, parse_ntriple, write_ntriple, ChildLayer, IdTriple, InternalLayer, Layer,
+    LayerBuilder, LayerBuilderOptions, LayerCounts, LayerObjectLookup, LayerPredicateLookup,
+    LayerSubjectLookup, Numeric, ObjectKind, ObjectLookup, ObjectType, PredicateLookup,
+    StringTriple, SubjectLookup, BLANK_NODE_PREFIX,
 };
-use crate::storage::directory::{DirectoryLabelStore, DirectoryLayerStore};
+pub use crate::storage::directory::DirectoryStoreOptions;
+use crate::storage::directory::{pack_layer_parents, DirectoryLabelStore, DirectoryLayerStore};
 use crate::storage::memory::{MemoryLabelStore, MemoryLayerStore};
-use crate::storage::{CachedLayerStore, LabelStore, LayerStore, LockingHashMapLayerCache};
+pub use crate::storage::{name_to_string, string_to_name, LayerVerification};
+use crate::storage::{
+    stand_in_parent_layer, CachedLayerStore, Label, LabelStore, LayerStore,
+    LockingHashMapLayerCache, PersistentLayerStore,
+};
 
-use std::io;
+use std::io::{self, BufRead, Write};
 
 use rayon;
 use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+
+use futures::stream::{self, Stream, StreamExt};
+use thiserror::Error;
+
+/// The error type for store operations
+///
+/// Conditions that callers may want to handle, such as a missing
+/// label, get their own variant. Everything else is passed along as
+/// an `Io` error.
+#[derive(Error, Debug)]
+pub enum StoreError {
+    #[error("label not found: {0}")]
+    LabelNotFound(String),
+    #[error("layer not found: {}", name_to_string(*.0))]
+    LayerNotFound([u32; 5]),
+    #[error("builder has already been committed")]
+    BuilderAlreadyCommitted,
+    #[error("layer {} is not an ancestor of layer {}", name_to_string(*.0), name_to_string(*.1))]
+    NotAnAncestor([u32; 5], [u32; 5]),
+    #[error("head of {0} was changed concurrently")]
+    HeadChanged(String),
+    #[error(transparent)]
+    Io(io::Error),
+}
+
+impl StoreError {
+    /// The `io::ErrorKind` this error corresponds to
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            StoreError::LabelNotFound(_) | StoreError::LayerNotFound(_) => io::ErrorKind::NotFound,
+            StoreError::BuilderAlreadyCommitted => io::ErrorKind::InvalidData,
+            StoreError::NotAnAncestor(_, _) => io::ErrorKind::InvalidInput,
+            StoreError::HeadChanged(_) => io::ErrorKind::Other,
+            StoreError::Io(e) => e.kind(),
+        }
+    }
+}
+
+impl From<io::Error> for StoreError {
+    fn from(e: io::Error) -> StoreError {
+        // a store error that was converted into an io error along the
+        // way is unwrapped again, so its variant isn't lost
+        if e.get_ref()
+            .and_then(|inner| inner.downcast_ref::<StoreError>())
+            .is_some()
+        {
+            *e.into_inner().unwrap().downcast::<StoreError>().unwrap()
+        } else {
+            StoreError::Io(e)
+        }
+    }
+}
+
+impl From<StoreError> for io::Error {
+    fn from(e: StoreError) -> io::Error {
+        match e {
+            StoreError::Io(e) => e,
+            e => io::Error::new(e.kind(), e),
+        }
+    }
+}
+
+/// A signal to stop a long-running operation, such as a squash or an import
+///
+/// Operations that take a token check it between batches of work,
+/// and fail with an `Interrupted` error once it is cancelled. Clones
+/// share the same signal.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Signal all operations using this token to stop
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn check(&self) -> io::Result<()> {
+        if self.is_cancelled() {
+            Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "operation was cancelled",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// A reader that stops reading once its token is cancelled
+//
+// An `Interrupted` error from a reader is retried by most consumers,
+// so instead of failing, this reader pretends the input ended. The
+// caller turns the resulting error into an `Interrupted` one.
+struct CancellableReader<'a, R> {
+    inner: R,
+    cancel: &'a CancellationToken,
+}
+
+impl<'a, R: io::Read> io::Read for CancellableReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.cancel.is_cancelled() {
+            Ok(0)
+        } else {
+            self.inner.read(buf)
+        }
+    }
+}
 
 /// A store, storing a set of layers and database labels pointing to these layers
 #[derive(Clone)]
 pub struct Store {
     label_store: Arc<dyn LabelStore>,
     layer_store: Arc<dyn LayerStore>,
+    open_builders: Arc<Mutex<OpenBuilders>>,
+    commit_callbacks: Arc<RwLock<Vec<CommitCallback>>>,
+    content_addressed_names: bool,
+    thread_pool: Option<Arc<rayon::ThreadPool>>,
+}
+
+// layer builders that are currently open, mapped to the parent they are building on
+type OpenBuilders = HashMap<[u32; 5], Option<[u32; 5]>>;
+
+/// A callback registered with `Store::on_commit`
+pub type CommitCallback = Arc<dyn Fn(LayerCommitEvent) + Send + Sync>;
+
+/// A change to a store, as passed to the callbacks registered with `Store::on_commit`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LayerCommitEvent {
+    /// a layer builder was committed
+    Layer {
+        layer: [u32; 5],
+        parent: Option<[u32; 5]>,
+    },
+    /// a label was pointed at a new head
+    Head { label: String, layer: [u32; 5] },
+}
+
+// the amount of triples squash resolves in one go
+const SQUASH_CHUNK_SIZE: usize = 4096;
+
+// the amount of triples a triple stream produces before yielding to the runtime
+const TRIPLE_STREAM_CHUNK_SIZE: usize = 1024;
+
+// the amount of ancestors `get_layer_from_id_eager` reads at the same time
+const EAGER_LOAD_CONCURRENCY: usize = 8;
+
+// Turn a triple iterator into a stream that pulls the triples in
+// chunks, yielding to the runtime in between, so that a long
+// iteration doesn't keep other tasks from running.
+fn triple_stream(
+    triples: Box<dyn Iterator<Item = IdTriple> + Send>,
+) -> impl Stream<Item = IdTriple> + Send {
+    stream::unfold((triples, true), |(mut triples, first)| async move {
+        if !first {
+            let _ = tokio::task::yield_now().await;
+        }
+
+        let chunk: Vec<_> = triples.by_ref().take(TRIPLE_STREAM_CHUNK_SIZE).collect();
+        if chunk.is_empty() {
+            None
+        } else {
+            Some((stream::iter(chunk), (triples, false)))
+        }
+    })
+    .flatten()
+}
+
+// every part is length-prefixed so that different triples can never hash the same
+fn hash_string_triples(hasher: &mut Sha256, triples: &[StringTriple]) {
+    for triple in triples {
+        let (object_type, object) = match &triple.object {
+            ObjectType::Node(node) => (0_u8, node.clone()),
+            object => (1_u8, object.value_dict_entry().unwrap()),
+        };
+        for part in &[&triple.subject, &triple.predicate] {
+            hasher.update((part.len() as u64).to_be_bytes());
+            hasher.update(part.as_bytes());
+        }
+        hasher.update([object_type]);
+        hasher.update((object.len() as u64).to_be_bytes());
+        hasher.update(object.as_bytes());
+    }
+}
+
+// the content-addressed name of a layer making the given net changes to its parent
+fn content_address(
+    parent: Option<[u32; 5]>,
+    additions: &[StringTriple],
+    removals: &[StringTriple],
+) -> [u32; 5] {
+    let mut hasher = Sha256::new();
+    match parent {
+        None => hasher.update([0_u8]),
+        Some(parent) => {
+            hasher.update([1_u8]);
+            for part in &parent {
+                hasher.update(part.to_be_bytes());
+            }
+        }
+    }
+    for triples in &[additions, removals] {
+        hasher.update((triples.len() as u64).to_be_bytes());
+        hash_string_triples(&mut hasher, triples);
+    }
+
+    let hash = hasher.finalize();
+    let mut name = [0_u32; 5];
+    for (part, bytes) in name.iter_mut().zip(hash.chunks(4)) {
+        *part = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+
+    name
 }
 
 /// A wrapper over a SimpleLayerBuilder, providing a thread-safe sharable interface
@@ -36,52 +260,87 @@ pub struct Store {
 /// as having committed, returning errors on further calls.
 pub struct StoreLayerBuilder {
     parent: Option<Arc<dyn Layer>>,
-    builder: RwLock<Option<Box<dyn LayerBuilder>>>,
+    builder: futures_locks::RwLock<Option<Box<dyn LayerBuilder>>>,
+    committed: AtomicBool,
     name: [u32; 5],
+    // the content-addressed name this layer was committed under, if any
+    committed_name: Mutex<Option<[u32; 5]>>,
+    // whether the layer is renamed to its content address on commit
+    content_addressed: bool,
     store: Store,
+    // the number of the last blank node handed out, lazily initialized from the parent
+    last_blank_node: Mutex<Option<u64>>,
 }
 
 impl StoreLayerBuilder {
     async fn new(store: Store) -> io::Result<Self> {
         let builder = store.layer_store.create_base_layer().await?;
 
-        Ok(Self {
-            parent: builder.parent(),
-            name: builder.name(),
-            builder: RwLock::new(Some(builder)),
-            store,
-        })
+        Ok(Self::wrap(builder, store))
     }
 
     fn wrap(builder: Box<dyn LayerBuilder>, store: Store) -> Self {
+        store.register_builder(builder.name(), builder.parent().map(|p| p.name()));
         StoreLayerBuilder {
             parent: builder.parent(),
             name: builder.name(),
-            builder: RwLock::new(Some(builder)),
+            builder: futures_locks::RwLock::new(Some(builder)),
+            committed: AtomicBool::new(false),
+            committed_name: Mutex::new(None),
+            content_addressed: store.content_addressed_names,
             store,
+            last_blank_node: Mutex::new(None),
         }
     }
 
+    // Wrap a builder for a layer whose name was chosen by the caller.
+    // That name is kept on commit, even in a store with
+    // content-addressed names.
+    fn wrap_named(builder: Box<dyn LayerBuilder>, store: Store) -> Self {
+        let mut wrapped = Self::wrap(builder, store);
+        wrapped.content_addressed = false;
+
+        wrapped
+    }
+
+    // The builder lock is futures-aware, so that a commit waiting for
+    // it yields to the runtime instead of blocking a worker thread.
+    // The synchronous methods have no runtime to yield to, so they
+    // block the calling thread until the lock is free. A batch method
+    // holds the lock while it drains its iterator, which can take
+    // arbitrarily long, so async code must use `with_builder_async`
+    // and the `_async` methods built on it instead.
     fn with_builder<R, F: FnOnce(&mut Box<dyn LayerBuilder>) -> R>(
         &self,
         f: F,
-    ) -> Result<R, io::Error> {
-        let mut builder = self
-            .builder
-            .write()
-            .expect("rwlock write should always succeed");
+    ) -> Result<R, StoreError> {
+        let mut builder = futures::executor::block_on(self.builder.write());
+        match (*builder).as_mut() {
+            None => Err(StoreError::BuilderAlreadyCommitted),
+            Some(builder) => Ok(f(builder)),
+        }
+    }
+
+    async fn with_builder_async<R, F: FnOnce(&mut Box<dyn LayerBuilder>) -> R>(
+        &self,
+        f: F,
+    ) -> Result<R, StoreError> {
+        let mut builder = self.builder.write().await;
         match (*builder).as_mut() {
-            None => Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "builder has already been committed",
-            )),
+            None => Err(StoreError::BuilderAlreadyCommitted),
             Some(builder) => Ok(f(builder)),
         }
     }
 
     /// Returns the name of the layer being built
+    ///
+    /// For a store using content-addressed names, this changes to the
+    /// final name of the layer once it is committed.
     pub fn name(&self) -> [u32; 5] {
-        self.name
+        self.committed_name
+            .lock()
+            .expect("mutex lock should always succeed")
+            .unwrap_or(self.name)
     }
 
     pub fn parent(&self) -> Option<Arc<dyn Layer>> {
@@ -89,59 +348,305 @@ impl StoreLayerBuilder {
     }
 
     /// Add a string triple
-    pub fn add_string_triple(&self, triple: StringTriple) -> Result<(), io::Error> {
+    pub fn add_string_triple(&self, triple: StringTriple) -> Result<(), StoreError> {
         self.with_builder(move |b| b.add_string_triple(triple))
     }
 
+    /// Add a string triple, returning whether it is new
+    ///
+    /// Like `HashSet::insert`, this returns false if the triple is
+    /// already in the parent layer or was already added in this
+    /// builder, in which case adding it does not change anything. The
+    /// triple is recorded either way, so it still cancels out a
+    /// removal of the same triple in this builder.
+    pub fn insert_string_triple(&self, triple: StringTriple) -> Result<bool, StoreError> {
+        self.with_builder(move |b| b.insert_string_triple(triple))
+    }
+
+    /// Add a batch of string triples
+    ///
+    /// This acquires the builder lock only once for the whole batch,
+    /// which is a lot cheaper than calling `add_string_triple` for
+    /// every triple when loading many triples at once.
+    pub fn add_string_triples<I: IntoIterator<Item = StringTriple>>(
+        &self,
+        triples: I,
+    ) -> Result<(), StoreError> {
+        self.with_builder(move |b| {
+            for triple in triples {
+                b.add_string_triple(triple);
+            }
+        })
+    }
+
+    /// Add a batch of string triples, waiting for the builder lock asynchronously
+    ///
+    /// This is the variant of `add_string_triples` to use from async
+    /// code, as it does not block the thread while another call holds
+    /// the builder lock.
+    pub async fn add_string_triples_async<I: IntoIterator<Item = StringTriple>>(
+        &self,
+        triples: I,
+    ) -> Result<(), StoreError> {
+        self.with_builder_async(move |b| {
+            for triple in triples {
+                b.add_string_triple(triple);
+            }
+        })
+        .await
+    }
+
+    /// Add a batch of string triples from a parallel iterator
+    ///
+    /// The triples are collected, sorted and deduplicated in parallel
+    /// before the builder lock is taken, and then handed over in one
+    /// go. On commit, the dictionaries are built from the distinct
+    /// terms of this sorted set, and a base layer builder that was
+    /// given no other additions skips sorting them again. The
+    /// resulting layer is the same as when adding the triples one by
+    /// one.
+    pub fn par_add_string_triples<I: IntoParallelIterator<Item = StringTriple>>(
+        &self,
+        triples: I,
+    ) -> Result<(), StoreError> {
+        let triples = triples.into_par_iter();
+        let triples = self.store.install(move || {
+            let mut triples: Vec<_> = triples.collect();
+            triples.par_sort_unstable();
+            triples.dedup();
+
+            triples
+        });
+
+        self.with_builder(move |b| b.add_sorted_string_triples(triples))
+    }
+
+    /// Returns a new blank node label, like `_:b1`
+    ///
+    /// The label is unique among the blank nodes handed out by this
+    /// builder and the blank nodes in the layers it is built on.
+    pub fn fresh_blank_node(&self) -> String {
+        let mut last = self
+            .last_blank_node
+            .lock()
+            .expect("mutex lock should always succeed");
+        let next = match *last {
+            Some(n) => n + 1,
+            None => {
+                let prefix = format!("{}b", BLANK_NODE_PREFIX);
+                let highest = self.parent.as_ref().and_then(|parent| {
+                    parent
+                        .object_nodes_with_prefix(&prefix)
+                        .filter_map(|(_, node)| node[prefix.len()..].parse::<u64>().ok())
+                        .max()
+                });
+                highest.map(|n| n + 1).unwrap_or(1)
+            }
+        };
+        *last = Some(next);
+
+        format!("{}b{}", BLANK_NODE_PREFIX, next)
+    }
+
+    /// Add all triples read from the given reader in N-Triples format, returning the amount of triples read
+    ///
+    /// IRI objects are added as nodes, and literals as values. Blank
+    /// node labels only have meaning within the input, so each of them
+    /// is replaced by a fresh blank node. Blank lines and comments are
+    /// skipped. A malformed line results in an `InvalidData` error
+    /// mentioning its line number.
+    pub fn import_ntriples<R: io::Read>(&self, reader: R) -> Result<usize, StoreError> {
+        let mut blank_nodes: HashMap<String, String> = HashMap::new();
+        let mut rename = |node: String| {
+            if is_blank_node(&node) {
+                blank_nodes
+                    .entry(node)
+                    .or_insert_with(|| self.fresh_blank_node())
+                    .clone()
+            } else {
+                node
+            }
+        };
+
+        let mut triples = Vec::new();
+        for (line_number, line) in io::BufReader::new(reader).lines().enumerate() {
+            let line = line?;
+            if let Some(mut triple) = parse_ntriple(&line).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", line_number + 1, e),
+                )
+            })? {
+                triple.subject = rename(triple.subject);
+                if let ObjectType::Node(node) = triple.object {
+                    triple.object = ObjectType::Node(rename(node));
+                }
+                triples.push(triple);
+            }
+        }
+
+        let count = triples.len();
+        self.add_string_triples(triples)?;
+
+        Ok(count)
+    }
+
     /// Add an id triple
-    pub fn add_id_triple(&self, triple: IdTriple) -> Result<(), io::Error> {
+    pub fn add_id_triple(&self, triple: IdTriple) -> Result<(), StoreError> {
         self.with_builder(move |b| b.add_id_triple(triple))
     }
 
     /// Remove a string triple
-    pub fn remove_string_triple(&self, triple: StringTriple) -> Result<(), io::Error> {
+    pub fn remove_string_triple(&self, triple: StringTriple) -> Result<(), StoreError> {
         self.with_builder(move |b| b.remove_string_triple(triple))
     }
 
+    /// Remove a batch of string triples
+    ///
+    /// Like `add_string_triples`, this acquires the builder lock only
+    /// once for the whole batch.
+    pub fn remove_string_triples<I: IntoIterator<Item = StringTriple>>(
+        &self,
+        triples: I,
+    ) -> Result<(), StoreError> {
+        self.with_builder(move |b| {
+            for triple in triples {
+                b.remove_string_triple(triple);
+            }
+        })
+    }
+
+    /// Remove a batch of string triples, waiting for the builder lock asynchronously
+    ///
+    /// This is the variant of `remove_string_triples` to use from
+    /// async code, as it does not block the thread while another call
+    /// holds the builder lock.
+    pub async fn remove_string_triples_async<I: IntoIterator<Item = StringTriple>>(
+        &self,
+        triples: I,
+    ) -> Result<(), StoreError> {
+        self.with_builder_async(move |b| {
+            for triple in triples {
+                b.remove_string_triple(triple);
+            }
+        })
+        .await
+    }
+
     /// Remove an id triple
-    pub fn remove_id_triple(&self, triple: IdTriple) -> Result<(), io::Error> {
+    pub fn remove_id_triple(&self, triple: IdTriple) -> Result<(), StoreError> {
         self.with_builder(move |b| b.remove_id_triple(triple))
     }
 
+    /// Remove all triples with the given subject, returning how many were newly removed
+    ///
+    /// Only triples in the parent layer are removed. Triples added
+    /// in this builder are left alone. For a base layer builder, or
+    /// if the parent doesn't know the subject, this removes nothing.
+    /// Triples this builder already removed are not counted again.
+    pub fn remove_subject(&self, subject: &str) -> Result<usize, StoreError> {
+        let triples: Vec<_> = match self.parent.as_ref() {
+            None => Vec::new(),
+            Some(parent) => match parent.subject_id(subject) {
+                None => Vec::new(),
+                Some(id) => parent.triples_s(id).collect(),
+            },
+        };
+
+        self.remove_id_triples(triples)
+    }
+
+    /// Remove all triples with the given node as their object, returning how many were newly removed
+    ///
+    /// Like `remove_subject`, this only removes triples in the parent layer.
+    pub fn remove_object(&self, object: &str) -> Result<usize, StoreError> {
+        let triples: Vec<_> = match self.parent.as_ref() {
+            None => Vec::new(),
+            Some(parent) => match parent.object_node_id(object) {
+                None => Vec::new(),
+                Some(id) => parent.triples_o(id).collect(),
+            },
+        };
+
+        self.remove_id_triples(triples)
+    }
+
+    fn remove_id_triples(&self, triples: Vec<IdTriple>) -> Result<usize, StoreError> {
+        if triples.is_empty() {
+            return Ok(0);
+        }
+
+        self.with_builder(move |b| {
+            triples
+                .into_iter()
+                .filter(|triple| b.delete_id_triple(*triple))
+                .count()
+        })
+    }
+
     /// Returns true if this layer has been committed, and false otherwise.
     pub fn committed(&self) -> bool {
-        self.builder
-            .read()
-            .expect("rwlock write should always succeed")
-            .is_none()
+        self.committed.load(Ordering::SeqCst)
+    }
+
+    /// Returns true if committing this builder would not change anything relative to its parent
+    ///
+    /// Additions that are already in the parent and removals that
+    /// aren't in it don't count as changes. A builder that was
+    /// already committed, or that is in use by another thread, is
+    /// assumed not to be empty.
+    pub fn is_empty(&self) -> bool {
+        match self.builder.try_read() {
+            Ok(builder) => match builder.as_ref() {
+                None => false,
+                Some(builder) => builder.is_empty(),
+            },
+            Err(_) => false,
+        }
     }
 
     /// Commit the layer to storage without loading the resulting layer
-    pub async fn commit_no_load(&self) -> io::Result<()> {
+    pub async fn commit_no_load(&self) -> Result<(), StoreError> {
         let mut builder = None;
         {
-            let mut guard = self
-                .builder
-                .write()
-                .expect("rwlock write should always succeed");
+            let mut guard = self.builder.write().await;
 
             // Setting the builder to None ensures that committed() detects we already committed (or tried to do so anyway)
             std::mem::swap(&mut builder, &mut guard);
+            self.committed.store(true, Ordering::SeqCst);
         }
 
         match builder {
-            None => Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "builder has already been committed",
-            )),
-            Some(builder) => builder.commit_boxed().await,
+            None => Err(StoreError::BuilderAlreadyCommitted),
+            Some(builder) => {
+                let name = if self.content_addressed {
+                    let name = self.store.commit_content_addressed(builder).await?;
+                    *self
+                        .committed_name
+                        .lock()
+                        .expect("mutex lock should always succeed") = Some(name);
+
+                    name
+                } else {
+                    builder.commit_boxed().await?;
+                    self.store.layer_store.write_checksums(self.name).await?;
+
+                    self.name
+                };
+                self.store.fire_commit_event(LayerCommitEvent::Layer {
+                    layer: name,
+                    parent: self.parent.as_ref().map(|p| p.name()),
+                });
+
+                Ok(())
+            }
         }
     }
 
     /// Commit the layer to storage
-    pub async fn commit(&self) -> io::Result<StoreLayer> {
-        let name = self.name;
+    pub async fn commit(&self) -> Result<StoreLayer, StoreError> {
         self.commit_no_load().await?;
+        let name = self.name();
 
         let layer = self.store.layer_store.get_layer(name).await?;
         Ok(StoreLayer::wrap(
@@ -150,60 +655,282 @@ impl StoreLayerBuilder {
         ))
     }
 
-    pub fn apply_delta(&self, delta: &StoreLayer) -> Result<(), io::Error> {
+    /// Commit the layer to storage, unless it would not change anything
+    ///
+    /// If the builder `is_empty`, no layer is created and this
+    /// returns `None`, leaving the builder uncommitted. Like
+    /// `commit`, this fails if the builder was already committed.
+    pub async fn commit_nonempty(&self) -> Result<Option<StoreLayer>, StoreError> {
+        match self.builder.read().await.as_ref() {
+            None => return Err(StoreError::BuilderAlreadyCommitted),
+            Some(builder) if builder.is_empty() => return Ok(None),
+            Some(_) => {}
+        }
+
+        self.commit().await.map(Some)
+    }
+
+    pub fn apply_delta(&self, delta: &StoreLayer) -> Result<(), StoreError> {
         // create a child builder and use it directly
         // first check what dictionary entries we don't know about, add those
-        rayon::join(
-            || {
-                delta.triple_additions().par_bridge().for_each(|t| {
+        // the triples are resolved in parallel, but handed to the
+        // builder in one go, as the builder can only be used by one
+        // thread at a time.
+        let (additions, removals): (Vec<_>, Vec<_>) = self.store.install(|| {
+            rayon::join(
+                || {
                     delta
-                        .id_triple_to_string(&t)
-                        .map(|st| self.add_string_triple(st));
-                });
-            },
-            || {
-                delta.triple_removals().par_bridge().for_each(|t| {
+                        .triple_additions()
+                        .par_bridge()
+                        .filter_map(|t| delta.id_triple_to_string(&t))
+                        .collect()
+                },
+                || {
                     delta
-                        .id_triple_to_string(&t)
-                        .map(|st| self.remove_string_triple(st));
-                })
-            },
-        );
+                        .triple_removals()
+                        .par_bridge()
+                        .filter_map(|t| delta.id_triple_to_string(&t))
+                        .collect()
+                },
+            )
+        });
+
+        self.add_string_triples(additions)?;
+        self.remove_string_triples(removals)
+    }
+
+    /// Apply the additions and removals from the given delta to this builder
+    pub fn apply_struct_delta(&self, delta: &Delta) -> Result<(), StoreError> {
+        for triple in delta.additions.iter() {
+            self.add_string_triple(triple.clone())?;
+        }
+        for triple in delta.removals.iter() {
+            self.remove_string_triple(triple.clone())?;
+        }
 
         Ok(())
     }
 
-    pub fn apply_diff(&self, other: &StoreLayer) -> Result<(), io::Error> {
+    pub fn apply_diff(&self, other: &StoreLayer) -> Result<(), StoreError> {
         // create a child builder and use it directly
         // first check what dictionary entries we don't know about, add those
-        rayon::join(
-            || {
-                if let Some(this) = self.parent() {
-                    this.triples().par_bridge().for_each(|t| {
-                        this.id_triple_to_string(&t).map(|st| {
-                            if !other.string_triple_exists(&st) {
-                                self.remove_string_triple(st).unwrap()
-                            };
-                        });
+        // a base builder has nothing to remove, and has to add everything
+        let parent = self.parent();
+        let (removals, additions): (Vec<_>, Vec<_>) = self.store.install(|| {
+            rayon::join(
+                || match &parent {
+                    Some(this) => this
+                        .triples()
+                        .par_bridge()
+                        .filter_map(|t| this.id_triple_to_string(&t))
+                        .filter(|st| !other.string_triple_exists(st))
+                        .collect(),
+                    None => Vec::new(),
+                },
+                || {
+                    other
+                        .triples()
+                        .par_bridge()
+                        .filter_map(|t| other.id_triple_to_string(&t))
+                        .filter(|st| match &parent {
+                            Some(this) => !this.string_triple_exists(st),
+                            None => true,
+                        })
+                        .collect()
+                },
+            )
+        });
+
+        self.remove_string_triples(removals)?;
+        self.add_string_triples(additions)
+    }
+
+    /// Add every triple of `other` to this builder
+    ///
+    /// Unlike `apply_delta`, this takes all triples `other` knows
+    /// about, not just the changes of its own layer. Triples are
+    /// converted to strings, so `other` may have been built with
+    /// entirely different dictionaries, like a base layer built
+    /// separately from the one this builder is for.
+    pub fn union_layer(&self, other: &StoreLayer) -> Result<(), StoreError> {
+        let triples = self.store.install(|| {
+            other
+                .triples()
+                .par_bridge()
+                .map(|t| {
+                    other.id_triple_to_string(&t).ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "triple in layer could not be converted to strings",
+                        )
                     })
-                };
-            },
-            || {
-                other.triples().par_bridge().for_each(|t| {
-                    other.id_triple_to_string(&t).map(|st| {
-                        if let Some(this) = self.parent() {
-                            if !this.string_triple_exists(&st) {
-                                self.add_string_triple(st).unwrap()
-                            }
-                        } else {
-                            self.add_string_triple(st).unwrap()
-                        };
-                    });
                 })
-            },
-        );
+                .collect::<io::Result<Vec<_>>>()
+        })?;
 
-        Ok(())
+        self.add_string_triples(triples)
+    }
+
+    /// Apply the changes `theirs` made relative to `base` to this builder
+    ///
+    /// This builder is expected to sit on top of our side of the
+    /// merge. Changes are grouped by subject and predicate. When both
+    /// sides changed the same subject and predicate, ended up with
+    /// different triples, and either side removed something, none of
+    /// their changes to that pair are applied and a conflict is
+    /// reported instead. Additions made by both sides never conflict.
+    pub fn merge(&self, base: &StoreLayer, theirs: &StoreLayer) -> Result<MergeResult, StoreError> {
+        let mut changes: BTreeMap<(String, String), Vec<DiffEntry>> = BTreeMap::new();
+        for entry in base.diff(theirs)? {
+            let triple = match &entry {
+                DiffEntry::Added(triple) | DiffEntry::Removed(triple) => triple,
+            };
+            changes
+                .entry((triple.subject.clone(), triple.predicate.clone()))
+                .or_default()
+                .push(entry);
+        }
+
+        let ours = self.parent();
+        let mut result = MergeResult::default();
+        for ((subject, predicate), their_changes) in changes {
+            let in_base = triples_with_subject_predicate(Some(base), &subject, &predicate);
+            let in_ours = triples_with_subject_predicate(ours.as_deref(), &subject, &predicate);
+
+            if in_ours != in_base {
+                let mut in_theirs = in_base.clone();
+                for entry in their_changes.iter() {
+                    match entry {
+                        DiffEntry::Added(triple) => in_theirs.insert(triple.clone()),
+                        DiffEntry::Removed(triple) => in_theirs.remove(triple),
+                    };
+                }
+
+                let ours_removed = in_base.iter().any(|t| !in_ours.contains(t));
+                let theirs_removed = their_changes
+                    .iter()
+                    .any(|e| matches!(e, DiffEntry::Removed(_)));
+                if in_ours != in_theirs && (ours_removed || theirs_removed) {
+                    let mut our_changes: Vec<_> = in_base
+                        .difference(&in_ours)
+                        .cloned()
+                        .map(DiffEntry::Removed)
+                        .chain(in_ours.difference(&in_base).cloned().map(DiffEntry::Added))
+                        .collect();
+                    let mut their_changes = their_changes;
+                    our_changes.sort();
+                    their_changes.sort();
+
+                    result.conflicts.push(MergeConflict {
+                        subject,
+                        predicate,
+                        ours: our_changes,
+                        theirs: their_changes,
+                    });
+                    continue;
+                }
+            }
+
+            for entry in their_changes {
+                match entry {
+                    DiffEntry::Added(triple) if !in_ours.contains(&triple) => {
+                        self.add_string_triple(triple.clone())?;
+                        result.added.push(triple);
+                    }
+                    DiffEntry::Removed(triple) if in_ours.contains(&triple) => {
+                        self.remove_string_triple(triple.clone())?;
+                        result.removed.push(triple);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+// all triples in the given layer with the given subject and predicate
+fn triples_with_subject_predicate<L: Layer + ?Sized>(
+    layer: Option<&L>,
+    subject: &str,
+    predicate: &str,
+) -> BTreeSet<StringTriple> {
+    let layer = match layer {
+        Some(layer) => layer,
+        None => return BTreeSet::new(),
+    };
+
+    match (layer.subject_id(subject), layer.predicate_id(predicate)) {
+        (Some(subject), Some(predicate)) => layer
+            .triples_sp(subject, predicate)
+            .filter_map(|t| layer.id_triple_to_string(&t))
+            .collect(),
+        _ => BTreeSet::new(),
+    }
+}
+
+impl Drop for StoreLayerBuilder {
+    fn drop(&mut self) {
+        self.store.unregister_builder(self.name);
+    }
+}
+
+/// The set of changes needed to get from one layer to another
+///
+/// A delta is a plain value, independent of any store. It can be
+/// inspected, kept around, and applied to a builder later using
+/// `StoreLayerBuilder::apply_struct_delta`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Delta {
+    /// triples that need to be added
+    pub additions: Vec<StringTriple>,
+    /// triples that need to be removed
+    pub removals: Vec<StringTriple>,
+}
+
+/// A single change between two layers, as returned by `StoreLayer::diff`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DiffEntry {
+    /// a triple that only exists in the other layer
+    Added(StringTriple),
+    /// a triple that only exists in this layer
+    Removed(StringTriple),
+}
+
+/// The outcome of `StoreLayerBuilder::merge`
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MergeResult {
+    /// triples that were added to the builder
+    pub added: Vec<StringTriple>,
+    /// triples that were removed from the builder
+    pub removed: Vec<StringTriple>,
+    /// changes that could not be applied because both sides disagree
+    pub conflicts: Vec<MergeConflict>,
+}
+
+impl MergeResult {
+    /// Returns true if any changes could not be merged
+    pub fn has_conflicts(&self) -> bool {
+        !self.conflicts.is_empty()
+    }
+}
+
+/// A subject and predicate that both sides of a merge changed in incompatible ways
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeConflict {
+    pub subject: String,
+    pub predicate: String,
+    /// our changes to this subject and predicate, relative to the base
+    pub ours: Vec<DiffEntry>,
+    /// their changes to this subject and predicate, relative to the base
+    pub theirs: Vec<DiffEntry>,
+}
+
+impl Delta {
+    /// Returns true if this delta contains no changes
+    pub fn is_empty(&self) -> bool {
+        self.additions.is_empty() && self.removals.is_empty()
     }
 }
 
@@ -211,17 +938,24 @@ impl StoreLayerBuilder {
 #[derive(Clone)]
 pub struct StoreLayer {
     // TODO this Arc here is not great
-    layer: Arc<dyn Layer>,
+    layer: Arc<InternalLayer>,
     store: Store,
 }
 
 impl StoreLayer {
-    fn wrap(layer: Arc<dyn Layer>, store: Store) -> Self {
+    fn wrap(layer: Arc<InternalLayer>, store: Store) -> Self {
         StoreLayer { layer, store }
     }
 
+    /// The name of this layer as a 40 character hex string
+    ///
+    /// This can be turned back into a name with `string_to_name`.
+    pub fn name_string(&self) -> String {
+        name_to_string(self.name())
+    }
+
     /// Create a layer builder based on this layer
-    pub async fn open_write(&self) -> io::Result<StoreLayerBuilder> {
+    pub async fn open_write(&self) -> Result<StoreLayerBuilder, StoreError> {
         let layer = self
             .store
             .layer_store
@@ -231,56 +965,454 @@ impl StoreLayer {
         Ok(StoreLayerBuilder::wrap(layer, self.store.clone()))
     }
 
-    pub async fn parent(&self) -> io::Result<Option<StoreLayer>> {
+    pub async fn parent(&self) -> Result<Option<StoreLayer>, StoreError> {
         let parent_name = self.layer.parent_name();
 
         match parent_name {
             None => Ok(None),
             Some(parent_name) => match self.store.layer_store.get_layer(parent_name).await? {
-                None => Err(io::Error::new(
-                    io::ErrorKind::NotFound,
-                    "parent layer not found even though it should exist",
-                )),
+                None => Err(StoreError::LayerNotFound(parent_name)),
                 Some(layer) => Ok(Some(StoreLayer::wrap(layer, self.store.clone()))),
             },
         }
     }
 
-    pub async fn squash(&self) -> io::Result<StoreLayer> {
-        // TODO check if we already committed
-        let new_builder = self.store.create_base_layer().await?;
-        self.triples().par_bridge().for_each(|t| {
-            let st = self.id_triple_to_string(&t).unwrap();
-            new_builder.add_string_triple(st).unwrap()
-        });
+    /// Create a new layer on top of the given ancestor, containing all changes between that ancestor and this layer
+    ///
+    /// This compacts the layers between the ancestor and this layer
+    /// into a single layer, while leaving the history up to and
+    /// including the ancestor intact. An error is returned if the
+    /// given layer is not an ancestor of this layer.
+    pub async fn squash_upto(&self, ancestor: &StoreLayer) -> Result<StoreLayer, StoreError> {
+        if !self
+            .store
+            .layer_store
+            .layer_is_ancestor_of(self.name(), ancestor.name())
+            .await?
+        {
+            return Err(StoreError::NotAnAncestor(ancestor.name(), self.name()));
+        }
 
-        new_builder.commit().await
-    }
-}
+        let delta = ancestor.delta_to(self)?;
+        let builder = ancestor.open_write().await?;
+        builder
+            .add_string_triples_async(delta.additions.iter().cloned())
+            .await?;
+        builder
+            .remove_string_triples_async(delta.removals.iter().cloned())
+            .await?;
 
-impl Layer for StoreLayer {
-    fn name(&self) -> [u32; 5] {
-        self.layer.name()
+        builder.commit().await
     }
 
-    fn parent_name(&self) -> Option<[u32; 5]> {
-        self.layer.parent_name()
+    /// Calculate a hash over the triples in this layer
+    ///
+    /// The hash only depends on the triples this layer contains, and
+    /// not on how the layer was built. Two layers with the same
+    /// triples will always have the same content hash, even if one
+    /// is a squashed version of the other.
+    pub fn content_hash(&self) -> Result<[u8; 32], StoreError> {
+        let triples: Option<Vec<StringTriple>> = self.store.install(|| {
+            let mut triples: Vec<_> = self
+                .triples()
+                .par_bridge()
+                .map(|t| self.id_triple_to_string(&t))
+                .collect::<Option<_>>()?;
+            triples.par_sort_unstable();
+
+            Some(triples)
+        });
+        let triples = triples.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "triple could not be resolved")
+        })?;
+
+        let mut hasher = Sha256::new();
+        hash_string_triples(&mut hasher, &triples);
+
+        let mut result = [0; 32];
+        result.copy_from_slice(&hasher.finalize());
+        Ok(result)
     }
 
-    fn node_and_value_count(&self) -> usize {
-        self.layer.node_and_value_count()
+    /// Returns a stream over all triples in this layer and its parents
+    ///
+    /// Unlike `triples`, this yields to the runtime after every chunk
+    /// of triples, so other tasks get to run during long iterations.
+    pub fn triples_stream(&self) -> impl Stream<Item = IdTriple> + Send {
+        triple_stream(self.triples())
     }
 
-    fn predicate_count(&self) -> usize {
-        self.layer.predicate_count()
+    /// Returns a stream over all triples with the given subject, like `triples_stream`
+    pub fn triples_s_stream(&self, subject: u64) -> impl Stream<Item = IdTriple> + Send {
+        triple_stream(self.triples_s(subject))
     }
 
-    fn subject_id(&self, subject: &str) -> Option<u64> {
-        self.layer.subject_id(subject)
+    /// Returns a stream over all triples with the given predicate, like `triples_stream`
+    pub fn triples_p_stream(&self, predicate: u64) -> impl Stream<Item = IdTriple> + Send {
+        triple_stream(self.triples_p(predicate))
     }
 
-    fn predicate_id(&self, predicate: &str) -> Option<u64> {
-        self.layer.predicate_id(predicate)
+    /// Returns a stream over all triples with the given object, like `triples_stream`
+    pub fn triples_o_stream(&self, object: u64) -> impl Stream<Item = IdTriple> + Send {
+        triple_stream(self.triples_o(object))
+    }
+
+    /// Send all triples in this layer and its parents to the given channel as string triples
+    ///
+    /// This waits for room in the channel whenever it is full, and
+    /// yields to the runtime between chunks of triples like
+    /// `triples_stream`. Returns an error if the receiver is dropped
+    /// before all triples were sent.
+    pub async fn send_triples(
+        &self,
+        mut tx: tokio::sync::mpsc::Sender<StringTriple>,
+    ) -> Result<(), StoreError> {
+        let mut triples = Box::pin(self.triples_stream());
+        while let Some(triple) = triples.next().await {
+            let triple = self.id_triple_to_string(&triple).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "triple could not be resolved")
+            })?;
+            tx.send(triple).await.map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "receiver of the triples was dropped",
+                )
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Like `predicate_histogram`, but with the predicates as strings
+    pub fn predicate_histogram_strings(&self) -> Vec<(String, usize)> {
+        self.predicate_histogram()
+            .into_iter()
+            .map(|(predicate, count)| {
+                (
+                    self.id_predicate(predicate)
+                        .expect("predicate in layer should have a string"),
+                    count,
+                )
+            })
+            .collect()
+    }
+
+    /// Returns the triples added and removed by this layer itself, as strings
+    ///
+    /// Unlike a diff against the parent layer, this is exactly what
+    /// was recorded when this layer was built. Removals refer to
+    /// triples of the parent layers, whose ids this layer can resolve
+    /// as well. This collects the same changes `change_stream`
+    /// yields.
+    pub fn layer_changes(&self) -> Result<(Vec<StringTriple>, Vec<StringTriple>), StoreError> {
+        let mut additions = Vec::new();
+        let mut removals = Vec::new();
+        for change in self.changes_resolved_through(self.clone()) {
+            match change? {
+                DiffEntry::Added(triple) => additions.push(triple),
+                DiffEntry::Removed(triple) => removals.push(triple),
+            }
+        }
+
+        Ok((additions, removals))
+    }
+
+    /// Returns the changes this layer itself made, as strings, ordered by triple id
+    ///
+    /// Like `layer_changes`, this only walks the additions and
+    /// removals recorded in this layer, but it does so lazily. The
+    /// removed triples are resolved through the parent layer, as that
+    /// is where they were added. A triple that can't be resolved is
+    /// yielded as an error.
+    ///
+    /// Changes are reported as the `DiffEntry` that `diff` against
+    /// the parent would return for them, rather than as a type of
+    /// their own, so both can be consumed the same way.
+    pub async fn change_stream(
+        &self,
+    ) -> Result<Box<dyn Iterator<Item = Result<DiffEntry, StoreError>> + Send>, StoreError> {
+        let removal_layer = self.parent().await?.unwrap_or_else(|| self.clone());
+
+        Ok(self.changes_resolved_through(removal_layer))
+    }
+
+    // the changes of this layer in triple id order, with removals resolved through the given layer
+    fn changes_resolved_through(
+        &self,
+        removal_layer: StoreLayer,
+    ) -> Box<dyn Iterator<Item = Result<DiffEntry, StoreError>> + Send> {
+        let this = self.clone();
+        let mut additions = self.triple_additions().peekable();
+        let mut removals = self.triple_removals().peekable();
+        let resolve = |layer: &StoreLayer, triple: IdTriple| {
+            layer.id_triple_to_string(&triple).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "triple could not be resolved").into()
+            })
+        };
+
+        Box::new(std::iter::from_fn(move || {
+            let take_addition = match (additions.peek(), removals.peek()) {
+                (None, None) => return None,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (Some(addition), Some(removal)) => addition < removal,
+            };
+
+            Some(if take_addition {
+                resolve(&this, additions.next().unwrap()).map(DiffEntry::Added)
+            } else {
+                resolve(&removal_layer, removals.next().unwrap()).map(DiffEntry::Removed)
+            })
+        }))
+    }
+
+    /// Returns true if this layer contains exactly the same triples as the other layer
+    pub fn content_eq(&self, other: &StoreLayer) -> Result<bool, StoreError> {
+        if self.triple_count() != other.triple_count() {
+            return Ok(false);
+        }
+
+        Ok(self.content_hash()? == other.content_hash()?)
+    }
+
+    /// Write all triples in this layer to the given writer in N-Triples format
+    ///
+    /// Nodes are written as IRIs and values as literals. Typed values
+    /// and language strings keep their datatype or language tag.
+    pub fn export_ntriples<W: Write>(&self, writer: W) -> Result<(), StoreError> {
+        let mut writer = io::BufWriter::new(writer);
+        for triple in self.id_triples_to_strings(self.triples()) {
+            write_ntriple(&mut writer, &triple)?;
+        }
+
+        Ok(writer.flush()?)
+    }
+
+    /// Pin this layer, keeping it and its ancestors safe from garbage collection until the returned guard is dropped
+    ///
+    /// A layer can be pinned several times. It only becomes eligible
+    /// for garbage collection again once all its pins are dropped.
+    pub fn pin(&self) -> Result<LayerPin, StoreError> {
+        Ok(LayerPin::new(self.name(), self.store.layer_store.clone()))
+    }
+
+    /// Returns this layer, followed by each of its ancestors down to the base layer
+    ///
+    /// If the ancestry contains a cycle, which should never happen in
+    /// a well-formed store, an error is returned.
+    pub async fn ancestry(&self) -> Result<Vec<StoreLayer>, StoreError> {
+        let mut seen = HashSet::new();
+        seen.insert(self.name());
+        let mut result = vec![self.clone()];
+
+        while let Some(parent) = result.last().unwrap().parent().await? {
+            if !seen.insert(parent.name()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "cycle detected in layer ancestry",
+                )
+                .into());
+            }
+
+            result.push(parent);
+        }
+
+        Ok(result)
+    }
+
+    /// Returns an estimate of the memory in bytes taken up by this layer and all its ancestors
+    pub async fn total_heap_size(&self) -> Result<usize, StoreError> {
+        Ok(self
+            .ancestry()
+            .await?
+            .iter()
+            .map(|layer| layer.heap_size())
+            .sum())
+    }
+
+    /// Calculate the changes needed to go from this layer to the other layer
+    ///
+    /// This compares the materialized triples of both layers, so the
+    /// two layers do not need to share any ancestry.
+    pub fn delta_to(&self, other: &StoreLayer) -> Result<Delta, StoreError> {
+        let mut additions = Vec::new();
+        for t in other.triples() {
+            let st = other.id_triple_to_string(&t).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "triple could not be resolved")
+            })?;
+            if !self.string_triple_exists(&st) {
+                additions.push(st);
+            }
+        }
+
+        let mut removals = Vec::new();
+        for t in self.triples() {
+            let st = self.id_triple_to_string(&t).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "triple could not be resolved")
+            })?;
+            if !other.string_triple_exists(&st) {
+                removals.push(st);
+            }
+        }
+
+        additions.sort();
+        removals.sort();
+
+        Ok(Delta {
+            additions,
+            removals,
+        })
+    }
+
+    /// Lazily calculate the changes needed to go from this layer to the other layer
+    ///
+    /// First all removals are returned, followed by all additions.
+    /// Unlike `delta_to`, nothing is collected up front, so this can
+    /// be used to stream very large diffs. The two layers may use
+    /// different ids for the same strings, so triples are compared by
+    /// their string form, by looking each triple of one layer up in
+    /// the other.
+    pub fn diff(
+        &self,
+        other: &StoreLayer,
+    ) -> Result<impl Iterator<Item = DiffEntry> + Send, StoreError> {
+        let (this, that) = (self.clone(), other.clone());
+        let removals = self.triples().filter_map(move |t| {
+            this.id_triple_to_string(&t)
+                .filter(|st| !that.string_triple_exists(st))
+                .map(DiffEntry::Removed)
+        });
+
+        let (this, that) = (self.clone(), other.clone());
+        let additions = other.triples().filter_map(move |t| {
+            that.id_triple_to_string(&t)
+                .filter(|st| !this.string_triple_exists(st))
+                .map(DiffEntry::Added)
+        });
+
+        Ok(removals.chain(additions))
+    }
+
+    pub async fn squash(&self) -> Result<StoreLayer, StoreError> {
+        self.squash_cancellable(&CancellationToken::new()).await
+    }
+
+    /// Create a new base layer consisting of all triples in this layer, stopping early if the token is cancelled
+    ///
+    /// The token is checked between chunks of triples. When
+    /// cancelled, the new layer is never committed.
+    pub async fn squash_cancellable(
+        &self,
+        cancel: &CancellationToken,
+    ) -> Result<StoreLayer, StoreError> {
+        // TODO check if we already committed
+        let new_builder = self.store.create_base_layer().await?;
+
+        // Resolving the triples is where the work is, so do that in
+        // parallel, then hand the result to the builder in one go
+        // rather than contending for the builder lock on every triple.
+        // Each chunk is resolved in order so that repeated subjects
+        // and predicates only get looked up once.
+        let id_triples: Vec<IdTriple> = self.triples().collect();
+        let triples: Vec<StringTriple> = self
+            .store
+            .install(|| {
+                id_triples
+                    .par_chunks(SQUASH_CHUNK_SIZE)
+                    .map(|chunk| {
+                        if cancel.is_cancelled() {
+                            return Vec::new();
+                        }
+                        self.id_triples_to_strings(chunk.iter().cloned())
+                            .collect::<Vec<_>>()
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .into_iter()
+            .flatten()
+            .collect();
+        cancel.check()?;
+        if triples.len() != id_triples.len() {
+            return Err(
+                io::Error::new(io::ErrorKind::InvalidData, "triple could not be resolved").into(),
+            );
+        }
+        new_builder.add_string_triples_async(triples).await?;
+        cancel.check()?;
+
+        new_builder.commit().await
+    }
+
+    /// Create a new base layer with the triples of this layer, and only the terms those triples use
+    ///
+    /// Removing triples leaves their terms behind in the
+    /// dictionaries. The new layer's dictionaries are rebuilt from
+    /// its triples, so its ids differ from this layer's, but its
+    /// content hash is the same. Returns the new layer along with the
+    /// number of nodes, values and predicates that were dropped.
+    pub async fn compact_dictionary(&self) -> Result<(StoreLayer, usize), StoreError> {
+        let compacted = self.squash().await?;
+        let term_count =
+            |layer: &StoreLayer| layer.node_and_value_count() + layer.predicate_count();
+        let dropped = term_count(self) - term_count(&compacted);
+
+        Ok((compacted, dropped))
+    }
+
+    /// Create a rollup of this layer
+    ///
+    /// Like `squash`, this materializes all triples into a single
+    /// layer. But where `squash` returns a new base layer with a new
+    /// name and no history, a rollup is stored alongside the original
+    /// layer. The layer store serves this layer from the rollup from
+    /// then on, so the returned layer keeps this layer's name and
+    /// parent. Queries against it no longer need to go through the
+    /// whole layer stack. Its layer-level additions and removals are
+    /// still those of this layer.
+    pub async fn rollup(&self) -> Result<StoreLayer, StoreError> {
+        let layer = self
+            .store
+            .layer_store
+            .get_layer(self.name())
+            .await?
+            .ok_or(StoreError::LayerNotFound(self.name()))?;
+
+        if !layer.is_rollup() {
+            self.store.layer_store.rollup(layer).await?;
+        }
+
+        self.store
+            .get_layer_from_id(self.name())
+            .await?
+            .ok_or(StoreError::LayerNotFound(self.name()))
+    }
+}
+
+// Layer-level additions and removals are answered by the changes
+// layer, so that a rolled up layer still reports its own changes.
+impl Layer for StoreLayer {
+    fn name(&self) -> [u32; 5] {
+        self.layer.name()
+    }
+
+    fn parent_name(&self) -> Option<[u32; 5]> {
+        self.layer.parent_name()
+    }
+
+    fn node_and_value_count(&self) -> usize {
+        self.layer.node_and_value_count()
+    }
+
+    fn predicate_count(&self) -> usize {
+        self.layer.predicate_count()
+    }
+
+    fn subject_id(&self, subject: &str) -> Option<u64> {
+        self.layer.subject_id(subject)
+    }
+
+    fn predicate_id(&self, predicate: &str) -> Option<u64> {
+        self.layer.predicate_id(predicate)
     }
 
     fn object_node_id(&self, object: &str) -> Option<u64> {
@@ -291,6 +1423,10 @@ impl Layer for StoreLayer {
         self.layer.object_value_id(object)
     }
 
+    fn object_id_of(&self, object: &ObjectType) -> Option<u64> {
+        self.layer.object_id_of(object)
+    }
+
     fn id_subject(&self, id: u64) -> Option<String> {
         self.layer.id_subject(id)
     }
@@ -303,16 +1439,32 @@ impl Layer for StoreLayer {
         self.layer.id_object(id)
     }
 
+    fn object_type_of_id(&self, id: u64) -> Option<ObjectKind> {
+        self.layer.object_type_of_id(id)
+    }
+
+    fn subjects_with_prefix(&self, prefix: &str) -> Box<dyn Iterator<Item = (u64, String)>> {
+        self.layer.subjects_with_prefix(prefix)
+    }
+
+    fn predicates_with_prefix(&self, prefix: &str) -> Box<dyn Iterator<Item = (u64, String)>> {
+        self.layer.predicates_with_prefix(prefix)
+    }
+
+    fn object_nodes_with_prefix(&self, prefix: &str) -> Box<dyn Iterator<Item = (u64, String)>> {
+        self.layer.object_nodes_with_prefix(prefix)
+    }
+
     fn subjects(&self) -> Box<dyn Iterator<Item = Box<dyn SubjectLookup>>> {
         self.layer.subjects()
     }
 
     fn subject_additions(&self) -> Box<dyn Iterator<Item = Box<dyn LayerSubjectLookup>>> {
-        self.layer.subject_additions()
+        self.layer.changes_layer().subject_additions()
     }
 
     fn subject_removals(&self) -> Box<dyn Iterator<Item = Box<dyn LayerSubjectLookup>>> {
-        self.layer.subject_removals()
+        self.layer.changes_layer().subject_removals()
     }
 
     fn lookup_subject(&self, subject: u64) -> Option<Box<dyn SubjectLookup>> {
@@ -320,11 +1472,11 @@ impl Layer for StoreLayer {
     }
 
     fn lookup_subject_addition(&self, subject: u64) -> Option<Box<dyn LayerSubjectLookup>> {
-        self.layer.lookup_subject_addition(subject)
+        self.layer.changes_layer().lookup_subject_addition(subject)
     }
 
     fn lookup_subject_removal(&self, subject: u64) -> Option<Box<dyn LayerSubjectLookup>> {
-        self.layer.lookup_subject_removal(subject)
+        self.layer.changes_layer().lookup_subject_removal(subject)
     }
 
     fn objects(&self) -> Box<dyn Iterator<Item = Box<dyn ObjectLookup>>> {
@@ -332,11 +1484,11 @@ impl Layer for StoreLayer {
     }
 
     fn object_additions(&self) -> Box<dyn Iterator<Item = Box<dyn LayerObjectLookup>>> {
-        self.layer.object_additions()
+        self.layer.changes_layer().object_additions()
     }
 
     fn object_removals(&self) -> Box<dyn Iterator<Item = Box<dyn LayerObjectLookup>>> {
-        self.layer.object_removals()
+        self.layer.changes_layer().object_removals()
     }
 
     fn lookup_object(&self, object: u64) -> Option<Box<dyn ObjectLookup>> {
@@ -344,11 +1496,11 @@ impl Layer for StoreLayer {
     }
 
     fn lookup_object_addition(&self, object: u64) -> Option<Box<dyn LayerObjectLookup>> {
-        self.layer.lookup_object_addition(object)
+        self.layer.changes_layer().lookup_object_addition(object)
     }
 
     fn lookup_object_removal(&self, object: u64) -> Option<Box<dyn LayerObjectLookup>> {
-        self.layer.lookup_object_removal(object)
+        self.layer.changes_layer().lookup_object_removal(object)
     }
 
     fn predicates(&self) -> Box<dyn Iterator<Item = Box<dyn PredicateLookup>>> {
@@ -356,11 +1508,11 @@ impl Layer for StoreLayer {
     }
 
     fn predicate_additions(&self) -> Box<dyn Iterator<Item = Box<dyn LayerPredicateLookup>>> {
-        self.layer.predicate_additions()
+        self.layer.changes_layer().predicate_additions()
     }
 
     fn predicate_removals(&self) -> Box<dyn Iterator<Item = Box<dyn LayerPredicateLookup>>> {
-        self.layer.predicate_removals()
+        self.layer.changes_layer().predicate_removals()
     }
 
     fn lookup_predicate(&self, predicate: u64) -> Option<Box<dyn PredicateLookup>> {
@@ -368,24 +1520,35 @@ impl Layer for StoreLayer {
     }
 
     fn lookup_predicate_addition(&self, predicate: u64) -> Option<Box<dyn LayerPredicateLookup>> {
-        self.layer.lookup_predicate_addition(predicate)
+        self.layer
+            .changes_layer()
+            .lookup_predicate_addition(predicate)
     }
 
     fn lookup_predicate_removal(&self, predicate: u64) -> Option<Box<dyn LayerPredicateLookup>> {
-        self.layer.lookup_predicate_removal(predicate)
+        self.layer
+            .changes_layer()
+            .lookup_predicate_removal(predicate)
     }
 
     fn triple_exists(&self, subject: u64, predicate: u64, object: u64) -> bool {
         self.layer.triple_exists(subject, predicate, object)
     }
 
+    fn triples_exist(&self, triples: &[(u64, u64, u64)]) -> Vec<bool> {
+        self.layer.triples_exist(triples)
+    }
+
     fn triple_addition_exists(&self, subject: u64, predicate: u64, object: u64) -> bool {
         self.layer
+            .changes_layer()
             .triple_addition_exists(subject, predicate, object)
     }
 
     fn triple_removal_exists(&self, subject: u64, predicate: u64, object: u64) -> bool {
-        self.layer.triple_removal_exists(subject, predicate, object)
+        self.layer
+            .changes_layer()
+            .triple_removal_exists(subject, predicate, object)
     }
 
     fn triples(&self) -> Box<dyn Iterator<Item = IdTriple> + Send> {
@@ -393,11 +1556,11 @@ impl Layer for StoreLayer {
     }
 
     fn triple_additions(&self) -> Box<dyn Iterator<Item = IdTriple> + Send> {
-        self.layer.triple_additions()
+        self.layer.changes_layer().triple_additions()
     }
 
     fn triple_removals(&self) -> Box<dyn Iterator<Item = IdTriple> + Send> {
-        self.layer.triple_removals()
+        self.layer.changes_layer().triple_removals()
     }
 
     fn triples_s(&self, subject: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
@@ -405,11 +1568,11 @@ impl Layer for StoreLayer {
     }
 
     fn triple_additions_s(&self, subject: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
-        self.layer.triple_additions_s(subject)
+        self.layer.changes_layer().triple_additions_s(subject)
     }
 
     fn triple_removals_s(&self, subject: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
-        self.layer.triple_removals_s(subject)
+        self.layer.changes_layer().triple_removals_s(subject)
     }
 
     fn triples_sp(
@@ -425,7 +1588,9 @@ impl Layer for StoreLayer {
         subject: u64,
         predicate: u64,
     ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
-        self.layer.triple_additions_sp(subject, predicate)
+        self.layer
+            .changes_layer()
+            .triple_additions_sp(subject, predicate)
     }
 
     fn triple_removals_sp(
@@ -433,43 +1598,149 @@ impl Layer for StoreLayer {
         subject: u64,
         predicate: u64,
     ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
-        self.layer.triple_removals_sp(subject, predicate)
+        self.layer
+            .changes_layer()
+            .triple_removals_sp(subject, predicate)
     }
 
     fn triples_p(&self, predicate: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
         self.layer.triples_p(predicate)
     }
 
+    fn predicate_occurrence_count(&self, predicate: u64) -> usize {
+        self.layer.predicate_occurrence_count(predicate)
+    }
+
+    fn nth_triple_with_predicate(&self, predicate: u64, n: usize) -> Option<IdTriple> {
+        self.layer.nth_triple_with_predicate(predicate, n)
+    }
+
+    fn predicate_histogram(&self) -> Vec<(u64, usize)> {
+        self.layer.predicate_histogram()
+    }
+
+    fn string_triples(&self) -> Box<dyn Iterator<Item = StringTriple> + Send> {
+        self.layer.string_triples()
+    }
+
+    fn iter_subject_dict(&self) -> Box<dyn Iterator<Item = (u64, String)> + Send> {
+        self.layer.iter_subject_dict()
+    }
+
+    fn distinct_subjects(&self) -> usize {
+        self.layer.distinct_subjects()
+    }
+
+    fn distinct_predicates(&self) -> usize {
+        self.layer.distinct_predicates()
+    }
+
+    fn distinct_objects(&self) -> usize {
+        self.layer.distinct_objects()
+    }
+
+    fn iter_predicate_dict(&self) -> Box<dyn Iterator<Item = (u64, String)> + Send> {
+        self.layer.iter_predicate_dict()
+    }
+
+    fn iter_value_dict(&self) -> Box<dyn Iterator<Item = (u64, ObjectType)> + Send> {
+        self.layer.iter_value_dict()
+    }
+
     fn triple_additions_p(&self, predicate: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
-        self.layer.triple_additions_p(predicate)
+        self.layer.changes_layer().triple_additions_p(predicate)
     }
 
     fn triple_removals_p(&self, predicate: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
-        self.layer.triple_removals_p(predicate)
+        self.layer.changes_layer().triple_removals_p(predicate)
     }
 
     fn triples_o(&self, object: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
         self.layer.triples_o(object)
     }
 
+    fn triples_by_object(&self) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer.triples_by_object()
+    }
+
+    fn out_neighbors(&self, subject: u64) -> Box<dyn Iterator<Item = (u64, u64)> + Send> {
+        self.layer.out_neighbors(subject)
+    }
+
+    fn in_neighbors(&self, object: u64) -> Box<dyn Iterator<Item = (u64, u64)> + Send> {
+        self.layer.in_neighbors(object)
+    }
+
+    fn triples_page(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer.triples_page(offset, limit)
+    }
+
+    fn triples_with_object_value_range(
+        &self,
+        low: &str,
+        high: &str,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer.triples_with_object_value_range(low, high)
+    }
+
+    fn triples_with_numeric_object_range(
+        &self,
+        low: Numeric,
+        high: Numeric,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer.triples_with_numeric_object_range(low, high)
+    }
+
     fn triple_additions_o(&self, object: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
-        self.layer.triple_additions_o(object)
+        self.layer.changes_layer().triple_additions_o(object)
     }
 
     fn triple_removals_o(&self, object: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
-        self.layer.triple_removals_o(object)
+        self.layer.changes_layer().triple_removals_o(object)
+    }
+
+    fn triple_additions_po(
+        &self,
+        predicate: u64,
+        object: u64,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer
+            .changes_layer()
+            .triple_additions_po(predicate, object)
+    }
+
+    fn triple_removals_po(
+        &self,
+        predicate: u64,
+        object: u64,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer
+            .changes_layer()
+            .triple_removals_po(predicate, object)
+    }
+
+    fn triples_po(&self, predicate: u64, object: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer.triples_po(predicate, object)
     }
 
     fn clone_boxed(&self) -> Box<dyn Layer> {
         Box::new(self.clone())
     }
 
+    fn heap_size(&self) -> usize {
+        self.layer.heap_size()
+    }
+
     fn triple_layer_addition_count(&self) -> usize {
-        self.layer.triple_layer_addition_count()
+        self.layer.changes_layer().triple_layer_addition_count()
     }
 
     fn triple_layer_removal_count(&self) -> usize {
-        self.layer.triple_layer_removal_count()
+        self.layer.changes_layer().triple_layer_removal_count()
     }
 
     fn triple_addition_count(&self) -> usize {
@@ -485,63 +1756,553 @@ impl Layer for StoreLayer {
     }
 }
 
-/// A named graph in terminus-store.
+/// A guard that keeps a layer safe from garbage collection
 ///
-/// Named graphs in terminus-store are basically just a label pointing
-/// to a layer. Opening a read transaction to a named graph is just
-/// getting hold of the layer it points at, as layers are
-/// read-only. Writing to a named graph is just making it point to a
-/// new layer.
-pub struct NamedGraph {
-    label: String,
-    store: Store,
+/// The layer, along with its ancestors, stays pinned in the layer
+/// store until the guard is dropped. Retrieve one through
+/// `StoreLayer::pin`.
+pub struct LayerPin {
+    name: [u32; 5],
+    layer_store: Arc<dyn LayerStore>,
 }
 
-impl NamedGraph {
-    fn new(label: String, store: Store) -> Self {
-        NamedGraph { label, store }
+impl LayerPin {
+    fn new(name: [u32; 5], layer_store: Arc<dyn LayerStore>) -> Self {
+        layer_store.pin_layer(name);
+        LayerPin { name, layer_store }
     }
 
-    pub fn name(&self) -> &str {
-        &self.label
+    /// The name of the pinned layer
+    pub fn name(&self) -> [u32; 5] {
+        self.name
     }
+}
 
-    /// Returns the layer this database points at
-    pub async fn head(&self) -> io::Result<Option<StoreLayer>> {
-        let new_label = self.store.label_store.get_label(&self.label).await?;
+impl Drop for LayerPin {
+    fn drop(&mut self) {
+        self.layer_store.unpin_layer(self.name);
+    }
+}
 
-        match new_label {
-            None => Err(io::Error::new(
-                io::ErrorKind::NotFound,
-                "database not found",
-            )),
-            Some(new_label) => match new_label.layer {
-                None => Ok(None),
-                Some(layer) => {
-                    let layer = self.store.layer_store.get_layer(layer).await?;
-                    match layer {
-                        None => Err(io::Error::new(
-                            io::ErrorKind::NotFound,
-                            "layer not found even though it is pointed at by a label",
-                        )),
-                        Some(layer) => Ok(Some(StoreLayer::wrap(layer, self.store.clone()))),
-                    }
-                }
-            },
-        }
+/// A read-only view on a layer that keeps it safe from garbage collection
+///
+/// A snapshot pins its layer in the layer store for as long as it
+/// exists, so the layer stays available even if the label it was
+/// retrieved through moves on and garbage gets collected. It can be
+/// queried like any other layer, and derefs to the `StoreLayer` it pins.
+pub struct Snapshot {
+    layer: StoreLayer,
+    _pin: LayerPin,
+}
+
+impl Snapshot {
+    fn new(layer: StoreLayer) -> Self {
+        let pin = LayerPin::new(layer.name(), layer.store.layer_store.clone());
+        Snapshot { layer, _pin: pin }
     }
 
-    /// Set the database label to the given layer if it is a valid ancestor, returning false otherwise
-    pub async fn set_head(&self, layer: &StoreLayer) -> io::Result<bool> {
-        let layer_name = layer.name();
-        let label = self.store.label_store.get_label(&self.label).await?;
-        if label.is_none() {
-            return Err(io::Error::new(io::ErrorKind::NotFound, "label not found"));
-        }
-        let label = label.unwrap();
+    /// The layer this snapshot is pinning
+    pub fn layer(&self) -> &StoreLayer {
+        &self.layer
+    }
+}
+
+impl Clone for Snapshot {
+    fn clone(&self) -> Self {
+        Snapshot::new(self.layer.clone())
+    }
+}
+
+impl std::ops::Deref for Snapshot {
+    type Target = StoreLayer;
+
+    fn deref(&self) -> &StoreLayer {
+        &self.layer
+    }
+}
+
+impl Layer for Snapshot {
+    fn name(&self) -> [u32; 5] {
+        self.layer.name()
+    }
+
+    fn parent_name(&self) -> Option<[u32; 5]> {
+        self.layer.parent_name()
+    }
+
+    fn node_and_value_count(&self) -> usize {
+        self.layer.node_and_value_count()
+    }
+
+    fn predicate_count(&self) -> usize {
+        self.layer.predicate_count()
+    }
+
+    fn subject_id(&self, subject: &str) -> Option<u64> {
+        self.layer.subject_id(subject)
+    }
+
+    fn predicate_id(&self, predicate: &str) -> Option<u64> {
+        self.layer.predicate_id(predicate)
+    }
+
+    fn object_node_id(&self, object: &str) -> Option<u64> {
+        self.layer.object_node_id(object)
+    }
+
+    fn object_value_id(&self, object: &str) -> Option<u64> {
+        self.layer.object_value_id(object)
+    }
+
+    fn object_id_of(&self, object: &ObjectType) -> Option<u64> {
+        self.layer.object_id_of(object)
+    }
+
+    fn id_subject(&self, id: u64) -> Option<String> {
+        self.layer.id_subject(id)
+    }
+
+    fn id_predicate(&self, id: u64) -> Option<String> {
+        self.layer.id_predicate(id)
+    }
+
+    fn id_object(&self, id: u64) -> Option<ObjectType> {
+        self.layer.id_object(id)
+    }
+
+    fn object_type_of_id(&self, id: u64) -> Option<ObjectKind> {
+        self.layer.object_type_of_id(id)
+    }
+
+    fn subjects_with_prefix(&self, prefix: &str) -> Box<dyn Iterator<Item = (u64, String)>> {
+        self.layer.subjects_with_prefix(prefix)
+    }
+
+    fn predicates_with_prefix(&self, prefix: &str) -> Box<dyn Iterator<Item = (u64, String)>> {
+        self.layer.predicates_with_prefix(prefix)
+    }
+
+    fn object_nodes_with_prefix(&self, prefix: &str) -> Box<dyn Iterator<Item = (u64, String)>> {
+        self.layer.object_nodes_with_prefix(prefix)
+    }
+
+    fn subjects(&self) -> Box<dyn Iterator<Item = Box<dyn SubjectLookup>>> {
+        self.layer.subjects()
+    }
+
+    fn subject_additions(&self) -> Box<dyn Iterator<Item = Box<dyn LayerSubjectLookup>>> {
+        self.layer.subject_additions()
+    }
+
+    fn subject_removals(&self) -> Box<dyn Iterator<Item = Box<dyn LayerSubjectLookup>>> {
+        self.layer.subject_removals()
+    }
+
+    fn lookup_subject(&self, subject: u64) -> Option<Box<dyn SubjectLookup>> {
+        self.layer.lookup_subject(subject)
+    }
+
+    fn lookup_subject_addition(&self, subject: u64) -> Option<Box<dyn LayerSubjectLookup>> {
+        self.layer.lookup_subject_addition(subject)
+    }
+
+    fn lookup_subject_removal(&self, subject: u64) -> Option<Box<dyn LayerSubjectLookup>> {
+        self.layer.lookup_subject_removal(subject)
+    }
+
+    fn objects(&self) -> Box<dyn Iterator<Item = Box<dyn ObjectLookup>>> {
+        self.layer.objects()
+    }
+
+    fn object_additions(&self) -> Box<dyn Iterator<Item = Box<dyn LayerObjectLookup>>> {
+        self.layer.object_additions()
+    }
+
+    fn object_removals(&self) -> Box<dyn Iterator<Item = Box<dyn LayerObjectLookup>>> {
+        self.layer.object_removals()
+    }
+
+    fn lookup_object(&self, object: u64) -> Option<Box<dyn ObjectLookup>> {
+        self.layer.lookup_object(object)
+    }
+
+    fn lookup_object_addition(&self, object: u64) -> Option<Box<dyn LayerObjectLookup>> {
+        self.layer.lookup_object_addition(object)
+    }
+
+    fn lookup_object_removal(&self, object: u64) -> Option<Box<dyn LayerObjectLookup>> {
+        self.layer.lookup_object_removal(object)
+    }
+
+    fn predicates(&self) -> Box<dyn Iterator<Item = Box<dyn PredicateLookup>>> {
+        self.layer.predicates()
+    }
+
+    fn predicate_additions(&self) -> Box<dyn Iterator<Item = Box<dyn LayerPredicateLookup>>> {
+        self.layer.predicate_additions()
+    }
+
+    fn predicate_removals(&self) -> Box<dyn Iterator<Item = Box<dyn LayerPredicateLookup>>> {
+        self.layer.predicate_removals()
+    }
+
+    fn lookup_predicate(&self, predicate: u64) -> Option<Box<dyn PredicateLookup>> {
+        self.layer.lookup_predicate(predicate)
+    }
+
+    fn lookup_predicate_addition(&self, predicate: u64) -> Option<Box<dyn LayerPredicateLookup>> {
+        self.layer.lookup_predicate_addition(predicate)
+    }
+
+    fn lookup_predicate_removal(&self, predicate: u64) -> Option<Box<dyn LayerPredicateLookup>> {
+        self.layer.lookup_predicate_removal(predicate)
+    }
+
+    fn triple_exists(&self, subject: u64, predicate: u64, object: u64) -> bool {
+        self.layer.triple_exists(subject, predicate, object)
+    }
+
+    fn triples_exist(&self, triples: &[(u64, u64, u64)]) -> Vec<bool> {
+        self.layer.triples_exist(triples)
+    }
+
+    fn triple_addition_exists(&self, subject: u64, predicate: u64, object: u64) -> bool {
+        self.layer
+            .triple_addition_exists(subject, predicate, object)
+    }
+
+    fn triple_removal_exists(&self, subject: u64, predicate: u64, object: u64) -> bool {
+        self.layer.triple_removal_exists(subject, predicate, object)
+    }
+
+    fn triples(&self) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer.triples()
+    }
+
+    fn triple_additions(&self) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer.triple_additions()
+    }
+
+    fn triple_removals(&self) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer.triple_removals()
+    }
+
+    fn triples_s(&self, subject: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer.triples_s(subject)
+    }
+
+    fn triple_additions_s(&self, subject: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer.triple_additions_s(subject)
+    }
+
+    fn triple_removals_s(&self, subject: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer.triple_removals_s(subject)
+    }
+
+    fn triples_sp(
+        &self,
+        subject: u64,
+        predicate: u64,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer.triples_sp(subject, predicate)
+    }
+
+    fn triple_additions_sp(
+        &self,
+        subject: u64,
+        predicate: u64,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer.triple_additions_sp(subject, predicate)
+    }
+
+    fn triple_removals_sp(
+        &self,
+        subject: u64,
+        predicate: u64,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer.triple_removals_sp(subject, predicate)
+    }
+
+    fn triples_p(&self, predicate: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer.triples_p(predicate)
+    }
+
+    fn predicate_occurrence_count(&self, predicate: u64) -> usize {
+        self.layer.predicate_occurrence_count(predicate)
+    }
+
+    fn nth_triple_with_predicate(&self, predicate: u64, n: usize) -> Option<IdTriple> {
+        self.layer.nth_triple_with_predicate(predicate, n)
+    }
+
+    fn predicate_histogram(&self) -> Vec<(u64, usize)> {
+        self.layer.predicate_histogram()
+    }
+
+    fn string_triples(&self) -> Box<dyn Iterator<Item = StringTriple> + Send> {
+        self.layer.string_triples()
+    }
+
+    fn string_triple_additions(&self) -> Box<dyn Iterator<Item = StringTriple> + Send> {
+        self.layer.string_triple_additions()
+    }
+
+    fn string_triple_removals(&self) -> Box<dyn Iterator<Item = StringTriple> + Send> {
+        self.layer.string_triple_removals()
+    }
+
+    fn iter_subject_dict(&self) -> Box<dyn Iterator<Item = (u64, String)> + Send> {
+        self.layer.iter_subject_dict()
+    }
+
+    fn distinct_subjects(&self) -> usize {
+        self.layer.distinct_subjects()
+    }
+
+    fn distinct_predicates(&self) -> usize {
+        self.layer.distinct_predicates()
+    }
+
+    fn distinct_objects(&self) -> usize {
+        self.layer.distinct_objects()
+    }
+
+    fn iter_predicate_dict(&self) -> Box<dyn Iterator<Item = (u64, String)> + Send> {
+        self.layer.iter_predicate_dict()
+    }
+
+    fn iter_value_dict(&self) -> Box<dyn Iterator<Item = (u64, ObjectType)> + Send> {
+        self.layer.iter_value_dict()
+    }
+
+    fn triple_additions_p(&self, predicate: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer.triple_additions_p(predicate)
+    }
+
+    fn triple_removals_p(&self, predicate: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer.triple_removals_p(predicate)
+    }
+
+    fn triples_o(&self, object: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer.triples_o(object)
+    }
+
+    fn triples_by_object(&self) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer.triples_by_object()
+    }
+
+    fn out_neighbors(&self, subject: u64) -> Box<dyn Iterator<Item = (u64, u64)> + Send> {
+        self.layer.out_neighbors(subject)
+    }
+
+    fn in_neighbors(&self, object: u64) -> Box<dyn Iterator<Item = (u64, u64)> + Send> {
+        self.layer.in_neighbors(object)
+    }
+
+    fn triples_page(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer.triples_page(offset, limit)
+    }
+
+    fn triples_with_object_value_range(
+        &self,
+        low: &str,
+        high: &str,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer.triples_with_object_value_range(low, high)
+    }
+
+    fn triples_with_numeric_object_range(
+        &self,
+        low: Numeric,
+        high: Numeric,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer.triples_with_numeric_object_range(low, high)
+    }
+
+    fn triple_additions_o(&self, object: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer.triple_additions_o(object)
+    }
+
+    fn triple_removals_o(&self, object: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer.triple_removals_o(object)
+    }
+
+    fn triple_additions_po(
+        &self,
+        predicate: u64,
+        object: u64,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer.triple_additions_po(predicate, object)
+    }
+
+    fn triple_removals_po(
+        &self,
+        predicate: u64,
+        object: u64,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer.triple_removals_po(predicate, object)
+    }
+
+    fn triples_po(&self, predicate: u64, object: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer.triples_po(predicate, object)
+    }
+
+    fn clone_boxed(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+
+    fn heap_size(&self) -> usize {
+        self.layer.heap_size()
+    }
+
+    fn triple_layer_addition_count(&self) -> usize {
+        self.layer.triple_layer_addition_count()
+    }
+
+    fn triple_layer_removal_count(&self) -> usize {
+        self.layer.triple_layer_removal_count()
+    }
+
+    fn triple_addition_count(&self) -> usize {
+        self.layer.triple_addition_count()
+    }
+
+    fn triple_removal_count(&self) -> usize {
+        self.layer.triple_removal_count()
+    }
+
+    fn all_counts(&self) -> LayerCounts {
+        self.layer.all_counts()
+    }
+}
+
+/// A named graph in terminus-store.
+///
+/// Named graphs in terminus-store are basically just a label pointing
+/// to a layer. Opening a read transaction to a named graph is just
+/// getting hold of the layer it points at, as layers are
+/// read-only. Writing to a named graph is just making it point to a
+/// new layer.
+pub struct NamedGraph {
+    label: String,
+    store: Store,
+}
+
+impl NamedGraph {
+    fn new(label: String, store: Store) -> Self {
+        NamedGraph { label, store }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.label
+    }
+
+    /// Returns the layer this database points at
+    pub async fn head(&self) -> Result<Option<StoreLayer>, StoreError> {
+        let (layer, _version) = self.head_with_version().await?;
+        Ok(layer)
+    }
+
+    /// Returns the layer this database points at, together with the version of the label
+    ///
+    /// The version is incremented every time the head of this
+    /// database changes.
+    pub async fn head_with_version(&self) -> Result<(Option<StoreLayer>, u64), StoreError> {
+        let new_label = self.store.label_store.get_label(&self.label).await?;
+
+        match new_label {
+            None => Err(StoreError::LabelNotFound(self.label.clone())),
+            Some(new_label) => match new_label.layer {
+                None => Ok((None, new_label.version)),
+                Some(layer_name) => {
+                    let layer = self.store.layer_store.get_layer(layer_name).await?;
+                    match layer {
+                        None => Err(StoreError::LayerNotFound(layer_name)),
+                        Some(layer) => Ok((
+                            Some(StoreLayer::wrap(layer, self.store.clone())),
+                            new_label.version,
+                        )),
+                    }
+                }
+            },
+        }
+    }
+
+    /// Returns a snapshot of the layer this database points at
+    ///
+    /// Unlike the layer returned by `head`, the snapshot's layer will
+    /// not be garbage collected until the snapshot is dropped.
+    pub async fn snapshot(&self) -> Result<Option<Snapshot>, StoreError> {
+        loop {
+            let label = self
+                .store
+                .label_store
+                .get_label(&self.label)
+                .await?
+                .ok_or_else(|| StoreError::LabelNotFound(self.label.clone()))?;
+            let layer_name = match label.layer {
+                None => return Ok(None),
+                Some(layer_name) => layer_name,
+            };
+
+            // The layer is pinned before it is loaded, so garbage
+            // collection can't remove it from here on. It may have
+            // removed it already if the label moved on after we read
+            // it, in which case the label is read again.
+            let pin = LayerPin::new(layer_name, self.store.layer_store.clone());
+            let result = self.store.layer_store.get_layer(layer_name).await;
+            if let Ok(Some(layer)) = result {
+                return Ok(Some(Snapshot {
+                    layer: StoreLayer::wrap(layer, self.store.clone()),
+                    _pin: pin,
+                }));
+            }
+
+            if self.head_version().await? == Some(label.version) {
+                return Err(match result {
+                    Err(e) => e.into(),
+                    _ => StoreError::LayerNotFound(layer_name),
+                });
+            }
+        }
+    }
+
+    /// Returns the counts of the current head layer, or None if there is no head
+    ///
+    /// Besides the node, predicate and value counts, this includes
+    /// how many layers deep the head is, which is a good indication of
+    /// whether the database could use a squash or rollup.
+    pub async fn counts(&self) -> Result<Option<LayerCounts>, StoreError> {
+        Ok(self.head().await?.map(|layer| layer.all_counts()))
+    }
+
+    /// Returns the current version of the label for this database, or None if the label no longer exists
+    pub async fn head_version(&self) -> Result<Option<u64>, StoreError> {
+        let label = self.store.label_store.get_label(&self.label).await?;
+        Ok(label.map(|label| label.version))
+    }
+
+    /// Set the database label to the given layer if it is a valid ancestor, returning false otherwise
+    ///
+    /// If the label already points at the given layer, this returns
+    /// true without writing the label, so its version stays the same.
+    /// This also returns false if the label was changed by someone
+    /// else while this was checking the ancestry.
+    pub async fn set_head(&self, layer: &StoreLayer) -> Result<bool, StoreError> {
+        let layer_name = layer.name();
+        let label = self.store.label_store.get_label(&self.label).await?;
+        if label.is_none() {
+            return Err(StoreError::LabelNotFound(self.label.clone()));
+        }
+        let label = label.unwrap();
 
         let set_is_ok = match label.layer {
             None => true,
+            Some(retrieved_layer_name) if retrieved_layer_name == layer_name => return Ok(true),
             Some(retrieved_layer_name) => {
                 self.store
                     .layer_store
@@ -550,300 +2311,5182 @@ impl NamedGraph {
             }
         };
 
-        if set_is_ok {
-            self.store.label_store.set_label(&label, layer_name).await?;
-        }
+        Ok(set_is_ok && self.set_label_and_fire(&label, layer_name).await?)
+    }
+
+    /// Roll the database label back to the given layer, if it is an ancestor of the current head
+    ///
+    /// Returns false, leaving the head as is, if the layer is not an
+    /// ancestor of the head or if there is no head at all. Rolling
+    /// back to the current head itself is allowed, and does nothing.
+    /// This also returns false if the label was changed by someone
+    /// else in the meantime.
+    pub async fn rollback_to(&self, layer: &StoreLayer) -> Result<bool, StoreError> {
+        let layer_name = layer.name();
+        let label = self
+            .store
+            .label_store
+            .get_label(&self.label)
+            .await?
+            .ok_or_else(|| StoreError::LabelNotFound(self.label.clone()))?;
+
+        let rollback_is_ok = match label.layer {
+            None => false,
+            Some(retrieved_layer_name) => {
+                self.store
+                    .layer_store
+                    .layer_is_ancestor_of(retrieved_layer_name, layer_name)
+                    .await?
+            }
+        };
+
+        Ok(rollback_is_ok && self.set_label_and_fire(&label, layer_name).await?)
+    }
+
+    /// Set the database label to the given layer, but only if it currently points at `expected`
+    ///
+    /// Unlike `set_head`, this does not check ancestry. Instead, it
+    /// ensures that nobody else moved the head since it was last
+    /// read, which allows for optimistic concurrency: read the head,
+    /// build a new layer on top of it, then try to set it, and retry
+    /// if this returns false.
+    pub async fn compare_and_set_head(
+        &self,
+        expected: Option<&StoreLayer>,
+        new: &StoreLayer,
+    ) -> Result<bool, StoreError> {
+        let set = self
+            .store
+            .label_store
+            .cas_label(&self.label, expected.map(|l| l.name()), Some(new.name()))
+            .await?;
+        if set {
+            self.fire_head_event(new.name());
+        }
+
+        Ok(set)
+    }
+
+    /// Set the database label to the given layer, even if it is not a valid ancestor
+    ///
+    /// Returns false if the label was changed by someone else between
+    /// reading and writing it, in which case the head is left alone.
+    pub async fn force_set_head(&self, layer: &StoreLayer) -> Result<bool, StoreError> {
+        let layer_name = layer.name();
+        let label = self.store.label_store.get_label(&self.label).await?;
+        match label {
+            None => Err(StoreError::LabelNotFound(self.label.clone())),
+            Some(label) => Ok(self.set_label_and_fire(&label, layer_name).await?),
+        }
+    }
+
+    /// Point the database at a new, empty base layer, returning that layer
+    ///
+    /// Unlike clearing the label, this leaves the database with a
+    /// head, so it can be queried like any other. The layers it
+    /// pointed at before are not removed, as other databases may
+    /// share them, until `collect_garbage` finds them to be
+    /// unreachable.
+    ///
+    /// If the label was changed by someone else while resetting, it
+    /// is left alone and `HeadChanged` is returned.
+    pub async fn reset_to_empty(&self) -> Result<StoreLayer, StoreError> {
+        let builder = self.store.create_base_layer().await?;
+        let layer = builder.commit().await?;
+        if self.force_set_head(&layer).await? {
+            Ok(layer)
+        } else {
+            Err(StoreError::HeadChanged(self.label.clone()))
+        }
+    }
+
+    /// Squash the head of this database, and point the database at the squashed layer
+    ///
+    /// The squashed layer has no ancestry in common with the old
+    /// head, but as it has the same content, the label is moved
+    /// anyway. If the head was changed by someone else while
+    /// squashing, the label is left alone and `HeadChanged` is
+    /// returned.
+    ///
+    /// Both the head and the squashed layer are pinned until the
+    /// label is moved, so garbage collection can't remove them in
+    /// the meantime.
+    pub async fn squash_head(&self) -> Result<StoreLayer, StoreError> {
+        let head = self.head().await?.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "database has no head to squash",
+            )
+        })?;
+        let _head_pin = head.pin()?;
+        let squashed = head.squash().await?;
+        let _squashed_pin = squashed.pin()?;
+
+        if self.compare_and_set_head(Some(&head), &squashed).await? {
+            Ok(squashed)
+        } else {
+            Err(StoreError::HeadChanged(self.label.clone()))
+        }
+    }
+
+    /// The amount of bytes the head of this database and all its ancestors take up in storage
+    ///
+    /// Layers that are shared between ancestors, such as rollups, are
+    /// only counted once. A database without a head has size 0.
+    pub async fn disk_size(&self) -> Result<u64, StoreError> {
+        let label = self
+            .store
+            .label_store
+            .get_label(&self.label)
+            .await?
+            .ok_or_else(|| StoreError::LabelNotFound(self.label.clone()))?;
+
+        let reachable = self
+            .store
+            .reachable_from(label.layer.into_iter().collect())
+            .await?;
+
+        Ok(self.store.disk_size_of(reachable).await?)
+    }
+
+    // write the label, firing a head event only if the write didn't lose a race
+    async fn set_label_and_fire(&self, label: &Label, layer: [u32; 5]) -> io::Result<bool> {
+        let set = self
+            .store
+            .label_store
+            .set_label(label, layer)
+            .await?
+            .is_some();
+        if set {
+            self.fire_head_event(layer);
+        }
+
+        Ok(set)
+    }
+
+    fn fire_head_event(&self, layer: [u32; 5]) {
+        self.store.fire_commit_event(LayerCommitEvent::Head {
+            label: self.label.clone(),
+            layer,
+        });
+    }
+}
+
+impl Store {
+    /// Create a new store from the given label and layer store
+    pub fn new<Labels: 'static + LabelStore, Layers: 'static + LayerStore>(
+        label_store: Labels,
+        layer_store: Layers,
+    ) -> Store {
+        Store {
+            label_store: Arc::new(label_store),
+            layer_store: Arc::new(layer_store),
+            open_builders: Arc::new(Mutex::new(HashMap::new())),
+            commit_callbacks: Arc::new(RwLock::new(Vec::new())),
+            content_addressed_names: false,
+            thread_pool: None,
+        }
+    }
+
+    /// Name newly committed layers after their content instead of randomly
+    ///
+    /// The name of a layer committed through the returned store is
+    /// derived from a SHA-256 hash over the triples it adds and
+    /// removes and the name of its parent. Making the same changes
+    /// on top of the same parent twice therefore results in the same
+    /// layer, and the second commit just returns the existing layer
+    /// without writing anything.
+    ///
+    /// Until a builder is committed, its `name` is a temporary one.
+    /// The builder has to be able to list its `net_changes`, which
+    /// the builders of the stores in this crate all do.
+    pub fn with_content_addressed_names(mut self) -> Store {
+        self.content_addressed_names = true;
+        self
+    }
+
+    /// Do the parallel work of this store on the given thread pool instead of the global one
+    ///
+    /// This covers resolving triples in `squash`, `apply_delta`,
+    /// `apply_diff` and `union_layer`. The resolved triples are
+    /// still handed to the builder under its lock, so writes to a
+    /// single builder are serialized regardless of the pool size.
+    pub fn with_thread_pool(mut self, pool: Arc<rayon::ThreadPool>) -> Store {
+        self.thread_pool = Some(pool);
+        self
+    }
+
+    // run parallel work on this store's thread pool, or the global one if it has none
+    fn install<R: Send, F: FnOnce() -> R + Send>(&self, op: F) -> R {
+        match &self.thread_pool {
+            Some(pool) => pool.install(op),
+            None => op(),
+        }
+    }
+
+    /// Register a callback to be called after every layer commit and head change in this store
+    ///
+    /// Callbacks are called on the task doing the commit, after it
+    /// succeeded, and without any locks held, so they are free to use
+    /// the store themselves.
+    pub fn on_commit(&self, f: CommitCallback) {
+        self.commit_callbacks
+            .write()
+            .expect("rwlock write should always succeed")
+            .push(f);
+    }
+
+    fn fire_commit_event(&self, event: LayerCommitEvent) {
+        let callbacks = self
+            .commit_callbacks
+            .read()
+            .expect("rwlock read should always succeed")
+            .clone();
+        for callback in callbacks {
+            callback(event.clone());
+        }
+    }
+
+    // Commit the changes of the given builder under a name derived
+    // from its parent and those changes, returning that name. If a
+    // layer with that name already exists, possibly written by a
+    // concurrent commit of the same changes, nothing is written. The
+    // builder's own layer is removed either way.
+    async fn commit_content_addressed(
+        &self,
+        builder: Box<dyn LayerBuilder>,
+    ) -> io::Result<[u32; 5]> {
+        let name = builder.name();
+        let parent = builder.parent().map(|p| p.name());
+        let (additions, removals) = builder.net_changes().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                "layer builder can't list its changes for a content-addressed name",
+            )
+        })?;
+        drop(builder);
+
+        let content_name = content_address(parent, &additions, &removals);
+        if !self.layer_store.layer_exists(content_name).await? {
+            let named_builder = match parent {
+                None => self.layer_store.create_named_base_layer(content_name).await,
+                Some(parent) => {
+                    self.layer_store
+                        .create_named_child_layer(parent, content_name)
+                        .await
+                }
+            };
+            match named_builder {
+                Ok(mut named_builder) => {
+                    named_builder.add_sorted_string_triples(additions);
+                    for triple in removals {
+                        named_builder.remove_string_triple(triple);
+                    }
+                    named_builder.commit_boxed().await?;
+                    self.layer_store.write_checksums(content_name).await?;
+                }
+                // someone else is committing the same changes
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.layer_store.delete_layer(name).await?;
+
+        Ok(content_name)
+    }
+
+    fn register_builder(&self, name: [u32; 5], parent: Option<[u32; 5]>) {
+        self.open_builders
+            .lock()
+            .expect("mutex lock should always succeed")
+            .insert(name, parent);
+    }
+
+    fn unregister_builder(&self, name: [u32; 5]) {
+        self.open_builders
+            .lock()
+            .expect("mutex lock should always succeed")
+            .remove(&name);
+    }
+
+    /// Create a new database with the given name
+    ///
+    /// If the database already exists, this will return an error
+    pub async fn create(&self, label: &str) -> Result<NamedGraph, StoreError> {
+        let label = self.label_store.create_label(label).await?;
+        Ok(NamedGraph::new(label.name, self.clone()))
+    }
+
+    /// Open an existing database with the given name, or None if it does not exist
+    pub async fn open(&self, label: &str) -> Result<Option<NamedGraph>, StoreError> {
+        let label = self.label_store.get_label(label).await?;
+        Ok(label.map(|label| NamedGraph::new(label.name, self.clone())))
+    }
+
+    /// Open the database with the given name, creating it if it does not exist yet
+    ///
+    /// Creation of the underlying label is atomic, so when several
+    /// callers race to create the same database, exactly one of them
+    /// creates it and all others end up opening the database that was
+    /// just created.
+    pub async fn create_or_open(&self, label: &str) -> Result<NamedGraph, StoreError> {
+        if let Some(graph) = self.open(label).await? {
+            return Ok(graph);
+        }
+
+        match self.create(label).await {
+            Ok(graph) => Ok(graph),
+            Err(e)
+                if e.kind() == io::ErrorKind::InvalidInput
+                    || e.kind() == io::ErrorKind::AlreadyExists =>
+            {
+                // somebody else created the database in the meantime
+                self.open(label).await?.ok_or(e)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Create a database with the given name, or reset it to have no head if it exists already
+    ///
+    /// Resetting only clears the label. The layers it pointed at are
+    /// left alone, as other databases may share them, until
+    /// `collect_garbage` finds them to be unreachable.
+    pub async fn create_overwrite(&self, label: &str) -> Result<NamedGraph, StoreError> {
+        loop {
+            let graph = self.create_or_open(label).await?;
+            let current = self
+                .label_store
+                .get_label(label)
+                .await?
+                .ok_or_else(|| StoreError::LabelNotFound(label.to_string()))?;
+            if current.layer.is_none() || self.label_store.clear_label(&current).await?.is_some() {
+                return Ok(graph);
+            }
+
+            // the label was changed in the meantime, so try again
+        }
+    }
+
+    /// Returns the names of all databases in this store, in sorted order
+    pub async fn labels(&self) -> Result<Vec<String>, StoreError> {
+        let labels = self.label_store.labels().await?;
+        Ok(labels.into_iter().map(|label| label.name).collect())
+    }
+
+    /// Set the heads of several databases at once, returning false if any of them could not be set
+    ///
+    /// Like `NamedGraph::set_head`, every new head has to be a
+    /// descendant of the current head of its database. All updates
+    /// are checked before any of them is applied, so if one of them is
+    /// invalid, none of the heads change. If a label turns out to have
+    /// been changed concurrently while applying the updates, or if
+    /// writing a label fails, the labels that were already written
+    /// are rolled back.
+    pub async fn set_heads(
+        &self,
+        updates: &[(&NamedGraph, &StoreLayer)],
+    ) -> Result<bool, StoreError> {
+        let mut checked = Vec::with_capacity(updates.len());
+        for (graph, layer) in updates {
+            if checked
+                .iter()
+                .any(|(label, _): &(Label, [u32; 5])| label.name == graph.label)
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "database appears more than once in set_heads",
+                )
+                .into());
+            }
+
+            let label = self
+                .label_store
+                .get_label(&graph.label)
+                .await?
+                .ok_or_else(|| StoreError::LabelNotFound(graph.label.clone()))?;
+            let set_is_ok = match label.layer {
+                None => true,
+                Some(retrieved_layer_name) => {
+                    self.layer_store
+                        .layer_is_ancestor_of(layer.name(), retrieved_layer_name)
+                        .await?
+                }
+            };
+            if !set_is_ok {
+                return Ok(false);
+            }
+
+            checked.push((label, layer.name()));
+        }
+
+        let mut written: Vec<(&Label, Label)> = Vec::with_capacity(checked.len());
+        for (label, layer) in checked.iter() {
+            match self.label_store.set_label(label, *layer).await {
+                Ok(Some(new_label)) => written.push((label, new_label)),
+                result => {
+                    // roll back in reverse order, on a best-effort basis
+                    for (old_label, new_label) in written.into_iter().rev() {
+                        let _ = self
+                            .label_store
+                            .set_label_option(&new_label, old_label.layer)
+                            .await;
+                    }
+
+                    return result.map(|_| false).map_err(StoreError::from);
+                }
+            }
+        }
+
+        for (graph, layer) in updates {
+            self.fire_commit_event(LayerCommitEvent::Head {
+                label: graph.label.clone(),
+                layer: layer.name(),
+            });
+        }
+
+        Ok(true)
+    }
+
+    pub async fn get_layer_from_id(
+        &self,
+        layer: [u32; 5],
+    ) -> Result<Option<StoreLayer>, StoreError> {
+        let layer = self.layer_store.get_layer(layer).await?;
+        Ok(layer.map(|layer| StoreLayer::wrap(layer, self.clone())))
+    }
+
+    /// Get a layer by its id, reading its ancestors concurrently
+    ///
+    /// The files of the entire ancestry are read up front, a few
+    /// layers at a time, so the first query doesn't wait on a long
+    /// chain of sequential loads. For the memory store this is the
+    /// same as `get_layer_from_id`.
+    pub async fn get_layer_from_id_eager(
+        &self,
+        layer: [u32; 5],
+    ) -> Result<Option<StoreLayer>, StoreError> {
+        let layer = self
+            .layer_store
+            .get_layer_eager(layer, EAGER_LOAD_CONCURRENCY)
+            .await?;
+        Ok(layer.map(|layer| StoreLayer::wrap(layer, self.clone())))
+    }
+
+    // the given layers along with their ancestors and rollups
+    async fn reachable_from(&self, roots: Vec<[u32; 5]>) -> io::Result<HashSet<[u32; 5]>> {
+        let mut reachable = HashSet::new();
+        for root in roots {
+            let mut current = Some(root);
+            while let Some(name) = current {
+                if !reachable.insert(name) {
+                    // we already walked the rest of this chain
+                    break;
+                }
+
+                if let Some(rollup) = self.layer_store.get_rollup(name).await? {
+                    reachable.insert(rollup);
+                }
+
+                current = match self.layer_store.get_layer(name).await? {
+                    Some(layer) => layer.parent_name(),
+                    None => None,
+                };
+            }
+        }
+
+        Ok(reachable)
+    }
+
+    /// Returns the layers that are not reachable from any database label, sorted by name
+    ///
+    /// Layers that are being built, and the layers they are being
+    /// built on top of, are considered reachable, as are pinned
+    /// layers and the rollups of reachable layers.
+    async fn unreachable_layers(&self) -> io::Result<Vec<[u32; 5]>> {
+        let mut roots: Vec<[u32; 5]> = self
+            .label_store
+            .labels()
+            .await?
+            .into_iter()
+            .filter_map(|label| label.layer)
+            .collect();
+
+        let mut building = Vec::new();
+        {
+            let open_builders = self
+                .open_builders
+                .lock()
+                .expect("mutex lock should always succeed");
+            for (name, parent) in open_builders.iter() {
+                building.push(*name);
+                if let Some(parent) = parent {
+                    roots.push(*parent);
+                }
+            }
+        }
+        roots.extend(self.layer_store.pinned_layers());
+
+        let mut reachable = self.reachable_from(roots).await?;
+        // builder names are only marked after the walk, as a
+        // committed builder may also be the start of a chain.
+        reachable.extend(building);
+
+        let mut result: Vec<_> = self
+            .layer_store
+            .layers()
+            .await?
+            .into_iter()
+            .filter(|name| !reachable.contains(name))
+            .collect();
+        result.sort();
+
+        Ok(result)
+    }
+
+    /// Returns the layers that would be removed by `collect_garbage`, without removing them
+    pub async fn collect_garbage_dry_run(&self) -> Result<Vec<[u32; 5]>, StoreError> {
+        Ok(self.unreachable_layers().await?)
+    }
+
+    /// Remove all layers that are not reachable from any database label
+    ///
+    /// A layer is reachable if it is the head of a label, or an
+    /// ancestor of such a head. Layers that are currently being built
+    /// through a `StoreLayerBuilder` are also kept, as are their
+    /// parents, and so are layers pinned by a `Snapshot` and their
+    /// ancestors. Layers that are committed but not yet attached to a
+    /// label will be removed, so this should not be run while another
+    /// thread is in between committing a layer and setting a head.
+    ///
+    /// Returns the names of the removed layers.
+    pub async fn collect_garbage(&self) -> Result<Vec<[u32; 5]>, StoreError> {
+        self.collect_garbage_cancellable(&CancellationToken::new())
+            .await
+    }
+
+    /// Remove all layers that are not reachable from any database label, stopping early if the token is cancelled
+    ///
+    /// The token is checked before every layer removal. Layers that
+    /// were removed before the cancellation stay removed.
+    pub async fn collect_garbage_cancellable(
+        &self,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<[u32; 5]>, StoreError> {
+        let garbage = self.unreachable_layers().await?;
+        for name in garbage.iter() {
+            cancel.check()?;
+            self.layer_store.delete_layer(*name).await?;
+        }
+
+        Ok(garbage)
+    }
+
+    /// Returns the nearest layer that is an ancestor of both given layers, or None if they share no ancestry
+    ///
+    /// A layer counts as its own ancestor, so if one of the layers is
+    /// an ancestor of the other, that layer is returned.
+    pub async fn common_ancestor(
+        &self,
+        a: [u32; 5],
+        b: [u32; 5],
+    ) -> Result<Option<[u32; 5]>, StoreError> {
+        let ancestors_a: HashSet<_> = self.layer_stack_names(a).await?.into_iter().collect();
+        Ok(self
+            .layer_stack_names(b)
+            .await?
+            .into_iter()
+            .find(|name| ancestors_a.contains(name)))
+    }
+
+    // the given layer followed by all its ancestors
+    async fn layer_stack_names(&self, name: [u32; 5]) -> io::Result<Vec<[u32; 5]>> {
+        let mut result = Vec::new();
+        let mut current = Some(name);
+        while let Some(name) = current {
+            let layer = self
+                .layer_store
+                .get_layer(name)
+                .await?
+                .ok_or(StoreError::LayerNotFound(name))?;
+            result.push(name);
+            current = layer.parent_name();
+        }
+
+        Ok(result)
+    }
+
+    /// Copy a layer, along with any of its ancestors that are missing, from another store into this one
+    ///
+    /// The copied layers keep their names, so ids referring to them
+    /// stay valid. Layers that already exist in this store are not
+    /// copied again. As the layers are rebuilt from their triples,
+    /// this works between any two kinds of store, unlike the pack
+    /// export and import.
+    pub async fn copy_layer_from(
+        &self,
+        source: &Store,
+        id: [u32; 5],
+    ) -> Result<StoreLayer, StoreError> {
+        let mut missing = Vec::new();
+        let mut current = Some(id);
+        while let Some(name) = current {
+            if self.layer_store.get_layer(name).await?.is_some() {
+                break;
+            }
+
+            let layer = source
+                .get_layer_from_id(name)
+                .await?
+                .ok_or(StoreError::LayerNotFound(name))?;
+            current = layer.parent_name();
+            missing.push(layer);
+        }
+
+        for layer in missing.into_iter().rev() {
+            let builder = match layer.parent_name() {
+                None => {
+                    self.layer_store
+                        .create_named_base_layer(layer.name())
+                        .await?
+                }
+                Some(parent) => {
+                    self.layer_store
+                        .create_named_child_layer(parent, layer.name())
+                        .await?
+                }
+            };
+            let builder = StoreLayerBuilder::wrap_named(builder, self.clone());
+
+            // The string iterators are not Send, so they are only
+            // created once the builder lock is held, rather than passed
+            // to `add_string_triples_async` across an await.
+            builder
+                .with_builder_async(|b| {
+                    for triple in layer.id_triples_to_strings(layer.triple_additions()) {
+                        b.add_string_triple(triple);
+                    }
+                    for triple in layer.id_triples_to_strings(layer.triple_removals()) {
+                        b.remove_string_triple(triple);
+                    }
+                })
+                .await?;
+
+            builder.commit_no_load().await?;
+        }
+
+        self.get_layer_from_id(id)
+            .await?
+            .ok_or(StoreError::LayerNotFound(id))
+    }
+
+    /// Check the files of the given layer against the checksums recorded when it was committed
+    ///
+    /// Layers for which no checksums were recorded, like layers
+    /// written by older versions or layers in a memory store, can't be
+    /// checked and are reported as `Unverifiable`.
+    pub async fn verify_layer(&self, layer: [u32; 5]) -> Result<LayerVerification, StoreError> {
+        Ok(self.layer_store.verify_layer(layer).await?)
+    }
+
+    /// Verify all layers reachable from a database label
+    ///
+    /// Returns the names of the layers found to be corrupt, sorted by
+    /// name. Layers without recorded checksums can't be checked and
+    /// are not included.
+    pub async fn verify_all(&self) -> Result<Vec<[u32; 5]>, StoreError> {
+        let roots = self
+            .label_store
+            .labels()
+            .await?
+            .into_iter()
+            .filter_map(|label| label.layer)
+            .collect();
+
+        let mut corrupt = Vec::new();
+        for name in self.reachable_from(roots).await? {
+            if self.verify_layer(name).await? == LayerVerification::Corrupt {
+                corrupt.push(name);
+            }
+        }
+        corrupt.sort();
+
+        Ok(corrupt)
+    }
+
+    /// The amount of bytes the given layer takes up in storage, not counting its ancestors
+    ///
+    /// For the memory store, this is an estimate of the memory used
+    /// by the layer instead.
+    pub async fn layer_disk_size(&self, layer: [u32; 5]) -> Result<u64, StoreError> {
+        Ok(self.layer_store.layer_disk_size(layer).await?)
+    }
+
+    /// The amount of bytes taken up in storage by all layers reachable from a database label
+    ///
+    /// Layers shared between databases are only counted once.
+    pub async fn total_disk_size(&self) -> Result<u64, StoreError> {
+        let roots = self
+            .label_store
+            .labels()
+            .await?
+            .into_iter()
+            .filter_map(|label| label.layer)
+            .collect();
+
+        Ok(self.disk_size_of(self.reachable_from(roots).await?).await?)
+    }
+
+    async fn disk_size_of(&self, layers: HashSet<[u32; 5]>) -> io::Result<u64> {
+        let mut size = 0;
+        for layer in layers {
+            size += self.layer_disk_size(layer).await?;
+        }
+
+        Ok(size)
+    }
+
+    /// Make sure all layers and labels have been written to disk
+    ///
+    /// For a directory store opened without `sync_on_commit`, call
+    /// this after a bulk load to make everything durable at once. For
+    /// the memory store, this does nothing.
+    pub async fn flush(&self) -> Result<(), StoreError> {
+        self.layer_store.flush().await?;
+        Ok(self.label_store.flush().await?)
+    }
+
+    /// Create a base layer builder, unattached to any database label
+    ///
+    /// After having committed it, use `set_head` on a `NamedGraph` to attach it.
+    pub async fn create_base_layer(&self) -> Result<StoreLayerBuilder, StoreError> {
+        Ok(StoreLayerBuilder::new(self.clone()).await?)
+    }
+
+    /// Create a base layer builder with the given options, unattached to any database label
+    ///
+    /// Returns an `InvalidInput` error if the options are invalid.
+    pub async fn create_base_layer_with_options(
+        &self,
+        options: LayerBuilderOptions,
+    ) -> Result<StoreLayerBuilder, StoreError> {
+        let builder = self
+            .layer_store
+            .create_base_layer_with_options(options)
+            .await?;
+
+        Ok(StoreLayerBuilder::wrap(builder, self.clone()))
+    }
+
+    /// Build and commit a base layer containing the given triples
+    ///
+    /// The returned layer is not attached to any database label. Use
+    /// `set_head` on a `NamedGraph` to attach it.
+    pub async fn create_base_layer_from<I: IntoIterator<Item = StringTriple>>(
+        &self,
+        triples: I,
+    ) -> Result<StoreLayer, StoreError> {
+        let triples: Vec<_> = triples.into_iter().collect();
+        let builder = self.create_base_layer().await?;
+        builder.add_string_triples_async(triples).await?;
+
+        builder.commit().await
+    }
+
+    /// Build and commit a base layer from triples that are already sorted
+    ///
+    /// This works like `create_base_layer_from`, but skips sorting the
+    /// triples. The triples must be in ascending order (as given by
+    /// the `Ord` implementation of `StringTriple`) and free of
+    /// duplicates, or the resulting layer will be broken.
+    pub async fn create_base_layer_from_sorted<I: IntoIterator<Item = StringTriple>>(
+        &self,
+        triples: I,
+    ) -> Result<StoreLayer, StoreError> {
+        let triples: Vec<_> = triples.into_iter().collect();
+        let builder = self.create_base_layer().await?;
+        builder
+            .with_builder_async(move |b| b.add_sorted_string_triples(triples))
+            .await?;
+
+        builder.commit().await
+    }
+
+    pub fn export_layers(
+        &self,
+        layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
+    ) -> Result<Vec<u8>, StoreError> {
+        Ok(self.layer_store.export_layers(layer_ids)?)
+    }
+
+    /// Export the given layers as a pack, leaving out the layers in `known`
+    ///
+    /// This is meant for incremental backups, where `known` are the
+    /// layers in earlier packs. Importing the earlier packs followed
+    /// by this one restores all layers.
+    pub fn export_layers_since(
+        &self,
+        known: &[[u32; 5]],
+        layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
+    ) -> Result<Vec<u8>, StoreError> {
+        let known: HashSet<[u32; 5]> = known.iter().cloned().collect();
+        self.export_layers(Box::new(layer_ids.filter(move |id| !known.contains(id))))
+    }
+
+    /// Export the given layers as a pack, writing it out to the given writer
+    ///
+    /// Unlike `export_layers`, this never keeps the whole pack in memory.
+    pub fn export_layers_to<W: io::Write>(
+        &self,
+        mut writer: W,
+        layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
+    ) -> Result<(), StoreError> {
+        Ok(self.layer_store.export_layers_to(&mut writer, layer_ids)?)
+    }
+
+    /// Import the given layers from a pack
+    ///
+    /// Layers that already exist in this store are not imported
+    /// again. Their files are compared to the ones in the pack by
+    /// size, and an `InvalidData` error is returned if they differ.
+    /// Layer ids that are not in the pack are ignored.
+    pub fn import_layers(
+        &self,
+        pack: &[u8],
+        layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
+    ) -> Result<(), StoreError> {
+        Ok(self.layer_store.import_layers(pack, layer_ids)?)
+    }
+
+    /// Import the given layers from a pack, reading it from the given reader
+    ///
+    /// Every layer id that is passed in is expected to be in the
+    /// pack. If one is missing, an error naming it is returned before
+    /// anything is written. Like with `import_layers`, layers that
+    /// already exist are skipped.
+    pub fn import_layers_from<R: io::Read>(
+        &self,
+        mut reader: R,
+        layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
+    ) -> Result<(), StoreError> {
+        Ok(self
+            .layer_store
+            .import_layers_from(&mut reader, layer_ids)?)
+    }
+
+    /// Check that a pack is intact, returning the names of the layers it contains
+    ///
+    /// This never touches a store, but it does write to disk: the
+    /// pack is unpacked into a directory under
+    /// `std::env::temp_dir()`, which is removed again afterwards. The
+    /// files of every layer are checked against their checksums, and
+    /// every layer whose ancestors are all in the pack is loaded in
+    /// full. A layer whose parent is not in the pack is taken to be
+    /// built on a layer that the importing store already has, so it
+    /// is loaded on top of an empty stand-in parent instead, which
+    /// still parses all its structures. The layers are checked
+    /// concurrently, and malformed packs result in an `InvalidData`
+    /// error.
+    pub async fn validate_pack(pack: &[u8]) -> Result<Vec<[u32; 5]>, StoreError> {
+        let parents = pack_layer_parents(pack).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("malformed pack: {}", e))
+        })?;
+        let dir = std::env::temp_dir().join(format!(
+            "terminus-store-pack-{}",
+            name_to_string(rand::random())
+        ));
+
+        let result = validate_unpacked_pack(&dir, pack, &parents).await;
+        // the directory may not even have been created, so errors are ignored
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        result?;
+
+        let mut names: Vec<_> = parents.keys().cloned().collect();
+        names.sort();
+
+        Ok(names)
+    }
+
+    /// Import the given layers from a pack, stopping early if the token is cancelled
+    ///
+    /// The token is checked whenever more of the pack is read. If the
+    /// import is cancelled before it finishes, none of the layers it
+    /// unpacked are kept, so it can simply be tried again.
+    pub fn import_layers_cancellable(
+        &self,
+        pack: &[u8],
+        layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
+        cancel: &CancellationToken,
+    ) -> Result<(), StoreError> {
+        let mut reader = CancellableReader {
+            inner: pack,
+            cancel,
+        };
+        match self.layer_store.import_layers_from(&mut reader, layer_ids) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                cancel.check()?;
+
+                Err(e.into())
+            }
+        }
+    }
+}
+
+// Unpack a pack into the given directory, then check and load its layers
+async fn validate_unpacked_pack(
+    dir: &PathBuf,
+    pack: &[u8],
+    parents: &HashMap<[u32; 5], Option<[u32; 5]>>,
+) -> io::Result<()> {
+    let layer_store = DirectoryLayerStore::new(dir);
+    let names: Vec<[u32; 5]> = parents.keys().cloned().collect();
+    LayerStore::import_layers(&layer_store, pack, Box::new(names.into_iter()))?;
+
+    let malformed = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let layer_store = &layer_store;
+    let stand_in_parent = &stand_in_parent_layer().await?;
+    let checks = parents.iter().map(|(&name, &parent)| async move {
+        match layer_store.verify_layer(name).await? {
+            LayerVerification::Corrupt => {
+                return Err(malformed(format!(
+                    "layer {} does not match its checksums",
+                    name_to_string(name)
+                )));
+            }
+            // layers written before checksums were recorded can only be checked by loading them
+            LayerVerification::Intact | LayerVerification::Unverifiable => {}
+        }
+
+        // walk up to the first ancestor outside the pack, guarding against cycles
+        let mut ancestor = parent;
+        let mut steps = 0;
+        while let Some(Some(grandparent)) = ancestor.map(|a| parents.get(&a)) {
+            steps += 1;
+            if steps > parents.len() {
+                return Err(malformed(format!(
+                    "layer {} is its own ancestor",
+                    name_to_string(name)
+                )));
+            }
+            ancestor = *grandparent;
+        }
+
+        if ancestor.is_none() {
+            if layer_store.get_layer(name).await?.is_none() {
+                return Err(malformed(format!(
+                    "layer {} could not be loaded",
+                    name_to_string(name)
+                )));
+            }
+        } else {
+            let files = layer_store.child_layer_files(name).await?;
+            ChildLayer::load_from_files(name, stand_in_parent.clone(), &files).await?;
+        }
+
+        Ok(())
+    });
+    futures::future::try_join_all(checks).await?;
+
+    Ok(())
+}
+
+/// Open a store that is entirely in memory
+///
+/// This is useful for testing purposes, or if the database is only going to be used for caching purposes
+pub fn open_memory_store() -> Store {
+    Store::new(
+        MemoryLabelStore::new(),
+        CachedLayerStore::new(MemoryLayerStore::new(), LockingHashMapLayerCache::new()),
+    )
+}
+
+/// Open a store that stores its data in the given directory
+pub fn open_directory_store<P: Into<PathBuf>>(path: P) -> Store {
+    open_directory_store_with(path, DirectoryStoreOptions::default())
+}
+
+/// Open a store that stores its data in the given directory, with the given options
+pub fn open_directory_store_with<P: Into<PathBuf>>(
+    path: P,
+    options: DirectoryStoreOptions,
+) -> Store {
+    let p = path.into();
+    Store::new(
+        DirectoryLabelStore::new_with_options(p.clone(), options),
+        CachedLayerStore::new(
+            DirectoryLayerStore::new_with_options(p, options),
+            LockingHashMapLayerCache::new(),
+        ),
+    )
+}
+
+/// Open a store that is stored in the given directory, without allowing any changes to it
+///
+/// Reading works just like in a store opened with
+/// `open_directory_store`, but creating or changing databases, and
+/// creating, importing or deleting layers, fails with a
+/// `PermissionDenied` error. This makes it safe to query a directory
+/// that another process is writing to.
+pub fn open_directory_store_readonly<P: Into<PathBuf>>(path: P) -> Store {
+    let p = path.into();
+    Store::new(
+        DirectoryLabelStore::new_readonly(p.clone()),
+        CachedLayerStore::new(
+            DirectoryLayerStore::new_readonly(p),
+            LockingHashMapLayerCache::new(),
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::Future;
+    use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+    use std::pin::Pin;
+    use tempfile::tempdir;
+    use tokio::runtime::Runtime;
+
+    fn create_and_manipulate_database(mut runtime: Runtime, store: Store) {
+        let database = runtime.block_on(store.create("foodb")).unwrap();
+
+        let head = runtime.block_on(database.head()).unwrap();
+        assert!(head.is_none());
+
+        let mut builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+
+        let layer = runtime.block_on(builder.commit()).unwrap();
+        assert!(runtime.block_on(database.set_head(&layer)).unwrap());
+
+        builder = runtime.block_on(layer.open_write()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+
+        let layer2 = runtime.block_on(builder.commit()).unwrap();
+        assert!(runtime.block_on(database.set_head(&layer2)).unwrap());
+        let layer2_name = layer2.name();
+
+        let layer = runtime.block_on(database.head()).unwrap().unwrap();
+
+        assert_eq!(layer2_name, layer.name());
+        assert!(layer.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+        assert!(layer.string_triple_exists(&StringTriple::new_value("pig", "says", "oink")));
+    }
+
+    #[test]
+    fn create_and_manipulate_memory_database() {
+        let runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        create_and_manipulate_database(runtime, store);
+    }
+
+    #[test]
+    fn create_and_manipulate_directory_database() {
+        let runtime = Runtime::new().unwrap();
+        let dir = tempdir().unwrap();
+        let store = open_directory_store(dir.path());
+
+        create_and_manipulate_database(runtime, store);
+    }
+
+    #[test]
+    fn create_layer_and_retrieve_it_by_id() {
+        let mut runtime = Runtime::new().unwrap();
+
+        let store = open_memory_store();
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+
+        let layer = runtime.block_on(builder.commit()).unwrap();
+
+        let id = layer.name();
+
+        let layer2 = runtime
+            .block_on(store.get_layer_from_id(id))
+            .unwrap()
+            .unwrap();
+
+        assert!(layer2.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+    }
+
+    #[test]
+    fn commit_builder_makes_builder_committed() {
+        let mut runtime = Runtime::new().unwrap();
+
+        let store = open_memory_store();
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+
+        assert!(!builder.committed());
+
+        runtime.block_on(builder.commit_no_load()).unwrap();
+
+        assert!(builder.committed());
+    }
+
+    #[test]
+    fn hard_reset() {
+        let mut runtime = Runtime::new().unwrap();
+
+        let store = open_memory_store();
+        let database = runtime.block_on(store.create("foodb")).unwrap();
+
+        let builder1 = runtime.block_on(store.create_base_layer()).unwrap();
+        builder1
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+
+        let layer1 = runtime.block_on(builder1.commit()).unwrap();
+
+        assert!(runtime.block_on(database.set_head(&layer1)).unwrap());
+
+        let builder2 = runtime.block_on(store.create_base_layer()).unwrap();
+        builder2
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+
+        let layer2 = runtime.block_on(builder2.commit()).unwrap();
+
+        assert!(runtime.block_on(database.force_set_head(&layer2)).unwrap());
+
+        let new_layer = runtime.block_on(database.head()).unwrap().unwrap();
+
+        assert!(new_layer.string_triple_exists(&StringTriple::new_value("duck", "says", "quack")));
+        assert!(!new_layer.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+    }
+
+    #[test]
+    fn create_two_layers_and_squash() {
+        let mut runtime = Runtime::new().unwrap();
+
+        let store = open_memory_store();
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+
+        let layer = runtime.block_on(builder.commit()).unwrap();
+
+        let builder2 = runtime.block_on(layer.open_write()).unwrap();
+
+        builder2
+            .add_string_triple(StringTriple::new_value("dog", "says", "woof"))
+            .unwrap();
+
+        let layer2 = runtime.block_on(builder2.commit()).unwrap();
+
+        let new = runtime.block_on(layer2.squash()).unwrap();
+
+        assert!(new.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+        assert!(new.string_triple_exists(&StringTriple::new_value("dog", "says", "woof")));
+        assert!(runtime.block_on(new.parent()).unwrap().is_none());
+    }
+
+    #[test]
+    fn apply_a_base_delta() {
+        let mut runtime = Runtime::new().unwrap();
+
+        let store = open_memory_store();
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+
+        let layer = runtime.block_on(builder.commit()).unwrap();
+
+        let builder2 = runtime.block_on(layer.open_write()).unwrap();
+
+        builder2
+            .add_string_triple(StringTriple::new_value("dog", "says", "woof"))
+            .unwrap();
+
+        let layer2 = runtime.block_on(builder2.commit()).unwrap();
+
+        let delta_builder_1 = runtime.block_on(store.create_base_layer()).unwrap();
+
+        delta_builder_1
+            .add_string_triple(StringTriple::new_value("dog", "says", "woof"))
+            .unwrap();
+        delta_builder_1
+            .add_string_triple(StringTriple::new_value("cat", "says", "meow"))
+            .unwrap();
+
+        let delta_1 = runtime.block_on(delta_builder_1.commit()).unwrap();
+
+        let delta_builder_2 = runtime.block_on(delta_1.open_write()).unwrap();
+
+        delta_builder_2
+            .add_string_triple(StringTriple::new_value("crow", "says", "caw"))
+            .unwrap();
+        delta_builder_2
+            .remove_string_triple(StringTriple::new_value("cat", "says", "meow"))
+            .unwrap();
+
+        let delta = runtime.block_on(delta_builder_2.commit()).unwrap();
+
+        let rebase_builder = runtime.block_on(layer2.open_write()).unwrap();
+
+        let _ = rebase_builder.apply_delta(&delta).unwrap();
+
+        let rebase_layer = runtime.block_on(rebase_builder.commit()).unwrap();
+
+        assert!(rebase_layer.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+        assert!(rebase_layer.string_triple_exists(&StringTriple::new_value("crow", "says", "caw")));
+        assert!(rebase_layer.string_triple_exists(&StringTriple::new_value("dog", "says", "woof")));
+        assert!(!rebase_layer.string_triple_exists(&StringTriple::new_value("cat", "says", "meow")));
+    }
+
+    #[test]
+    fn list_memory_labels() {
+        let mut runtime = Runtime::new().unwrap();
+
+        let store = open_memory_store();
+        runtime.block_on(store.create("foo")).unwrap();
+        runtime.block_on(store.create("bar")).unwrap();
+        runtime.block_on(store.create("baz")).unwrap();
+
+        let labels = runtime.block_on(store.labels()).unwrap();
+        assert_eq!(vec!["bar", "baz", "foo"], labels);
+    }
+
+    #[test]
+    fn list_directory_labels() {
+        let mut runtime = Runtime::new().unwrap();
+        let dir = tempdir().unwrap();
+
+        let store = open_directory_store(dir.path());
+        runtime.block_on(store.create("foo")).unwrap();
+        runtime.block_on(store.create("bar")).unwrap();
+
+        // unrelated files and directories should be ignored
+        std::fs::write(dir.path().join("README"), b"not a label").unwrap();
+        std::fs::create_dir(dir.path().join("quux.label")).unwrap();
+
+        let labels = runtime.block_on(store.labels()).unwrap();
+        assert_eq!(vec!["bar", "foo"], labels);
+    }
+
+    #[test]
+    fn delta_between_unrelated_layers() {
+        let mut runtime = Runtime::new().unwrap();
+
+        let store = open_memory_store();
+        let builder1 = runtime.block_on(store.create_base_layer()).unwrap();
+        builder1
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder1
+            .add_string_triple(StringTriple::new_value("cat", "says", "meow"))
+            .unwrap();
+        let layer1 = runtime.block_on(builder1.commit()).unwrap();
+
+        let builder2 = runtime.block_on(store.create_base_layer()).unwrap();
+        builder2
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder2
+            .add_string_triple(StringTriple::new_value("dog", "says", "woof"))
+            .unwrap();
+        let layer2 = runtime.block_on(builder2.commit()).unwrap();
+
+        let delta = layer1.delta_to(&layer2).unwrap();
+        assert_eq!(
+            Delta {
+                additions: vec![StringTriple::new_value("dog", "says", "woof")],
+                removals: vec![StringTriple::new_value("cat", "says", "meow")],
+            },
+            delta
+        );
+
+        let builder3 = runtime.block_on(layer1.open_write()).unwrap();
+        builder3.apply_struct_delta(&delta).unwrap();
+        let layer3 = runtime.block_on(builder3.commit()).unwrap();
+
+        assert!(layer3.delta_to(&layer2).unwrap().is_empty());
+    }
+
+    #[test]
+    fn apply_delta_to_committed_builder_fails() {
+        let mut runtime = Runtime::new().unwrap();
+
+        let store = open_memory_store();
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let layer = runtime.block_on(builder.commit()).unwrap();
+
+        let builder2 = runtime.block_on(store.create_base_layer()).unwrap();
+        runtime.block_on(builder2.commit_no_load()).unwrap();
+
+        assert!(builder2.apply_delta(&layer).is_err());
+        assert!(builder2.apply_diff(&layer).is_err());
+    }
+
+    #[test]
+    fn add_and_remove_string_triples_in_batch() {
+        let mut runtime = Runtime::new().unwrap();
+
+        let store = open_memory_store();
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triples(vec![
+                StringTriple::new_value("cow", "says", "moo"),
+                StringTriple::new_value("duck", "says", "quack"),
+                StringTriple::new_value("pig", "says", "oink"),
+            ])
+            .unwrap();
+        let layer = runtime.block_on(builder.commit()).unwrap();
+
+        let builder2 = runtime.block_on(layer.open_write()).unwrap();
+        builder2
+            .remove_string_triples(vec![
+                StringTriple::new_value("cow", "says", "moo"),
+                StringTriple::new_value("pig", "says", "oink"),
+            ])
+            .unwrap();
+        let layer2 = runtime.block_on(builder2.commit()).unwrap();
+
+        assert!(!layer2.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+        assert!(layer2.string_triple_exists(&StringTriple::new_value("duck", "says", "quack")));
+        assert!(!layer2.string_triple_exists(&StringTriple::new_value("pig", "says", "oink")));
+
+        assert!(builder2
+            .add_string_triples(vec![StringTriple::new_value("cow", "says", "moo")])
+            .is_err());
+    }
+
+    #[test]
+    fn async_batch_methods_wait_for_the_builder_lock() {
+        use futures::FutureExt;
+
+        let mut runtime = Runtime::new().unwrap();
+
+        let store = open_memory_store();
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+
+        let guard = runtime.block_on(builder.builder.write());
+        let mut add = Box::pin(builder.add_string_triples_async(vec![
+            StringTriple::new_value("cow", "says", "moo"),
+            StringTriple::new_value("duck", "says", "quack"),
+        ]));
+        assert!((&mut add).now_or_never().is_none());
+        std::mem::drop(guard);
+        runtime.block_on(add).unwrap();
+
+        runtime
+            .block_on(
+                builder.remove_string_triples_async(vec![StringTriple::new_value(
+                    "duck", "says", "quack",
+                )]),
+            )
+            .unwrap();
+        let layer = runtime.block_on(builder.commit()).unwrap();
+
+        assert!(layer.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+        assert!(!layer.string_triple_exists(&StringTriple::new_value("duck", "says", "quack")));
+
+        assert!(runtime
+            .block_on(
+                builder
+                    .add_string_triples_async(vec![StringTriple::new_value("cow", "says", "moo")])
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn export_layers_to_file() {
+        let mut runtime = Runtime::new().unwrap();
+        let dir = tempdir().unwrap();
+        let store = open_directory_store(dir.path());
+
+        let builder1 = runtime.block_on(store.create_base_layer()).unwrap();
+        builder1
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let layer1 = runtime.block_on(builder1.commit()).unwrap();
+
+        let builder2 = runtime.block_on(layer1.open_write()).unwrap();
+        builder2
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        let layer2 = runtime.block_on(builder2.commit()).unwrap();
+
+        let ids = vec![layer1.name(), layer2.name()];
+        let pack_path = dir.path().join("export.tar.gz");
+        let file = std::fs::File::create(&pack_path).unwrap();
+        store
+            .export_layers_to(file, Box::new(ids.clone().into_iter()))
+            .unwrap();
+
+        let pack = std::fs::read(&pack_path).unwrap();
+        assert_eq!(
+            store.export_layers(Box::new(ids.into_iter())).unwrap(),
+            pack
+        );
+
+        let parents_map =
+            crate::storage::directory::pack_layer_parents(io::Cursor::new(&pack)).unwrap();
+        assert_eq!(2, parents_map.len());
+        assert_eq!(None, parents_map[&layer1.name()]);
+        assert_eq!(Some(layer1.name()), parents_map[&layer2.name()]);
+    }
+
+    #[test]
+    fn import_layers_from_file() {
+        let mut runtime = Runtime::new().unwrap();
+        let dir1 = tempdir().unwrap();
+        let store1 = open_directory_store(dir1.path());
+        let dir2 = tempdir().unwrap();
+        let store2 = open_directory_store(dir2.path());
+
+        let builder1 = runtime.block_on(store1.create_base_layer()).unwrap();
+        builder1
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let layer1 = runtime.block_on(builder1.commit()).unwrap();
+
+        let builder2 = runtime.block_on(layer1.open_write()).unwrap();
+        builder2
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        let layer2 = runtime.block_on(builder2.commit()).unwrap();
+
+        let ids = vec![layer1.name(), layer2.name()];
+        let pack_path = dir1.path().join("export.tar.gz");
+        let file = std::fs::File::create(&pack_path).unwrap();
+        store1
+            .export_layers_to(file, Box::new(ids.clone().into_iter()))
+            .unwrap();
+
+        let file = std::fs::File::open(&pack_path).unwrap();
+        store2
+            .import_layers_from(file, Box::new(ids.into_iter()))
+            .unwrap();
+
+        let result_layer = runtime
+            .block_on(store2.get_layer_from_id(layer2.name()))
+            .unwrap()
+            .unwrap();
+        assert!(result_layer.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+        assert!(
+            result_layer.string_triple_exists(&StringTriple::new_value("duck", "says", "quack"))
+        );
+    }
+
+    #[test]
+    fn import_layers_from_pack_with_missing_layer() {
+        let mut runtime = Runtime::new().unwrap();
+        let dir1 = tempdir().unwrap();
+        let store1 = open_directory_store(dir1.path());
+        let dir2 = tempdir().unwrap();
+        let store2 = open_directory_store(dir2.path());
+
+        let builder = runtime.block_on(store1.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let layer = runtime.block_on(builder.commit()).unwrap();
+
+        let pack = store1
+            .export_layers(Box::new(vec![layer.name()].into_iter()))
+            .unwrap();
+
+        let missing = [1, 2, 3, 4, 5];
+        let err = store2
+            .import_layers_from(
+                io::Cursor::new(&pack),
+                Box::new(vec![layer.name(), missing].into_iter()),
+            )
+            .unwrap_err();
+
+        assert_eq!(io::ErrorKind::NotFound, err.kind());
+        assert!(err
+            .to_string()
+            .contains(&crate::storage::name_to_string(missing)));
+        // nothing was unpacked
+        assert!(runtime
+            .block_on(store2.get_layer_from_id(layer.name()))
+            .unwrap()
+            .is_none());
+        let name = crate::storage::name_to_string(layer.name());
+        assert!(!dir2.path().join(&name[0..3]).exists());
+
+        // import_layers ignores ids that are not in the pack
+        store2
+            .import_layers(&pack, Box::new(vec![layer.name(), missing].into_iter()))
+            .unwrap();
+        assert!(runtime
+            .block_on(store2.get_layer_from_id(layer.name()))
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn import_layers_skips_existing_layers() {
+        let mut runtime = Runtime::new().unwrap();
+        let dir1 = tempdir().unwrap();
+        let store1 = open_directory_store(dir1.path());
+        let dir2 = tempdir().unwrap();
+        let store2 = open_directory_store(dir2.path());
+
+        let builder1 = runtime.block_on(store1.create_base_layer()).unwrap();
+        builder1
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let layer1 = runtime.block_on(builder1.commit()).unwrap();
+
+        let builder2 = runtime.block_on(layer1.open_write()).unwrap();
+        builder2
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        let layer2 = runtime.block_on(builder2.commit()).unwrap();
+
+        let pack = store1
+            .export_layers(Box::new(vec![layer1.name(), layer2.name()].into_iter()))
+            .unwrap();
+        store2
+            .import_layers(&pack, Box::new(vec![layer1.name()].into_iter()))
+            .unwrap();
+
+        // the base layer is in the store already, so only the child is
+        // extracted. Changing a byte of a base layer file without
+        // changing its size makes this visible.
+        let id_string = crate::storage::name_to_string(layer1.name());
+        let base_path = dir2.path().join(&id_string[0..3]).join(&id_string);
+        let file = std::fs::read_dir(&base_path)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .find(|p| std::fs::metadata(p).unwrap().len() > 0)
+            .unwrap();
+        let mut contents = std::fs::read(&file).unwrap();
+        contents[0] ^= 0xff;
+        std::fs::write(&file, &contents).unwrap();
+        store2
+            .import_layers(
+                &pack,
+                Box::new(vec![layer1.name(), layer2.name()].into_iter()),
+            )
+            .unwrap();
+        assert_eq!(contents, std::fs::read(&file).unwrap());
+        contents[0] ^= 0xff;
+        std::fs::write(&file, &contents).unwrap();
+
+        let result_layer = runtime
+            .block_on(store2.get_layer_from_id(layer2.name()))
+            .unwrap()
+            .unwrap();
+        assert!(
+            result_layer.string_triple_exists(&StringTriple::new_value("duck", "says", "quack"))
+        );
+
+        // a local layer with different content under the same id is an error
+        contents.push(0);
+        std::fs::write(&file, contents).unwrap();
+        let err = store2
+            .import_layers(&pack, Box::new(vec![layer1.name()].into_iter()))
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    fn reimport_after_rollup_compares_only_layer_structure() {
+        let mut runtime = Runtime::new().unwrap();
+        let dir1 = tempdir().unwrap();
+        let store1 = open_directory_store(dir1.path());
+        let dir2 = tempdir().unwrap();
+        let store2 = open_directory_store(dir2.path());
+
+        let builder1 = runtime.block_on(store1.create_base_layer()).unwrap();
+        builder1
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let layer1 = runtime.block_on(builder1.commit()).unwrap();
+
+        let builder2 = runtime.block_on(layer1.open_write()).unwrap();
+        builder2
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        let layer2 = runtime.block_on(builder2.commit()).unwrap();
+        let ids = vec![layer1.name(), layer2.name()];
+
+        let pack = store1
+            .export_layers(Box::new(ids.clone().into_iter()))
+            .unwrap();
+        store2
+            .import_layers(&pack, Box::new(ids.clone().into_iter()))
+            .unwrap();
+
+        // the source now has a rollup file the destination lacks
+        runtime.block_on(layer2.rollup()).unwrap();
+        let pack = store1
+            .export_layers(Box::new(ids.clone().into_iter()))
+            .unwrap();
+        store2
+            .import_layers(&pack, Box::new(ids.clone().into_iter()))
+            .unwrap();
+
+        // a file of the same size that doesn't match the recorded checksum is still caught
+        let id_string = crate::storage::name_to_string(layer2.name());
+        let checksums_path = dir2
+            .path()
+            .join(&id_string[0..3])
+            .join(&id_string)
+            .join("checksums.sha256");
+        let checksums = std::fs::read_to_string(&checksums_path).unwrap();
+        let tampered: String = checksums
+            .lines()
+            .map(|line| {
+                let (checksum, file) = line.split_at(line.find("  ").unwrap());
+                let flipped = if checksum.starts_with('0') { "1" } else { "0" };
+                format!("{}{}{}\n", flipped, &checksum[1..], file)
+            })
+            .collect();
+        std::fs::write(&checksums_path, tampered).unwrap();
+        let err = store2
+            .import_layers(&pack, Box::new(ids.into_iter()))
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
+    fn collect_garbage(mut runtime: Runtime, store: Store) {
+        let database = runtime.block_on(store.create("foodb")).unwrap();
+
+        let builder1 = runtime.block_on(store.create_base_layer()).unwrap();
+        builder1
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let layer1 = runtime.block_on(builder1.commit()).unwrap();
+
+        let builder2 = runtime.block_on(layer1.open_write()).unwrap();
+        builder2
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        let layer2 = runtime.block_on(builder2.commit()).unwrap();
+        assert!(runtime.block_on(database.set_head(&layer2)).unwrap());
+
+        // a layer that no label points at
+        let builder3 = runtime.block_on(store.create_base_layer()).unwrap();
+        builder3
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let layer3 = runtime.block_on(builder3.commit()).unwrap();
+        std::mem::drop(builder3);
+
+        // a layer that no label points at, but which has an open builder on top
+        let builder4 = runtime.block_on(store.create_base_layer()).unwrap();
+        builder4
+            .add_string_triple(StringTriple::new_value("horse", "says", "neigh"))
+            .unwrap();
+        let layer4 = runtime.block_on(builder4.commit()).unwrap();
+        std::mem::drop(builder4);
+        let builder5 = runtime.block_on(layer4.open_write()).unwrap();
+        let builder5_name = builder5.name();
+
+        let dry_run = runtime.block_on(store.collect_garbage_dry_run()).unwrap();
+        assert_eq!(vec![layer3.name()], dry_run);
+
+        let collected = runtime.block_on(store.collect_garbage()).unwrap();
+        assert_eq!(vec![layer3.name()], collected);
+
+        assert!(runtime
+            .block_on(store.get_layer_from_id(layer3.name()))
+            .unwrap()
+            .is_none());
+        assert!(runtime
+            .block_on(store.get_layer_from_id(layer1.name()))
+            .unwrap()
+            .is_some());
+        assert!(runtime
+            .block_on(store.get_layer_from_id(layer4.name()))
+            .unwrap()
+            .is_some());
+
+        // once the builder is gone, both it and its parent are garbage
+        std::mem::drop(builder5);
+        let mut expected = vec![layer4.name(), builder5_name];
+        expected.sort();
+        let collected = runtime.block_on(store.collect_garbage()).unwrap();
+        assert_eq!(expected, collected);
+
+        assert!(runtime
+            .block_on(store.collect_garbage_dry_run())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn collect_memory_garbage() {
+        let runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        collect_garbage(runtime, store);
+    }
+
+    #[test]
+    fn collect_directory_garbage() {
+        let runtime = Runtime::new().unwrap();
+        let dir = tempdir().unwrap();
+        let store = open_directory_store(dir.path());
+
+        collect_garbage(runtime, store);
+    }
+
+    #[test]
+    fn retrieve_layer_ancestry() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        let builder1 = runtime.block_on(store.create_base_layer()).unwrap();
+        builder1
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let layer1 = runtime.block_on(builder1.commit()).unwrap();
+
+        let builder2 = runtime.block_on(layer1.open_write()).unwrap();
+        builder2
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        let layer2 = runtime.block_on(builder2.commit()).unwrap();
+
+        let builder3 = runtime.block_on(layer2.open_write()).unwrap();
+        builder3
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let layer3 = runtime.block_on(builder3.commit()).unwrap();
+
+        let ancestry: Vec<_> = runtime
+            .block_on(layer3.ancestry())
+            .unwrap()
+            .iter()
+            .map(|l| l.name())
+            .collect();
+        assert_eq!(vec![layer3.name(), layer2.name(), layer1.name()], ancestry);
+
+        let ancestry: Vec<_> = runtime
+            .block_on(layer1.ancestry())
+            .unwrap()
+            .iter()
+            .map(|l| l.name())
+            .collect();
+        assert_eq!(vec![layer1.name()], ancestry);
+    }
+
+    #[test]
+    fn squash_upto_ancestor() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        let builder1 = runtime.block_on(store.create_base_layer()).unwrap();
+        builder1
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let layer1 = runtime.block_on(builder1.commit()).unwrap();
+
+        let builder2 = runtime.block_on(layer1.open_write()).unwrap();
+        builder2
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        builder2
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let layer2 = runtime.block_on(builder2.commit()).unwrap();
+
+        let builder3 = runtime.block_on(layer2.open_write()).unwrap();
+        builder3
+            .remove_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        builder3
+            .remove_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let layer3 = runtime.block_on(builder3.commit()).unwrap();
+
+        let squashed = runtime.block_on(layer3.squash_upto(&layer1)).unwrap();
+        assert_eq!(Some(layer1.name()), squashed.parent_name());
+        assert_eq!(1, squashed.triple_layer_addition_count());
+        assert_eq!(1, squashed.triple_layer_removal_count());
+        assert!(layer3.delta_to(&squashed).unwrap().is_empty());
+
+        let unrelated_builder = runtime.block_on(store.create_base_layer()).unwrap();
+        let unrelated = runtime.block_on(unrelated_builder.commit()).unwrap();
+        assert!(runtime.block_on(layer3.squash_upto(&unrelated)).is_err());
+    }
+
+    #[test]
+    fn squash_large_layer_stack() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        let builder1 = runtime.block_on(store.create_base_layer()).unwrap();
+        builder1
+            .add_string_triples((0..5000).map(|i| {
+                StringTriple::new_value(&format!("node{}", i), "value", &format!("{}", i))
+            }))
+            .unwrap();
+        let layer1 = runtime.block_on(builder1.commit()).unwrap();
+
+        let builder2 = runtime.block_on(layer1.open_write()).unwrap();
+        builder2
+            .add_string_triples((0..5000).map(|i| {
+                StringTriple::new_node(&format!("node{}", i), "next", &format!("node{}", i + 1))
+            }))
+            .unwrap();
+        builder2
+            .remove_string_triples((0..5000).step_by(3).map(|i| {
+                StringTriple::new_value(&format!("node{}", i), "value", &format!("{}", i))
+            }))
+            .unwrap();
+        let layer2 = runtime.block_on(builder2.commit()).unwrap();
+
+        let squashed = runtime.block_on(layer2.squash()).unwrap();
+
+        assert!(squashed.parent_name().is_none());
+        assert_eq!(layer2.triple_count(), squashed.triple_count());
+        assert!(layer2.delta_to(&squashed).unwrap().is_empty());
+    }
+
+    #[test]
+    fn content_hash_is_independent_of_layer_structure() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        let builder1 = runtime.block_on(store.create_base_layer()).unwrap();
+        builder1
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder1
+            .add_string_triple(StringTriple::new_node("cow", "likes", "duck"))
+            .unwrap();
+        let layer1 = runtime.block_on(builder1.commit()).unwrap();
+
+        let builder2 = runtime.block_on(layer1.open_write()).unwrap();
+        builder2
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        builder2
+            .remove_string_triple(StringTriple::new_node("cow", "likes", "duck"))
+            .unwrap();
+        let layer2 = runtime.block_on(builder2.commit()).unwrap();
+
+        let builder3 = runtime.block_on(store.create_base_layer()).unwrap();
+        builder3
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        builder3
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let layer3 = runtime.block_on(builder3.commit()).unwrap();
+
+        let squashed = runtime.block_on(layer2.squash()).unwrap();
+
+        assert_eq!(
+            layer2.content_hash().unwrap(),
+            layer3.content_hash().unwrap()
+        );
+        assert_eq!(
+            layer2.content_hash().unwrap(),
+            squashed.content_hash().unwrap()
+        );
+        assert_ne!(
+            layer1.content_hash().unwrap(),
+            layer2.content_hash().unwrap()
+        );
+        assert!(layer2.content_eq(&layer3).unwrap());
+        assert!(!layer1.content_eq(&layer3).unwrap());
+    }
+
+    fn compare_and_set_head(mut runtime: Runtime, store: Store) {
+        let database = runtime.block_on(store.create("foodb")).unwrap();
+
+        let builder1 = runtime.block_on(store.create_base_layer()).unwrap();
+        builder1
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let layer1 = runtime.block_on(builder1.commit()).unwrap();
+
+        let builder2 = runtime.block_on(store.create_base_layer()).unwrap();
+        builder2
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        let layer2 = runtime.block_on(builder2.commit()).unwrap();
+
+        assert!(runtime
+            .block_on(database.compare_and_set_head(None, &layer1))
+            .unwrap());
+        // somebody else already moved the head away from None
+        assert!(!runtime
+            .block_on(database.compare_and_set_head(None, &layer2))
+            .unwrap());
+        assert!(!runtime
+            .block_on(database.compare_and_set_head(Some(&layer2), &layer2))
+            .unwrap());
+        assert_eq!(
+            layer1.name(),
+            runtime.block_on(database.head()).unwrap().unwrap().name()
+        );
+
+        assert!(runtime
+            .block_on(database.compare_and_set_head(Some(&layer1), &layer2))
+            .unwrap());
+        assert_eq!(
+            layer2.name(),
+            runtime.block_on(database.head()).unwrap().unwrap().name()
+        );
+    }
+
+    #[test]
+    fn compare_and_set_memory_head() {
+        let runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        compare_and_set_head(runtime, store);
+    }
+
+    #[test]
+    fn compare_and_set_directory_head() {
+        let runtime = Runtime::new().unwrap();
+        let dir = tempdir().unwrap();
+        let store = open_directory_store(dir.path());
+
+        compare_and_set_head(runtime, store);
+    }
+
+    #[test]
+    fn head_version_increments_on_set_head() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+        let database = runtime.block_on(store.create("foodb")).unwrap();
+
+        assert_eq!(Some(0), runtime.block_on(database.head_version()).unwrap());
+        let (head, version) = runtime.block_on(database.head_with_version()).unwrap();
+        assert!(head.is_none());
+        assert_eq!(0, version);
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let layer = runtime.block_on(builder.commit()).unwrap();
+        assert!(runtime.block_on(database.set_head(&layer)).unwrap());
+
+        assert_eq!(Some(1), runtime.block_on(database.head_version()).unwrap());
+        let (head, version) = runtime.block_on(database.head_with_version()).unwrap();
+        assert_eq!(layer.name(), head.unwrap().name());
+        assert_eq!(1, version);
+    }
+
+    #[test]
+    fn redundant_set_head_keeps_version() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+        let database = runtime.block_on(store.create("foodb")).unwrap();
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let layer = runtime.block_on(builder.commit()).unwrap();
+        assert!(runtime.block_on(database.set_head(&layer)).unwrap());
+        assert_eq!(Some(1), runtime.block_on(database.head_version()).unwrap());
+
+        assert!(runtime.block_on(database.set_head(&layer)).unwrap());
+        assert!(runtime.block_on(database.set_head(&layer)).unwrap());
+        assert_eq!(Some(1), runtime.block_on(database.head_version()).unwrap());
+        assert_eq!(
+            layer.name(),
+            runtime.block_on(database.head()).unwrap().unwrap().name()
+        );
+    }
+
+    fn rollup_layer_stack(mut runtime: Runtime, store: Store) {
+        let database = runtime.block_on(store.create("foodb")).unwrap();
+
+        let builder1 = runtime.block_on(store.create_base_layer()).unwrap();
+        builder1
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder1
+            .add_string_triple(StringTriple::new_node("cow", "likes", "duck"))
+            .unwrap();
+        let layer1 = runtime.block_on(builder1.commit()).unwrap();
+
+        let builder2 = runtime.block_on(layer1.open_write()).unwrap();
+        builder2
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        builder2
+            .remove_string_triple(StringTriple::new_node("cow", "likes", "duck"))
+            .unwrap();
+        let layer2 = runtime.block_on(builder2.commit()).unwrap();
+        assert!(runtime.block_on(database.set_head(&layer2)).unwrap());
+        let additions: Vec<_> = layer2.triple_additions().collect();
+        let removals: Vec<_> = layer2.triple_removals().collect();
+
+        let rollup = runtime.block_on(layer2.rollup()).unwrap();
+        assert_eq!(layer2.name(), rollup.name());
+        assert_eq!(Some(layer1.name()), rollup.parent_name());
+        assert!(runtime
+            .block_on(store.layer_store.get_rollup(layer2.name()))
+            .unwrap()
+            .is_some());
+
+        let retrieved = runtime
+            .block_on(store.get_layer_from_id(layer2.name()))
+            .unwrap()
+            .unwrap();
+        let expected: Vec<_> = layer2
+            .triples()
+            .map(|t| layer2.id_triple_to_string(&t).unwrap())
+            .collect();
+        let actual: Vec<_> = retrieved
+            .triples()
+            .map(|t| retrieved.id_triple_to_string(&t).unwrap())
+            .collect();
+        assert_eq!(expected, actual);
+
+        // the rollup still reports the changes of the original layer
+        for layer in &[&rollup, &retrieved] {
+            assert_eq!(additions, layer.triple_additions().collect::<Vec<_>>());
+            assert_eq!(removals, layer.triple_removals().collect::<Vec<_>>());
+            assert_eq!(1, layer.triple_layer_addition_count());
+            assert_eq!(1, layer.triple_layer_removal_count());
+            assert_eq!(
+                vec![StringTriple::new_value("duck", "says", "quack")],
+                layer.string_triple_additions().collect::<Vec<_>>()
+            );
+            assert_eq!(
+                vec![StringTriple::new_node("cow", "likes", "duck")],
+                layer.string_triple_removals().collect::<Vec<_>>()
+            );
+        }
+
+        // the rollup can be built on like the original layer
+        let builder3 = runtime.block_on(retrieved.open_write()).unwrap();
+        builder3
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        builder3
+            .remove_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let layer3 = runtime.block_on(builder3.commit()).unwrap();
+        assert!(layer3.string_triple_exists(&StringTriple::new_value("duck", "says", "quack")));
+        assert!(layer3.string_triple_exists(&StringTriple::new_value("pig", "says", "oink")));
+        assert!(!layer3.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+        assert!(!layer3.string_triple_exists(&StringTriple::new_node("cow", "likes", "duck")));
+
+        // the rollup is kept alive by the layer it was made for
+        std::mem::drop(builder1);
+        std::mem::drop(builder2);
+        std::mem::drop(builder3);
+        assert_eq!(
+            vec![layer3.name()],
+            runtime.block_on(store.collect_garbage_dry_run()).unwrap()
+        );
+    }
+
+    #[test]
+    fn rollup_memory_layer_stack() {
+        let runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        rollup_layer_stack(runtime, store);
+    }
+
+    #[test]
+    fn rollup_directory_layer_stack() {
+        let runtime = Runtime::new().unwrap();
+        let dir = tempdir().unwrap();
+        let store = open_directory_store(dir.path());
+
+        rollup_layer_stack(runtime, store);
+    }
+
+    #[test]
+    fn bulk_triple_conversion_matches_single_conversion() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triples((0..3000).map(|i| {
+                StringTriple::new_value(&format!("node{}", i % 100), "value", &format!("{}", i))
+            }))
+            .unwrap();
+        builder
+            .add_string_triples((0..100).map(|i| {
+                StringTriple::new_node(&format!("node{}", i), "next", &format!("node{}", i + 1))
+            }))
+            .unwrap();
+        let layer = runtime.block_on(builder.commit()).unwrap();
+
+        let expected: Vec<_> = layer
+            .triples()
+            .map(|t| layer.id_triple_to_string(&t).unwrap())
+            .collect();
+        let actual: Vec<_> = layer.id_triples_to_strings(layer.triples()).collect();
+        assert_eq!(expected, actual);
+
+        let squashed = runtime.block_on(layer.squash()).unwrap();
+        let squashed_triples: Vec<_> = squashed.id_triples_to_strings(squashed.triples()).collect();
+        assert_eq!(expected, squashed_triples);
+    }
+
+    fn snapshot_survives_garbage_collection(mut runtime: Runtime, store: Store) {
+        let database = runtime.block_on(store.create("foodb")).unwrap();
+
+        let builder1 = runtime.block_on(store.create_base_layer()).unwrap();
+        builder1
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let layer1 = runtime.block_on(builder1.commit()).unwrap();
+        std::mem::drop(builder1);
+        assert!(runtime.block_on(database.set_head(&layer1)).unwrap());
+
+        let snapshot = runtime.block_on(database.snapshot()).unwrap().unwrap();
+        assert_eq!(layer1.name(), snapshot.name());
+        assert_eq!(vec![layer1.name()], store.layer_store.pinned_layers());
+
+        // move the label away from the snapshotted layer
+        let builder2 = runtime.block_on(store.create_base_layer()).unwrap();
+        builder2
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        let layer2 = runtime.block_on(builder2.commit()).unwrap();
+        std::mem::drop(builder2);
+        assert!(runtime.block_on(database.force_set_head(&layer2)).unwrap());
+
+        assert!(runtime
+            .block_on(store.collect_garbage())
+            .unwrap()
+            .is_empty());
+        assert!(runtime
+            .block_on(store.get_layer_from_id(layer1.name()))
+            .unwrap()
+            .is_some());
+        assert!(snapshot.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+        let boxed: Box<dyn Layer> = Box::new(snapshot.clone());
+        assert_eq!(1, boxed.triple_addition_count());
+        std::mem::drop(boxed);
+
+        std::mem::drop(snapshot);
+        assert!(store.layer_store.pinned_layers().is_empty());
+        assert_eq!(
+            vec![layer1.name()],
+            runtime.block_on(store.collect_garbage()).unwrap()
+        );
+    }
+
+    #[test]
+    fn snapshot_survives_memory_garbage_collection() {
+        let runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        snapshot_survives_garbage_collection(runtime, store);
+    }
+
+    #[test]
+    fn snapshot_survives_directory_garbage_collection() {
+        let runtime = Runtime::new().unwrap();
+        let dir = tempdir().unwrap();
+        let store = open_directory_store(dir.path());
+
+        snapshot_survives_garbage_collection(runtime, store);
+    }
+
+    #[test]
+    fn snapshot_of_missing_layer_fails_without_pinning() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+        let database = runtime.block_on(store.create("foodb")).unwrap();
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let layer = runtime.block_on(builder.commit()).unwrap();
+        let name = layer.name();
+        assert!(runtime.block_on(database.set_head(&layer)).unwrap());
+        std::mem::drop(layer);
+        std::mem::drop(builder);
+        runtime
+            .block_on(store.layer_store.delete_layer(name))
+            .unwrap();
+
+        match runtime.block_on(database.snapshot()) {
+            Err(StoreError::LayerNotFound(missing)) => assert_eq!(name, missing),
+            _ => panic!("expected the layer to be missing"),
+        }
+        assert!(store.layer_store.pinned_layers().is_empty());
+    }
+
+    #[test]
+    fn total_heap_size_sums_layer_stack() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let base = runtime.block_on(builder.commit()).unwrap();
+
+        let builder = runtime.block_on(base.open_write()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let child = runtime.block_on(builder.commit()).unwrap();
+
+        assert!(base.heap_size() > 0);
+        assert!(child.heap_size() > 0);
+        assert_eq!(
+            base.heap_size() + child.heap_size(),
+            runtime.block_on(child.total_heap_size()).unwrap()
+        );
+    }
+
+    #[test]
+    fn export_layer_as_ntriples() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("cow", "likes", "pig"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_lang_value("pig", "says", "oink", "en"))
+            .unwrap();
+        let base = runtime.block_on(builder.commit()).unwrap();
+
+        let builder = runtime.block_on(base.open_write()).unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_node("cow", "likes", "pig"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_typed_value(
+                "cow",
+                "age",
+                "3",
+                "xsd:integer",
+            ))
+            .unwrap();
+        let child = runtime.block_on(builder.commit()).unwrap();
+
+        let mut output = Vec::new();
+        child.export_ntriples(&mut output).unwrap();
+        let mut lines: Vec<_> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|l| l.to_owned())
+            .collect();
+        lines.sort();
+
+        assert_eq!(
+            vec![
+                "<cow> <age> \"3\"^^<xsd:integer> .",
+                "<cow> <says> \"moo\" .",
+                "<pig> <says> \"oink\"@en .",
+            ],
+            lines
+        );
+    }
+
+    #[test]
+    fn import_ntriples_round_trips_export() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("cow", "likes", "pig"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "\"moo\"\n"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_lang_value("pig", "says", "oink", "en"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_typed_value(
+                "cow",
+                "age",
+                "3",
+                "xsd:integer",
+            ))
+            .unwrap();
+        let layer = runtime.block_on(builder.commit()).unwrap();
+
+        let mut output = b"# exported triples\n\n".to_vec();
+        layer.export_ntriples(&mut output).unwrap();
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        assert_eq!(4, builder.import_ntriples(&output[..]).unwrap());
+        let imported = runtime.block_on(builder.commit()).unwrap();
+
+        assert!(layer.content_eq(&imported).unwrap());
+    }
+
+    #[test]
+    fn import_ntriples_reports_malformed_line() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        let input = "<cow> <says> \"moo\" .\n\n<pig> <says> oink .\n";
+        let error = builder.import_ntriples(input.as_bytes()).unwrap_err();
+
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        assert!(error.to_string().starts_with("line 3:"));
+    }
+
+    #[test]
+    fn fresh_blank_nodes_are_unique_in_layer_stack() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        let cow = builder.fresh_blank_node();
+        let pig = builder.fresh_blank_node();
+        assert_eq!("_:b1", cow);
+        assert_eq!("_:b2", pig);
+        builder
+            .add_string_triple(StringTriple::new_node(&cow, "likes", &pig))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node(&cow, "likes", "duck"))
+            .unwrap();
+        let base = runtime.block_on(builder.commit()).unwrap();
+
+        let builder = runtime.block_on(base.open_write()).unwrap();
+        let horse = builder.fresh_blank_node();
+        assert_eq!("_:b3", horse);
+        builder
+            .add_string_triple(StringTriple::new_node(&horse, "likes", &cow))
+            .unwrap();
+        let child = runtime.block_on(builder.commit()).unwrap();
+
+        let cow_id = child.subject_id(&cow).unwrap();
+        assert!(child.is_blank(cow_id));
+        assert!(child.is_blank(child.object_node_id(&pig).unwrap()));
+        assert!(!child.is_blank(child.object_node_id("duck").unwrap()));
+        assert!(child.id_object(cow_id).unwrap().is_blank_node());
+
+        let mut output = Vec::new();
+        child.export_ntriples(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("_:b3 <likes> _:b1 .\n"));
+    }
+
+    #[test]
+    fn import_ntriples_renames_blank_nodes() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("_:b1", "likes", "duck"))
+            .unwrap();
+        let base = runtime.block_on(builder.commit()).unwrap();
+
+        let builder = runtime.block_on(base.open_write()).unwrap();
+        let input = "_:cow <likes> _:pig .\n_:pig <likes> _:cow .\n_:b1 <says> \"moo\" .\n";
+        assert_eq!(3, builder.import_ntriples(input.as_bytes()).unwrap());
+        let child = runtime.block_on(builder.commit()).unwrap();
+
+        assert!(child.string_triple_exists(&StringTriple::new_node("_:b2", "likes", "_:b3")));
+        assert!(child.string_triple_exists(&StringTriple::new_node("_:b3", "likes", "_:b2")));
+        assert!(child.string_triple_exists(&StringTriple::new_value("_:b4", "says", "moo")));
+        assert!(!child.string_triple_exists(&StringTriple::new_value("_:b1", "says", "moo")));
+    }
+
+    #[test]
+    fn batch_triples_exist_matches_single_lookups() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        for s in 0..30 {
+            for o in 0..20 {
+                if (s + o) % 3 != 0 {
+                    builder
+                        .add_string_triple(StringTriple::new_node(
+                            &format!("s{}", s),
+                            &format!("p{}", (s * o) % 4),
+                            &format!("o{}", o),
+                        ))
+                        .unwrap();
+                }
+            }
+        }
+        let base = runtime.block_on(builder.commit()).unwrap();
+
+        let builder = runtime.block_on(base.open_write()).unwrap();
+        for triple in base.triples().filter(|t| t.object % 5 == 0) {
+            builder.remove_id_triple(triple).unwrap();
+        }
+        for s in 0..30 {
+            builder
+                .add_string_triple(StringTriple::new_node(
+                    &format!("s{}", s),
+                    "p1",
+                    &format!("o{}", s % 7),
+                ))
+                .unwrap();
+        }
+        let child = runtime.block_on(builder.commit()).unwrap();
+
+        let mut rng = StdRng::seed_from_u64(42);
+        let node_count = child.node_and_value_count() as u64;
+        let predicate_count = child.predicate_count() as u64;
+        let probes: Vec<_> = (0..5000)
+            .map(|_| {
+                (
+                    rng.gen_range(0, node_count + 2),
+                    rng.gen_range(0, predicate_count + 2),
+                    rng.gen_range(0, node_count + 2),
+                )
+            })
+            .chain(child.triples().map(|t| (t.subject, t.predicate, t.object)))
+            .collect();
+
+        let expected: Vec<_> = probes
+            .iter()
+            .map(|&(s, p, o)| child.triple_exists(s, p, o))
+            .collect();
+        assert!(expected.iter().filter(|&&e| e).count() > 200);
+        assert!(expected.iter().filter(|&&e| !e).count() > 500);
+        assert_eq!(expected, child.triples_exist(&probes));
+    }
+
+    #[test]
+    fn diff_between_unrelated_layers() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let layer1 = runtime.block_on(builder.commit()).unwrap();
+
+        // different insertion order and extra nodes give a different id space
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("aardvark", "likes", "cow"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        let layer2 = runtime.block_on(builder.commit()).unwrap();
+
+        let diff: Vec<_> = layer1.diff(&layer2).unwrap().collect();
+        assert_eq!(
+            vec![
+                DiffEntry::Removed(StringTriple::new_value("cow", "says", "moo")),
+                DiffEntry::Added(StringTriple::new_node("aardvark", "likes", "cow")),
+                DiffEntry::Added(StringTriple::new_value("duck", "says", "quack")),
+            ],
+            diff
+        );
+
+        let delta = layer1.delta_to(&layer2).unwrap();
+        assert_eq!(delta.removals.len() + delta.additions.len(), diff.len());
+        assert_eq!(0, layer2.diff(&layer2).unwrap().count());
+    }
+
+    fn concurrent_create_or_open(mut runtime: Runtime, store: Store) {
+        let graphs = runtime.block_on(futures::future::join_all((0..8).map(|_| {
+            let store = store.clone();
+            runtime.spawn(async move { store.create_or_open("foo").await })
+        })));
+
+        for graph in graphs {
+            assert_eq!("foo", graph.unwrap().unwrap().name());
+        }
+        assert_eq!(
+            vec!["foo".to_string()],
+            runtime.block_on(store.labels()).unwrap()
+        );
+
+        let graph = runtime.block_on(store.create_or_open("foo")).unwrap();
+        assert_eq!(
+            None,
+            runtime.block_on(graph.head()).unwrap().map(|l| l.name())
+        );
+    }
+
+    #[test]
+    fn concurrent_memory_create_or_open() {
+        let runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        concurrent_create_or_open(runtime, store);
+    }
+
+    #[test]
+    fn concurrent_directory_create_or_open() {
+        let runtime = Runtime::new().unwrap();
+        let dir = tempdir().unwrap();
+        let store = open_directory_store(dir.path());
+
+        concurrent_create_or_open(runtime, store);
+    }
+
+    fn set_heads_all_or_nothing(mut runtime: Runtime, store: Store) {
+        let graph1 = runtime.block_on(store.create("foo")).unwrap();
+        let graph2 = runtime.block_on(store.create("bar")).unwrap();
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let base = runtime.block_on(builder.commit()).unwrap();
+        let builder = runtime.block_on(base.open_write()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let child = runtime.block_on(builder.commit()).unwrap();
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        let unrelated = runtime.block_on(builder.commit()).unwrap();
+
+        assert!(runtime
+            .block_on(store.set_heads(&[(&graph1, &base), (&graph2, &base)]))
+            .unwrap());
+        assert_eq!(
+            base.name(),
+            runtime.block_on(graph1.head()).unwrap().unwrap().name()
+        );
+        assert_eq!(
+            base.name(),
+            runtime.block_on(graph2.head()).unwrap().unwrap().name()
+        );
+
+        // graph2 can't move to an unrelated layer, so graph1 doesn't move either
+        assert!(!runtime
+            .block_on(store.set_heads(&[(&graph1, &child), (&graph2, &unrelated)]))
+            .unwrap());
+        assert_eq!(
+            base.name(),
+            runtime.block_on(graph1.head()).unwrap().unwrap().name()
+        );
+        assert_eq!(
+            base.name(),
+            runtime.block_on(graph2.head()).unwrap().unwrap().name()
+        );
+
+        assert!(runtime
+            .block_on(store.set_heads(&[(&graph1, &child), (&graph2, &child)]))
+            .unwrap());
+        assert_eq!(
+            child.name(),
+            runtime.block_on(graph1.head()).unwrap().unwrap().name()
+        );
+        assert_eq!(
+            child.name(),
+            runtime.block_on(graph2.head()).unwrap().unwrap().name()
+        );
+
+        let error = runtime
+            .block_on(store.set_heads(&[(&graph1, &child), (&graph1, &child)]))
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, error.kind());
+    }
+
+    #[test]
+    fn memory_set_heads_all_or_nothing() {
+        let runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        set_heads_all_or_nothing(runtime, store);
+    }
+
+    #[test]
+    fn directory_set_heads_all_or_nothing() {
+        let runtime = Runtime::new().unwrap();
+        let dir = tempdir().unwrap();
+        let store = open_directory_store(dir.path());
+
+        set_heads_all_or_nothing(runtime, store);
+    }
+
+    fn pinned_layer_survives_garbage_collection(mut runtime: Runtime, store: Store) {
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let base = runtime.block_on(builder.commit()).unwrap();
+        std::mem::drop(builder);
+        let builder = runtime.block_on(base.open_write()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let child = runtime.block_on(builder.commit()).unwrap();
+        std::mem::drop(builder);
+
+        let pin1 = child.pin().unwrap();
+        let pin2 = child.pin().unwrap();
+        assert_eq!(child.name(), pin1.name());
+        assert_eq!(vec![child.name()], store.layer_store.pinned_layers());
+
+        // the pinned layer keeps its ancestors alive as well
+        assert!(runtime
+            .block_on(store.collect_garbage())
+            .unwrap()
+            .is_empty());
+
+        std::mem::drop(pin1);
+        assert!(runtime
+            .block_on(store.collect_garbage())
+            .unwrap()
+            .is_empty());
+
+        std::mem::drop(pin2);
+        assert!(store.layer_store.pinned_layers().is_empty());
+        let mut collected = runtime.block_on(store.collect_garbage()).unwrap();
+        collected.sort();
+        let mut expected = vec![base.name(), child.name()];
+        expected.sort();
+        assert_eq!(expected, collected);
+    }
+
+    #[test]
+    fn pinned_layer_survives_memory_garbage_collection() {
+        let runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        pinned_layer_survives_garbage_collection(runtime, store);
+    }
+
+    #[test]
+    fn pinned_layer_survives_directory_garbage_collection() {
+        let runtime = Runtime::new().unwrap();
+        let dir = tempdir().unwrap();
+        let store = open_directory_store(dir.path());
+
+        pinned_layer_survives_garbage_collection(runtime, store);
+    }
+
+    #[test]
+    fn verify_detects_corrupt_directory_layer() {
+        let mut runtime = Runtime::new().unwrap();
+        let dir = tempdir().unwrap();
+        let store = open_directory_store(dir.path());
+        let database = runtime.block_on(store.create("foodb")).unwrap();
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let base = runtime.block_on(builder.commit()).unwrap();
+        let builder = runtime.block_on(base.open_write()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let child = runtime.block_on(builder.commit()).unwrap();
+        assert!(runtime.block_on(database.set_head(&child)).unwrap());
+
+        assert_eq!(
+            LayerVerification::Intact,
+            runtime.block_on(store.verify_layer(base.name())).unwrap()
+        );
+        assert_eq!(
+            LayerVerification::Intact,
+            runtime.block_on(store.verify_layer(child.name())).unwrap()
+        );
+        assert!(runtime.block_on(store.verify_all()).unwrap().is_empty());
+
+        let name = crate::storage::name_to_string(base.name());
+        let mut layer_dir = dir.path().join(&name[0..3]);
+        layer_dir.push(&name);
+
+        // flip a bit in the base layer's value dictionary
+        let dict_path = layer_dir.join("value_dictionary_blocks.pfc");
+        let mut data = std::fs::read(&dict_path).unwrap();
+        data[0] ^= 1;
+        std::fs::write(&dict_path, data).unwrap();
+
+        assert_eq!(
+            LayerVerification::Corrupt,
+            runtime.block_on(store.verify_layer(base.name())).unwrap()
+        );
+        assert_eq!(
+            LayerVerification::Intact,
+            runtime.block_on(store.verify_layer(child.name())).unwrap()
+        );
+        assert_eq!(
+            vec![base.name()],
+            runtime.block_on(store.verify_all()).unwrap()
+        );
+
+        // without checksums, a layer can't be verified
+        std::fs::remove_file(layer_dir.join("checksums.sha256")).unwrap();
+        assert_eq!(
+            LayerVerification::Unverifiable,
+            runtime.block_on(store.verify_layer(base.name())).unwrap()
+        );
+        assert!(runtime.block_on(store.verify_all()).unwrap().is_empty());
+
+        assert_eq!(
+            io::ErrorKind::NotFound,
+            runtime
+                .block_on(store.verify_layer([1, 2, 3, 4, 5]))
+                .unwrap_err()
+                .kind()
+        );
+    }
+
+    #[test]
+    fn copy_layer_stack_between_stores() {
+        let mut runtime = Runtime::new().unwrap();
+        let source = open_memory_store();
+        let dir = tempdir().unwrap();
+        let destination = open_directory_store(dir.path());
+
+        let builder = runtime.block_on(source.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let base = runtime.block_on(builder.commit()).unwrap();
+        let builder = runtime.block_on(base.open_write()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("cow", "likes", "pig"))
+            .unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let child = runtime.block_on(builder.commit()).unwrap();
+
+        let copy = runtime
+            .block_on(destination.copy_layer_from(&source, child.name()))
+            .unwrap();
+        assert_eq!(child.name(), copy.name());
+        assert_eq!(Some(base.name()), copy.parent_name());
+        assert!(copy.content_eq(&child).unwrap());
+        assert_eq!(1, copy.triple_layer_removal_count());
+        let copied_base = runtime.block_on(copy.parent()).unwrap().unwrap();
+        assert!(copied_base.content_eq(&base).unwrap());
+
+        // a rollup is copied as the layer it stands in for
+        let builder = runtime.block_on(child.open_write()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        let grandchild = runtime.block_on(builder.commit()).unwrap();
+        let grandchild = runtime.block_on(grandchild.rollup()).unwrap();
+
+        let copy = runtime
+            .block_on(destination.copy_layer_from(&source, grandchild.name()))
+            .unwrap();
+        assert_eq!(grandchild.name(), copy.name());
+        assert_eq!(Some(child.name()), copy.parent_name());
+        assert!(copy.content_eq(&grandchild).unwrap());
+        assert_eq!(1, copy.triple_layer_addition_count());
+        assert_eq!(0, copy.triple_layer_removal_count());
+
+        // copying again is a no-op
+        let mut layers = runtime.block_on(destination.layer_store.layers()).unwrap();
+        runtime
+            .block_on(destination.copy_layer_from(&source, grandchild.name()))
+            .unwrap();
+        let mut layers_after = runtime.block_on(destination.layer_store.layers()).unwrap();
+        layers.sort();
+        layers_after.sort();
+        assert_eq!(3, layers.len());
+        assert_eq!(layers, layers_after);
+    }
+
+    #[test]
+    fn copy_layer_stack_into_content_addressed_store() {
+        let mut runtime = Runtime::new().unwrap();
+        let source = open_memory_store();
+        let destination = open_memory_store().with_content_addressed_names();
+
+        let builder = runtime.block_on(source.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let base = runtime.block_on(builder.commit()).unwrap();
+        let builder = runtime.block_on(base.open_write()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let child = runtime.block_on(builder.commit()).unwrap();
+
+        let copy = runtime
+            .block_on(destination.copy_layer_from(&source, child.name()))
+            .unwrap();
+        assert_eq!(child.name(), copy.name());
+        assert_eq!(Some(base.name()), copy.parent_name());
+        assert!(copy.content_eq(&child).unwrap());
+
+        // layers built in the destination are still content addressed
+        let builder = runtime.block_on(copy.open_write()).unwrap();
+        let temporary_name = builder.name();
+        builder
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        let grandchild = runtime.block_on(builder.commit()).unwrap();
+        assert_ne!(temporary_name, grandchild.name());
+    }
+
+    #[test]
+    fn readonly_directory_store_refuses_writes() {
+        let mut runtime = Runtime::new().unwrap();
+        let dir = tempdir().unwrap();
+        let store = open_directory_store(dir.path());
+        let database = runtime.block_on(store.create("foodb")).unwrap();
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let layer = runtime.block_on(builder.commit()).unwrap();
+        assert!(runtime.block_on(database.set_head(&layer)).unwrap());
+
+        let readonly = open_directory_store_readonly(dir.path());
+        let readonly_database = runtime.block_on(readonly.open("foodb")).unwrap().unwrap();
+        let readonly_layer = runtime.block_on(readonly_database.head()).unwrap().unwrap();
+        assert_eq!(layer.name(), readonly_layer.name());
+        assert!(readonly_layer.content_eq(&layer).unwrap());
+
+        // setting the head to the layer it already is doesn't write, so use a new layer
+        let builder = runtime.block_on(layer.open_write()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let child = runtime.block_on(builder.commit()).unwrap();
+        let readonly_child = runtime
+            .block_on(readonly.get_layer_from_id(child.name()))
+            .unwrap()
+            .unwrap();
+
+        let mut layers = runtime.block_on(store.layer_store.layers()).unwrap();
+        let denied = |result: Result<_, StoreError>| match result {
+            Err(e) => assert_eq!(io::ErrorKind::PermissionDenied, e.kind()),
+            Ok(_) => panic!("expected write to read-only store to fail"),
+        };
+        denied(runtime.block_on(readonly.create("bardb")).map(|_| ()));
+        denied(runtime.block_on(readonly.create_base_layer()).map(|_| ()));
+        denied(runtime.block_on(readonly_layer.open_write()).map(|_| ()));
+        denied(
+            runtime
+                .block_on(readonly_database.set_head(&readonly_child))
+                .map(|_| ()),
+        );
+        denied(
+            runtime
+                .block_on(readonly_database.compare_and_set_head(None, &readonly_layer))
+                .map(|_| ()),
+        );
+        denied(runtime.block_on(readonly_layer.rollup()).map(|_| ()));
+
+        let mut layers_after = runtime.block_on(store.layer_store.layers()).unwrap();
+        layers.sort();
+        layers_after.sort();
+        assert_eq!(layers, layers_after);
+        assert_eq!(
+            vec!["foodb".to_string()],
+            runtime.block_on(store.labels()).unwrap()
+        );
+        assert_eq!(
+            layer.name(),
+            runtime.block_on(database.head()).unwrap().unwrap().name()
+        );
+    }
+
+    #[test]
+    fn triple_count_of_layer_stack() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        let base = runtime.block_on(builder.commit()).unwrap();
+
+        let builder = runtime.block_on(base.open_write()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("cow", "likes", "pig"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("pig", "likes", "duck"))
+            .unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        let child = runtime.block_on(builder.commit()).unwrap();
+
+        assert_eq!(3, base.triple_count());
+        assert_eq!(4, child.triple_count());
+        assert_eq!(child.triples().count(), child.triple_count());
+    }
+
+    #[test]
+    fn common_ancestor_of_layers() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let base = runtime.block_on(builder.commit()).unwrap();
+
+        let mut branch = |parent: &StoreLayer, triple: StringTriple| {
+            let builder = runtime.block_on(parent.open_write()).unwrap();
+            builder.add_string_triple(triple).unwrap();
+            runtime.block_on(builder.commit()).unwrap()
+        };
+        let child = branch(&base, StringTriple::new_value("pig", "says", "oink"));
+        let left = branch(&child, StringTriple::new_value("duck", "says", "quack"));
+        let left2 = branch(&left, StringTriple::new_value("duck", "says", "quack"));
+        let right = branch(&child, StringTriple::new_value("horse", "says", "neigh"));
+        let unrelated = runtime.block_on(store.create_base_layer()).unwrap();
+        let unrelated = runtime.block_on(unrelated.commit()).unwrap();
+
+        let common = |a: &StoreLayer, b: &StoreLayer| {
+            let mut runtime = Runtime::new().unwrap();
+            runtime
+                .block_on(store.common_ancestor(a.name(), b.name()))
+                .unwrap()
+        };
+        assert_eq!(Some(child.name()), common(&left2, &right));
+        assert_eq!(Some(child.name()), common(&right, &left2));
+        assert_eq!(Some(left.name()), common(&left, &left2));
+        assert_eq!(Some(left.name()), common(&left2, &left));
+        assert_eq!(Some(base.name()), common(&base, &right));
+        assert_eq!(Some(right.name()), common(&right, &right));
+        assert_eq!(None, common(&unrelated, &right));
+
+        assert_eq!(
+            io::ErrorKind::NotFound,
+            runtime
+                .block_on(store.common_ancestor(base.name(), [1, 2, 3, 4, 5]))
+                .unwrap_err()
+                .kind()
+        );
+    }
+
+    #[test]
+    fn merge_applies_their_changes_and_reports_conflicts() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triples(vec![
+                StringTriple::new_value("cow", "says", "moo"),
+                StringTriple::new_value("pig", "says", "oink"),
+                StringTriple::new_value("duck", "says", "quack"),
+            ])
+            .unwrap();
+        let base = runtime.block_on(builder.commit()).unwrap();
+
+        let builder = runtime.block_on(base.open_write()).unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triples(vec![
+                StringTriple::new_value("cow", "says", "moo!"),
+                StringTriple::new_value("dog", "says", "woof"),
+            ])
+            .unwrap();
+        let ours = runtime.block_on(builder.commit()).unwrap();
+
+        let builder = runtime.block_on(base.open_write()).unwrap();
+        builder
+            .remove_string_triples(vec![
+                StringTriple::new_value("cow", "says", "moo"),
+                StringTriple::new_value("pig", "says", "oink"),
+            ])
+            .unwrap();
+        builder
+            .add_string_triples(vec![
+                StringTriple::new_value("cow", "says", "mooo"),
+                StringTriple::new_value("duck", "says", "quack quack"),
+                StringTriple::new_value("dog", "says", "woof"),
+            ])
+            .unwrap();
+        let theirs = runtime.block_on(builder.commit()).unwrap();
+
+        let builder = runtime.block_on(ours.open_write()).unwrap();
+        let result = builder.merge(&base, &theirs).unwrap();
+        let merged = runtime.block_on(builder.commit()).unwrap();
+
+        assert_eq!(
+            vec![StringTriple::new_value("duck", "says", "quack quack")],
+            result.added
+        );
+        assert_eq!(
+            vec![StringTriple::new_value("pig", "says", "oink")],
+            result.removed
+        );
+        assert!(result.has_conflicts());
+        assert_eq!(
+            vec![MergeConflict {
+                subject: "cow".to_string(),
+                predicate: "says".to_string(),
+                ours: vec![
+                    DiffEntry::Added(StringTriple::new_value("cow", "says", "moo!")),
+                    DiffEntry::Removed(StringTriple::new_value("cow", "says", "moo")),
+                ],
+                theirs: vec![
+                    DiffEntry::Added(StringTriple::new_value("cow", "says", "mooo")),
+                    DiffEntry::Removed(StringTriple::new_value("cow", "says", "moo")),
+                ],
+            }],
+            result.conflicts
+        );
+
+        let mut triples: Vec<_> = merged
+            .triples()
+            .map(|t| merged.id_triple_to_string(&t).unwrap())
+            .collect();
+        triples.sort();
+        assert_eq!(
+            vec![
+                StringTriple::new_value("cow", "says", "moo!"),
+                StringTriple::new_value("dog", "says", "woof"),
+                StringTriple::new_value("duck", "says", "quack"),
+                StringTriple::new_value("duck", "says", "quack quack"),
+            ],
+            triples
+        );
+    }
+
+    #[test]
+    fn merge_without_conflicts_on_identical_changes() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let base = runtime.block_on(builder.commit()).unwrap();
+
+        let change = |runtime: &mut Runtime| {
+            let builder = runtime.block_on(base.open_write()).unwrap();
+            builder
+                .remove_string_triple(StringTriple::new_value("cow", "says", "moo"))
+                .unwrap();
+            builder
+                .add_string_triple(StringTriple::new_value("cow", "says", "mooo"))
+                .unwrap();
+            runtime.block_on(builder.commit()).unwrap()
+        };
+        let ours = change(&mut runtime);
+        let theirs = change(&mut runtime);
+
+        let builder = runtime.block_on(ours.open_write()).unwrap();
+        let result = builder.merge(&base, &theirs).unwrap();
+
+        assert_eq!(MergeResult::default(), result);
+    }
+
+    #[test]
+    fn case_insensitive_subject_lookup() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triples(vec![
+                StringTriple::new_value("Cow", "says", "moo"),
+                StringTriple::new_value("cows", "say", "moo"),
+                StringTriple::new_value("\u{c9}lan", "is", "energy"),
+                StringTriple::new_value("pig", "says", "oink"),
+            ])
+            .unwrap();
+        let base = runtime.block_on(builder.commit()).unwrap();
+
+        let builder = runtime.block_on(base.open_write()).unwrap();
+        builder
+            .add_string_triples(vec![
+                StringTriple::new_value("cOW", "says", "moo"),
+                StringTriple::new_value("\u{e9}LAN", "is", "style"),
+            ])
+            .unwrap();
+        let layer = runtime.block_on(builder.commit()).unwrap();
+
+        let subjects = |query: &str| {
+            let mut subjects: Vec<_> = layer
+                .subject_id_ci(query)
+                .into_iter()
+                .map(|id| layer.id_subject(id).unwrap())
+                .collect();
+            subjects.sort();
+            subjects
+        };
+
+        assert_eq!(vec!["Cow", "cOW"], subjects("COW"));
+        assert_eq!(vec!["Cow", "cOW"], subjects("cow"));
+        assert_eq!(vec!["\u{c9}lan", "\u{e9}LAN"], subjects("\u{e9}lan"));
+        assert_eq!(vec!["pig"], subjects("PIG"));
+        assert!(subjects("horse").is_empty());
+        assert!(subjects("").is_empty());
+    }
+
+    #[test]
+    fn triples_with_object_value_range_matches_filter() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        for (i, date) in ["2020-01-15", "2020-02-01", "2020-03-31", "2021-01-01"]
+            .iter()
+            .enumerate()
+        {
+            builder
+                .add_string_triple(StringTriple::new_value(&format!("e{}", i), "date", date))
+                .unwrap();
+        }
+        builder
+            .add_string_triples(vec![
+                StringTriple::new_value("e0", "also", "2020-02-01"),
+                StringTriple::new_typed_value("e1", "date", "2020-02-02", "xsd:date"),
+                StringTriple::new_value("e9", "date", "\u{2}escaped"),
+                StringTriple::new_node("e0", "next", "2020-02-05"),
+            ])
+            .unwrap();
+        let base = runtime.block_on(builder.commit()).unwrap();
+
+        let builder = runtime.block_on(base.open_write()).unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("e2", "date", "2020-03-31"))
+            .unwrap();
+        builder
+            .add_string_triples(vec![
+                StringTriple::new_value("e4", "date", "2020-02-15"),
+                StringTriple::new_value("e5", "date", "2020-01-15"),
+            ])
+            .unwrap();
+        let layer = runtime.block_on(builder.commit()).unwrap();
+
+        for &(low, high) in &[
+            ("2020-02", "2020-03"),
+            ("2020-01-15", "2020-02-15"),
+            ("2020", "2021"),
+            ("2020-03", "2020-02"),
+            ("", "\u{10ffff}"),
+            ("\u{2}", "\u{3}"),
+            ("x", "y"),
+        ] {
+            let mut expected: Vec<_> = layer
+                .triples()
+                .filter(|t| match layer.id_object(t.object) {
+                    Some(ObjectType::Value(value)) => {
+                        low <= value.as_str() && value.as_str() < high
+                    }
+                    _ => false,
+                })
+                .collect();
+            expected.sort();
+
+            let mut result: Vec<_> = layer.triples_with_object_value_range(low, high).collect();
+            result.sort();
+            assert_eq!(expected, result);
+        }
+
+        let result: Vec<_> = layer
+            .triples_with_object_value_range("2020-01", "2020-02")
+            .map(|t| layer.id_triple_to_string(&t).unwrap())
+            .collect();
+        assert_eq!(2, result.len());
+        assert!(result.contains(&StringTriple::new_value("e0", "date", "2020-01-15")));
+        assert!(result.contains(&StringTriple::new_value("e5", "date", "2020-01-15")));
+    }
+
+    #[test]
+    fn triples_with_numeric_object_range_matches_filter() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triples(vec![
+                StringTriple::new_numeric_value("a", "size", -5),
+                StringTriple::new_numeric_value("b", "size", 3),
+                StringTriple::new_numeric_value("c", "size", 300),
+                StringTriple::new_numeric_value("d", "size", -0.5),
+                StringTriple::new_numeric_value("e", "size", 2.5),
+                StringTriple::new_numeric_value("e", "other", 2.5),
+                StringTriple::new_value("f", "size", "3"),
+            ])
+            .unwrap();
+        let base = runtime.block_on(builder.commit()).unwrap();
+
+        let builder = runtime.block_on(base.open_write()).unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_numeric_value("b", "size", 3))
+            .unwrap();
+        builder
+            .add_string_triples(vec![
+                StringTriple::new_numeric_value("g", "size", 4),
+                StringTriple::new_numeric_value("h", "size", f64::INFINITY),
+            ])
+            .unwrap();
+        let layer = runtime.block_on(builder.commit()).unwrap();
+
+        for &(low, high) in &[
+            (Numeric::Integer(0), Numeric::Integer(100)),
+            (Numeric::Integer(-5), Numeric::Integer(4)),
+            (Numeric::Integer(i64::MIN), Numeric::Float(f64::NAN)),
+            (Numeric::Integer(10), Numeric::Float(0.0)),
+            (Numeric::Float(0.0), Numeric::Float(f64::INFINITY)),
+            (Numeric::Integer(100), Numeric::Integer(0)),
+        ] {
+            let mut expected: Vec<_> = layer
+                .triples()
+                .filter(|t| match layer.id_object(t.object) {
+                    Some(ObjectType::Numeric(n)) => low <= n && n < high,
+                    _ => false,
+                })
+                .collect();
+            expected.sort();
+
+            let mut result: Vec<_> = layer.triples_with_numeric_object_range(low, high).collect();
+            result.sort();
+            assert_eq!(expected, result);
+        }
+
+        let result: Vec<_> = layer
+            .triples_with_numeric_object_range(Numeric::Integer(0), Numeric::Integer(100))
+            .map(|t| layer.id_triple_to_string(&t).unwrap())
+            .collect();
+        assert_eq!(
+            vec![StringTriple::new_numeric_value("g", "size", 4)],
+            result
+        );
+    }
+
+    #[test]
+    fn commit_callbacks_receive_events() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+        let events = Arc::new(Mutex::new(Vec::new()));
+
+        let events2 = events.clone();
+        let store2 = store.clone();
+        store.on_commit(Arc::new(move |event| {
+            // calling back into the store from a callback should not deadlock
+            store2.on_commit(Arc::new(|_| {}));
+            events2.lock().unwrap().push(event);
+        }));
+
+        let graph = runtime.block_on(store.create("foo")).unwrap();
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let base = runtime.block_on(builder.commit()).unwrap();
+        assert!(runtime.block_on(graph.set_head(&base)).unwrap());
+
+        let builder = runtime.block_on(base.open_write()).unwrap();
+        runtime.block_on(builder.commit_no_load()).unwrap();
+        let child = builder.name();
+
+        // failed commits and head changes don't fire events
+        assert!(runtime.block_on(builder.commit_no_load()).is_err());
+        let unrelated = runtime.block_on(store.create_base_layer()).unwrap();
+        let unrelated = runtime.block_on(unrelated.commit()).unwrap();
+        assert!(!runtime.block_on(graph.set_head(&unrelated)).unwrap());
+
+        assert_eq!(
+            vec![
+                LayerCommitEvent::Layer {
+                    layer: base.name(),
+                    parent: None
+                },
+                LayerCommitEvent::Head {
+                    label: "foo".to_string(),
+                    layer: base.name()
+                },
+                LayerCommitEvent::Layer {
+                    layer: child,
+                    parent: Some(base.name())
+                },
+                LayerCommitEvent::Layer {
+                    layer: unrelated.name(),
+                    parent: None
+                },
+            ],
+            *events.lock().unwrap()
+        );
+    }
+
+    fn disk_sizes_for_store(store: Store) {
+        let mut runtime = Runtime::new().unwrap();
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let base = runtime.block_on(builder.commit()).unwrap();
+
+        let mut children = Vec::new();
+        for (label, animal) in &[("foo", "duck"), ("bar", "pig")] {
+            let builder = runtime.block_on(base.open_write()).unwrap();
+            builder
+                .add_string_triple(StringTriple::new_value(animal, "says", "something"))
+                .unwrap();
+            let child = runtime.block_on(builder.commit()).unwrap();
+            let graph = runtime.block_on(store.create(label)).unwrap();
+            assert!(runtime.block_on(graph.set_head(&child)).unwrap());
+            children.push(child);
+        }
+
+        let base_size = runtime
+            .block_on(store.layer_disk_size(base.name()))
+            .unwrap();
+        let foo_size = runtime
+            .block_on(store.layer_disk_size(children[0].name()))
+            .unwrap();
+        let bar_size = runtime
+            .block_on(store.layer_disk_size(children[1].name()))
+            .unwrap();
+        assert!(base_size > 0 && foo_size > 0 && bar_size > 0);
+
+        let foo = runtime.block_on(store.open("foo")).unwrap().unwrap();
+        assert_eq!(
+            base_size + foo_size,
+            runtime.block_on(foo.disk_size()).unwrap()
+        );
+        assert_eq!(
+            base_size + foo_size + bar_size,
+            runtime.block_on(store.total_disk_size()).unwrap()
+        );
+
+        let empty = runtime.block_on(store.create("empty")).unwrap();
+        assert_eq!(0, runtime.block_on(empty.disk_size()).unwrap());
+
+        let err = runtime
+            .block_on(store.layer_disk_size([1, 2, 3, 4, 5]))
+            .err()
+            .unwrap();
+        assert_eq!(io::ErrorKind::NotFound, err.kind());
+    }
+
+    #[test]
+    fn memory_disk_sizes() {
+        disk_sizes_for_store(open_memory_store());
+    }
+
+    #[test]
+    fn directory_disk_sizes() {
+        let dir = tempdir().unwrap();
+        disk_sizes_for_store(open_directory_store(dir.path()));
+    }
+
+    #[test]
+    fn create_base_layer_from_triples() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+        let triples = vec![
+            StringTriple::new_value("pig", "says", "oink"),
+            StringTriple::new_node("cow", "likes", "pig"),
+            StringTriple::new_value("cow", "says", "moo"),
+            StringTriple::new_value("pig", "says", "oink"),
+        ];
+
+        let layer = runtime
+            .block_on(store.create_base_layer_from(triples.clone()))
+            .unwrap();
+        let mut sorted = triples;
+        sorted.sort();
+        sorted.dedup();
+        let sorted_layer = runtime
+            .block_on(store.create_base_layer_from_sorted(sorted.clone()))
+            .unwrap();
+
+        for layer in &[layer, sorted_layer] {
+            let result: Vec<_> = layer
+                .triples()
+                .map(|t| layer.id_triple_to_string(&t).unwrap())
+                .collect();
+            assert_eq!(sorted, result);
+        }
+
+        let graph = runtime.block_on(store.create("foo")).unwrap();
+        let layer = runtime
+            .block_on(store.create_base_layer_from(Vec::new()))
+            .unwrap();
+        assert!(runtime.block_on(graph.set_head(&layer)).unwrap());
+    }
+
+    #[test]
+    fn contended_builder_waits_for_lock() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+        let builder = Arc::new(runtime.block_on(store.create_base_layer()).unwrap());
+
+        let guard = runtime.block_on(builder.builder.write());
+        let waiting_builder = builder.clone();
+        let waiting = std::thread::spawn(move || {
+            waiting_builder.add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+        });
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(!waiting.is_finished());
+        drop(guard);
+        waiting.join().unwrap().unwrap();
+        assert!(!builder.committed());
+
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let layer = runtime.block_on(builder.commit()).unwrap();
+        assert!(builder.committed());
+        assert!(layer.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+    }
+
+    fn content_addressed_names_for_store(store: Store) {
+        let mut runtime = Runtime::new().unwrap();
+        let triples = vec![
+            StringTriple::new_value("cow", "says", "moo"),
+            StringTriple::new_node("cow", "likes", "pig"),
+        ];
+
+        let base1 = runtime
+            .block_on(store.create_base_layer_from(triples.clone()))
+            .unwrap();
+        let base2 = runtime
+            .block_on(store.create_base_layer_from(triples.into_iter().rev()))
+            .unwrap();
+        assert_eq!(base1.name(), base2.name());
+
+        let other = runtime
+            .block_on(
+                store.create_base_layer_from(vec![StringTriple::new_value("pig", "says", "oink")]),
+            )
+            .unwrap();
+        assert_ne!(base1.name(), other.name());
+
+        let mut children = Vec::new();
+        for _ in 0..2 {
+            let builder = runtime.block_on(base1.open_write()).unwrap();
+            let temporary_name = builder.name();
+            builder
+                .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+                .unwrap();
+            builder
+                .remove_string_triple(StringTriple::new_node("cow", "likes", "pig"))
+                .unwrap();
+            let child = runtime.block_on(builder.commit()).unwrap();
+            assert_ne!(temporary_name, child.name());
+            assert_eq!(builder.name(), child.name());
+            assert!(runtime
+                .block_on(store.get_layer_from_id(temporary_name))
+                .unwrap()
+                .is_none());
+            children.push(child);
+        }
+        assert_eq!(children[0].name(), children[1].name());
+        assert_eq!(Some(base1.name()), children[0].parent_name());
+        assert!(children[0].string_triple_exists(&StringTriple::new_value("duck", "says", "quack")));
+        assert!(!children[0].string_triple_exists(&StringTriple::new_node("cow", "likes", "pig")));
+
+        // the same content on a different parent gets a different name
+        let builder = runtime.block_on(other.open_write()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        let child = runtime.block_on(builder.commit()).unwrap();
+        assert_ne!(children[0].name(), child.name());
+
+        let mut layers = runtime.block_on(store.layer_store.layers()).unwrap();
+        layers.sort();
+        let mut expected = vec![base1.name(), other.name(), children[0].name(), child.name()];
+        expected.sort();
+        assert_eq!(expected, layers);
+    }
+
+    #[test]
+    fn memory_content_addressed_names() {
+        content_addressed_names_for_store(open_memory_store().with_content_addressed_names());
+    }
+
+    #[test]
+    fn directory_content_addressed_names() {
+        let dir = tempdir().unwrap();
+        content_addressed_names_for_store(
+            open_directory_store(dir.path()).with_content_addressed_names(),
+        );
+    }
+
+    #[test]
+    fn content_addressed_commit_racing_an_identical_commit() {
+        let mut runtime = Runtime::new().unwrap();
+        let dir = tempdir().unwrap();
+        let store = open_directory_store(dir.path()).with_content_addressed_names();
+        let triple = StringTriple::new_value("cow", "says", "moo");
+        let name = content_address(None, std::slice::from_ref(&triple), &[]);
+
+        // another commit of the same triple has started writing the layer
+        let mut racing = runtime
+            .block_on(store.layer_store.create_named_base_layer(name))
+            .unwrap();
+        racing.add_string_triple(triple.clone());
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        let temporary_name = builder.name();
+        builder.add_string_triple(triple.clone()).unwrap();
+        runtime.block_on(builder.commit_no_load()).unwrap();
+        assert_eq!(name, builder.name());
+        assert!(!runtime
+            .block_on(store.layer_store.layer_exists(temporary_name))
+            .unwrap());
+
+        runtime.block_on(racing.commit_boxed()).unwrap();
+        let layer = runtime
+            .block_on(store.get_layer_from_id(name))
+            .unwrap()
+            .unwrap();
+        assert!(layer.string_triple_exists(&triple));
+    }
+
+    #[test]
+    fn random_names_by_default() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+        let triples = vec![StringTriple::new_value("cow", "says", "moo")];
+        let layer1 = runtime
+            .block_on(store.create_base_layer_from(triples.clone()))
+            .unwrap();
+        let layer2 = runtime
+            .block_on(store.create_base_layer_from(triples))
+            .unwrap();
+        assert_ne!(layer1.name(), layer2.name());
+    }
+
+    #[test]
+    fn predicate_histogram_counts_live_triples() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+        let base = runtime
+            .block_on(store.create_base_layer_from(vec![
+                StringTriple::new_value("cow", "says", "moo"),
+                StringTriple::new_value("pig", "says", "oink"),
+                StringTriple::new_node("cow", "likes", "pig"),
+                StringTriple::new_node("pig", "likes", "cow"),
+                StringTriple::new_value("cow", "name", "bessie"),
+            ]))
+            .unwrap();
+
+        let builder = runtime.block_on(base.open_write()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_node("cow", "likes", "pig"))
+            .unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_node("pig", "likes", "cow"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("duck", "colour", "white"))
+            .unwrap();
+        let child = runtime.block_on(builder.commit()).unwrap();
+
+        assert_eq!(
+            vec![
+                ("likes".to_string(), 2),
+                ("says".to_string(), 2),
+                ("name".to_string(), 1)
+            ],
+            base.predicate_histogram_strings()
+        );
+        let says = child.predicate_id("says").unwrap();
+        assert_eq!(3, child.predicate_histogram()[0].1);
+        assert_eq!(says, child.predicate_histogram()[0].0);
+        assert_eq!(
+            vec![
+                ("says".to_string(), 3),
+                ("colour".to_string(), 1),
+                ("name".to_string(), 1)
+            ],
+            {
+                let mut histogram = child.predicate_histogram_strings();
+                histogram[1..].sort();
+                histogram
+            }
+        );
+    }
+
+    #[test]
+    fn rollback_to_ancestor() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+        let graph = runtime.block_on(store.create("foo")).unwrap();
+        let base = runtime
+            .block_on(
+                store.create_base_layer_from(vec![StringTriple::new_value("cow", "says", "moo")]),
+            )
+            .unwrap();
+        // rolling back without a head is not possible
+        assert!(!runtime.block_on(graph.rollback_to(&base)).unwrap());
+        assert!(runtime.block_on(graph.set_head(&base)).unwrap());
+
+        let builder = runtime.block_on(base.open_write()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let child = runtime.block_on(builder.commit()).unwrap();
+        assert!(runtime.block_on(graph.set_head(&child)).unwrap());
+
+        // neither a descendant nor an unrelated layer is a rollback
+        let grandchild = runtime.block_on(child.open_write()).unwrap();
+        let grandchild = runtime.block_on(grandchild.commit()).unwrap();
+        assert!(!runtime.block_on(graph.rollback_to(&grandchild)).unwrap());
+        let unrelated = runtime.block_on(store.create_base_layer()).unwrap();
+        let unrelated = runtime.block_on(unrelated.commit()).unwrap();
+        assert!(!runtime.block_on(graph.rollback_to(&unrelated)).unwrap());
+        assert_eq!(
+            child.name(),
+            runtime.block_on(graph.head()).unwrap().unwrap().name()
+        );
+
+        assert!(runtime.block_on(graph.rollback_to(&base)).unwrap());
+        let head = runtime.block_on(graph.head()).unwrap().unwrap();
+        assert_eq!(base.name(), head.name());
+        assert!(head.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+        assert!(!head.string_triple_exists(&StringTriple::new_value("pig", "says", "oink")));
+    }
+
+    #[test]
+    fn string_triple_iterators() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+        let base = runtime
+            .block_on(store.create_base_layer_from(vec![
+                StringTriple::new_value("cow", "says", "moo"),
+                StringTriple::new_node("cow", "likes", "pig"),
+            ]))
+            .unwrap();
+        let builder = runtime.block_on(base.open_write()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_node("cow", "likes", "pig"))
+            .unwrap();
+        let child = runtime.block_on(builder.commit()).unwrap();
+
+        let mut triples: Vec<_> = child.string_triples().collect();
+        triples.sort();
+        assert_eq!(
+            vec![
+                StringTriple::new_value("cow", "says", "moo"),
+                StringTriple::new_value("pig", "says", "oink"),
+            ],
+            triples
+        );
+        assert_eq!(
+            vec![StringTriple::new_value("pig", "says", "oink")],
+            child.string_triple_additions().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![StringTriple::new_node("cow", "likes", "pig")],
+            child.string_triple_removals().collect::<Vec<_>>()
+        );
+        assert_eq!(0, base.string_triple_removals().count());
+    }
+
+    #[test]
+    fn apply_diff_to_base_builder() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+        let mut triples: Vec<_> = (0..1000)
+            .map(|i| StringTriple::new_value(&format!("cow{}", i), "says", "moo"))
+            .collect();
+        let other = runtime
+            .block_on(store.create_base_layer_from(triples.clone()))
+            .unwrap();
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow0", "says", "moo"))
+            .unwrap();
+        builder.apply_diff(&other).unwrap();
+        let layer = runtime.block_on(builder.commit()).unwrap();
+
+        let mut result: Vec<_> = layer.string_triples().collect();
+        result.sort();
+        triples.sort();
+        assert_eq!(triples, result);
+        assert_eq!(0, layer.triple_removal_count());
+    }
+
+    #[test]
+    fn triples_with_predicate_and_object() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+        let base = runtime
+            .block_on(store.create_base_layer_from(vec![
+                StringTriple::new_node("cow", "likes", "pig"),
+                StringTriple::new_node("duck", "likes", "pig"),
+                StringTriple::new_node("duck", "hates", "pig"),
+                StringTriple::new_node("pig", "likes", "cow"),
+            ]))
+            .unwrap();
+        let builder = runtime.block_on(base.open_write()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("horse", "likes", "pig"))
+            .unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_node("cow", "likes", "pig"))
+            .unwrap();
+        let child = runtime.block_on(builder.commit()).unwrap();
+
+        let likes = child.predicate_id("likes").unwrap();
+        let pig = child.object_node_id("pig").unwrap();
+        let subjects = |triples: Box<dyn Iterator<Item = IdTriple> + Send>| {
+            let mut subjects: Vec<_> = triples
+                .map(|t| child.id_subject(t.subject).unwrap())
+                .collect();
+            subjects.sort();
+            subjects
+        };
+
+        assert_eq!(
+            vec!["duck", "horse"],
+            subjects(child.triples_po(likes, pig))
+        );
+        assert_eq!(
+            vec!["horse"],
+            subjects(child.triple_additions_po(likes, pig))
+        );
+        assert_eq!(vec!["cow"], subjects(child.triple_removals_po(likes, pig)));
+        assert_eq!(vec!["cow", "duck"], subjects(base.triples_po(likes, pig)));
+    }
+
+    #[test]
+    fn triple_streams_match_iterators() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+        let layer = runtime
+            .block_on(store.create_base_layer_from((0..3000).map(|i| {
+                StringTriple::new_value(&format!("cow{}", i % 7), "says", &format!("moo{}", i))
+            })))
+            .unwrap();
+
+        let triples: Vec<_> = runtime.block_on(layer.triples_stream().collect());
+        assert_eq!(3000, triples.len());
+        assert_eq!(layer.triples().collect::<Vec<_>>(), triples);
+
+        let cow = layer.subject_id("cow3").unwrap();
+        let says = layer.predicate_id("says").unwrap();
+        let moo = layer.object_value_id("moo3").unwrap();
+        assert_eq!(
+            layer.triples_s(cow).collect::<Vec<_>>(),
+            runtime.block_on(layer.triples_s_stream(cow).collect::<Vec<_>>())
+        );
+        assert_eq!(
+            layer.triples_p(says).collect::<Vec<_>>(),
+            runtime.block_on(layer.triples_p_stream(says).collect::<Vec<_>>())
+        );
+        assert_eq!(
+            layer.triples_o(moo).collect::<Vec<_>>(),
+            runtime.block_on(layer.triples_o_stream(moo).collect::<Vec<_>>())
+        );
+    }
+
+    #[test]
+    fn create_base_layer_with_dictionary_block_size() {
+        let mut runtime = Runtime::new().unwrap();
+        let dir = tempdir().unwrap();
+        let triples: Vec<_> = (0..50)
+            .map(|i| {
+                StringTriple::new_value(
+                    &format!("http://example.com/a/long/shared/prefix/{}", i),
+                    "http://example.com/a/long/shared/prefix/says",
+                    &format!("value {}", i),
+                )
+            })
+            .collect();
+
+        let mut sizes = Vec::new();
+        for &block_size in &[1, 3, 32] {
+            let store = open_directory_store(dir.path());
+            let options = LayerBuilderOptions {
+                dict_block_size: block_size,
+            };
+            let builder = runtime
+                .block_on(store.create_base_layer_with_options(options))
+                .unwrap();
+            builder.add_string_triples(triples.clone()).unwrap();
+            let base_layer = runtime.block_on(builder.commit()).unwrap();
+            sizes.push(
+                runtime
+                    .block_on(store.layer_disk_size(base_layer.name()))
+                    .unwrap(),
+            );
+
+            let builder = runtime.block_on(base_layer.open_write()).unwrap();
+            builder
+                .add_string_triple(StringTriple::new_value(
+                    "http://example.com/a/long/shared/prefix/7",
+                    "http://example.com/a/long/shared/prefix/says",
+                    "something else",
+                ))
+                .unwrap();
+            let child_layer = runtime.block_on(builder.commit()).unwrap();
+
+            // reopen the store so the layers are read back from disk
+            let store = open_directory_store(dir.path());
+            let layer = runtime
+                .block_on(store.get_layer_from_id(child_layer.name()))
+                .unwrap()
+                .unwrap();
+            for triple in &triples {
+                assert!(layer.string_triple_exists(triple));
+            }
+            assert_eq!(51, layer.triples().count());
+        }
+
+        // larger blocks front-code more strings against each other
+        assert!(sizes[0] > sizes[1]);
+        assert!(sizes[1] > sizes[2]);
+
+        let store = open_memory_store();
+        for &block_size in &[0, crate::structure::MAX_BLOCK_SIZE + 1] {
+            let options = LayerBuilderOptions {
+                dict_block_size: block_size,
+            };
+            let error = runtime
+                .block_on(store.create_base_layer_with_options(options))
+                .err()
+                .unwrap();
+            assert_eq!(io::ErrorKind::InvalidInput, error.kind());
+        }
+    }
+
+    #[test]
+    fn layer_changes_of_single_layer() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("cow", "likes", "duck"))
+            .unwrap();
+        let base_layer = runtime.block_on(builder.commit()).unwrap();
+
+        let builder = runtime.block_on(base_layer.open_write()).unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_node("cow", "likes", "duck"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        let child_layer = runtime.block_on(builder.commit()).unwrap();
+
+        let (additions, removals) = base_layer.layer_changes().unwrap();
+        assert_eq!(
+            vec![
+                StringTriple::new_node("cow", "likes", "duck"),
+                StringTriple::new_value("cow", "says", "moo"),
+            ],
+            additions
+        );
+        assert!(removals.is_empty());
 
-        Ok(set_is_ok)
+        let (additions, removals) = child_layer.layer_changes().unwrap();
+        assert_eq!(
+            vec![StringTriple::new_value("duck", "says", "quack")],
+            additions
+        );
+        assert_eq!(
+            vec![StringTriple::new_node("cow", "likes", "duck")],
+            removals
+        );
     }
 
-    /// Set the database label to the given layer if it is a valid ancestor, returning false otherwise
-    pub async fn force_set_head(&self, layer: &StoreLayer) -> io::Result<bool> {
-        let layer_name = layer.name();
-        let label = self.store.label_store.get_label(&self.label).await?;
-        match label {
-            None => Err(io::Error::new(io::ErrorKind::NotFound, "label not found")),
-            Some(label) => {
-                self.store.label_store.set_label(&label, layer_name).await?;
+    #[test]
+    fn named_graph_counts() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+        let database = runtime.block_on(store.create("foodb")).unwrap();
+        assert!(runtime.block_on(database.counts()).unwrap().is_none());
 
-                Ok(true)
-            }
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("cow", "likes", "duck"))
+            .unwrap();
+        let base_layer = runtime.block_on(builder.commit()).unwrap();
+        assert!(runtime.block_on(database.set_head(&base_layer)).unwrap());
+
+        let counts = runtime.block_on(database.counts()).unwrap().unwrap();
+        assert_eq!(2, counts.node_count);
+        assert_eq!(2, counts.predicate_count);
+        assert_eq!(1, counts.value_count);
+        assert_eq!(1, counts.chain_depth);
+
+        let mut layer = base_layer;
+        for animal in &["pig", "horse"] {
+            let builder = runtime.block_on(layer.open_write()).unwrap();
+            builder
+                .add_string_triple(StringTriple::new_value(animal, "says", "something"))
+                .unwrap();
+            layer = runtime.block_on(builder.commit()).unwrap();
         }
+        assert!(runtime.block_on(database.set_head(&layer)).unwrap());
+
+        let counts = runtime.block_on(database.counts()).unwrap().unwrap();
+        assert_eq!(4, counts.node_count);
+        assert_eq!(2, counts.predicate_count);
+        assert_eq!(2, counts.value_count);
+        assert_eq!(3, counts.chain_depth);
     }
-}
 
-impl Store {
-    /// Create a new store from the given label and layer store
-    pub fn new<Labels: 'static + LabelStore, Layers: 'static + LayerStore>(
-        label_store: Labels,
-        layer_store: Layers,
-    ) -> Store {
-        Store {
-            label_store: Arc::new(label_store),
-            layer_store: Arc::new(layer_store),
+    #[test]
+    fn remove_subject_and_object_from_builder() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triples(vec![
+                StringTriple::new_value("cow", "says", "moo"),
+                StringTriple::new_node("cow", "likes", "duck"),
+                StringTriple::new_node("cow", "likes", "pig"),
+                StringTriple::new_node("pig", "likes", "duck"),
+                StringTriple::new_node("duck", "likes", "pig"),
+                StringTriple::new_value("duck", "says", "quack"),
+            ])
+            .unwrap();
+        assert_eq!(0, builder.remove_subject("cow").unwrap());
+        let base_layer = runtime.block_on(builder.commit()).unwrap();
+
+        let builder = runtime.block_on(base_layer.open_write()).unwrap();
+        assert_eq!(0, builder.remove_subject("horse").unwrap());
+        assert_eq!(0, builder.remove_object("horse").unwrap());
+        assert_eq!(3, builder.remove_subject("cow").unwrap());
+        assert_eq!(1, builder.remove_object("duck").unwrap());
+        assert_eq!(0, builder.remove_subject("cow").unwrap());
+        let layer = runtime.block_on(builder.commit()).unwrap();
+
+        let result: Vec<_> = layer
+            .triples()
+            .map(|t| layer.id_triple_to_string(&t).unwrap())
+            .collect();
+        assert_eq!(
+            vec![
+                StringTriple::new_node("duck", "likes", "pig"),
+                StringTriple::new_value("duck", "says", "quack"),
+            ],
+            result
+        );
+    }
+
+    #[test]
+    fn create_overwrite_resets_head() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        let database = runtime.block_on(store.create_overwrite("foodb")).unwrap();
+        assert!(runtime.block_on(database.head()).unwrap().is_none());
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let layer = runtime.block_on(builder.commit()).unwrap();
+        assert!(runtime.block_on(database.set_head(&layer)).unwrap());
+        let other = runtime.block_on(store.create("bardb")).unwrap();
+        assert!(runtime.block_on(other.set_head(&layer)).unwrap());
+
+        let database = runtime.block_on(store.create_overwrite("foodb")).unwrap();
+        assert!(runtime.block_on(database.head()).unwrap().is_none());
+
+        // the layer itself is still there for the other database
+        assert_eq!(
+            layer.name(),
+            runtime.block_on(other.head()).unwrap().unwrap().name()
+        );
+        assert!(runtime
+            .block_on(store.get_layer_from_id(layer.name()))
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn par_add_string_triples_matches_sequential_insertion() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        let mut triples = Vec::new();
+        for i in 0..300 {
+            triples.push(StringTriple::new_node(
+                &format!("s{}", i % 40),
+                &format!("p{}", i % 7),
+                &format!("o{}", i % 23),
+            ));
+            triples.push(StringTriple::new_value(
+                &format!("s{}", i % 40),
+                &format!("p{}", i % 5),
+                &format!("v{}", i % 31),
+            ));
+        }
+        let mut shuffled = triples.clone();
+        shuffled.extend(triples.iter().take(50).cloned());
+        shuffled.shuffle(&mut StdRng::seed_from_u64(42));
+
+        let sequential_builder = runtime.block_on(store.create_base_layer()).unwrap();
+        for triple in triples.iter().cloned() {
+            sequential_builder.add_string_triple(triple).unwrap();
+        }
+        let sequential = runtime.block_on(sequential_builder.commit()).unwrap();
+
+        let parallel_builder = runtime.block_on(store.create_base_layer()).unwrap();
+        parallel_builder
+            .par_add_string_triples(shuffled.clone())
+            .unwrap();
+        let parallel = runtime.block_on(parallel_builder.commit()).unwrap();
+
+        let child_triples: Vec<_> = shuffled
+            .iter()
+            .map(|t| StringTriple::new_value(&t.subject, "child", &t.predicate))
+            .collect();
+        let sequential_child_builder = runtime.block_on(sequential.open_write()).unwrap();
+        sequential_child_builder
+            .add_string_triples(child_triples.clone())
+            .unwrap();
+        let sequential_child = runtime.block_on(sequential_child_builder.commit()).unwrap();
+        let parallel_child_builder = runtime.block_on(parallel.open_write()).unwrap();
+        parallel_child_builder
+            .par_add_string_triples(child_triples)
+            .unwrap();
+        let parallel_child = runtime.block_on(parallel_child_builder.commit()).unwrap();
+
+        for (expected, actual) in &[
+            (&sequential, &parallel),
+            (&sequential_child, &parallel_child),
+        ] {
+            assert_eq!(
+                expected.triples().collect::<Vec<_>>(),
+                actual.triples().collect::<Vec<_>>()
+            );
+            assert_eq!(
+                expected.iter_subject_dict().collect::<Vec<_>>(),
+                actual.iter_subject_dict().collect::<Vec<_>>()
+            );
+            assert_eq!(
+                expected.iter_predicate_dict().collect::<Vec<_>>(),
+                actual.iter_predicate_dict().collect::<Vec<_>>()
+            );
+            assert_eq!(
+                expected.iter_value_dict().collect::<Vec<_>>(),
+                actual.iter_value_dict().collect::<Vec<_>>()
+            );
         }
     }
 
-    /// Create a new database with the given name
-    ///
-    /// If the database already exists, this will return an error
-    pub async fn create(&self, label: &str) -> io::Result<NamedGraph> {
-        let label = self.label_store.create_label(label).await?;
-        Ok(NamedGraph::new(label.name, self.clone()))
+    #[test]
+    fn insert_string_triple_reports_new_triples() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        assert!(builder
+            .insert_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap());
+        assert!(!builder
+            .insert_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap());
+        let base_layer = runtime.block_on(builder.commit()).unwrap();
+
+        let builder = runtime.block_on(base_layer.open_write()).unwrap();
+        assert!(!builder
+            .insert_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap());
+        assert!(builder
+            .insert_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap());
+        assert!(!builder
+            .insert_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap());
+        let child_layer = runtime.block_on(builder.commit()).unwrap();
+
+        assert_eq!(1, child_layer.triple_layer_addition_count());
+        assert_eq!(2, child_layer.triple_count());
     }
 
-    /// Open an existing database with the given name, or None if it does not exist
-    pub async fn open(&self, label: &str) -> io::Result<Option<NamedGraph>> {
-        let label = self.label_store.get_label(label).await?;
-        Ok(label.map(|label| NamedGraph::new(label.name, self.clone())))
+    #[test]
+    fn commit_nonempty_skips_builders_without_changes() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        assert!(builder.is_empty());
+        assert!(runtime
+            .block_on(builder.commit_nonempty())
+            .unwrap()
+            .is_none());
+        assert!(!builder.committed());
+
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        assert!(!builder.is_empty());
+        let base_layer = runtime
+            .block_on(builder.commit_nonempty())
+            .unwrap()
+            .unwrap();
+        assert!(!builder.is_empty());
+        match runtime.block_on(builder.commit_nonempty()) {
+            Err(StoreError::BuilderAlreadyCommitted) => {}
+            _ => panic!("expected the second commit to fail"),
+        }
+
+        let builder = runtime.block_on(base_layer.open_write()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        assert!(builder.is_empty());
+        assert!(runtime
+            .block_on(builder.commit_nonempty())
+            .unwrap()
+            .is_none());
+
+        // removing an added triple cancels the addition
+        builder
+            .remove_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        assert!(builder.is_empty());
+
+        builder
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        assert!(!builder.is_empty());
+        let child_layer = runtime
+            .block_on(builder.commit_nonempty())
+            .unwrap()
+            .unwrap();
+        assert_eq!(Some(base_layer.name()), child_layer.parent_name());
     }
 
-    pub async fn get_layer_from_id(&self, layer: [u32; 5]) -> io::Result<Option<StoreLayer>> {
-        let layer = self.layer_store.get_layer(layer).await?;
-        Ok(layer.map(|layer| StoreLayer::wrap(layer, self.clone())))
+    #[test]
+    fn directory_store_with_sync_on_commit() {
+        let mut runtime = Runtime::new().unwrap();
+        let dir = tempdir().unwrap();
+        let store = open_directory_store_with(
+            dir.path(),
+            DirectoryStoreOptions {
+                sync_on_commit: true,
+            },
+        );
+
+        let database = runtime.block_on(store.create("foodb")).unwrap();
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let layer = runtime.block_on(builder.commit()).unwrap();
+        assert!(runtime.block_on(database.set_head(&layer)).unwrap());
+        runtime.block_on(store.flush()).unwrap();
+
+        let reopened = open_directory_store(dir.path());
+        let database = runtime.block_on(reopened.open("foodb")).unwrap().unwrap();
+        let head = runtime.block_on(database.head()).unwrap().unwrap();
+        assert_eq!(layer.name(), head.name());
+        assert!(head.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+
+        runtime.block_on(open_memory_store().flush()).unwrap();
     }
 
-    /// Create a base layer builder, unattached to any database label
-    ///
-    /// After having committed it, use `set_head` on a `NamedGraph` to attach it.
-    pub async fn create_base_layer(&self) -> io::Result<StoreLayerBuilder> {
-        StoreLayerBuilder::new(self.clone()).await
+    #[test]
+    fn store_operations_return_specific_errors() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+
+        let missing = NamedGraph::new("missing".to_string(), store.clone());
+        match runtime.block_on(missing.head()) {
+            Err(StoreError::LabelNotFound(label)) => assert_eq!("missing", label),
+            other => panic!("expected LabelNotFound, got {:?}", other.map(|_| ())),
+        }
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let base = runtime.block_on(builder.commit()).unwrap();
+        assert!(matches!(
+            runtime.block_on(builder.commit()),
+            Err(StoreError::BuilderAlreadyCommitted)
+        ));
+        assert!(matches!(
+            builder.add_string_triple(StringTriple::new_value("pig", "says", "oink")),
+            Err(StoreError::BuilderAlreadyCommitted)
+        ));
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        let other = runtime.block_on(builder.commit()).unwrap();
+        match runtime.block_on(base.squash_upto(&other)) {
+            Err(StoreError::NotAnAncestor(ancestor, descendant)) => {
+                assert_eq!(other.name(), ancestor);
+                assert_eq!(base.name(), descendant);
+            }
+            result => panic!("expected NotAnAncestor, got {:?}", result.map(|_| ())),
+        }
+
+        let id = [1, 2, 3, 4, 5];
+        assert!(runtime
+            .block_on(store.get_layer_from_id(id))
+            .unwrap()
+            .is_none());
+        assert!(matches!(
+            runtime.block_on(store.copy_layer_from(&open_memory_store(), id)),
+            Err(StoreError::LayerNotFound(name)) if name == id
+        ));
+
+        // converting to io::Error and back keeps the variant
+        let err: io::Error = StoreError::LabelNotFound("foo".to_string()).into();
+        assert_eq!(io::ErrorKind::NotFound, err.kind());
+        assert!(matches!(
+            StoreError::from(err),
+            StoreError::LabelNotFound(label) if label == "foo"
+        ));
+
+        let err: StoreError = io::Error::new(io::ErrorKind::BrokenPipe, "pipe closed").into();
+        assert_eq!(io::ErrorKind::BrokenPipe, err.kind());
+        assert!(matches!(err, StoreError::Io(_)));
     }
 
-    pub fn export_layers(&self, layer_ids: Box<dyn Iterator<Item = [u32; 5]>>) -> Vec<u8> {
-        self.layer_store.export_layers(layer_ids)
+    #[test]
+    fn squash_head_moves_label_to_squashed_layer() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+        let database = runtime.block_on(store.create("foodb")).unwrap();
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let base = runtime.block_on(builder.commit()).unwrap();
+        let builder = runtime.block_on(base.open_write()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let child = runtime.block_on(builder.commit()).unwrap();
+        assert!(runtime.block_on(database.set_head(&child)).unwrap());
+
+        let squashed = runtime.block_on(database.squash_head()).unwrap();
+        let head = runtime.block_on(database.head()).unwrap().unwrap();
+        assert_eq!(squashed.name(), head.name());
+        assert!(head.parent_name().is_none());
+        assert!(head.content_eq(&child).unwrap());
+
+        let empty = runtime.block_on(store.create("empty")).unwrap();
+        assert_eq!(
+            io::ErrorKind::InvalidInput,
+            runtime.block_on(empty.squash_head()).err().unwrap().kind()
+        );
     }
-    pub fn import_layers(
-        &self,
-        pack: &[u8],
-        layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
-    ) -> Result<(), io::Error> {
-        self.layer_store.import_layers(pack, layer_ids)
+
+    #[test]
+    fn export_layers_since_leaves_out_known_layers() {
+        let mut runtime = Runtime::new().unwrap();
+        let dir1 = tempdir().unwrap();
+        let store1 = open_directory_store(dir1.path());
+        let dir2 = tempdir().unwrap();
+        let store2 = open_directory_store(dir2.path());
+
+        let builder = runtime.block_on(store1.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let base = runtime.block_on(builder.commit()).unwrap();
+        let first_pack = store1
+            .export_layers(Box::new(vec![base.name()].into_iter()))
+            .unwrap();
+
+        let builder = runtime.block_on(base.open_write()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let child = runtime.block_on(builder.commit()).unwrap();
+        let second_pack = store1
+            .export_layers_since(
+                &[base.name()],
+                Box::new(vec![base.name(), child.name()].into_iter()),
+            )
+            .unwrap();
+
+        // the second pack only contains the child layer
+        let err = store2
+            .import_layers_from(
+                io::Cursor::new(&second_pack),
+                Box::new(vec![base.name()].into_iter()),
+            )
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::NotFound, err.kind());
+
+        store2
+            .import_layers(&first_pack, Box::new(vec![base.name()].into_iter()))
+            .unwrap();
+        store2
+            .import_layers(&second_pack, Box::new(vec![child.name()].into_iter()))
+            .unwrap();
+        let imported = runtime
+            .block_on(store2.get_layer_from_id(child.name()))
+            .unwrap()
+            .unwrap();
+        assert!(imported.content_eq(&child).unwrap());
     }
-}
 
-/// Open a store that is entirely in memory
-///
-/// This is useful for testing purposes, or if the database is only going to be used for caching purposes
-pub fn open_memory_store() -> Store {
-    Store::new(
-        MemoryLabelStore::new(),
-        CachedLayerStore::new(MemoryLayerStore::new(), LockingHashMapLayerCache::new()),
-    )
-}
+    #[test]
+    fn import_pack_missing_layer_from_its_manifest_fails() {
+        let dir = tempdir().unwrap();
+        let store = open_directory_store(dir.path());
 
-/// Open a store that stores its data in the given directory
-pub fn open_directory_store<P: Into<PathBuf>>(path: P) -> Store {
-    let p = path.into();
-    Store::new(
-        DirectoryLabelStore::new(p.clone()),
-        CachedLayerStore::new(DirectoryLayerStore::new(p), LockingHashMapLayerCache::new()),
-    )
-}
+        // a pack whose manifest lists a layer it doesn't contain
+        let mut tar = tar::Builder::new(flate2::write::GzEncoder::new(
+            Vec::new(),
+            flate2::Compression::default(),
+        ));
+        let manifest = format!("{}\n", crate::storage::name_to_string([1, 2, 3, 4, 5]));
+        let mut header = tar::Header::new_gnu();
+        header.set_size(manifest.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append_data(&mut header, "manifest", manifest.as_bytes())
+            .unwrap();
+        let pack = tar.into_inner().unwrap().finish().unwrap();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tempfile::tempdir;
-    use tokio::runtime::Runtime;
+        let err = store
+            .import_layers(&pack, Box::new(std::iter::empty()))
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
 
-    fn create_and_manipulate_database(mut runtime: Runtime, store: Store) {
-        let database = runtime.block_on(store.create("foodb")).unwrap();
+    #[test]
+    fn change_stream_resolves_removals_through_parent() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
 
-        let head = runtime.block_on(database.head()).unwrap();
-        assert!(head.is_none());
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("cow", "likes", "duck"))
+            .unwrap();
+        let base = runtime.block_on(builder.commit()).unwrap();
 
-        let mut builder = runtime.block_on(store.create_base_layer()).unwrap();
+        let builder = runtime.block_on(base.open_write()).unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let child = runtime.block_on(builder.commit()).unwrap();
+
+        let changes: Vec<_> = runtime
+            .block_on(child.change_stream())
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            vec![
+                DiffEntry::Removed(StringTriple::new_value("cow", "says", "moo")),
+                DiffEntry::Added(StringTriple::new_value("pig", "says", "oink")),
+            ],
+            changes
+        );
+
+        let (additions, removals) = child.layer_changes().unwrap();
+        assert_eq!(additions.len() + removals.len(), changes.len());
+
+        let changes: Vec<_> = runtime
+            .block_on(base.change_stream())
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            vec![
+                DiffEntry::Added(StringTriple::new_node("cow", "likes", "duck")),
+                DiffEntry::Added(StringTriple::new_value("cow", "says", "moo")),
+            ],
+            changes
+        );
+    }
+
+    #[test]
+    fn cancelled_operations_are_interrupted() {
+        let mut runtime = Runtime::new().unwrap();
+        let dir = tempdir().unwrap();
+        let store = open_directory_store(dir.path());
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
         builder
             .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
             .unwrap();
-
-        let layer = runtime.block_on(builder.commit()).unwrap();
-        assert!(runtime.block_on(database.set_head(&layer)).unwrap());
-
-        builder = runtime.block_on(layer.open_write()).unwrap();
+        let base = runtime.block_on(builder.commit()).unwrap();
+        let builder = runtime.block_on(base.open_write()).unwrap();
         builder
             .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
             .unwrap();
+        let child = runtime.block_on(builder.commit()).unwrap();
+        let pack = store
+            .export_layers(Box::new(vec![base.name(), child.name()].into_iter()))
+            .unwrap();
 
-        let layer2 = runtime.block_on(builder.commit()).unwrap();
-        assert!(runtime.block_on(database.set_head(&layer2)).unwrap());
-        let layer2_name = layer2.name();
+        let cancel = CancellationToken::new();
+        let squashed = runtime.block_on(child.squash_cancellable(&cancel)).unwrap();
+        assert!(squashed.content_eq(&child).unwrap());
 
-        let layer = runtime.block_on(database.head()).unwrap().unwrap();
+        cancel.cancel();
+        assert!(cancel.clone().is_cancelled());
+        let err = runtime
+            .block_on(child.squash_cancellable(&cancel))
+            .err()
+            .unwrap();
+        assert_eq!(io::ErrorKind::Interrupted, err.kind());
+
+        let err = runtime
+            .block_on(store.collect_garbage_cancellable(&cancel))
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::Interrupted, err.kind());
+        assert!(runtime
+            .block_on(store.get_layer_from_id(child.name()))
+            .unwrap()
+            .is_some());
+
+        let dir2 = tempdir().unwrap();
+        let store2 = open_directory_store(dir2.path());
+        let err = store2
+            .import_layers_cancellable(
+                &pack,
+                Box::new(vec![base.name(), child.name()].into_iter()),
+                &cancel,
+            )
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::Interrupted, err.kind());
+
+        // cancel halfway through the pack, leaving a layer half unpacked
+        let cancel = CancellationToken::new();
+        let mut reader = CancellableReader {
+            inner: CancelAfter {
+                inner: &pack[..],
+                remaining: pack.len() / 2,
+                cancel: cancel.clone(),
+            },
+            cancel: &cancel,
+        };
+        assert!(store2
+            .layer_store
+            .import_layers_from(
+                &mut reader,
+                Box::new(vec![base.name(), child.name()].into_iter()),
+            )
+            .is_err());
+        assert!(cancel.is_cancelled());
+        assert!(runtime
+            .block_on(store2.layer_store.layers())
+            .unwrap()
+            .is_empty());
+
+        store2
+            .import_layers_cancellable(
+                &pack,
+                Box::new(vec![base.name(), child.name()].into_iter()),
+                &CancellationToken::new(),
+            )
+            .unwrap();
+        let imported = runtime
+            .block_on(store2.get_layer_from_id(child.name()))
+            .unwrap()
+            .unwrap();
+        assert!(imported.content_eq(&child).unwrap());
+    }
 
-        assert_eq!(layer2_name, layer.name());
-        assert!(layer.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
-        assert!(layer.string_triple_exists(&StringTriple::new_value("pig", "says", "oink")));
+    // a reader that hands out its input in small reads, cancelling the token after the given amount of bytes
+    struct CancelAfter<'a> {
+        inner: &'a [u8],
+        remaining: usize,
+        cancel: CancellationToken,
     }
 
-    #[test]
-    fn create_and_manipulate_memory_database() {
-        let runtime = Runtime::new().unwrap();
-        let store = open_memory_store();
+    impl<'a> io::Read for CancelAfter<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.remaining == 0 {
+                self.cancel.cancel();
+            }
+            let len = buf.len().min(self.remaining).min(16);
+            let read = self.inner.read(&mut buf[..len])?;
+            self.remaining -= read;
 
-        create_and_manipulate_database(runtime, store);
+            Ok(read)
+        }
     }
 
     #[test]
-    fn create_and_manipulate_directory_database() {
-        let runtime = Runtime::new().unwrap();
-        let dir = tempdir().unwrap();
-        let store = open_directory_store(dir.path());
+    fn compact_dictionary_drops_unused_terms() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
 
-        create_and_manipulate_database(runtime, store);
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("cow", "likes", "duck"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        let base = runtime.block_on(builder.commit()).unwrap();
+        let builder = runtime.block_on(base.open_write()).unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_node("cow", "likes", "duck"))
+            .unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        let child = runtime.block_on(builder.commit()).unwrap();
+
+        let (compacted, dropped) = runtime.block_on(child.compact_dictionary()).unwrap();
+        // duck, quack and likes are no longer used
+        assert_eq!(3, dropped);
+        assert_eq!(2, compacted.node_and_value_count());
+        assert_eq!(1, compacted.predicate_count());
+        assert!(compacted.parent_name().is_none());
+        assert_eq!(
+            child.content_hash().unwrap(),
+            compacted.content_hash().unwrap()
+        );
+
+        let (_, dropped) = runtime.block_on(compacted.compact_dictionary()).unwrap();
+        assert_eq!(0, dropped);
     }
 
     #[test]
-    fn create_layer_and_retrieve_it_by_id() {
+    fn reset_to_empty_keeps_old_layers_until_gc() {
         let mut runtime = Runtime::new().unwrap();
-
         let store = open_memory_store();
+        let database = runtime.block_on(store.create("foodb")).unwrap();
+
         let builder = runtime.block_on(store.create_base_layer()).unwrap();
         builder
             .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
             .unwrap();
-
         let layer = runtime.block_on(builder.commit()).unwrap();
+        std::mem::drop(builder);
+        assert!(runtime.block_on(database.set_head(&layer)).unwrap());
 
-        let id = layer.name();
+        let empty = runtime.block_on(database.reset_to_empty()).unwrap();
+        assert_eq!(0, empty.triple_count());
+        let head = runtime.block_on(database.head()).unwrap().unwrap();
+        assert_eq!(empty.name(), head.name());
 
-        let layer2 = runtime
-            .block_on(store.get_layer_from_id(id))
+        // the old head is left alone until it is collected
+        assert!(runtime
+            .block_on(store.get_layer_from_id(layer.name()))
             .unwrap()
-            .unwrap();
-
-        assert!(layer2.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+            .is_some());
+        assert_eq!(
+            vec![layer.name()],
+            runtime.block_on(store.collect_garbage()).unwrap()
+        );
     }
 
     #[test]
-    fn commit_builder_makes_builder_committed() {
+    fn validate_pack_checks_layers_without_importing() {
         let mut runtime = Runtime::new().unwrap();
+        let dir = tempdir().unwrap();
+        let store = open_directory_store(dir.path());
 
-        let store = open_memory_store();
         let builder = runtime.block_on(store.create_base_layer()).unwrap();
-
         builder
             .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
             .unwrap();
+        let base = runtime.block_on(builder.commit()).unwrap();
+        let builder = runtime.block_on(base.open_write()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let child = runtime.block_on(builder.commit()).unwrap();
 
-        assert!(!builder.committed());
+        let pack = store
+            .export_layers(Box::new(vec![base.name(), child.name()].into_iter()))
+            .unwrap();
+        let mut expected = vec![base.name(), child.name()];
+        expected.sort();
+        assert_eq!(
+            expected,
+            runtime.block_on(Store::validate_pack(&pack)).unwrap()
+        );
 
-        runtime.block_on(builder.commit_no_load()).unwrap();
+        // the parent of the child layer is not in this pack
+        let pack = store
+            .export_layers(Box::new(vec![child.name()].into_iter()))
+            .unwrap();
+        assert_eq!(
+            vec![child.name()],
+            runtime.block_on(Store::validate_pack(&pack)).unwrap()
+        );
 
-        assert!(builder.committed());
+        // rewrite the files of the pack, leaving out those the function returns false for
+        let tamper = |f: &dyn Fn(&std::path::Path, &mut Vec<u8>) -> bool| {
+            let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(&pack[..]));
+            let mut tampered = tar::Builder::new(flate2::write::GzEncoder::new(
+                Vec::new(),
+                flate2::Compression::default(),
+            ));
+            for entry in archive.entries().unwrap() {
+                let mut entry = entry.unwrap();
+                let mut header = entry.header().clone();
+                let path = entry.path().unwrap().into_owned();
+                let mut contents = Vec::new();
+                io::Read::read_to_end(&mut entry, &mut contents).unwrap();
+                if f(&path, &mut contents) {
+                    tampered
+                        .append_data(&mut header, path, &contents[..])
+                        .unwrap();
+                }
+            }
+            tampered.into_inner().unwrap().finish().unwrap()
+        };
+
+        // flip a byte in one of the files, keeping its size
+        let tampered = tamper(&|path, contents| {
+            if path.ends_with("node_dictionary_blocks.pfc") {
+                contents[0] ^= 0xff;
+            }
+            true
+        });
+        assert_eq!(
+            io::ErrorKind::InvalidData,
+            runtime
+                .block_on(Store::validate_pack(&tampered))
+                .unwrap_err()
+                .kind()
+        );
+
+        // without checksums, the child layer still gets parsed
+        let tampered = tamper(&|path, contents| {
+            if path.ends_with("pos_sp_o_adjacency_list_nums.logarray") {
+                let len = contents.len();
+                contents[len - 3] = 2;
+            }
+            !path.ends_with("checksums.sha256")
+        });
+        assert_eq!(
+            io::ErrorKind::InvalidData,
+            runtime
+                .block_on(Store::validate_pack(&tampered))
+                .unwrap_err()
+                .kind()
+        );
+
+        assert_eq!(
+            io::ErrorKind::InvalidData,
+            runtime
+                .block_on(Store::validate_pack(b"not a pack at all"))
+                .unwrap_err()
+                .kind()
+        );
     }
 
     #[test]
-    fn hard_reset() {
+    fn union_independent_base_layers() {
         let mut runtime = Runtime::new().unwrap();
-
         let store = open_memory_store();
-        let database = runtime.block_on(store.create("foodb")).unwrap();
-
-        let builder1 = runtime.block_on(store.create_base_layer()).unwrap();
-        builder1
-            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+        let left = runtime
+            .block_on(store.create_base_layer_from(vec![
+                StringTriple::new_node("cow", "likes", "duck"),
+                StringTriple::new_value("cow", "says", "moo"),
+            ]))
             .unwrap();
-
-        let layer1 = runtime.block_on(builder1.commit()).unwrap();
-
-        assert!(runtime.block_on(database.set_head(&layer1)).unwrap());
-
-        let builder2 = runtime.block_on(store.create_base_layer()).unwrap();
-        builder2
+        let right = runtime
+            .block_on(store.create_base_layer_from(vec![
+                StringTriple::new_node("duck", "likes", "cow"),
+                StringTriple::new_value("pig", "says", "oink"),
+            ]))
+            .unwrap();
+        let child_builder = runtime.block_on(right.open_write()).unwrap();
+        child_builder
             .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
             .unwrap();
+        child_builder
+            .remove_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let right = runtime.block_on(child_builder.commit()).unwrap();
 
-        let layer2 = runtime.block_on(builder2.commit()).unwrap();
-
-        assert!(runtime.block_on(database.force_set_head(&layer2)).unwrap());
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder.union_layer(&left).unwrap();
+        builder.union_layer(&right).unwrap();
+        let layer = runtime.block_on(builder.commit()).unwrap();
 
-        let new_layer = runtime.block_on(database.head()).unwrap().unwrap();
+        let mut expected: Vec<_> = left
+            .string_triples()
+            .chain(right.string_triples())
+            .collect();
+        expected.sort();
+        let mut result: Vec<_> = layer.string_triples().collect();
+        result.sort();
+        assert_eq!(expected, result);
+        assert_eq!(4, result.len());
+
+        assert!(builder.union_layer(&left).is_err());
+    }
 
-        assert!(new_layer.string_triple_exists(&StringTriple::new_value("duck", "says", "quack")));
-        assert!(!new_layer.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+    #[test]
+    fn get_layer_from_id_eager_loads_whole_ancestry() {
+        let mut runtime = Runtime::new().unwrap();
+        let dir = tempdir().unwrap();
+        let directory_store = open_directory_store(dir.path());
+        let memory_store = open_memory_store();
+
+        for store in &[directory_store, memory_store] {
+            let mut layer =
+                runtime
+                    .block_on(store.create_base_layer_from(vec![StringTriple::new_value(
+                        "cow", "says", "moo",
+                    )]))
+                    .unwrap();
+            for i in 0..20 {
+                let builder = runtime.block_on(layer.open_write()).unwrap();
+                builder
+                    .add_string_triple(StringTriple::new_value(&format!("cow{}", i), "says", "moo"))
+                    .unwrap();
+                if i == 10 {
+                    builder
+                        .remove_string_triple(StringTriple::new_value("cow", "says", "moo"))
+                        .unwrap();
+                }
+                layer = runtime.block_on(builder.commit()).unwrap();
+            }
+            let expected: Vec<_> = layer.string_triples().collect();
+            let name = layer.name();
+            std::mem::drop(layer);
+
+            let eager = runtime
+                .block_on(store.get_layer_from_id_eager(name))
+                .unwrap()
+                .unwrap();
+            assert_eq!(expected, eager.string_triples().collect::<Vec<_>>());
+            assert_eq!(21, eager.triple_addition_count());
+            assert!(runtime
+                .block_on(store.get_layer_from_id_eager([0, 0, 0, 0, 1]))
+                .unwrap()
+                .is_none());
+        }
     }
 
     #[test]
-    fn create_two_layers_and_squash() {
+    fn loading_layer_with_incomplete_ancestor_fails() {
         let mut runtime = Runtime::new().unwrap();
+        let dir = tempdir().unwrap();
+        let store = open_directory_store(dir.path());
 
-        let store = open_memory_store();
+        let base = runtime
+            .block_on(
+                store.create_base_layer_from(vec![StringTriple::new_value("cow", "says", "moo")]),
+            )
+            .unwrap();
+        let builder = runtime.block_on(base.open_write()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let child = runtime.block_on(builder.commit()).unwrap();
+
+        let base_name = name_to_string(base.name());
+        std::fs::File::create(
+            dir.path()
+                .join(&base_name[..3])
+                .join(&base_name)
+                .join("incomplete.marker"),
+        )
+        .unwrap();
+
+        // a fresh store, so nothing is cached
+        let store = open_directory_store(dir.path());
+        let err = runtime
+            .block_on(store.get_layer_from_id(child.name()))
+            .err()
+            .unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        let err = runtime
+            .block_on(store.get_layer_from_id_eager(child.name()))
+            .err()
+            .unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert!(runtime
+            .block_on(store.get_layer_from_id(base.name()))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn parallel_work_runs_on_configured_thread_pool() {
+        let mut runtime = Runtime::new().unwrap();
+        let pool = Arc::new(
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(2)
+                .build()
+                .unwrap(),
+        );
+        let store = open_memory_store().with_thread_pool(pool.clone());
         let builder = runtime.block_on(store.create_base_layer()).unwrap();
         builder
-            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .par_add_string_triples((0..100).into_par_iter().map(|i| {
+                assert!(pool.current_thread_index().is_some());
+                StringTriple::new_value(&format!("cow{}", i), "says", "moo")
+            }))
+            .unwrap();
+        let base = runtime.block_on(builder.commit()).unwrap();
+        let builder = runtime.block_on(base.open_write()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("cow0", "says", "moo"))
             .unwrap();
+        let child = runtime.block_on(builder.commit()).unwrap();
 
-        let layer = runtime.block_on(builder.commit()).unwrap();
+        let squashed = runtime.block_on(child.squash()).unwrap();
+        assert!(squashed.content_eq(&child).unwrap());
 
-        let builder2 = runtime.block_on(layer.open_write()).unwrap();
+        let delta_builder = runtime.block_on(base.open_write()).unwrap();
+        delta_builder.apply_delta(&child).unwrap();
+        let delta_applied = runtime.block_on(delta_builder.commit()).unwrap();
+        assert!(delta_applied.content_eq(&child).unwrap());
 
-        builder2
-            .add_string_triple(StringTriple::new_value("dog", "says", "woof"))
+        let diff_builder = runtime.block_on(base.open_write()).unwrap();
+        diff_builder.apply_diff(&squashed).unwrap();
+        let diff_applied = runtime.block_on(diff_builder.commit()).unwrap();
+        assert!(diff_applied.content_eq(&child).unwrap());
+    }
+
+    #[test]
+    fn layer_names_round_trip_through_strings() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = open_memory_store();
+        let layer = runtime
+            .block_on(
+                store.create_base_layer_from(vec![StringTriple::new_value("cow", "says", "moo")]),
+            )
             .unwrap();
 
-        let layer2 = runtime.block_on(builder2.commit()).unwrap();
+        let name = layer.name_string();
+        assert_eq!(40, name.len());
+        assert_eq!(name, name_to_string(layer.name()));
+        let parsed = string_to_name(&name).unwrap();
+        assert_eq!(layer.name(), parsed);
+        assert!(runtime
+            .block_on(store.get_layer_from_id(parsed))
+            .unwrap()
+            .is_some());
 
-        let new = runtime.block_on(layer2.squash()).unwrap();
+        assert_eq!(
+            [0x0123abcd, 0, 1, 0xffffffff, 0x10],
+            string_to_name("0123ABCD0000000000000001ffffffff00000010").unwrap()
+        );
 
-        assert!(new.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
-        assert!(new.string_triple_exists(&StringTriple::new_value("dog", "says", "woof")));
-        assert!(runtime.block_on(new.parent()).unwrap().is_none());
+        for malformed in &[
+            "",
+            "0123abcd",
+            "0123abcd0000000000000001ffffffff000000100",
+            "0123abcd0000000000000001ffffffff0000001g",
+            "+123abcd0000000000000001ffffffff00000010",
+            "0123abcd0000000000000001ffffffff000000é",
+        ] {
+            let error = string_to_name(malformed).err().unwrap();
+            assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        }
     }
 
     #[test]
-    fn apply_a_base_delta() {
+    fn send_triples_to_channel() {
         let mut runtime = Runtime::new().unwrap();
-
         let store = open_memory_store();
-        let builder = runtime.block_on(store.create_base_layer()).unwrap();
-
+        let base = runtime
+            .block_on(store.create_base_layer_from(
+                (0..100).map(|i| StringTriple::new_value(&format!("cow{}", i), "says", "moo")),
+            ))
+            .unwrap();
+        let builder = runtime.block_on(base.open_write()).unwrap();
         builder
-            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .remove_string_triple(StringTriple::new_value("cow0", "says", "moo"))
             .unwrap();
-
         let layer = runtime.block_on(builder.commit()).unwrap();
 
-        let builder2 = runtime.block_on(layer.open_write()).unwrap();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(2);
+        let sender = layer.clone();
+        let sending = runtime.spawn(async move { sender.send_triples(tx).await });
+        let received = runtime.block_on(async move {
+            let mut received = Vec::new();
+            while let Some(triple) = rx.recv().await {
+                received.push(triple);
+            }
+            received
+        });
+        assert!(runtime.block_on(sending).unwrap().is_ok());
+        assert_eq!(layer.string_triples().collect::<Vec<_>>(), received);
+        assert_eq!(99, received.len());
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(2);
+        let sender = layer.clone();
+        let sending = runtime.spawn(async move { sender.send_triples(tx).await });
+        runtime.block_on(async move {
+            rx.recv().await.unwrap();
+        });
+        let error = runtime.block_on(sending).unwrap().err().unwrap();
+        assert_eq!(io::ErrorKind::BrokenPipe, error.kind());
+    }
 
-        builder2
-            .add_string_triple(StringTriple::new_value("dog", "says", "woof"))
-            .unwrap();
+    // a label store where, once `racing` is set, someone else writes
+    // the label right after every read of it
+    #[derive(Clone)]
+    struct RacingLabelStore {
+        inner: MemoryLabelStore,
+        racing: Arc<AtomicBool>,
+    }
 
-        let layer2 = runtime.block_on(builder2.commit()).unwrap();
+    impl LabelStore for RacingLabelStore {
+        fn labels(&self) -> Pin<Box<dyn Future<Output = io::Result<Vec<Label>>> + Send>> {
+            self.inner.labels()
+        }
 
-        let delta_builder_1 = runtime.block_on(store.create_base_layer()).unwrap();
+        fn create_label(
+            &self,
+            name: &str,
+        ) -> Pin<Box<dyn Future<Output = io::Result<Label>> + Send>> {
+            self.inner.create_label(name)
+        }
 
-        delta_builder_1
-            .add_string_triple(StringTriple::new_value("dog", "says", "woof"))
-            .unwrap();
-        delta_builder_1
-            .add_string_triple(StringTriple::new_value("cat", "says", "meow"))
-            .unwrap();
+        fn get_label(
+            &self,
+            name: &str,
+        ) -> Pin<Box<dyn Future<Output = io::Result<Option<Label>>> + Send>> {
+            let inner = self.inner.clone();
+            let racing = self.racing.load(Ordering::SeqCst);
+            let name = name.to_owned();
+            Box::pin(async move {
+                let label = inner.get_label(&name).await?;
+                if let (true, Some(label)) = (racing, &label) {
+                    inner.set_label_option(label, label.layer).await?;
+                }
 
-        let delta_1 = runtime.block_on(delta_builder_1.commit()).unwrap();
+                Ok(label)
+            })
+        }
 
-        let delta_builder_2 = runtime.block_on(delta_1.open_write()).unwrap();
+        fn set_label_option(
+            &self,
+            label: &Label,
+            layer: Option<[u32; 5]>,
+        ) -> Pin<Box<dyn Future<Output = io::Result<Option<Label>>> + Send>> {
+            self.inner.set_label_option(label, layer)
+        }
 
-        delta_builder_2
-            .add_string_triple(StringTriple::new_value("crow", "says", "caw"))
-            .unwrap();
-        delta_builder_2
-            .remove_string_triple(StringTriple::new_value("cat", "says", "meow"))
-            .unwrap();
+        fn cas_label(
+            &self,
+            name: &str,
+            expected: Option<[u32; 5]>,
+            new: Option<[u32; 5]>,
+        ) -> Pin<Box<dyn Future<Output = io::Result<bool>> + Send>> {
+            self.inner.cas_label(name, expected, new)
+        }
 
-        let delta = runtime.block_on(delta_builder_2.commit()).unwrap();
+        fn flush(&self) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+            self.inner.flush()
+        }
+    }
 
-        let rebase_builder = runtime.block_on(layer2.open_write()).unwrap();
+    #[test]
+    fn reset_to_empty_that_loses_a_race_fails() {
+        let mut runtime = Runtime::new().unwrap();
+        let store = Store::new(
+            RacingLabelStore {
+                inner: MemoryLabelStore::new(),
+                racing: Arc::new(AtomicBool::new(true)),
+            },
+            CachedLayerStore::new(MemoryLayerStore::new(), LockingHashMapLayerCache::new()),
+        );
+        let graph = runtime.block_on(store.create("foo")).unwrap();
 
-        let _ = rebase_builder.apply_delta(&delta).unwrap();
+        match runtime.block_on(graph.reset_to_empty()) {
+            Err(StoreError::HeadChanged(label)) => assert_eq!("foo", label),
+            _ => panic!("expected the reset to lose the race"),
+        }
+        assert!(runtime.block_on(graph.head()).unwrap().is_none());
+    }
 
-        let rebase_layer = runtime.block_on(rebase_builder.commit()).unwrap();
+    #[test]
+    fn head_changes_that_lose_a_race_fire_no_events() {
+        let mut runtime = Runtime::new().unwrap();
+        let racing = Arc::new(AtomicBool::new(false));
+        let store = Store::new(
+            RacingLabelStore {
+                inner: MemoryLabelStore::new(),
+                racing: racing.clone(),
+            },
+            CachedLayerStore::new(MemoryLayerStore::new(), LockingHashMapLayerCache::new()),
+        );
+        let graph = runtime.block_on(store.create("foo")).unwrap();
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        let base = runtime.block_on(builder.commit()).unwrap();
+        let builder = runtime.block_on(base.open_write()).unwrap();
+        let child = runtime.block_on(builder.commit()).unwrap();
+        let builder = runtime.block_on(child.open_write()).unwrap();
+        let grandchild = runtime.block_on(builder.commit()).unwrap();
+        assert!(runtime.block_on(graph.set_head(&child)).unwrap());
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events2 = events.clone();
+        store.on_commit(Arc::new(move |event| events2.lock().unwrap().push(event)));
+        racing.store(true, Ordering::SeqCst);
+
+        assert!(!runtime.block_on(graph.set_head(&grandchild)).unwrap());
+        assert!(!runtime.block_on(graph.force_set_head(&grandchild)).unwrap());
+        assert!(!runtime.block_on(graph.rollback_to(&base)).unwrap());
+
+        assert!(events.lock().unwrap().is_empty());
+        assert_eq!(
+            child.name(),
+            runtime.block_on(graph.head()).unwrap().unwrap().name()
+        );
+    }
 
-        assert!(rebase_layer.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
-        assert!(rebase_layer.string_triple_exists(&StringTriple::new_value("crow", "says", "caw")));
-        assert!(rebase_layer.string_triple_exists(&StringTriple::new_value("dog", "says", "woof")));
-        assert!(!rebase_layer.string_triple_exists(&StringTriple::new_value("cat", "says", "meow")));
+    // a label store where someone else moves the label to `move_to`
+    // right before every compare and set
+    #[derive(Clone)]
+    struct MovingLabelStore {
+        inner: MemoryLabelStore,
+        move_to: Arc<Mutex<Option<[u32; 5]>>>,
+    }
+
+    impl LabelStore for MovingLabelStore {
+        fn labels(&self) -> Pin<Box<dyn Future<Output = io::Result<Vec<Label>>> + Send>> {
+            self.inner.labels()
+        }
+
+        fn create_label(
+            &self,
+            name: &str,
+        ) -> Pin<Box<dyn Future<Output = io::Result<Label>> + Send>> {
+            self.inner.create_label(name)
+        }
+
+        fn get_label(
+            &self,
+            name: &str,
+        ) -> Pin<Box<dyn Future<Output = io::Result<Option<Label>>> + Send>> {
+            self.inner.get_label(name)
+        }
+
+        fn set_label_option(
+            &self,
+            label: &Label,
+            layer: Option<[u32; 5]>,
+        ) -> Pin<Box<dyn Future<Output = io::Result<Option<Label>>> + Send>> {
+            self.inner.set_label_option(label, layer)
+        }
+
+        fn cas_label(
+            &self,
+            name: &str,
+            expected: Option<[u32; 5]>,
+            new: Option<[u32; 5]>,
+        ) -> Pin<Box<dyn Future<Output = io::Result<bool>> + Send>> {
+            let inner = self.inner.clone();
+            let move_to = *self.move_to.lock().unwrap();
+            let name = name.to_owned();
+            Box::pin(async move {
+                if let (Some(move_to), Some(label)) = (move_to, inner.get_label(&name).await?) {
+                    inner.set_label(&label, move_to).await?;
+                }
+
+                inner.cas_label(&name, expected, new).await
+            })
+        }
+    }
+
+    #[test]
+    fn squash_head_aborts_when_head_changes_concurrently() {
+        let mut runtime = Runtime::new().unwrap();
+        let move_to = Arc::new(Mutex::new(None));
+        let store = Store::new(
+            MovingLabelStore {
+                inner: MemoryLabelStore::new(),
+                move_to: move_to.clone(),
+            },
+            CachedLayerStore::new(MemoryLayerStore::new(), LockingHashMapLayerCache::new()),
+        );
+        let database = runtime.block_on(store.create("foodb")).unwrap();
+
+        let builder = runtime.block_on(store.create_base_layer()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let base = runtime.block_on(builder.commit()).unwrap();
+        let builder = runtime.block_on(base.open_write()).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let child = runtime.block_on(builder.commit()).unwrap();
+        assert!(runtime.block_on(database.set_head(&child)).unwrap());
+
+        *move_to.lock().unwrap() = Some(base.name());
+        match runtime.block_on(database.squash_head()) {
+            Err(StoreError::HeadChanged(label)) => assert_eq!("foodb", label),
+            _ => panic!("expected the squash to be aborted"),
+        }
+
+        let head = runtime.block_on(database.head()).unwrap().unwrap();
+        assert_eq!(base.name(), head.name());
+        assert!(store.layer_store.pinned_layers().is_empty());
     }
 }
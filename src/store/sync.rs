@@ -6,15 +6,21 @@
 use futures::Future;
 use tokio::runtime::Runtime;
 
+use rayon::iter::IntoParallelIterator;
 use std::io;
 use std::path::PathBuf;
+use std::sync::Arc;
 
 use crate::layer::{
-    IdTriple, Layer, LayerCounts, LayerObjectLookup, LayerPredicateLookup, LayerSubjectLookup,
-    ObjectLookup, ObjectType, PredicateLookup, StringTriple, SubjectLookup,
+    IdTriple, Layer, LayerBuilderOptions, LayerCounts, LayerObjectLookup, LayerPredicateLookup,
+    LayerSubjectLookup, Numeric, ObjectKind, ObjectLookup, ObjectType, PredicateLookup,
+    StringTriple, SubjectLookup,
 };
 use crate::store::{
-    open_directory_store, open_memory_store, NamedGraph, Store, StoreLayer, StoreLayerBuilder,
+    open_directory_store, open_directory_store_readonly, open_directory_store_with,
+    open_memory_store, CancellationToken, CommitCallback, Delta, DiffEntry, DirectoryStoreOptions,
+    LayerPin, LayerVerification, MergeResult, NamedGraph, Snapshot, Store, StoreError, StoreLayer,
+    StoreLayerBuilder,
 };
 
 lazy_static! {
@@ -54,82 +60,626 @@ impl SyncStoreLayerBuilder {
     }
 
     /// Add a string triple
-    pub fn add_string_triple(&self, triple: StringTriple) -> Result<(), io::Error> {
+    pub fn add_string_triple(&self, triple: StringTriple) -> Result<(), StoreError> {
         self.inner.add_string_triple(triple)
     }
 
+    /// Add a string triple, returning false if it is already in the parent layer or this builder
+    pub fn insert_string_triple(&self, triple: StringTriple) -> Result<bool, StoreError> {
+        self.inner.insert_string_triple(triple)
+    }
+
+    /// Add a batch of string triples, acquiring the builder lock only once
+    pub fn add_string_triples<I: IntoIterator<Item = StringTriple>>(
+        &self,
+        triples: I,
+    ) -> Result<(), StoreError> {
+        self.inner.add_string_triples(triples)
+    }
+
+    /// Add a batch of string triples from a parallel iterator, sorting them in parallel first
+    pub fn par_add_string_triples<I: IntoParallelIterator<Item = StringTriple>>(
+        &self,
+        triples: I,
+    ) -> Result<(), StoreError> {
+        self.inner.par_add_string_triples(triples)
+    }
+
+    /// Returns a new blank node label, like `_:b1`
+    pub fn fresh_blank_node(&self) -> String {
+        self.inner.fresh_blank_node()
+    }
+
+    /// Add all triples read from the given reader in N-Triples format, returning the amount of triples read
+    pub fn import_ntriples<R: io::Read>(&self, reader: R) -> Result<usize, StoreError> {
+        self.inner.import_ntriples(reader)
+    }
+
     /// Add an id triple
-    pub fn add_id_triple(&self, triple: IdTriple) -> Result<(), io::Error> {
+    pub fn add_id_triple(&self, triple: IdTriple) -> Result<(), StoreError> {
         self.inner.add_id_triple(triple)
     }
 
-    /// Remove a string triple
-    pub fn remove_string_triple(&self, triple: StringTriple) -> Result<(), io::Error> {
-        self.inner.remove_string_triple(triple)
+    /// Remove a string triple
+    pub fn remove_string_triple(&self, triple: StringTriple) -> Result<(), StoreError> {
+        self.inner.remove_string_triple(triple)
+    }
+
+    /// Remove a batch of string triples, acquiring the builder lock only once
+    pub fn remove_string_triples<I: IntoIterator<Item = StringTriple>>(
+        &self,
+        triples: I,
+    ) -> Result<(), StoreError> {
+        self.inner.remove_string_triples(triples)
+    }
+
+    /// Remove an id triple
+    pub fn remove_id_triple(&self, triple: IdTriple) -> Result<(), StoreError> {
+        self.inner.remove_id_triple(triple)
+    }
+
+    /// Remove all triples in the parent layer with the given subject, returning how many were newly removed
+    pub fn remove_subject(&self, subject: &str) -> Result<usize, StoreError> {
+        self.inner.remove_subject(subject)
+    }
+
+    /// Remove all triples in the parent layer with the given node as their object, returning how many were newly removed
+    pub fn remove_object(&self, object: &str) -> Result<usize, StoreError> {
+        self.inner.remove_object(object)
+    }
+
+    /// Returns a boolean result which is true if this builder has been committed, and false otherwise.
+    pub fn committed(&self) -> bool {
+        self.inner.committed()
+    }
+
+    /// Commit the layer to storage without loading the resulting layer
+    pub fn commit_no_load(&self) -> Result<(), StoreError> {
+        task_sync(self.inner.commit_no_load())
+    }
+
+    /// Commit the layer to storage
+    pub fn commit(&self) -> Result<SyncStoreLayer, StoreError> {
+        let inner = task_sync(self.inner.commit());
+
+        inner.map(|i| SyncStoreLayer::wrap(i))
+    }
+
+    /// Returns true if committing this builder would not change anything relative to its parent
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Commit the layer to storage, unless it would not change anything
+    pub fn commit_nonempty(&self) -> Result<Option<SyncStoreLayer>, StoreError> {
+        let inner = task_sync(self.inner.commit_nonempty());
+
+        inner.map(|i| i.map(SyncStoreLayer::wrap))
+    }
+
+    pub fn apply_delta(&self, delta: &SyncStoreLayer) -> Result<(), StoreError> {
+        self.inner.apply_delta(&delta.inner)
+    }
+
+    pub fn apply_diff(&self, other: &SyncStoreLayer) -> Result<(), StoreError> {
+        self.inner.apply_diff(&other.inner)
+    }
+
+    /// Add every triple of `other` to this builder
+    pub fn union_layer(&self, other: &SyncStoreLayer) -> Result<(), StoreError> {
+        self.inner.union_layer(&other.inner)
+    }
+
+    /// Apply the changes `theirs` made relative to `base` to this builder
+    pub fn merge(
+        &self,
+        base: &SyncStoreLayer,
+        theirs: &SyncStoreLayer,
+    ) -> Result<MergeResult, StoreError> {
+        self.inner.merge(&base.inner, &theirs.inner)
+    }
+
+    /// Apply the additions and removals from the given delta to this builder
+    pub fn apply_struct_delta(&self, delta: &Delta) -> Result<(), StoreError> {
+        self.inner.apply_struct_delta(delta)
+    }
+}
+
+/// A layer that keeps track of the store it came out of, allowing the creation of a layer builder on top of this layer
+#[derive(Clone)]
+pub struct SyncStoreLayer {
+    inner: StoreLayer,
+}
+
+impl SyncStoreLayer {
+    fn wrap(inner: StoreLayer) -> Self {
+        Self { inner }
+    }
+
+    /// The name of this layer as a 40 character hex string
+    pub fn name_string(&self) -> String {
+        self.inner.name_string()
+    }
+
+    /// Create a layer builder based on this layer
+    pub fn open_write(&self) -> Result<SyncStoreLayerBuilder, StoreError> {
+        let inner = task_sync(self.inner.open_write());
+
+        inner.map(|i| SyncStoreLayerBuilder::wrap(i))
+    }
+
+    pub fn parent(&self) -> Result<Option<SyncStoreLayer>, StoreError> {
+        let inner = task_sync(self.inner.parent());
+        inner.map(|p| p.map(|p| SyncStoreLayer { inner: p }))
+    }
+
+    /// Calculate the changes needed to go from this layer to the other layer
+    pub fn delta_to(&self, other: &SyncStoreLayer) -> Result<Delta, StoreError> {
+        self.inner.delta_to(&other.inner)
+    }
+
+    /// Create a new layer on top of the given ancestor, containing all changes between that ancestor and this layer
+    pub fn squash_upto(&self, ancestor: &SyncStoreLayer) -> Result<SyncStoreLayer, StoreError> {
+        let inner = task_sync(self.inner.squash_upto(&ancestor.inner));
+
+        inner.map(SyncStoreLayer::wrap)
+    }
+
+    /// Calculate a hash over the triples in this layer, independent of how the layer was built
+    pub fn content_hash(&self) -> Result<[u8; 32], StoreError> {
+        self.inner.content_hash()
+    }
+
+    /// Returns the triples added and removed by this layer itself, as strings
+    pub fn layer_changes(&self) -> Result<(Vec<StringTriple>, Vec<StringTriple>), StoreError> {
+        self.inner.layer_changes()
+    }
+
+    /// Returns the changes this layer itself made, as strings, ordered by triple id
+    pub fn change_stream(
+        &self,
+    ) -> Result<Box<dyn Iterator<Item = Result<DiffEntry, StoreError>> + Send>, StoreError> {
+        task_sync(self.inner.change_stream())
+    }
+
+    /// Returns true if this layer contains exactly the same triples as the other layer
+    pub fn content_eq(&self, other: &SyncStoreLayer) -> Result<bool, StoreError> {
+        self.inner.content_eq(&other.inner)
+    }
+
+    /// Write all triples in this layer to the given writer in N-Triples format
+    pub fn export_ntriples<W: io::Write>(&self, writer: W) -> Result<(), StoreError> {
+        self.inner.export_ntriples(writer)
+    }
+
+    /// Lazily calculate the changes needed to go from this layer to the other layer
+    pub fn diff(
+        &self,
+        other: &SyncStoreLayer,
+    ) -> Result<impl Iterator<Item = DiffEntry> + Send, StoreError> {
+        self.inner.diff(&other.inner)
+    }
+
+    /// Pin this layer, keeping it and its ancestors safe from garbage collection until the returned guard is dropped
+    pub fn pin(&self) -> Result<LayerPin, StoreError> {
+        self.inner.pin()
+    }
+
+    /// Returns this layer, followed by each of its ancestors down to the base layer
+    pub fn ancestry(&self) -> Result<Vec<SyncStoreLayer>, StoreError> {
+        let inner = task_sync(self.inner.ancestry());
+
+        inner.map(|layers| layers.into_iter().map(SyncStoreLayer::wrap).collect())
+    }
+
+    pub fn total_heap_size(&self) -> Result<usize, StoreError> {
+        task_sync(self.inner.total_heap_size())
+    }
+
+    pub fn squash(&self) -> Result<SyncStoreLayer, StoreError> {
+        let inner = task_sync(self.inner.clone().squash());
+
+        inner.map(|i| SyncStoreLayer::wrap(i))
+    }
+
+    /// Create a new base layer consisting of all triples in this layer, stopping early if the token is cancelled
+    pub fn squash_cancellable(
+        &self,
+        cancel: &CancellationToken,
+    ) -> Result<SyncStoreLayer, StoreError> {
+        task_sync(self.inner.squash_cancellable(cancel)).map(SyncStoreLayer::wrap)
+    }
+
+    /// Create a new base layer with the triples of this layer, and only the terms those triples use
+    pub fn compact_dictionary(&self) -> Result<(SyncStoreLayer, usize), StoreError> {
+        task_sync(self.inner.compact_dictionary())
+            .map(|(layer, dropped)| (SyncStoreLayer::wrap(layer), dropped))
+    }
+
+    pub fn rollup(&self) -> Result<SyncStoreLayer, StoreError> {
+        let inner = task_sync(self.inner.clone().rollup());
+
+        inner.map(SyncStoreLayer::wrap)
+    }
+}
+
+impl Layer for SyncStoreLayer {
+    fn name(&self) -> [u32; 5] {
+        self.inner.name()
+    }
+
+    fn parent_name(&self) -> Option<[u32; 5]> {
+        self.inner.parent_name()
+    }
+
+    fn node_and_value_count(&self) -> usize {
+        self.inner.node_and_value_count()
+    }
+
+    fn predicate_count(&self) -> usize {
+        self.inner.predicate_count()
+    }
+
+    fn subject_id(&self, subject: &str) -> Option<u64> {
+        self.inner.subject_id(subject)
+    }
+
+    fn predicate_id(&self, predicate: &str) -> Option<u64> {
+        self.inner.predicate_id(predicate)
+    }
+
+    fn object_node_id(&self, object: &str) -> Option<u64> {
+        self.inner.object_node_id(object)
+    }
+
+    fn object_value_id(&self, object: &str) -> Option<u64> {
+        self.inner.object_value_id(object)
+    }
+
+    fn object_id_of(&self, object: &ObjectType) -> Option<u64> {
+        self.inner.object_id_of(object)
+    }
+
+    fn id_subject(&self, id: u64) -> Option<String> {
+        self.inner.id_subject(id)
+    }
+
+    fn id_predicate(&self, id: u64) -> Option<String> {
+        self.inner.id_predicate(id)
+    }
+
+    fn id_object(&self, id: u64) -> Option<ObjectType> {
+        self.inner.id_object(id)
+    }
+
+    fn object_type_of_id(&self, id: u64) -> Option<ObjectKind> {
+        self.inner.object_type_of_id(id)
+    }
+
+    fn subjects_with_prefix(&self, prefix: &str) -> Box<dyn Iterator<Item = (u64, String)>> {
+        self.inner.subjects_with_prefix(prefix)
+    }
+
+    fn predicates_with_prefix(&self, prefix: &str) -> Box<dyn Iterator<Item = (u64, String)>> {
+        self.inner.predicates_with_prefix(prefix)
+    }
+
+    fn object_nodes_with_prefix(&self, prefix: &str) -> Box<dyn Iterator<Item = (u64, String)>> {
+        self.inner.object_nodes_with_prefix(prefix)
+    }
+
+    fn subjects(&self) -> Box<dyn Iterator<Item = Box<dyn SubjectLookup>>> {
+        self.inner.subjects()
+    }
+
+    fn subject_additions(&self) -> Box<dyn Iterator<Item = Box<dyn LayerSubjectLookup>>> {
+        self.inner.subject_additions()
+    }
+
+    fn subject_removals(&self) -> Box<dyn Iterator<Item = Box<dyn LayerSubjectLookup>>> {
+        self.inner.subject_removals()
+    }
+
+    fn lookup_subject(&self, subject: u64) -> Option<Box<dyn SubjectLookup>> {
+        self.inner.lookup_subject(subject)
+    }
+
+    fn lookup_subject_addition(&self, subject: u64) -> Option<Box<dyn LayerSubjectLookup>> {
+        self.inner.lookup_subject_addition(subject)
+    }
+
+    fn lookup_subject_removal(&self, subject: u64) -> Option<Box<dyn LayerSubjectLookup>> {
+        self.inner.lookup_subject_removal(subject)
+    }
+
+    fn objects(&self) -> Box<dyn Iterator<Item = Box<dyn ObjectLookup>>> {
+        self.inner.objects()
+    }
+
+    fn object_additions(&self) -> Box<dyn Iterator<Item = Box<dyn LayerObjectLookup>>> {
+        self.inner.object_additions()
+    }
+
+    fn object_removals(&self) -> Box<dyn Iterator<Item = Box<dyn LayerObjectLookup>>> {
+        self.inner.object_removals()
+    }
+
+    fn lookup_object(&self, object: u64) -> Option<Box<dyn ObjectLookup>> {
+        self.inner.lookup_object(object)
+    }
+
+    fn lookup_object_addition(&self, object: u64) -> Option<Box<dyn LayerObjectLookup>> {
+        self.inner.lookup_object_addition(object)
+    }
+
+    fn lookup_object_removal(&self, object: u64) -> Option<Box<dyn LayerObjectLookup>> {
+        self.inner.lookup_object_removal(object)
+    }
+
+    fn predicates(&self) -> Box<dyn Iterator<Item = Box<dyn PredicateLookup>>> {
+        self.inner.predicates()
+    }
+
+    fn predicate_additions(&self) -> Box<dyn Iterator<Item = Box<dyn LayerPredicateLookup>>> {
+        self.inner.predicate_additions()
+    }
+
+    fn predicate_removals(&self) -> Box<dyn Iterator<Item = Box<dyn LayerPredicateLookup>>> {
+        self.inner.predicate_removals()
+    }
+
+    fn lookup_predicate(&self, predicate: u64) -> Option<Box<dyn PredicateLookup>> {
+        self.inner.lookup_predicate(predicate)
+    }
+
+    fn lookup_predicate_addition(&self, predicate: u64) -> Option<Box<dyn LayerPredicateLookup>> {
+        self.inner.lookup_predicate_addition(predicate)
+    }
+
+    fn lookup_predicate_removal(&self, predicate: u64) -> Option<Box<dyn LayerPredicateLookup>> {
+        self.inner.lookup_predicate_removal(predicate)
+    }
+
+    fn triple_exists(&self, subject: u64, predicate: u64, object: u64) -> bool {
+        self.inner.triple_exists(subject, predicate, object)
+    }
+
+    fn triples_exist(&self, triples: &[(u64, u64, u64)]) -> Vec<bool> {
+        self.inner.triples_exist(triples)
+    }
+
+    fn triple_addition_exists(&self, subject: u64, predicate: u64, object: u64) -> bool {
+        self.inner
+            .triple_addition_exists(subject, predicate, object)
+    }
+
+    fn triple_removal_exists(&self, subject: u64, predicate: u64, object: u64) -> bool {
+        self.inner.triple_removal_exists(subject, predicate, object)
+    }
+
+    fn triples(&self) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triples()
+    }
+
+    fn triple_additions(&self) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triple_additions()
+    }
+
+    fn triple_removals(&self) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triple_removals()
+    }
+
+    fn triples_s(&self, subject: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triples_s(subject)
+    }
+
+    fn triple_additions_s(&self, subject: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triple_additions_s(subject)
+    }
+
+    fn triple_removals_s(&self, subject: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triple_removals_s(subject)
+    }
+
+    fn triples_sp(
+        &self,
+        subject: u64,
+        predicate: u64,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triples_sp(subject, predicate)
+    }
+
+    fn triple_additions_sp(
+        &self,
+        subject: u64,
+        predicate: u64,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triple_additions_sp(subject, predicate)
+    }
+
+    fn triple_removals_sp(
+        &self,
+        subject: u64,
+        predicate: u64,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triple_removals_sp(subject, predicate)
+    }
+
+    fn triples_p(&self, predicate: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triples_p(predicate)
+    }
+
+    fn predicate_occurrence_count(&self, predicate: u64) -> usize {
+        self.inner.predicate_occurrence_count(predicate)
+    }
+
+    fn nth_triple_with_predicate(&self, predicate: u64, n: usize) -> Option<IdTriple> {
+        self.inner.nth_triple_with_predicate(predicate, n)
+    }
+
+    fn predicate_histogram(&self) -> Vec<(u64, usize)> {
+        self.inner.predicate_histogram()
+    }
+
+    fn string_triples(&self) -> Box<dyn Iterator<Item = StringTriple> + Send> {
+        self.inner.string_triples()
+    }
+
+    fn string_triple_additions(&self) -> Box<dyn Iterator<Item = StringTriple> + Send> {
+        self.inner.string_triple_additions()
+    }
+
+    fn string_triple_removals(&self) -> Box<dyn Iterator<Item = StringTriple> + Send> {
+        self.inner.string_triple_removals()
+    }
+
+    fn iter_subject_dict(&self) -> Box<dyn Iterator<Item = (u64, String)> + Send> {
+        self.inner.iter_subject_dict()
+    }
+
+    fn distinct_subjects(&self) -> usize {
+        self.inner.distinct_subjects()
+    }
+
+    fn distinct_predicates(&self) -> usize {
+        self.inner.distinct_predicates()
+    }
+
+    fn distinct_objects(&self) -> usize {
+        self.inner.distinct_objects()
+    }
+
+    fn iter_predicate_dict(&self) -> Box<dyn Iterator<Item = (u64, String)> + Send> {
+        self.inner.iter_predicate_dict()
+    }
+
+    fn iter_value_dict(&self) -> Box<dyn Iterator<Item = (u64, ObjectType)> + Send> {
+        self.inner.iter_value_dict()
+    }
+
+    fn triple_additions_p(&self, predicate: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triple_additions_p(predicate)
+    }
+
+    fn triple_removals_p(&self, predicate: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triple_removals_p(predicate)
+    }
+
+    fn triples_o(&self, object: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triples_o(object)
+    }
+
+    fn triples_by_object(&self) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triples_by_object()
+    }
+
+    fn out_neighbors(&self, subject: u64) -> Box<dyn Iterator<Item = (u64, u64)> + Send> {
+        self.inner.out_neighbors(subject)
+    }
+
+    fn in_neighbors(&self, object: u64) -> Box<dyn Iterator<Item = (u64, u64)> + Send> {
+        self.inner.in_neighbors(object)
+    }
+
+    fn triples_page(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triples_page(offset, limit)
+    }
+
+    fn triples_with_object_value_range(
+        &self,
+        low: &str,
+        high: &str,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triples_with_object_value_range(low, high)
+    }
+
+    fn triples_with_numeric_object_range(
+        &self,
+        low: Numeric,
+        high: Numeric,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triples_with_numeric_object_range(low, high)
+    }
+
+    fn triple_additions_o(&self, object: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triple_additions_o(object)
     }
 
-    /// Remove an id triple
-    pub fn remove_id_triple(&self, triple: IdTriple) -> Result<(), io::Error> {
-        self.inner.remove_id_triple(triple)
+    fn triple_removals_o(&self, object: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triple_removals_o(object)
     }
 
-    /// Returns a boolean result which is true if this builder has been committed, and false otherwise.
-    pub fn committed(&self) -> bool {
-        self.inner.committed()
+    fn triple_additions_po(
+        &self,
+        predicate: u64,
+        object: u64,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triple_additions_po(predicate, object)
     }
 
-    /// Commit the layer to storage without loading the resulting layer
-    pub fn commit_no_load(&self) -> Result<(), io::Error> {
-        task_sync(self.inner.commit_no_load())
+    fn triple_removals_po(
+        &self,
+        predicate: u64,
+        object: u64,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triple_removals_po(predicate, object)
     }
 
-    /// Commit the layer to storage
-    pub fn commit(&self) -> Result<SyncStoreLayer, io::Error> {
-        let inner = task_sync(self.inner.commit());
-
-        inner.map(|i| SyncStoreLayer::wrap(i))
+    fn triples_po(&self, predicate: u64, object: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triples_po(predicate, object)
     }
 
-    pub fn apply_delta(&self, delta: &SyncStoreLayer) -> Result<(), io::Error> {
-        self.inner.apply_delta(&delta.inner)
+    fn clone_boxed(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
     }
 
-    pub fn apply_diff(&self, other: &SyncStoreLayer) -> Result<(), io::Error> {
-        self.inner.apply_diff(&other.inner)
+    fn heap_size(&self) -> usize {
+        self.inner.heap_size()
     }
-}
 
-/// A layer that keeps track of the store it came out of, allowing the creation of a layer builder on top of this layer
-#[derive(Clone)]
-pub struct SyncStoreLayer {
-    inner: StoreLayer,
-}
+    fn triple_layer_addition_count(&self) -> usize {
+        self.inner.triple_layer_addition_count()
+    }
 
-impl SyncStoreLayer {
-    fn wrap(inner: StoreLayer) -> Self {
-        Self { inner }
+    fn triple_layer_removal_count(&self) -> usize {
+        self.inner.triple_layer_removal_count()
     }
 
-    /// Create a layer builder based on this layer
-    pub fn open_write(&self) -> Result<SyncStoreLayerBuilder, io::Error> {
-        let inner = task_sync(self.inner.open_write());
+    fn triple_addition_count(&self) -> usize {
+        self.inner.triple_addition_count()
+    }
 
-        inner.map(|i| SyncStoreLayerBuilder::wrap(i))
+    fn triple_removal_count(&self) -> usize {
+        self.inner.triple_removal_count()
     }
 
-    pub fn parent(&self) -> Result<Option<SyncStoreLayer>, io::Error> {
-        let inner = task_sync(self.inner.parent());
-        inner.map(|p| p.map(|p| SyncStoreLayer { inner: p }))
+    fn all_counts(&self) -> LayerCounts {
+        self.inner.all_counts()
     }
+}
 
-    pub fn squash(&self) -> Result<SyncStoreLayer, io::Error> {
-        let inner = task_sync(self.inner.clone().squash());
+/// A read-only view on a layer that keeps it safe from garbage collection
+#[derive(Clone)]
+pub struct SyncSnapshot {
+    inner: Snapshot,
+}
 
-        inner.map(|i| SyncStoreLayer::wrap(i))
+impl SyncSnapshot {
+    fn wrap(inner: Snapshot) -> Self {
+        Self { inner }
+    }
+
+    /// The layer this snapshot is pinning
+    pub fn layer(&self) -> SyncStoreLayer {
+        SyncStoreLayer::wrap(self.inner.layer().clone())
     }
 }
 
-impl Layer for SyncStoreLayer {
+impl Layer for SyncSnapshot {
     fn name(&self) -> [u32; 5] {
         self.inner.name()
     }
@@ -162,6 +712,10 @@ impl Layer for SyncStoreLayer {
         self.inner.object_value_id(object)
     }
 
+    fn object_id_of(&self, object: &ObjectType) -> Option<u64> {
+        self.inner.object_id_of(object)
+    }
+
     fn id_subject(&self, id: u64) -> Option<String> {
         self.inner.id_subject(id)
     }
@@ -174,6 +728,22 @@ impl Layer for SyncStoreLayer {
         self.inner.id_object(id)
     }
 
+    fn object_type_of_id(&self, id: u64) -> Option<ObjectKind> {
+        self.inner.object_type_of_id(id)
+    }
+
+    fn subjects_with_prefix(&self, prefix: &str) -> Box<dyn Iterator<Item = (u64, String)>> {
+        self.inner.subjects_with_prefix(prefix)
+    }
+
+    fn predicates_with_prefix(&self, prefix: &str) -> Box<dyn Iterator<Item = (u64, String)>> {
+        self.inner.predicates_with_prefix(prefix)
+    }
+
+    fn object_nodes_with_prefix(&self, prefix: &str) -> Box<dyn Iterator<Item = (u64, String)>> {
+        self.inner.object_nodes_with_prefix(prefix)
+    }
+
     fn subjects(&self) -> Box<dyn Iterator<Item = Box<dyn SubjectLookup>>> {
         self.inner.subjects()
     }
@@ -250,6 +820,10 @@ impl Layer for SyncStoreLayer {
         self.inner.triple_exists(subject, predicate, object)
     }
 
+    fn triples_exist(&self, triples: &[(u64, u64, u64)]) -> Vec<bool> {
+        self.inner.triples_exist(triples)
+    }
+
     fn triple_addition_exists(&self, subject: u64, predicate: u64, object: u64) -> bool {
         self.inner
             .triple_addition_exists(subject, predicate, object)
@@ -311,6 +885,54 @@ impl Layer for SyncStoreLayer {
         self.inner.triples_p(predicate)
     }
 
+    fn predicate_occurrence_count(&self, predicate: u64) -> usize {
+        self.inner.predicate_occurrence_count(predicate)
+    }
+
+    fn nth_triple_with_predicate(&self, predicate: u64, n: usize) -> Option<IdTriple> {
+        self.inner.nth_triple_with_predicate(predicate, n)
+    }
+
+    fn predicate_histogram(&self) -> Vec<(u64, usize)> {
+        self.inner.predicate_histogram()
+    }
+
+    fn string_triples(&self) -> Box<dyn Iterator<Item = StringTriple> + Send> {
+        self.inner.string_triples()
+    }
+
+    fn string_triple_additions(&self) -> Box<dyn Iterator<Item = StringTriple> + Send> {
+        self.inner.string_triple_additions()
+    }
+
+    fn string_triple_removals(&self) -> Box<dyn Iterator<Item = StringTriple> + Send> {
+        self.inner.string_triple_removals()
+    }
+
+    fn iter_subject_dict(&self) -> Box<dyn Iterator<Item = (u64, String)> + Send> {
+        self.inner.iter_subject_dict()
+    }
+
+    fn distinct_subjects(&self) -> usize {
+        self.inner.distinct_subjects()
+    }
+
+    fn distinct_predicates(&self) -> usize {
+        self.inner.distinct_predicates()
+    }
+
+    fn distinct_objects(&self) -> usize {
+        self.inner.distinct_objects()
+    }
+
+    fn iter_predicate_dict(&self) -> Box<dyn Iterator<Item = (u64, String)> + Send> {
+        self.inner.iter_predicate_dict()
+    }
+
+    fn iter_value_dict(&self) -> Box<dyn Iterator<Item = (u64, ObjectType)> + Send> {
+        self.inner.iter_value_dict()
+    }
+
     fn triple_additions_p(&self, predicate: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
         self.inner.triple_additions_p(predicate)
     }
@@ -323,6 +945,42 @@ impl Layer for SyncStoreLayer {
         self.inner.triples_o(object)
     }
 
+    fn triples_by_object(&self) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triples_by_object()
+    }
+
+    fn out_neighbors(&self, subject: u64) -> Box<dyn Iterator<Item = (u64, u64)> + Send> {
+        self.inner.out_neighbors(subject)
+    }
+
+    fn in_neighbors(&self, object: u64) -> Box<dyn Iterator<Item = (u64, u64)> + Send> {
+        self.inner.in_neighbors(object)
+    }
+
+    fn triples_page(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triples_page(offset, limit)
+    }
+
+    fn triples_with_object_value_range(
+        &self,
+        low: &str,
+        high: &str,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triples_with_object_value_range(low, high)
+    }
+
+    fn triples_with_numeric_object_range(
+        &self,
+        low: Numeric,
+        high: Numeric,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triples_with_numeric_object_range(low, high)
+    }
+
     fn triple_additions_o(&self, object: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
         self.inner.triple_additions_o(object)
     }
@@ -331,10 +989,34 @@ impl Layer for SyncStoreLayer {
         self.inner.triple_removals_o(object)
     }
 
+    fn triple_additions_po(
+        &self,
+        predicate: u64,
+        object: u64,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triple_additions_po(predicate, object)
+    }
+
+    fn triple_removals_po(
+        &self,
+        predicate: u64,
+        object: u64,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triple_removals_po(predicate, object)
+    }
+
+    fn triples_po(&self, predicate: u64, object: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triples_po(predicate, object)
+    }
+
     fn clone_boxed(&self) -> Box<dyn Layer> {
         Box::new(self.clone())
     }
 
+    fn heap_size(&self) -> usize {
+        self.inner.heap_size()
+    }
+
     fn triple_layer_addition_count(&self) -> usize {
         self.inner.triple_layer_addition_count()
     }
@@ -377,20 +1059,79 @@ impl SyncNamedGraph {
     }
 
     /// Returns the layer this database points at
-    pub fn head(&self) -> Result<Option<SyncStoreLayer>, io::Error> {
+    pub fn head(&self) -> Result<Option<SyncStoreLayer>, StoreError> {
         let inner = task_sync(self.inner.head());
 
         inner.map(|i| i.map(|i| SyncStoreLayer::wrap(i)))
     }
 
+    /// Returns the layer this database points at, together with the version of the label
+    pub fn head_with_version(&self) -> Result<(Option<SyncStoreLayer>, u64), StoreError> {
+        let inner = task_sync(self.inner.head_with_version());
+
+        inner.map(|(layer, version)| (layer.map(SyncStoreLayer::wrap), version))
+    }
+
+    /// Returns a snapshot of the layer this database points at
+    ///
+    /// The snapshot keeps its layer safe from garbage collection
+    /// until it is dropped. It can be queried directly through `Layer`.
+    pub fn snapshot(&self) -> Result<Option<SyncSnapshot>, StoreError> {
+        let inner = task_sync(self.inner.snapshot());
+
+        inner.map(|s| s.map(SyncSnapshot::wrap))
+    }
+
+    /// Returns the counts of the current head layer, or None if there is no head
+    pub fn counts(&self) -> Result<Option<LayerCounts>, StoreError> {
+        task_sync(self.inner.counts())
+    }
+
+    /// Returns the current version of the label for this database, or None if the label no longer exists
+    pub fn head_version(&self) -> Result<Option<u64>, StoreError> {
+        task_sync(self.inner.head_version())
+    }
+
     /// Set the database label to the given layer if it is a valid ancestor, returning false otherwise
-    pub fn set_head(&self, layer: &SyncStoreLayer) -> Result<bool, io::Error> {
+    pub fn set_head(&self, layer: &SyncStoreLayer) -> Result<bool, StoreError> {
         task_sync(self.inner.set_head(&layer.inner))
     }
 
-    pub fn force_set_head(&self, layer: &SyncStoreLayer) -> Result<bool, io::Error> {
+    pub fn force_set_head(&self, layer: &SyncStoreLayer) -> Result<bool, StoreError> {
         task_sync(self.inner.force_set_head(&layer.inner))
     }
+
+    /// Roll the database label back to the given layer, if it is an ancestor of the current head
+    pub fn rollback_to(&self, layer: &SyncStoreLayer) -> Result<bool, StoreError> {
+        task_sync(self.inner.rollback_to(&layer.inner))
+    }
+
+    /// Set the database label to the given layer, but only if it currently points at `expected`
+    pub fn compare_and_set_head(
+        &self,
+        expected: Option<&SyncStoreLayer>,
+        new: &SyncStoreLayer,
+    ) -> Result<bool, StoreError> {
+        task_sync(
+            self.inner
+                .compare_and_set_head(expected.map(|l| &l.inner), &new.inner),
+        )
+    }
+
+    /// Point the database at a new, empty base layer, returning that layer
+    pub fn reset_to_empty(&self) -> Result<SyncStoreLayer, StoreError> {
+        task_sync(self.inner.reset_to_empty()).map(SyncStoreLayer::wrap)
+    }
+
+    /// Squash the head of this database, and point the database at the squashed layer
+    pub fn squash_head(&self) -> Result<SyncStoreLayer, StoreError> {
+        task_sync(self.inner.squash_head()).map(SyncStoreLayer::wrap)
+    }
+
+    /// The amount of bytes the head of this database and all its ancestors take up in storage
+    pub fn disk_size(&self) -> Result<u64, StoreError> {
+        task_sync(self.inner.disk_size())
+    }
 }
 
 /// A store, storing a set of layers and database labels pointing to these layers
@@ -407,49 +1148,251 @@ impl SyncStore {
         Self { inner }
     }
 
+    /// Name newly committed layers after their content instead of randomly
+    ///
+    /// See `Store::with_content_addressed_names`.
+    pub fn with_content_addressed_names(self) -> Self {
+        Self::wrap(self.inner.with_content_addressed_names())
+    }
+
+    /// Do the parallel work of this store on the given thread pool instead of the global one
+    ///
+    /// See `Store::with_thread_pool`.
+    pub fn with_thread_pool(self, pool: Arc<rayon::ThreadPool>) -> Self {
+        Self::wrap(self.inner.with_thread_pool(pool))
+    }
+
+    /// Register a callback to be called after every layer commit and head change in this store
+    pub fn on_commit(&self, f: CommitCallback) {
+        self.inner.on_commit(f)
+    }
+
     /// Create a new database with the given name
     ///
     /// If the database already exists, this will return an error
-    pub fn create(&self, label: &str) -> Result<SyncNamedGraph, io::Error> {
+    pub fn create(&self, label: &str) -> Result<SyncNamedGraph, StoreError> {
         let inner = task_sync(self.inner.create(label));
 
         inner.map(|i| SyncNamedGraph::wrap(i))
     }
 
     /// Open an existing database with the given name, or None if it does not exist
-    pub fn open(&self, label: &str) -> Result<Option<SyncNamedGraph>, io::Error> {
+    pub fn open(&self, label: &str) -> Result<Option<SyncNamedGraph>, StoreError> {
         let inner = task_sync(self.inner.open(label));
 
         inner.map(|i| i.map(|i| SyncNamedGraph::wrap(i)))
     }
 
-    pub fn get_layer_from_id(
+    /// Open the database with the given name, creating it if it does not exist yet
+    pub fn create_or_open(&self, label: &str) -> Result<SyncNamedGraph, StoreError> {
+        let inner = task_sync(self.inner.create_or_open(label));
+
+        inner.map(SyncNamedGraph::wrap)
+    }
+
+    /// Create a database with the given name, or reset it to have no head if it exists already
+    pub fn create_overwrite(&self, label: &str) -> Result<SyncNamedGraph, StoreError> {
+        let inner = task_sync(self.inner.create_overwrite(label));
+
+        inner.map(SyncNamedGraph::wrap)
+    }
+
+    /// Set the heads of several databases at once, returning false if any of them could not be set
+    pub fn set_heads(
         &self,
-        layer: [u32; 5],
-    ) -> Result<Option<SyncStoreLayer>, std::io::Error> {
+        updates: &[(&SyncNamedGraph, &SyncStoreLayer)],
+    ) -> Result<bool, StoreError> {
+        let updates: Vec<_> = updates
+            .iter()
+            .map(|(graph, layer)| (&graph.inner, &layer.inner))
+            .collect();
+
+        task_sync(self.inner.set_heads(&updates))
+    }
+
+    /// Returns the nearest layer that is an ancestor of both given layers, or None if they share no ancestry
+    pub fn common_ancestor(
+        &self,
+        a: [u32; 5],
+        b: [u32; 5],
+    ) -> Result<Option<[u32; 5]>, StoreError> {
+        task_sync(self.inner.common_ancestor(a, b))
+    }
+
+    /// Copy a layer, along with any of its ancestors that are missing, from another store into this one
+    pub fn copy_layer_from(
+        &self,
+        source: &SyncStore,
+        id: [u32; 5],
+    ) -> Result<SyncStoreLayer, StoreError> {
+        let inner = task_sync(self.inner.copy_layer_from(&source.inner, id));
+
+        inner.map(SyncStoreLayer::wrap)
+    }
+
+    /// Check the files of the given layer against the checksums recorded when it was committed
+    pub fn verify_layer(&self, layer: [u32; 5]) -> Result<LayerVerification, StoreError> {
+        task_sync(self.inner.verify_layer(layer))
+    }
+
+    /// The amount of bytes the given layer takes up in storage, not counting its ancestors
+    pub fn layer_disk_size(&self, layer: [u32; 5]) -> Result<u64, StoreError> {
+        task_sync(self.inner.layer_disk_size(layer))
+    }
+
+    /// The amount of bytes taken up in storage by all layers reachable from a database label
+    pub fn total_disk_size(&self) -> Result<u64, StoreError> {
+        task_sync(self.inner.total_disk_size())
+    }
+
+    /// Make sure all layers and labels have been written to disk
+    pub fn flush(&self) -> Result<(), StoreError> {
+        task_sync(self.inner.flush())
+    }
+
+    /// Verify all layers reachable from a database label, returning the ones found to be corrupt
+    pub fn verify_all(&self) -> Result<Vec<[u32; 5]>, StoreError> {
+        task_sync(self.inner.verify_all())
+    }
+
+    /// Returns the names of all databases in this store, in sorted order
+    pub fn labels(&self) -> Result<Vec<String>, StoreError> {
+        task_sync(self.inner.labels())
+    }
+
+    pub fn get_layer_from_id(&self, layer: [u32; 5]) -> Result<Option<SyncStoreLayer>, StoreError> {
         let inner = task_sync(self.inner.get_layer_from_id(layer));
 
         inner.map(|layer| layer.map(|l| SyncStoreLayer::wrap(l)))
     }
 
+    /// Get a layer by its id, reading its ancestors concurrently
+    pub fn get_layer_from_id_eager(
+        &self,
+        layer: [u32; 5],
+    ) -> Result<Option<SyncStoreLayer>, StoreError> {
+        let inner = task_sync(self.inner.get_layer_from_id_eager(layer));
+
+        inner.map(|layer| layer.map(SyncStoreLayer::wrap))
+    }
+
     /// Create a base layer builder, unattached to any database label
     ///
     /// After having committed it, use `set_head` on a `NamedGraph` to attach it.
-    pub fn create_base_layer(&self) -> Result<SyncStoreLayerBuilder, io::Error> {
+    pub fn create_base_layer(&self) -> Result<SyncStoreLayerBuilder, StoreError> {
         let inner = task_sync(self.inner.create_base_layer());
 
         inner.map(|i| SyncStoreLayerBuilder::wrap(i))
     }
 
-    pub fn export_layers(&self, layer_ids: Box<dyn Iterator<Item = [u32; 5]>>) -> Vec<u8> {
-        self.inner.layer_store.export_layers(layer_ids)
+    /// Create a base layer builder with the given options, unattached to any database label
+    pub fn create_base_layer_with_options(
+        &self,
+        options: LayerBuilderOptions,
+    ) -> Result<SyncStoreLayerBuilder, StoreError> {
+        let inner = task_sync(self.inner.create_base_layer_with_options(options));
+
+        inner.map(SyncStoreLayerBuilder::wrap)
+    }
+
+    /// Build and commit a base layer containing the given triples
+    pub fn create_base_layer_from<I: IntoIterator<Item = StringTriple>>(
+        &self,
+        triples: I,
+    ) -> Result<SyncStoreLayer, StoreError> {
+        let triples: Vec<_> = triples.into_iter().collect();
+        let inner = task_sync(self.inner.create_base_layer_from(triples));
+
+        inner.map(SyncStoreLayer::wrap)
+    }
+
+    /// Build and commit a base layer from triples that are already sorted and free of duplicates
+    pub fn create_base_layer_from_sorted<I: IntoIterator<Item = StringTriple>>(
+        &self,
+        triples: I,
+    ) -> Result<SyncStoreLayer, StoreError> {
+        let triples: Vec<_> = triples.into_iter().collect();
+        let inner = task_sync(self.inner.create_base_layer_from_sorted(triples));
+
+        inner.map(SyncStoreLayer::wrap)
+    }
+
+    /// Returns the layers that would be removed by `collect_garbage`, without removing them
+    pub fn collect_garbage_dry_run(&self) -> Result<Vec<[u32; 5]>, StoreError> {
+        task_sync(self.inner.collect_garbage_dry_run())
+    }
+
+    /// Remove all layers that are not reachable from any database label
+    ///
+    /// Returns the names of the removed layers.
+    pub fn collect_garbage(&self) -> Result<Vec<[u32; 5]>, StoreError> {
+        task_sync(self.inner.collect_garbage())
+    }
+
+    /// Remove all layers that are not reachable from any database label, stopping early if the token is cancelled
+    pub fn collect_garbage_cancellable(
+        &self,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<[u32; 5]>, StoreError> {
+        task_sync(self.inner.collect_garbage_cancellable(cancel))
+    }
+
+    pub fn export_layers(
+        &self,
+        layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
+    ) -> Result<Vec<u8>, StoreError> {
+        self.inner.export_layers(layer_ids)
+    }
+
+    /// Export the given layers as a pack, leaving out the layers in `known`
+    pub fn export_layers_since(
+        &self,
+        known: &[[u32; 5]],
+        layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
+    ) -> Result<Vec<u8>, StoreError> {
+        self.inner.export_layers_since(known, layer_ids)
+    }
+
+    /// Export the given layers as a pack, writing it out to the given writer
+    pub fn export_layers_to<W: io::Write>(
+        &self,
+        writer: W,
+        layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
+    ) -> Result<(), StoreError> {
+        self.inner.export_layers_to(writer, layer_ids)
     }
+
     pub fn import_layers(
         &self,
         pack: &[u8],
         layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
-    ) -> Result<(), io::Error> {
-        self.inner.layer_store.import_layers(pack, layer_ids)
+    ) -> Result<(), StoreError> {
+        Ok(self.inner.layer_store.import_layers(pack, layer_ids)?)
+    }
+
+    /// Import the given layers from a pack, reading it from the given reader
+    pub fn import_layers_from<R: io::Read>(
+        &self,
+        reader: R,
+        layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
+    ) -> Result<(), StoreError> {
+        self.inner.import_layers_from(reader, layer_ids)
+    }
+
+    /// Check that a pack is intact, returning the names of the layers it contains
+    pub fn validate_pack(pack: &[u8]) -> Result<Vec<[u32; 5]>, StoreError> {
+        task_sync(Store::validate_pack(pack))
+    }
+
+    /// Import the given layers from a pack, stopping early if the token is cancelled
+    pub fn import_layers_cancellable(
+        &self,
+        pack: &[u8],
+        layer_ids: Box<dyn Iterator<Item = [u32; 5]>>,
+        cancel: &CancellationToken,
+    ) -> Result<(), StoreError> {
+        self.inner
+            .import_layers_cancellable(pack, layer_ids, cancel)
     }
 }
 
@@ -465,6 +1408,19 @@ pub fn open_sync_directory_store<P: Into<PathBuf>>(path: P) -> SyncStore {
     SyncStore::wrap(open_directory_store(path))
 }
 
+/// Open a store that stores its data in the given directory, with the given options
+pub fn open_sync_directory_store_with<P: Into<PathBuf>>(
+    path: P,
+    options: DirectoryStoreOptions,
+) -> SyncStore {
+    SyncStore::wrap(open_directory_store_with(path, options))
+}
+
+/// Open a store that is stored in the given directory, without allowing any changes to it
+pub fn open_sync_directory_store_readonly<P: Into<PathBuf>>(path: P) -> SyncStore {
+    SyncStore::wrap(open_directory_store_readonly(path))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -594,7 +1550,9 @@ mod tests {
         let layer3 = builder3.commit().unwrap();
 
         let ids = vec![layer1.name(), layer2.name(), layer3.name()];
-        let pack = store1.export_layers(Box::new(ids.clone().into_iter()));
+        let pack = store1
+            .export_layers(Box::new(ids.clone().into_iter()))
+            .unwrap();
 
         let parents_map = pack_layer_parents(io::Cursor::new(&pack)).unwrap();
 
@@ -615,4 +1573,25 @@ mod tests {
             result_layer.string_triple_exists(&StringTriple::new_value("horse", "says", "neigh"))
         );
     }
+
+    #[test]
+    fn sync_snapshot_can_be_queried_as_layer() {
+        let store = open_sync_memory_store();
+        let database = store.create("foodb").unwrap();
+        assert!(database.snapshot().unwrap().is_none());
+
+        let builder = store.create_base_layer().unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let layer = builder.commit().unwrap();
+        assert!(database.set_head(&layer).unwrap());
+
+        let snapshot = database.snapshot().unwrap().unwrap();
+        assert_eq!(layer.name(), snapshot.layer().name());
+
+        let boxed: Box<dyn Layer> = Box::new(snapshot);
+        assert_eq!(layer.name(), boxed.name());
+        assert!(boxed.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+    }
 }
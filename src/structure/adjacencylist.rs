@@ -19,10 +19,12 @@ use tokio::prelude::*;
 use super::bitarray::*;
 use super::bitindex::*;
 use super::logarray::*;
+use super::util;
 use crate::storage::*;
 use futures::future;
 use futures::stream::{Stream, StreamExt, TryStreamExt};
 use futures::task::{Context, Poll};
+use rayon::prelude::*;
 
 #[derive(Clone)]
 pub struct AdjacencyList {
@@ -41,14 +43,15 @@ impl AdjacencyList {
         bits_slice: Bytes,
         bits_block_slice: Bytes,
         bits_sblock_slice: Bytes,
-    ) -> AdjacencyList {
-        let nums = LogArray::parse(nums_slice).unwrap();
-        let bit_array = BitArray::from_bits(bits_slice).unwrap();
-        let bits_block_array = LogArray::parse(bits_block_slice).unwrap();
-        let bits_sblock_array = LogArray::parse(bits_sblock_slice).unwrap();
-        let bits = BitIndex::from_parts(bit_array, bits_block_array, bits_sblock_array);
+    ) -> io::Result<AdjacencyList> {
+        let nums = LogArray::parse(nums_slice)?;
+        let bits = BitIndex::from_maps(bits_slice, bits_block_slice, bits_sblock_slice)?;
 
-        Self::from_parts(nums, bits)
+        Ok(Self::from_parts(nums, bits))
+    }
+
+    pub fn heap_size(&self) -> usize {
+        self.nums.heap_size() + self.bits.heap_size()
     }
 
     pub fn left_count(&self) -> usize {
@@ -305,6 +308,60 @@ where
         Ok(())
     }
 
+    /// Build an adjacency list from a batch of pairs in one go.
+    ///
+    /// The pairs are expected to be sorted already, but if they are
+    /// not, they get sorted (in parallel) and deduplicated first. The
+    /// bits and numbers are then worked out in a single pass over the
+    /// pairs and written out in bulk. The resulting files are
+    /// identical to those produced by pushing the same pairs one by
+    /// one and calling `finalize`.
+    pub async fn from_sorted_pairs(
+        bitfile: F,
+        bitindex_blocks: W1,
+        bitindex_sblocks: W2,
+        nums_writer: W3,
+        width: u8,
+        mut pairs: Vec<(u64, u64)>,
+    ) -> io::Result<()> {
+        if pairs.iter().any(|&(left, right)| left == 0 || right == 0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "adjacency list pairs cannot contain 0",
+            ));
+        }
+        if pairs.windows(2).any(|w| w[0] >= w[1]) {
+            pairs.par_sort_unstable();
+            pairs.dedup();
+        }
+
+        let mut bits = Vec::with_capacity(pairs.len());
+        let mut nums = Vec::with_capacity(pairs.len());
+        let mut next_left = 1;
+        for (i, &(left, right)) in pairs.iter().enumerate() {
+            // holes are filled with a 0 that immediately closes the segment
+            for _ in next_left..left {
+                nums.push(0);
+                bits.push(true);
+            }
+
+            let last_for_left = pairs.get(i + 1).map(|&(l, _)| l != left).unwrap_or(true);
+            nums.push(right);
+            bits.push(last_for_left);
+            next_left = left + 1;
+        }
+
+        let mut bitarray = BitArrayFileBuilder::new(bitfile.open_write());
+        bitarray.push_all(util::stream_iter_ok(bits)).await?;
+        bitarray.finalize().await?;
+
+        let mut nums_builder = LogArrayFileBuilder::new(nums_writer, width);
+        nums_builder.push_all(util::stream_iter_ok(nums)).await?;
+        nums_builder.finalize().await?;
+
+        build_bitindex(bitfile.open_read(), bitindex_blocks, bitindex_sblocks).await
+    }
+
     pub async fn push_all<S: Stream<Item = io::Result<(u64, u64)>> + Unpin>(
         &mut self,
         mut stream: S,
@@ -387,7 +444,8 @@ mod tests {
             bitfile_contents,
             bitindex_blocks_contents,
             bitindex_sblocks_contents,
-        );
+        )
+        .unwrap();
 
         let slice = adjacencylist.get(1);
         assert_eq!(2, slice.len());
@@ -451,7 +509,8 @@ mod tests {
             bitfile_contents,
             bitindex_blocks_contents,
             bitindex_sblocks_contents,
-        );
+        )
+        .unwrap();
 
         assert_eq!(0, adjacencylist.left_count());
     }
@@ -490,7 +549,8 @@ mod tests {
             bitfile_contents,
             bitindex_blocks_contents,
             bitindex_sblocks_contents,
-        );
+        )
+        .unwrap();
 
         let slice = adjacencylist.get(1);
         assert_eq!(1, slice.len());
@@ -555,7 +615,8 @@ mod tests {
             bitfile_contents,
             bitindex_blocks_contents,
             bitindex_sblocks_contents,
-        );
+        )
+        .unwrap();
 
         assert_eq!(
             vec![(1, 1), (1, 3), (2, 5), (7, 4)],
@@ -644,7 +705,8 @@ mod tests {
             bitfile_contents,
             bitindex_blocks_contents,
             bitindex_sblocks_contents,
-        );
+        )
+        .unwrap();
 
         let result: Vec<_> = (0..adjacencylist.right_count())
             .map(|i| adjacencylist.pair_at_pos(i as u64))
@@ -721,7 +783,8 @@ mod tests {
             bitfile_contents,
             bitindex_blocks_contents,
             bitindex_sblocks_contents,
-        );
+        )
+        .unwrap();
 
         let result: Vec<_> = (0..adjacencylist.right_count())
             .map(|i| adjacencylist.pair_at_pos(i as u64))
@@ -749,4 +812,87 @@ mod tests {
             result
         );
     }
+
+    fn adjacency_list_file_contents(
+        pairs: Vec<(u64, u64)>,
+        bulk: bool,
+    ) -> (Bytes, Bytes, Bytes, Bytes) {
+        let bitfile = MemoryBackedStore::new();
+        let bitindex_blocks_file = MemoryBackedStore::new();
+        let bitindex_sblocks_file = MemoryBackedStore::new();
+        let nums_file = MemoryBackedStore::new();
+
+        block_on(async {
+            if bulk {
+                AdjacencyListBuilder::from_sorted_pairs(
+                    bitfile.clone(),
+                    bitindex_blocks_file.open_write(),
+                    bitindex_sblocks_file.open_write(),
+                    nums_file.open_write(),
+                    8,
+                    pairs,
+                )
+                .await
+            } else {
+                let mut builder = AdjacencyListBuilder::new(
+                    bitfile.clone(),
+                    bitindex_blocks_file.open_write(),
+                    bitindex_sblocks_file.open_write(),
+                    nums_file.open_write(),
+                    8,
+                );
+                builder.push_all(util::stream_iter_ok(pairs)).await?;
+                builder.finalize().await
+            }
+        })
+        .unwrap();
+
+        (
+            block_on(bitfile.map()).unwrap(),
+            block_on(bitindex_blocks_file.map()).unwrap(),
+            block_on(bitindex_sblocks_file.map()).unwrap(),
+            block_on(nums_file.map()).unwrap(),
+        )
+    }
+
+    #[test]
+    fn bulk_build_matches_incremental_build() {
+        let mut pairs = vec![(1, 1), (1, 3), (2, 5), (7, 4), (7, 200), (9, 2)];
+        pairs.extend((300..1000).flat_map(|left| (1..(left % 4 + 1)).map(move |r| (left, r))));
+
+        assert_eq!(
+            adjacency_list_file_contents(pairs.clone(), false),
+            adjacency_list_file_contents(pairs, true)
+        );
+        assert_eq!(
+            adjacency_list_file_contents(Vec::new(), false),
+            adjacency_list_file_contents(Vec::new(), true)
+        );
+    }
+
+    #[test]
+    fn bulk_build_sorts_and_deduplicates_pairs() {
+        let sorted = vec![(1, 1), (1, 3), (2, 5), (7, 4)];
+        let unsorted = vec![(7, 4), (1, 3), (2, 5), (1, 1), (1, 3)];
+
+        assert_eq!(
+            adjacency_list_file_contents(sorted, false),
+            adjacency_list_file_contents(unsorted, true)
+        );
+    }
+
+    #[test]
+    fn bulk_build_rejects_zero() {
+        let bitfile = MemoryBackedStore::new();
+        let result = block_on(AdjacencyListBuilder::from_sorted_pairs(
+            bitfile,
+            MemoryBackedStore::new().open_write(),
+            MemoryBackedStore::new().open_write(),
+            MemoryBackedStore::new().open_write(),
+            8,
+            vec![(0, 1), (1, 2)],
+        ));
+
+        assert_eq!(io::ErrorKind::InvalidInput, result.unwrap_err().kind());
+    }
 }
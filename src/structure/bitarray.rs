@@ -9,7 +9,10 @@
 //! # Notes
 //!
 //! * All words are stored in a standard big-endian encoding.
-//! * The maximum number of bits is 2^64-1.
+//! * The last word is the control word. Its most significant byte holds the format version (see
+//!   [`FORMAT_VERSION`](../util/constant.FORMAT_VERSION.html)) and the remaining 56 bits hold the
+//!   number of bits.
+//! * The maximum number of bits is 2^56-1.
 //!
 //! # Naming
 //!
@@ -69,6 +72,7 @@ pub struct BitArray {
 pub enum BitArrayError {
     InputBufferTooSmall(usize),
     UnexpectedInputBufferSize(u64, u64, u64),
+    UnsupportedFormatVersion(u8),
 }
 
 impl BitArrayError {
@@ -129,6 +133,7 @@ impl fmt::Display for BitArrayError {
                 "expected input buffer size ({}) to be {} for {} bits",
                 input_buf_size, expected_buf_size, len
             ),
+            UnsupportedFormatVersion(version) => util::fmt_unsupported_format_version(f, *version),
         }
     }
 }
@@ -141,10 +146,19 @@ impl From<BitArrayError> for io::Error {
     }
 }
 
+/// The position of the format version in the control word.
+const VERSION_SHIFT: u32 = 56;
+const LEN_MASK: u64 = (1 << VERSION_SHIFT) - 1;
+
 /// Read the length from the control word buffer. `buf` must start at the first word after the data
 /// buffer. `input_buf_size` is used for validation.
 fn read_control_word(buf: &[u8], input_buf_size: usize) -> Result<u64, BitArrayError> {
-    let len = BigEndian::read_u64(buf);
+    let word = BigEndian::read_u64(buf);
+    let version = (word >> VERSION_SHIFT) as u8;
+    if version > util::FORMAT_VERSION {
+        return Err(BitArrayError::UnsupportedFormatVersion(version));
+    }
+    let len = word & LEN_MASK;
     BitArrayError::validate_len(input_buf_size, len)?;
     Ok(len)
 }
@@ -181,6 +195,11 @@ impl BitArray {
         self.len == 0
     }
 
+    /// Returns the size in bytes of the buffer backing this bit array.
+    pub fn heap_size(&self) -> usize {
+        self.buf.len()
+    }
+
     /// Reads the data buffer and returns the logical value of the bit at the bit `index`.
     ///
     /// Panics if `index` is >= the length of the bit array.
@@ -198,6 +217,58 @@ impl BitArray {
         let bits = self.clone();
         (0..bits.len()).map(move |index| bits.get(index))
     }
+
+    /// Returns the amount of 1-bits in the given range (up to but excluding end).
+    ///
+    /// This counts a byte at a time. If the bit array is part of a
+    /// `BitIndex`, `BitIndex::count_ones_range` will use its rank
+    /// index instead.
+    ///
+    /// Panics if `start` > `end` or `end` > the length of the bit array.
+    pub fn count_ones_range(&self, start: usize, end: usize) -> usize {
+        let len = self.len();
+        assert!(
+            start <= end && end <= len,
+            "expected range {}..{} to be within length ({})",
+            start,
+            end,
+            len
+        );
+        if start == end {
+            return 0;
+        }
+
+        let first_byte = start / 8;
+        let last_byte = (end - 1) / 8;
+        let mut count = 0;
+        for byte_index in first_byte..=last_byte {
+            let mut byte = self.buf[byte_index];
+            if byte_index == first_byte {
+                byte &= 0xff >> (start % 8);
+            }
+            if byte_index == last_byte {
+                byte &= 0xff << (7 - (end - 1) % 8);
+            }
+
+            count += byte.count_ones() as usize;
+        }
+
+        count
+    }
+
+    /// Returns an iterator over the indexes of all 1-bits, in ascending order.
+    pub fn iter_ones(&self) -> impl Iterator<Item = usize> {
+        let buf = self.buf.clone();
+        let len = self.len();
+        (0..buf.len())
+            .flat_map(move |byte_index| {
+                let byte = buf[byte_index];
+                (0..8)
+                    .filter(move |bit| byte & (0b1000_0000 >> bit) != 0)
+                    .map(move |bit| byte_index * 8 + bit)
+            })
+            .take_while(move |&index| index < len)
+    }
 }
 
 pub struct BitArrayFileBuilder<W> {
@@ -264,7 +335,8 @@ impl<W: AsyncWrite + Unpin> BitArrayFileBuilder<W> {
         // Write the final data word.
         self.finalize_data().await?;
         // Write the control word.
-        util::write_u64(&mut self.dest, count).await?;
+        let control_word = count | u64::from(util::FORMAT_VERSION) << VERSION_SHIFT;
+        util::write_u64(&mut self.dest, control_word).await?;
         // Flush the `dest`.
         self.dest.flush().await?;
 
@@ -532,4 +604,76 @@ mod tests {
 
         assert_eq!(contents, result);
     }
+
+    fn bitarray_from_bools(contents: Vec<bool>) -> BitArray {
+        let x = MemoryBackedStore::new();
+        let mut builder = BitArrayFileBuilder::new(x.open_write());
+        block_on(async {
+            builder.push_all(util::stream_iter_ok(contents)).await?;
+            builder.finalize().await?;
+
+            Ok::<_, io::Error>(())
+        })
+        .unwrap();
+
+        BitArray::from_bits(block_on(x.map()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn count_ones_in_range() {
+        let contents: Vec<_> = (0..1000).map(|n| n % 3 == 0 || n % 7 == 0).collect();
+        let bitarray = bitarray_from_bools(contents.clone());
+
+        for &(start, end) in &[
+            (0, 0),
+            (5, 5),
+            (1000, 1000),
+            (0, 1),
+            (1, 3),
+            (3, 4),
+            (0, 8),
+            (7, 9),
+            (5, 133),
+            (64, 128),
+            (999, 1000),
+            (0, 1000),
+        ] {
+            let expected = contents[start..end].iter().filter(|&&b| b).count();
+            assert_eq!(expected, bitarray.count_ones_range(start, end));
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn count_ones_out_of_range_panics() {
+        let bitarray = bitarray_from_bools(vec![true; 10]);
+        bitarray.count_ones_range(5, 11);
+    }
+
+    #[test]
+    fn iterate_ones() {
+        let contents: Vec<_> = (0..1000).map(|n| n % 3 == 0 || n % 7 == 0).collect();
+        let bitarray = bitarray_from_bools(contents.clone());
+        let expected: Vec<_> = (0..1000).filter(|&n| contents[n]).collect();
+        assert_eq!(expected, bitarray.iter_ones().collect::<Vec<_>>());
+
+        let bitarray = bitarray_from_bools(vec![false, false, true, true, true]);
+        assert_eq!(vec![2, 3, 4], bitarray.iter_ones().collect::<Vec<_>>());
+
+        let bitarray = BitArray::from_bits(Bytes::from([0u8; 8].as_ref())).unwrap();
+        assert_eq!(0, bitarray.iter_ones().count());
+        assert_eq!(0, bitarray.count_ones_range(0, 0));
+    }
+
+    #[test]
+    fn newer_format_version_is_rejected() {
+        let error = BitArray::from_bits(Bytes::from([2u8, 0, 0, 0, 0, 0, 0, 0].as_ref()))
+            .err()
+            .unwrap();
+        assert_eq!(BitArrayError::UnsupportedFormatVersion(2), error);
+        assert_eq!(
+            "layer written with store format v2, this build supports up to v1",
+            error.to_string()
+        );
+    }
 }
@@ -23,12 +23,24 @@ pub struct BitIndex {
 }
 
 impl BitIndex {
-    pub fn from_maps(bitarray_map: Bytes, blocks_map: Bytes, sblocks_map: Bytes) -> BitIndex {
-        let bitarray = BitArray::from_bits(bitarray_map).unwrap();
-        let blocks_logarray = LogArray::parse(blocks_map).unwrap();
-        let sblocks_logarray = LogArray::parse(sblocks_map).unwrap();
-
-        BitIndex::from_parts(bitarray, blocks_logarray, sblocks_logarray)
+    /// Construct a `BitIndex` from its maps.
+    ///
+    /// Returns an error if any of the underlying structures is malformed
+    /// or was written with a newer format version than this build supports.
+    pub fn from_maps(
+        bitarray_map: Bytes,
+        blocks_map: Bytes,
+        sblocks_map: Bytes,
+    ) -> io::Result<BitIndex> {
+        let bitarray = BitArray::from_bits(bitarray_map)?;
+        let blocks_logarray = LogArray::parse(blocks_map)?;
+        let sblocks_logarray = LogArray::parse(sblocks_map)?;
+
+        Ok(BitIndex::from_parts(
+            bitarray,
+            blocks_logarray,
+            sblocks_logarray,
+        ))
     }
 
     pub fn from_parts(array: BitArray, blocks: LogArray, sblocks: LogArray) -> BitIndex {
@@ -53,12 +65,20 @@ impl BitIndex {
         self.array.len()
     }
 
+    /// Returns the size in bytes of the bit array and its index.
+    pub fn heap_size(&self) -> usize {
+        self.array.heap_size() + self.blocks.heap_size() + self.sblocks.heap_size()
+    }
+
     /// Returns the bit at the given index.
     pub fn get(&self, index: u64) -> bool {
         self.array.get(index as usize)
     }
 
     /// Returns the amount of 1-bits in the bitarray up to and including the given index.
+    ///
+    /// Indexes are 0-based, so `rank1(0)` is 1 if the first bit is
+    /// set and 0 otherwise. The index has to be smaller than `len()`.
     pub fn rank1(&self, index: u64) -> u64 {
         let block_index = index / 64;
         let sblock_index = block_index / SBLOCK_SIZE as u64;
@@ -142,7 +162,16 @@ impl BitIndex {
     }
 
     /// Returns the index of the 1-bit in the bitarray corresponding with the given rank.
+    ///
+    /// Ranks are 1-based and indexes are 0-based, so `select1(1)` is
+    /// the index of the first 1-bit, and `rank1(select1(n).unwrap())`
+    /// is `n`. Returns None for rank 0, or if there are fewer than
+    /// `rank` 1-bits.
     pub fn select1(&self, rank: u64) -> Option<u64> {
+        if rank == 0 {
+            return None;
+        }
+
         let sblock = self.select1_sblock(rank);
         let sblock_rank = self.sblocks.entry(sblock);
         if sblock_rank < rank {
@@ -261,7 +290,14 @@ impl BitIndex {
     }
 
     /// Returns the index of the 0-bit in the bitarray corresponding with the given rank.
+    ///
+    /// Like `select1`, ranks are 1-based. Returns None for rank 0, or
+    /// if there are fewer than `rank` 0-bits.
     pub fn select0(&self, rank: u64) -> Option<u64> {
+        if rank == 0 {
+            return None;
+        }
+
         let sblock = self.select0_sblock(rank);
         let sblock_rank = ((1 + sblock) * SBLOCK_SIZE * 64) as u64 - self.sblocks.entry(sblock);
 
@@ -283,7 +319,13 @@ impl BitIndex {
                 tally -= 1;
 
                 if tally == 0 {
-                    return Some(block as u64 * 64 + i);
+                    let index = block as u64 * 64 + i;
+                    // the padding at the end of the last block is not part of the bitarray
+                    if index >= self.len() as u64 {
+                        return None;
+                    }
+
+                    return Some(index);
                 }
             }
 
@@ -311,6 +353,17 @@ impl BitIndex {
     pub fn iter(&self) -> impl Iterator<Item = bool> {
         self.array.iter()
     }
+
+    /// Returns the amount of 1-bits in the given range (up to but excluding end), using the rank index.
+    pub fn count_ones_range(&self, start: usize, end: usize) -> usize {
+        assert!(start <= end && end <= self.len());
+        self.rank1_from_range(start as u64, end as u64) as usize
+    }
+
+    /// Returns an iterator over the indexes of all 1-bits, in ascending order.
+    pub fn iter_ones(&self) -> impl Iterator<Item = usize> {
+        self.array.iter_ones()
+    }
 }
 
 pub async fn build_bitindex<
@@ -389,7 +442,8 @@ mod tests {
             block_on(bits.map()).unwrap(),
             block_on(index_blocks.map()).unwrap(),
             block_on(index_sblocks.map()).unwrap(),
-        );
+        )
+        .unwrap();
 
         for i in 0..123456 {
             assert_eq!(i / 3 + 1, index.rank1(i));
@@ -423,7 +477,8 @@ mod tests {
             block_on(bits.map()).unwrap(),
             block_on(index_blocks.map()).unwrap(),
             block_on(index_sblocks.map()).unwrap(),
-        );
+        )
+        .unwrap();
 
         for i in 1..(123456 / 3) {
             assert_eq!((i - 1) * 3, index.select1(i).unwrap());
@@ -459,7 +514,8 @@ mod tests {
             block_on(bits.map()).unwrap(),
             block_on(index_blocks.map()).unwrap(),
             block_on(index_sblocks.map()).unwrap(),
-        );
+        )
+        .unwrap();
 
         assert_eq!(0, index.rank1_from_range(6, 6));
         assert_eq!(1, index.rank1_from_range(6, 7));
@@ -495,7 +551,8 @@ mod tests {
             block_on(bits.map()).unwrap(),
             block_on(index_blocks.map()).unwrap(),
             block_on(index_sblocks.map()).unwrap(),
-        );
+        )
+        .unwrap();
 
         assert_eq!(None, index.select1_from_range(0, 6, 6));
         assert_eq!(None, index.select1_from_range(0, 6, 7));
@@ -532,7 +589,8 @@ mod tests {
             block_on(bits.map()).unwrap(),
             block_on(index_blocks.map()).unwrap(),
             block_on(index_sblocks.map()).unwrap(),
-        );
+        )
+        .unwrap();
 
         for i in 0..123456 {
             assert_eq!(1 + i - (i / 3 + 1), index.rank0(i));
@@ -566,7 +624,8 @@ mod tests {
             block_on(bits.map()).unwrap(),
             block_on(index_blocks.map()).unwrap(),
             block_on(index_sblocks.map()).unwrap(),
-        );
+        )
+        .unwrap();
 
         for i in 1..=(123456 * 2 / 3) {
             assert_eq!(i + (i - 1) / 2, index.select0(i).unwrap());
@@ -602,7 +661,8 @@ mod tests {
             block_on(bits.map()).unwrap(),
             block_on(index_blocks.map()).unwrap(),
             block_on(index_sblocks.map()).unwrap(),
-        );
+        )
+        .unwrap();
 
         assert_eq!(0, index.rank0_from_range(5, 5));
         assert_eq!(1, index.rank0_from_range(5, 6));
@@ -639,7 +699,8 @@ mod tests {
             block_on(bits.map()).unwrap(),
             block_on(index_blocks.map()).unwrap(),
             block_on(index_sblocks.map()).unwrap(),
-        );
+        )
+        .unwrap();
 
         assert_eq!(None, index.select0_from_range(0, 6, 6));
         assert_eq!(Some(6), index.select0_from_range(0, 6, 7));
@@ -649,4 +710,110 @@ mod tests {
         assert_eq!(Some(10), index.select0_from_range(4, 5, 11));
         assert_eq!(None, index.select0_from_range(123456, 5, 10));
     }
+
+    #[test]
+    pub fn count_ones_range_uses_rank() {
+        let bits = MemoryBackedStore::new();
+        let mut ba_builder = BitArrayFileBuilder::new(bits.open_write());
+        let contents = (0..).map(|n| n % 3 == 0).take(123456);
+
+        block_on(async {
+            ba_builder.push_all(stream_iter_ok(contents)).await?;
+            ba_builder.finalize().await?;
+
+            Ok::<_, io::Error>(())
+        })
+        .unwrap();
+
+        let index_blocks = MemoryBackedStore::new();
+        let index_sblocks = MemoryBackedStore::new();
+        block_on(build_bitindex(
+            bits.open_read(),
+            index_blocks.open_write(),
+            index_sblocks.open_write(),
+        ))
+        .unwrap();
+
+        let index = BitIndex::from_maps(
+            block_on(bits.map()).unwrap(),
+            block_on(index_blocks.map()).unwrap(),
+            block_on(index_sblocks.map()).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(0, index.count_ones_range(0, 0));
+        assert_eq!(0, index.count_ones_range(100, 100));
+        assert_eq!(1, index.count_ones_range(0, 1));
+        assert_eq!(0, index.count_ones_range(1, 3));
+        assert_eq!(41152, index.count_ones_range(0, 123456));
+        assert_eq!(
+            index.count_ones_range(1000, 70000),
+            index.array.count_ones_range(1000, 70000)
+        );
+        assert!(index
+            .iter_ones()
+            .zip((0..).step_by(3))
+            .all(|(index, expected)| index == expected));
+        assert_eq!(41152, index.iter_ones().count());
+    }
+
+    fn build_index(contents: Vec<bool>) -> BitIndex {
+        let bits = MemoryBackedStore::new();
+        let mut ba_builder = BitArrayFileBuilder::new(bits.open_write());
+
+        block_on(async {
+            ba_builder.push_all(stream_iter_ok(contents)).await?;
+            ba_builder.finalize().await?;
+
+            Ok::<_, io::Error>(())
+        })
+        .unwrap();
+
+        let index_blocks = MemoryBackedStore::new();
+        let index_sblocks = MemoryBackedStore::new();
+        block_on(build_bitindex(
+            bits.open_read(),
+            index_blocks.open_write(),
+            index_sblocks.open_write(),
+        ))
+        .unwrap();
+
+        BitIndex::from_maps(
+            block_on(bits.map()).unwrap(),
+            block_on(index_blocks.map()).unwrap(),
+            block_on(index_sblocks.map()).unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    pub fn rank1_and_select1_boundaries() {
+        // set bits at 0, 63, 64 and 129, spanning three blocks
+        let set = [0, 63, 64, 129];
+        let index = build_index((0..130).map(|i| set.contains(&i)).collect());
+
+        assert_eq!(1, index.rank1(0));
+        assert_eq!(1, index.rank1(62));
+        assert_eq!(2, index.rank1(63));
+        assert_eq!(3, index.rank1(64));
+        assert_eq!(4, index.rank1(129));
+
+        assert_eq!(None, index.select1(0));
+        for (rank, &position) in set.iter().enumerate() {
+            let rank = rank as u64 + 1;
+            assert_eq!(Some(position), index.select1(rank));
+            assert_eq!(rank, index.rank1(position));
+        }
+        assert_eq!(None, index.select1(5));
+        assert_eq!(None, index.select1(1000));
+
+        assert_eq!(None, index.select0(0));
+        assert_eq!(Some(1), index.select0(1));
+        assert_eq!(None, index.select0(127));
+
+        let unset_first = build_index(vec![false, true, false]);
+        assert_eq!(0, unset_first.rank1(0));
+        assert_eq!(Some(1), unset_first.select1(1));
+        assert_eq!(None, unset_first.select1(2));
+    }
 }
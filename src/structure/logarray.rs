@@ -15,8 +15,9 @@
 //! 3. The L+1 word is the control word and contains the following sequence:
 //!    1. a 32-bit unsigned integer representing N, the number of elements,
 //!    2. an 8-bit unsigned integer representing W, the number of bits used to store each element,
-//!       and
-//!    3. 24 unused bits.
+//!    3. an 8-bit unsigned integer representing the format version (see
+//!       [`FORMAT_VERSION`](../util/constant.FORMAT_VERSION.html)), and
+//!    4. 16 unused bits.
 //!
 //! # Notes
 //!
@@ -55,7 +56,10 @@ use byteorder::{BigEndian, ByteOrder};
 use bytes::{Bytes, BytesMut};
 use futures::future::FutureExt;
 use futures::stream::{self, Stream, StreamExt};
+use memmap2::Mmap;
+use std::ops::Deref;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::{cmp::Ordering, convert::TryFrom, error, fmt, io};
 use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio_util::codec::{Decoder, FramedRead};
@@ -85,7 +89,28 @@ pub struct LogArray {
     /// Shared reference to the input buffer
     ///
     /// Index 0 points to the first byte of the first element. The last word is the control word.
-    input_buf: Bytes,
+    input_buf: LogArrayBuffer,
+}
+
+/// The buffer backing a log array
+///
+/// This is either an owned buffer, or a memory-mapped file whose pages
+/// are only loaded by the OS once they are actually read.
+#[derive(Clone)]
+enum LogArrayBuffer {
+    Bytes(Bytes),
+    Mmap(Arc<Mmap>),
+}
+
+impl Deref for LogArrayBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            LogArrayBuffer::Bytes(bytes) => bytes,
+            LogArrayBuffer::Mmap(mmap) => mmap,
+        }
+    }
 }
 
 /// An error that occurred during a log array operation.
@@ -94,6 +119,7 @@ pub enum LogArrayError {
     InputBufferTooSmall(usize),
     WidthTooLarge(u8),
     UnexpectedInputBufferSize(u64, u64, u32, u8),
+    UnsupportedFormatVersion(u8),
 }
 
 impl LogArrayError {
@@ -149,6 +175,7 @@ impl fmt::Display for LogArrayError {
                 "expected input buffer size ({}) to be {} for {} elements and width {}",
                 input_buf_size, expected_buf_size, len, width
             ),
+            UnsupportedFormatVersion(version) => util::fmt_unsupported_format_version(f, *version),
         }
     }
 }
@@ -185,6 +212,10 @@ impl Iterator for LogArrayIterator {
 /// Read the length and bit width from the control word buffer. `buf` must start at the first word
 /// after the data buffer. `input_buf_size` is used for validation.
 fn read_control_word(buf: &[u8], input_buf_size: usize) -> Result<(u32, u8), LogArrayError> {
+    let version = buf[5];
+    if version > util::FORMAT_VERSION {
+        return Err(LogArrayError::UnsupportedFormatVersion(version));
+    }
     let len = BigEndian::read_u32(buf);
     let width = buf[4];
     LogArrayError::validate_len_and_width(input_buf_size, len, width)?;
@@ -194,6 +225,19 @@ fn read_control_word(buf: &[u8], input_buf_size: usize) -> Result<(u32, u8), Log
 impl LogArray {
     /// Construct a `LogArray` by parsing a `Bytes` buffer.
     pub fn parse(input_buf: Bytes) -> Result<LogArray, LogArrayError> {
+        Self::from_buffer(LogArrayBuffer::Bytes(input_buf))
+    }
+
+    /// Construct a `LogArray` backed by a memory-mapped file.
+    ///
+    /// The resulting log array behaves exactly like one constructed
+    /// with `parse`, but only the parts of the file that are read are
+    /// paged into memory.
+    pub fn from_mmap(mmap: Mmap) -> Result<LogArray, LogArrayError> {
+        Self::from_buffer(LogArrayBuffer::Mmap(Arc::new(mmap)))
+    }
+
+    fn from_buffer(input_buf: LogArrayBuffer) -> Result<LogArray, LogArrayError> {
         let input_buf_size = input_buf.len();
         LogArrayError::validate_input_buf_size(input_buf_size)?;
         let (len, width) = read_control_word(&input_buf[input_buf_size - 8..], input_buf_size)?;
@@ -216,6 +260,18 @@ impl LogArray {
         self.len == 0
     }
 
+    /// Returns the size in bytes of the buffer backing this log array.
+    ///
+    /// For a slice, this is the size of the buffer it was sliced from.
+    /// A log array backed by a memory map holds no heap memory, so
+    /// this returns 0 for it.
+    pub fn heap_size(&self) -> usize {
+        match &self.input_buf {
+            LogArrayBuffer::Bytes(bytes) => bytes.len(),
+            LogArrayBuffer::Mmap(_) => 0,
+        }
+    }
+
     /// Returns the bit width.
     pub fn width(&self) -> u8 {
         self.width
@@ -426,6 +482,7 @@ impl<W: AsyncWrite + Unpin> LogArrayFileBuilder<W> {
         let mut buf = [0; 8];
         BigEndian::write_u32(&mut buf, len);
         buf[4] = width;
+        buf[5] = util::FORMAT_VERSION;
         self.file.write_all(&buf).await?;
 
         self.file.flush().await?;
@@ -618,6 +675,10 @@ impl MonotonicLogArray {
         self.0.is_empty()
     }
 
+    pub fn heap_size(&self) -> usize {
+        self.0.heap_size()
+    }
+
     pub fn entry(&self, index: usize) -> u64 {
         self.0.entry(index)
     }
@@ -1059,4 +1120,52 @@ mod tests {
         assert_eq!(16, logarray.len());
         assert_eq!(4, logarray.width());
     }
+
+    #[test]
+    fn newer_format_version_is_rejected() {
+        let error = LogArray::parse(Bytes::from([0u8, 0, 0, 0, 1, 2, 0, 0].as_ref()))
+            .err()
+            .unwrap();
+        assert_eq!(LogArrayError::UnsupportedFormatVersion(2), error);
+        assert_eq!(
+            "layer written with store format v2, this build supports up to v1",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn mmap_logarray_matches_parsed_logarray() {
+        let store = MemoryBackedStore::new();
+        let mut builder = LogArrayFileBuilder::new(store.open_write(), 17);
+        block_on(async {
+            builder
+                .push_all(stream_iter_ok((0..10000).map(|i| i * 13 % 100000)))
+                .await?;
+            builder.finalize().await?;
+
+            Ok::<_, io::Error>(())
+        })
+        .unwrap();
+
+        let content = block_on(store.map()).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("foo");
+        std::fs::write(&path, &content).unwrap();
+
+        let parsed = LogArray::parse(content).unwrap();
+        // the file is not touched again while `mapped` is alive
+        let mmap = unsafe { Mmap::map(&std::fs::File::open(&path).unwrap()) }.unwrap();
+        let mapped = LogArray::from_mmap(mmap).unwrap();
+
+        assert_eq!(parsed.len(), mapped.len());
+        assert!(parsed.heap_size() > 0);
+        assert_eq!(0, mapped.heap_size());
+        assert_eq!(parsed.width(), mapped.width());
+        assert!(parsed.iter().eq(mapped.iter()));
+        assert!(parsed
+            .slice(100, 50)
+            .iter()
+            .eq(mapped.slice(100, 50).iter()));
+        assert_eq!(parsed.entry(9999), mapped.entry(9999));
+    }
 }
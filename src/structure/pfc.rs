@@ -21,17 +21,28 @@ use crate::storage::*;
 pub enum PfcError {
     InvalidCoding,
     NotEnoughData,
+    UnsupportedFormatVersion(u8),
 }
 
 impl Display for PfcError {
     fn fmt(&self, formatter: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
-        write!(formatter, "{:?}", self)
+        match self {
+            PfcError::UnsupportedFormatVersion(version) => {
+                fmt_unsupported_format_version(formatter, *version)
+            }
+            _ => write!(formatter, "{:?}", self),
+        }
     }
 }
 
 impl From<LogArrayError> for PfcError {
-    fn from(_err: LogArrayError) -> PfcError {
-        PfcError::InvalidCoding
+    fn from(err: LogArrayError) -> PfcError {
+        match err {
+            LogArrayError::UnsupportedFormatVersion(version) => {
+                PfcError::UnsupportedFormatVersion(version)
+            }
+            _ => PfcError::InvalidCoding,
+        }
     }
 }
 
@@ -49,7 +60,43 @@ pub struct PfcBlock {
     n_strings: usize,
 }
 
-const BLOCK_SIZE: usize = 8;
+/// The amount of strings in a block, unless configured otherwise.
+pub const DEFAULT_BLOCK_SIZE: usize = 8;
+/// The largest block size a dictionary can be built with.
+pub const MAX_BLOCK_SIZE: usize = 255;
+
+// The string count at the end of the blocks file keeps the block size
+// in its second byte. A zero there means the default block size, so
+// dictionaries built with the default block size are laid out exactly
+// like they were before the block size was configurable. The third
+// byte holds the format version. The first byte stays zero, as that is
+// what tells a streaming reader that the dictionary has ended.
+const BLOCK_SIZE_SHIFT: u32 = 48;
+const VERSION_SHIFT: u32 = 40;
+const COUNT_MASK: u64 = (1 << VERSION_SHIFT) - 1;
+
+fn encode_count_and_block_size(count: u64, block_size: usize) -> u64 {
+    let word = count | u64::from(FORMAT_VERSION) << VERSION_SHIFT;
+    if block_size == DEFAULT_BLOCK_SIZE {
+        word
+    } else {
+        word | ((block_size as u64) << BLOCK_SIZE_SHIFT)
+    }
+}
+
+fn decode_count_and_block_size(word: u64) -> Result<(u64, usize), PfcError> {
+    let version = (word >> VERSION_SHIFT) as u8;
+    if version > FORMAT_VERSION {
+        return Err(PfcError::UnsupportedFormatVersion(version));
+    }
+
+    let block_size = match (word >> BLOCK_SIZE_SHIFT) as u8 as usize {
+        0 => DEFAULT_BLOCK_SIZE,
+        block_size => block_size,
+    };
+
+    Ok((word & COUNT_MASK, block_size))
+}
 
 pub struct PfcBlockEntryIterator {
     block: PfcBlock,
@@ -124,7 +171,7 @@ impl PfcBlock {
     pub fn parse(data: Bytes) -> Result<PfcBlock, PfcError> {
         Ok(PfcBlock {
             encoded_strings: data,
-            n_strings: BLOCK_SIZE,
+            n_strings: DEFAULT_BLOCK_SIZE,
         })
     }
 
@@ -157,14 +204,14 @@ impl PfcBlock {
     fn entries(&self) -> PfcDictEntryIterator {
         PfcDictEntryIterator {
             block_iter: self.block_entries(),
-            parts: Vec::with_capacity(BLOCK_SIZE),
+            parts: Vec::with_capacity(self.n_strings),
         }
     }
 
     pub fn strings(&self) -> PfcBlockIterator {
         PfcBlockIterator {
             entry_iterator: self.block_entries(),
-            string: Vec::with_capacity(BLOCK_SIZE),
+            string: Vec::new(),
         }
     }
 
@@ -191,12 +238,13 @@ impl PfcBlock {
             // Having written down the prefixes, we now turn it into a list
             // of how much prefix we're interested in for every individual string.
             // This is a simple matter of subtracting two adjacent entries.
-            for ix in 1..take_prefix_lengths.len() {
+            // We go in reverse so we always subtract an unmodified entry.
+            for ix in (1..take_prefix_lengths.len()).rev() {
                 take_prefix_lengths[ix] -= take_prefix_lengths[ix - 1];
             }
 
             let (_, postfix) = &entries[index];
-            let mut result = Vec::with_capacity(BLOCK_SIZE);
+            let mut result = Vec::with_capacity(entries.len());
 
             for ((_, entry), take) in entries.iter().zip(take_prefix_lengths.iter()) {
                 result.push(entry.slice(..*take));
@@ -250,7 +298,8 @@ impl Iterator for PfcDictBlockIterator {
             } else {
                 self.dict.block_offsets.entry(self.block_index - 1)
             } as usize;
-            let remainder = self.dict.n_strings as usize - self.block_index * BLOCK_SIZE;
+            let block_size = self.dict.block_size;
+            let remainder = self.dict.n_strings as usize - self.block_index * block_size;
 
             if remainder == 0 {
                 return None;
@@ -260,11 +309,7 @@ impl Iterator for PfcDictBlockIterator {
 
             let mut block = self.dict.blocks.clone();
             block.advance(block_offset);
-            if remainder >= BLOCK_SIZE {
-                Some(PfcBlock::parse(block).unwrap())
-            } else {
-                Some(PfcBlock::parse_incomplete(block, remainder).unwrap())
-            }
+            Some(PfcBlock::parse_incomplete(block, std::cmp::min(remainder, block_size)).unwrap())
         }
     }
 }
@@ -371,47 +416,38 @@ impl PfcDictEntry {
         }
     }
 
-    pub fn buf_eq<B: Buf>(&self, mut b: B) -> bool {
-        if self.len() != b.remaining() {
-            false
-        } else if self.len() == 0 {
-            true
-        } else {
-            let mut it = self.parts.iter();
-            let mut part = it.next().unwrap();
-            loop {
-                let slice = b.bytes();
+    /// The first byte of this entry, or None if it is empty.
+    pub fn first_byte(&self) -> Option<u8> {
+        self.parts.iter().find(|p| !p.is_empty()).map(|p| p[0])
+    }
 
-                match part.len().cmp(&slice.len()) {
-                    Ordering::Less => {
-                        if part.as_ref() != &slice[..part.len()] {
-                            return false;
-                        }
-                    }
-                    Ordering::Equal => {
-                        if &part != &slice {
-                            return false;
-                        }
+    /// Returns this entry with the given bytes put in front of it.
+    pub fn with_prefix(mut self, prefix: Bytes) -> Self {
+        self.parts.insert(0, prefix);
 
-                        if let Some(next) = it.next() {
-                            part = next;
-                        } else {
-                            // done!
-                            return true;
-                        }
-                    }
-                    Ordering::Greater => {
-                        if part.slice(..slice.len()) != slice {
-                            return false;
-                        }
+        self
+    }
 
-                        part = it.next().unwrap();
-                    }
+    pub fn buf_eq<B: Buf>(&self, mut b: B) -> bool {
+        if self.len() != b.remaining() {
+            return false;
+        }
+
+        for part in self.parts.iter() {
+            let mut part = part.as_ref();
+            while !part.is_empty() {
+                let slice = b.bytes();
+                let len = std::cmp::min(part.len(), slice.len());
+                if part[..len] != slice[..len] {
+                    return false;
                 }
 
-                b.advance(part.len());
+                part = &part[len..];
+                b.advance(len);
             }
         }
+
+        true
     }
 }
 
@@ -512,18 +548,23 @@ impl PartialOrd for PfcDictEntry {
 #[derive(Clone)]
 pub struct PfcDict {
     n_strings: u64,
+    block_size: usize,
+    format_version: u8,
     block_offsets: LogArray,
     blocks: Bytes,
 }
 
 impl PfcDict {
     pub fn parse(blocks: Bytes, offsets: Bytes) -> Result<PfcDict, PfcError> {
-        let n_strings = BigEndian::read_u64(&blocks.as_ref()[blocks.as_ref().len() - 8..]);
+        let word = BigEndian::read_u64(&blocks.as_ref()[blocks.as_ref().len() - 8..]);
+        let (n_strings, block_size) = decode_count_and_block_size(word)?;
 
         let block_offsets = LogArray::parse(offsets)?;
 
         Ok(PfcDict {
             n_strings: n_strings,
+            block_size,
+            format_version: (word >> VERSION_SHIFT) as u8,
             block_offsets: block_offsets,
             blocks: blocks,
         })
@@ -533,16 +574,31 @@ impl PfcDict {
         self.n_strings as usize
     }
 
+    /// The store format version this dictionary was written with.
+    pub fn format_version(&self) -> u8 {
+        self.format_version
+    }
+
+    /// The amount of strings in each block of this dictionary.
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Returns the size in bytes of the blocks and block offsets of this dictionary.
+    pub fn heap_size(&self) -> usize {
+        self.blocks.len() + self.block_offsets.heap_size()
+    }
+
     fn calculate_block_offset_index(&self, ix: usize) -> Option<(u64, usize)> {
         if (ix as u64) < self.n_strings {
-            let block_index = ix / BLOCK_SIZE;
+            let block_index = ix / self.block_size;
             let block_offset = if block_index == 0 {
                 0
             } else {
                 self.block_offsets.entry(block_index - 1)
             };
 
-            let index_in_block = ix % BLOCK_SIZE;
+            let index_in_block = ix % self.block_size;
             Some((block_offset, index_in_block))
         } else {
             None
@@ -554,7 +610,7 @@ impl PfcDict {
             let mut block_bytes = self.blocks.clone();
             block_bytes.advance(block_offset as usize);
 
-            let block = PfcBlock::parse(block_bytes).unwrap();
+            let block = PfcBlock::parse_incomplete(block_bytes, self.block_size).unwrap();
             block.entry(index_in_block)
         } else {
             None
@@ -566,7 +622,7 @@ impl PfcDict {
             let mut block_bytes = self.blocks.clone();
             block_bytes.advance(block_offset as usize);
 
-            let block = PfcBlock::parse(block_bytes).unwrap();
+            let block = PfcBlock::parse_incomplete(block_bytes, self.block_size).unwrap();
             block.get(index_in_block)
         } else {
             None
@@ -602,7 +658,7 @@ impl PfcDict {
                     max = mid - 1;
                 }
                 Ordering::Greater => min = mid + 1,
-                Ordering::Equal => return Some((mid * BLOCK_SIZE) as u64), // what luck! turns out the string we were looking for was the block head
+                Ordering::Equal => return Some((mid * self.block_size) as u64), // what luck! turns out the string we were looking for was the block head
             }
         }
 
@@ -614,19 +670,16 @@ impl PfcDict {
         } else {
             self.block_offsets.entry(found - 1) as usize
         };
-        let remainder = self.n_strings as usize - (found * BLOCK_SIZE);
+        let remainder = self.n_strings as usize - (found * self.block_size);
         let mut block = self.blocks.clone();
         block.advance(block_start);
-        let block = if remainder >= BLOCK_SIZE {
-            PfcBlock::parse(block).unwrap()
-        } else {
-            PfcBlock::parse_incomplete(block, remainder as usize).unwrap()
-        };
+        let block =
+            PfcBlock::parse_incomplete(block, std::cmp::min(remainder, self.block_size)).unwrap();
 
         let mut count = 0;
         for block_entry in block.entries() {
             if block_entry.buf_eq(s_bytes) {
-                return Some((found * BLOCK_SIZE + count) as u64);
+                return Some((found * self.block_size + count) as u64);
             }
             count += 1;
         }
@@ -634,6 +687,98 @@ impl PfcDict {
         None
     }
 
+    fn block_head(&self, block_index: usize) -> &[u8] {
+        let block_offset = if block_index == 0 {
+            0
+        } else {
+            self.block_offsets.entry(block_index - 1) as usize
+        };
+        let block_slice = &self.blocks.as_ref()[block_offset..];
+        let head_end = block_slice.iter().position(|&b| b == 0).unwrap();
+
+        &block_slice[..head_end]
+    }
+
+    /// Returns all strings starting with the given prefix, along with their index, in dictionary order.
+    ///
+    /// Only the block the prefix range starts in is located through
+    /// a binary search over the block heads. From there, strings are
+    /// read until the prefix range ends.
+    pub fn strings_with_prefix(&self, prefix: &str) -> impl Iterator<Item = (u64, String)> {
+        let prefix = prefix.to_owned();
+        let block_count = if self.len() == 0 {
+            0
+        } else {
+            self.block_offsets.len() + 1
+        };
+
+        // find the first block whose head is not smaller than the prefix
+        let mut min = 0;
+        let mut max = block_count;
+        while min < max {
+            let mid = (min + max) / 2;
+            if self.block_head(mid) < prefix.as_bytes() {
+                min = mid + 1;
+            } else {
+                max = mid;
+            }
+        }
+
+        // matching strings may start at the end of the block before it
+        let start_block = min.saturating_sub(1);
+        let block_iterator = PfcDictBlockIterator {
+            dict: self.clone(),
+            block_index: start_block,
+        };
+
+        let skip_prefix = prefix.clone();
+        let block_size = self.block_size;
+        block_iterator
+            .flat_map(|block| block.strings())
+            .enumerate()
+            .map(move |(i, s)| ((start_block * block_size + i) as u64, s))
+            .skip_while(move |(_, s)| s.as_str() < skip_prefix.as_str())
+            .take_while(move |(_, s)| s.starts_with(&prefix))
+    }
+
+    /// All strings from `low` (inclusive) up to `high` (exclusive), along with their index, in sorted order.
+    pub fn strings_in_range(&self, low: &str, high: &str) -> impl Iterator<Item = (u64, String)> {
+        let low = low.to_owned();
+        let high = high.to_owned();
+        let block_count = if self.len() == 0 {
+            0
+        } else {
+            self.block_offsets.len() + 1
+        };
+
+        // find the first block whose head is not smaller than low
+        let mut min = 0;
+        let mut max = block_count;
+        while min < max {
+            let mid = (min + max) / 2;
+            if self.block_head(mid) < low.as_bytes() {
+                min = mid + 1;
+            } else {
+                max = mid;
+            }
+        }
+
+        // matching strings may start at the end of the block before it
+        let start_block = min.saturating_sub(1);
+        let block_iterator = PfcDictBlockIterator {
+            dict: self.clone(),
+            block_index: start_block,
+        };
+
+        let block_size = self.block_size;
+        block_iterator
+            .flat_map(|block| block.strings())
+            .enumerate()
+            .map(move |(i, s)| ((start_block * block_size + i) as u64, s))
+            .skip_while(move |(_, s)| s.as_str() < low.as_str())
+            .take_while(move |(_, s)| s.as_str() < high.as_str())
+    }
+
     pub fn strings(&self) -> impl Iterator<Item = String> {
         let block_iterator = PfcDictBlockIterator::new(self.clone());
 
@@ -656,17 +801,42 @@ pub struct PfcDictFileBuilder<W: tokio::io::AsyncWrite + Unpin + Send> {
     count: usize,
     /// the size in bytes of the pfc data structure so far
     size: usize,
+    /// the amount of strings in a block
+    block_size: usize,
     last: Option<Vec<u8>>,
     index: Vec<u64>,
 }
 
 impl<W: 'static + tokio::io::AsyncWrite + Unpin + Send> PfcDictFileBuilder<W> {
     pub fn new(pfc_blocks_file: W, pfc_block_offsets_file: W) -> PfcDictFileBuilder<W> {
+        Self::with_block_size(pfc_blocks_file, pfc_block_offsets_file, DEFAULT_BLOCK_SIZE)
+    }
+
+    /// Create a builder that puts `block_size` strings in every block.
+    ///
+    /// Only the first string of a block is stored in full, and every
+    /// other string only stores what it doesn't share with the string
+    /// before it. Larger blocks therefore compress better, especially
+    /// when many strings share a long prefix, but a lookup has to
+    /// decode more strings within a block to find the one it wants.
+    ///
+    /// Panics if `block_size` is 0 or larger than `MAX_BLOCK_SIZE`.
+    pub fn with_block_size(
+        pfc_blocks_file: W,
+        pfc_block_offsets_file: W,
+        block_size: usize,
+    ) -> PfcDictFileBuilder<W> {
+        assert!(
+            block_size != 0 && block_size <= MAX_BLOCK_SIZE,
+            "invalid pfc block size {}",
+            block_size
+        );
         PfcDictFileBuilder {
             pfc_blocks_file,
             pfc_block_offsets_file,
             count: 0,
             size: 0,
+            block_size,
             last: None,
             index: Vec::new(),
         }
@@ -683,7 +853,7 @@ impl<W: 'static + tokio::io::AsyncWrite + Unpin + Send> PfcDictFileBuilder<W> {
     }
 
     pub async fn add_bytes(&mut self, bytes: &[u8]) -> io::Result<u64> {
-        if self.count % BLOCK_SIZE == 0 {
+        if self.count % self.block_size == 0 {
             if self.count != 0 {
                 // this is the start of a block, but not the start of the first block
                 // we need to store an index
@@ -739,7 +909,7 @@ impl<W: 'static + tokio::io::AsyncWrite + Unpin + Send> PfcDictFileBuilder<W> {
             64 - self.index[self.index.len() - 1].leading_zeros()
         };
         let mut builder = LogArrayFileBuilder::new(self.pfc_block_offsets_file, width as u8);
-        let count = self.count as u64;
+        let count = encode_count_and_block_size(self.count as u64, self.block_size);
 
         builder.push_all(stream_iter_ok(self.index)).await?;
         builder.finalize().await?;
@@ -755,14 +925,16 @@ impl<W: 'static + tokio::io::AsyncWrite + Unpin + Send> PfcDictFileBuilder<W> {
 struct PfcDecoder {
     last: Option<BytesMut>,
     index: usize,
+    block_size: usize,
     done: bool,
 }
 
 impl PfcDecoder {
-    fn new() -> Self {
+    fn new(block_size: usize) -> Self {
         Self {
             last: None,
             index: 0,
+            block_size,
             done: false,
         }
     }
@@ -787,7 +959,7 @@ impl Decoder for PfcDecoder {
 
         match pos {
             None => Ok(None),
-            Some(pos) => match self.index % 8 == 0 {
+            Some(pos) => match self.index % self.block_size == 0 {
                 true => {
                     // this is the start of a block. we expect a 0-delimited cstring
                     let b = bytes.split_to(pos);
@@ -825,21 +997,84 @@ pub async fn dict_file_get_count<F: 'static + FileLoad>(file: F) -> io::Result<u
     file.open_read_from(file.size() - 8)
         .read_exact(&mut result)
         .await?;
-    Ok(BigEndian::read_u64(&result))
+    let (count, _) = decode_count_and_block_size(BigEndian::read_u64(&result))?;
+    Ok(count)
 }
 
+/// Returns the block size of the dictionary stored in the given blocks file.
+pub async fn dict_file_get_block_size<F: 'static + FileLoad>(file: F) -> io::Result<usize> {
+    let mut result = vec![0; 8];
+    file.open_read_from(file.size() - 8)
+        .read_exact(&mut result)
+        .await?;
+    let (_, block_size) = decode_count_and_block_size(BigEndian::read_u64(&result))?;
+    Ok(block_size)
+}
+
+/// Stream the strings of a dictionary that was built with the default block size.
+///
+/// A dictionary built with any other block size is decoded wrongly.
+/// Use `dict_file_to_stream`, which reads the block size from the
+/// dictionary file, instead.
+#[deprecated(note = "assumes the default block size, use dict_file_to_stream instead")]
 pub fn dict_reader_to_stream<A: 'static + AsyncRead + Unpin + Send>(
     r: A,
 ) -> impl Stream<Item = io::Result<String>> + Unpin + Send {
-    FramedRead::new(r, PfcDecoder::new())
+    dict_reader_to_stream_with_block_size(r, DEFAULT_BLOCK_SIZE)
+}
+
+/// Stream the strings of a dictionary that was built with the given block size.
+pub fn dict_reader_to_stream_with_block_size<A: 'static + AsyncRead + Unpin + Send>(
+    r: A,
+    block_size: usize,
+) -> impl Stream<Item = io::Result<String>> + Unpin + Send {
+    FramedRead::new(r, PfcDecoder::new(block_size))
+}
+
+/// Stream the strings of the dictionary stored in the given blocks file.
+///
+/// The block size the dictionary was built with is read from the
+/// end of the file first.
+pub async fn dict_file_to_stream<F: 'static + FileLoad>(
+    file: F,
+) -> io::Result<impl Stream<Item = io::Result<String>> + Unpin + Send> {
+    let block_size = dict_file_get_block_size(file.clone()).await?;
+
+    Ok(dict_reader_to_stream_with_block_size(
+        file.open_read(),
+        block_size,
+    ))
 }
 
+/// Stream the strings of a dictionary that was built with the default block size, along with their ids.
+///
+/// Ids start right after `offset`. Like `dict_reader_to_stream`,
+/// this decodes a dictionary built with any other block size wrongly.
+#[deprecated(note = "assumes the default block size, use dict_file_to_indexed_stream instead")]
 pub fn dict_reader_to_indexed_stream<A: 'static + AsyncRead + Unpin + Send>(
     r: A,
     offset: u64,
 ) -> impl Stream<Item = io::Result<(u64, String)>> + Send {
-    let dict_stream = dict_reader_to_stream(r);
+    index_dict_stream(
+        dict_reader_to_stream_with_block_size(r, DEFAULT_BLOCK_SIZE),
+        offset,
+    )
+}
 
+/// Stream the strings of the dictionary stored in the given blocks file, along with their ids.
+///
+/// Ids start right after `offset`.
+pub async fn dict_file_to_indexed_stream<F: 'static + FileLoad>(
+    file: F,
+    offset: u64,
+) -> io::Result<impl Stream<Item = io::Result<(u64, String)>> + Send> {
+    Ok(index_dict_stream(dict_file_to_stream(file).await?, offset))
+}
+
+fn index_dict_stream<S: Stream<Item = io::Result<String>> + Send>(
+    dict_stream: S,
+    offset: u64,
+) -> impl Stream<Item = io::Result<(u64, String)>> + Send {
     dict_stream.enumerate().map(move |(i, x)| match x {
         Ok(x) => Ok(((i + 1) as u64 + offset, x)),
         Err(e) => Err(e),
@@ -856,6 +1091,17 @@ pub async fn merge_dictionaries<
 ) -> io::Result<()> {
     let iterators: Vec<_> = dictionaries.map(|d| d.entries()).collect();
 
+    merge_dictionary_entries(iterators, dict_files).await
+}
+
+/// Merge several sorted iterators of dictionary entries into a new dictionary.
+pub async fn merge_dictionary_entries<
+    F: 'static + FileLoad + FileStore,
+    I: 'static + Iterator<Item = PfcDictEntry> + Send,
+>(
+    iterators: Vec<I>,
+    dict_files: DictionaryFiles<F>,
+) -> io::Result<()> {
     let pick_fn = |vals: &[Option<&PfcDictEntry>]| {
         vals.iter()
             .enumerate()
@@ -882,6 +1128,65 @@ mod tests {
     use futures::executor::block_on;
     use futures::stream::TryStreamExt;
 
+    #[test]
+    fn get_entry_with_nested_prefixes() {
+        let contents = vec!["ab", "abc", "abcd", "abcde", "abcdf", "abd"];
+        let blocks = MemoryBackedStore::new();
+        let offsets = MemoryBackedStore::new();
+        let mut builder = PfcDictFileBuilder::new(blocks.open_write(), offsets.open_write());
+        block_on(async {
+            builder.add_all(contents.clone().into_iter()).await?;
+            builder.finalize().await?;
+
+            Ok::<_, io::Error>(())
+        })
+        .unwrap();
+
+        let p = PfcDict::parse(
+            block_on(blocks.map()).unwrap(),
+            block_on(offsets.map()).unwrap(),
+        )
+        .unwrap();
+
+        for (ix, s) in contents.into_iter().enumerate() {
+            assert_eq!(Some(s.to_string()), p.get(ix));
+        }
+    }
+
+    #[test]
+    fn id_of_entries_sharing_a_prefix() {
+        // long enough that the entries are kept as several parts
+        let contents = vec![
+            "http://example.com/a/rather/long/prefix/a",
+            "http://example.com/a/rather/long/prefix/ab",
+            "http://example.com/a/rather/long/prefix/abc",
+            "http://example.com/a/rather/long/prefix/abcd",
+            "http://example.com/a/rather/long/prefix/abd",
+            "http://example.com/a/rather/long/prefix/b",
+        ];
+        let blocks = MemoryBackedStore::new();
+        let offsets = MemoryBackedStore::new();
+        let mut builder = PfcDictFileBuilder::new(blocks.open_write(), offsets.open_write());
+        block_on(async {
+            builder.add_all(contents.clone().into_iter()).await?;
+            builder.finalize().await?;
+
+            Ok::<_, io::Error>(())
+        })
+        .unwrap();
+
+        let p = PfcDict::parse(
+            block_on(blocks.map()).unwrap(),
+            block_on(offsets.map()).unwrap(),
+        )
+        .unwrap();
+
+        for (ix, s) in contents.iter().enumerate() {
+            assert_eq!(Some(ix as u64), p.id(s));
+        }
+        assert_eq!(None, p.id("http://example.com/a/rather/long/prefix/abce"));
+    }
+
     #[test]
     fn can_create_pfc_dict_small() {
         let contents = vec!["aaaaa", "aabbb", "ccccc"];
@@ -1089,7 +1394,7 @@ mod tests {
         })
         .unwrap();
 
-        let stream = dict_reader_to_stream(blocks.open_read());
+        let stream = block_on(dict_file_to_stream(blocks.clone())).unwrap();
 
         let result: Vec<String> = block_on(stream.try_collect()).unwrap();
         assert_eq!(contents, result);
@@ -1128,7 +1433,7 @@ mod tests {
         })
         .unwrap();
 
-        let stream = dict_reader_to_stream(blocks.open_read());
+        let stream = block_on(dict_file_to_stream(blocks.clone())).unwrap();
 
         let result: Vec<String> = block_on(stream.try_collect()).unwrap();
         assert_eq!(contents, result);
@@ -1169,7 +1474,7 @@ mod tests {
         })
         .unwrap();
 
-        let stream = dict_reader_to_indexed_stream(blocks.open_read(), 0);
+        let stream = block_on(dict_file_to_indexed_stream(blocks.clone(), 0)).unwrap();
 
         let result: Vec<(u64, String)> = block_on(stream.try_collect()).unwrap();
         assert_eq!((1, "aaaaa".to_string()), result[0]);
@@ -1216,4 +1521,240 @@ mod tests {
 
         assert_eq!(18, count);
     }
+
+    #[test]
+    fn strings_with_prefix_spanning_blocks() {
+        let contents = vec![
+            "aaaaa",
+            "aaaaaaaaaa",
+            "aaaabbbbbb",
+            "abcdefghijk",
+            "addeeerafa",
+            "arf",
+            "bapofsi",
+            "barf",
+            "berf",
+            "boo boo boo boo",
+            "bzwas baraf",
+            "dradsfadfvbbb",
+            "eadfpoicvu",
+            "eeeee ee e eee",
+            "faadsafdfaf sdfasdf",
+            "frumps framps fremps",
+            "gahh",
+            "hai hai hai",
+        ];
+
+        let blocks = MemoryBackedStore::new();
+        let offsets = MemoryBackedStore::new();
+        let mut builder = PfcDictFileBuilder::new(blocks.open_write(), offsets.open_write());
+
+        block_on(async {
+            builder.add_all(contents.clone().into_iter()).await?;
+            builder.finalize().await?;
+
+            Ok::<_, io::Error>(())
+        })
+        .unwrap();
+
+        let p = PfcDict::parse(
+            block_on(blocks.map()).unwrap(),
+            block_on(offsets.map()).unwrap(),
+        )
+        .unwrap();
+
+        let expected: Vec<_> = vec![
+            (6, "bapofsi".to_string()),
+            (7, "barf".to_string()),
+            (8, "berf".to_string()),
+            (9, "boo boo boo boo".to_string()),
+            (10, "bzwas baraf".to_string()),
+        ];
+        assert_eq!(expected, p.strings_with_prefix("b").collect::<Vec<_>>());
+
+        let expected: Vec<_> = vec![(0, "aaaaa".to_string()), (1, "aaaaaaaaaa".to_string())];
+        assert_eq!(expected, p.strings_with_prefix("aaaaa").collect::<Vec<_>>());
+
+        let expected: Vec<_> = vec![(17, "hai hai hai".to_string())];
+        assert_eq!(expected, p.strings_with_prefix("h").collect::<Vec<_>>());
+
+        assert_eq!(18, p.strings_with_prefix("").count());
+        assert_eq!(0, p.strings_with_prefix("c").count());
+        assert_eq!(0, p.strings_with_prefix("zzz").count());
+    }
+
+    #[test]
+    fn strings_in_range_spanning_blocks() {
+        let contents = vec![
+            "entry 00", "entry 01", "entry 02", "entry 03", "entry 04", "entry 05", "entry 06",
+            "entry 07", "entry 08", "entry 09", "entry 10", "entry 11", "entry 12", "entry 13",
+            "entry 14", "entry 15", "entry 16", "entry 17", "entry 18", "entry 19", "entry 20",
+            "entry 21", "entry 22", "entry 23", "entry 24", "entry 25", "entry 26", "entry 27",
+            "entry 28", "entry 29",
+        ];
+
+        let blocks = MemoryBackedStore::new();
+        let offsets = MemoryBackedStore::new();
+        let mut builder = PfcDictFileBuilder::new(blocks.open_write(), offsets.open_write());
+
+        block_on(async {
+            builder.add_all(contents.clone().into_iter()).await?;
+            builder.finalize().await?;
+
+            Ok::<_, io::Error>(())
+        })
+        .unwrap();
+
+        let p = PfcDict::parse(
+            block_on(blocks.map()).unwrap(),
+            block_on(offsets.map()).unwrap(),
+        )
+        .unwrap();
+
+        let expected: Vec<_> = (5..22)
+            .map(|i| (i, contents[i as usize].to_string()))
+            .collect();
+        assert_eq!(
+            expected,
+            p.strings_in_range("entry 05", "entry 22")
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(30, p.strings_in_range("", "z").count());
+        assert_eq!(10, p.strings_in_range("entry 1", "entry 2").count());
+        assert_eq!(0, p.strings_in_range("entry 22", "entry 05").count());
+        assert_eq!(0, p.strings_in_range("entry 05", "entry 05").count());
+    }
+
+    #[test]
+    fn lookups_work_across_block_sizes() {
+        // strings of varying length, so that entries are built from
+        // differently sized parts of the strings before them
+        let mut contents: Vec<String> = (0..100)
+            .map(|i| format!("http://example.com/deep/iri/{}", i))
+            .collect();
+        contents.sort();
+
+        for &block_size in &[1, 2, 3, 7, DEFAULT_BLOCK_SIZE, 16, 100, MAX_BLOCK_SIZE] {
+            for &len in &[0, 1, block_size, block_size + 1, contents.len()] {
+                let contents = &contents[..std::cmp::min(len, contents.len())];
+                let blocks = MemoryBackedStore::new();
+                let offsets = MemoryBackedStore::new();
+                let mut builder = PfcDictFileBuilder::with_block_size(
+                    blocks.open_write(),
+                    offsets.open_write(),
+                    block_size,
+                );
+                block_on(async {
+                    for s in contents {
+                        builder.add(s).await?;
+                    }
+                    builder.finalize().await?;
+
+                    Ok::<_, io::Error>(())
+                })
+                .unwrap();
+
+                assert_eq!(
+                    contents.len() as u64,
+                    block_on(dict_file_get_count(blocks.clone())).unwrap()
+                );
+                assert_eq!(
+                    block_size,
+                    block_on(dict_file_get_block_size(blocks.clone())).unwrap()
+                );
+                let streamed: Vec<String> = block_on(
+                    dict_reader_to_stream_with_block_size(blocks.open_read(), block_size)
+                        .try_collect(),
+                )
+                .unwrap();
+                assert_eq!(contents, &streamed[..]);
+                let streamed: Vec<String> = block_on(async {
+                    dict_file_to_stream(blocks.clone())
+                        .await?
+                        .try_collect()
+                        .await
+                })
+                .unwrap();
+                assert_eq!(contents, &streamed[..]);
+
+                let dict = PfcDict::parse(
+                    block_on(blocks.map()).unwrap(),
+                    block_on(offsets.map()).unwrap(),
+                )
+                .unwrap();
+
+                assert_eq!(block_size, dict.block_size());
+                assert_eq!(contents.len(), dict.len());
+                assert_eq!(contents, &dict.strings().collect::<Vec<_>>()[..]);
+                assert_eq!(
+                    contents,
+                    &dict.entries().map(|e| e.to_string()).collect::<Vec<_>>()[..]
+                );
+                for (ix, s) in contents.iter().enumerate() {
+                    assert_eq!(Some(s.clone()), dict.get(ix));
+                    assert_eq!(Some(ix as u64), dict.id(s));
+                }
+                assert_eq!(None, dict.get(contents.len()));
+                assert_eq!(None, dict.id("http://example.com/deep/iri/05"));
+
+                let with_prefix: Vec<_> = dict
+                    .strings_with_prefix("http://example.com/deep/iri/5")
+                    .collect();
+                let expected: Vec<_> = contents
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, s)| s.starts_with("http://example.com/deep/iri/5"))
+                    .map(|(ix, s)| (ix as u64, s.clone()))
+                    .collect();
+                assert_eq!(expected, with_prefix);
+            }
+        }
+    }
+
+    #[test]
+    fn default_block_size_keeps_the_original_layout() {
+        let blocks = MemoryBackedStore::new();
+        let offsets = MemoryBackedStore::new();
+        let mut builder = PfcDictFileBuilder::new(blocks.open_write(), offsets.open_write());
+        block_on(async {
+            builder.add_all(vec!["a", "b", "c"].into_iter()).await?;
+            builder.finalize().await?;
+
+            Ok::<_, io::Error>(())
+        })
+        .unwrap();
+
+        // only the count and the format version are set, the block size byte stays zero
+        let map = block_on(blocks.map()).unwrap();
+        assert_eq!(
+            3 | u64::from(FORMAT_VERSION) << VERSION_SHIFT,
+            BigEndian::read_u64(&map.as_ref()[map.len() - 8..])
+        );
+    }
+
+    #[test]
+    fn newer_format_version_is_rejected() {
+        let blocks = MemoryBackedStore::new();
+        let offsets = MemoryBackedStore::new();
+        let mut builder = PfcDictFileBuilder::new(blocks.open_write(), offsets.open_write());
+        block_on(async {
+            builder.add_all(vec!["a", "b", "c"].into_iter()).await?;
+            builder.finalize().await?;
+
+            Ok::<_, io::Error>(())
+        })
+        .unwrap();
+
+        let mut bytes = block_on(blocks.map()).unwrap().to_vec();
+        let len = bytes.len();
+        bytes[len - 6] = 2;
+
+        let error = PfcDict::parse(Bytes::from(bytes), block_on(offsets.map()).unwrap())
+            .err()
+            .unwrap();
+        assert_eq!(
+            "layer written with store format v2, this build supports up to v1",
+            error.to_string()
+        );
+    }
 }
@@ -1,10 +1,30 @@
 use futures::io::Result;
 use futures::stream::{Peekable, Stream, StreamExt};
 use futures::task::{Context, Poll};
+use std::fmt;
 use std::marker::Unpin;
 use std::pin::Pin;
 use tokio::io::{AsyncWrite, AsyncWriteExt};
 
+/// The serialization format version of the structures written by this build.
+///
+/// Log arrays, bit arrays and dictionaries keep this version in a byte of
+/// their control word that used to be unused, so anything written before
+/// the version was recorded reads as version 0.
+///
+/// Version 1 changed what the value dictionary of a layer holds. Values
+/// in a version 0 value dictionary are all plain strings, stored as is.
+pub const FORMAT_VERSION: u8 = 1;
+
+/// Describe a structure that was written with a newer format version than this build supports.
+pub fn fmt_unsupported_format_version(f: &mut fmt::Formatter, version: u8) -> fmt::Result {
+    write!(
+        f,
+        "layer written with store format v{}, this build supports up to v{}",
+        version, FORMAT_VERSION
+    )
+}
+
 pub fn find_common_prefix(b1: &[u8], b2: &[u8]) -> usize {
     let mut common = 0;
     while common < b1.len() && common < b2.len() {
@@ -176,6 +176,105 @@ where
     Ok(len)
 }
 
+/// The size of the buffer `VByteReader` reads into.
+const READ_BUFFER_LEN: usize = 4096;
+
+/// Decodes a stream of variable-byte-encoded `u64` values from a `Read`.
+///
+/// The input is read in chunks, so the bytes of a single value may be
+/// spread over multiple reads. If the input ends in the middle of a
+/// value, the iterator returns an `UnexpectedEof` error rather than a
+/// partially decoded value. After any error, the iterator is finished.
+pub struct VByteReader<R> {
+    reader: R,
+    buf: Box<[u8; READ_BUFFER_LEN]>,
+    pos: usize,
+    len: usize,
+    done: bool,
+}
+
+impl<R: std::io::Read> VByteReader<R> {
+    /// Creates a new reader decoding values from `reader`.
+    pub fn new(reader: R) -> Self {
+        VByteReader {
+            reader,
+            buf: Box::new([0; READ_BUFFER_LEN]),
+            pos: 0,
+            len: 0,
+            done: false,
+        }
+    }
+
+    /// Returns the next byte of the input, or `None` at the end of the input.
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        while self.pos == self.len {
+            match self.reader.read(&mut self.buf[..]) {
+                Ok(0) => return Ok(None),
+                Ok(len) => {
+                    self.pos = 0;
+                    self.len = len;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let byte = self.buf[self.pos];
+        self.pos += 1;
+
+        Ok(Some(byte))
+    }
+
+    fn next_value(&mut self) -> io::Result<Option<u64>> {
+        let mut encoded = [0; MAX_ENCODING_LEN];
+        let mut encoded_len = 0;
+        loop {
+            let byte = match self.next_byte()? {
+                Some(byte) => byte,
+                None if encoded_len == 0 => return Ok(None),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "input ended in the middle of a variable-byte-encoded value",
+                    ))
+                }
+            };
+
+            encoded[encoded_len] = byte;
+            encoded_len += 1;
+            if is_last_encoded_byte(byte) || encoded_len == MAX_ENCODING_LEN {
+                return decode(&encoded[..encoded_len])
+                    .map(|(num, _)| Some(num))
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)));
+            }
+        }
+    }
+
+    /// Returns the underlying reader.
+    ///
+    /// Any input that was read ahead but not yet decoded is lost.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: std::io::Read> Iterator for VByteReader<R> {
+    type Item = io::Result<u64>;
+
+    fn next(&mut self) -> Option<io::Result<u64>> {
+        if self.done {
+            return None;
+        }
+
+        let result = self.next_value().transpose();
+        if !matches!(result, Some(Ok(_))) {
+            self.done = true;
+        }
+
+        result
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -299,4 +398,87 @@ mod tests {
             assert_eq!(len, encoding_len(num));
         }
     }
+
+    /// A reader that hands out a single byte per read.
+    struct OneByteReader<'a>(&'a [u8]);
+
+    impl<'a> std::io::Read for OneByteReader<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.0[0];
+            self.0 = &self.0[1..];
+
+            Ok(1)
+        }
+    }
+
+    fn numbers() -> Vec<u64> {
+        let mut nums: Vec<u64> = (0..64).map(|shift| 1 << shift).collect();
+        nums.extend((0..5000).map(|i| i * 7919));
+        nums.extend(&[0, u64::MAX, u64::MAX - 1]);
+        nums
+    }
+
+    #[test]
+    fn read_values_from_stream() {
+        let nums = numbers();
+        let encoded: Vec<u8> = nums.iter().flat_map(|&n| encode_vec(n)).collect();
+
+        let decoded: Vec<u64> = VByteReader::new(&encoded[..])
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(nums, decoded);
+    }
+
+    #[test]
+    fn read_values_one_byte_at_a_time() {
+        let nums = numbers();
+        let encoded: Vec<u8> = nums.iter().flat_map(|&n| encode_vec(n)).collect();
+
+        let decoded: Vec<u64> = VByteReader::new(OneByteReader(&encoded))
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(nums, decoded);
+    }
+
+    #[test]
+    fn read_empty_stream() {
+        assert_eq!(0, VByteReader::new(&[][..]).count());
+    }
+
+    #[test]
+    fn read_truncated_value_fails() {
+        let mut encoded = encode_vec(42);
+        encoded.extend(encode_vec(u64::MAX - 1));
+        encoded.pop();
+
+        let mut reader = VByteReader::new(OneByteReader(&encoded));
+        assert_eq!(42, reader.next().unwrap().unwrap());
+        assert_eq!(
+            io::ErrorKind::UnexpectedEof,
+            reader.next().unwrap().unwrap_err().kind()
+        );
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn read_invalid_value_fails() {
+        let mut encoded = [0; 10];
+        encoded[9] = set_msb(0x02);
+        let mut reader = VByteReader::new(&encoded[..]);
+        assert_eq!(
+            io::ErrorKind::InvalidData,
+            reader.next().unwrap().unwrap_err().kind()
+        );
+        assert!(reader.next().is_none());
+
+        let encoded = [0; 11];
+        let mut reader = VByteReader::new(&encoded[..]);
+        assert_eq!(
+            io::ErrorKind::InvalidData,
+            reader.next().unwrap().unwrap_err().kind()
+        );
+    }
 }
@@ -102,6 +102,11 @@ impl WaveletTree {
         }
     }
 
+    /// Returns the size in bytes of the structures backing this wavelet tree.
+    pub fn heap_size(&self) -> usize {
+        self.bits.heap_size()
+    }
+
     /// Returns the amount of layers.
     pub fn num_layers(&self) -> usize {
         self.num_layers as usize
@@ -161,7 +166,8 @@ impl WaveletTree {
         let mut alphabet_start = 0;
         let mut alphabet_end = 2_u64.pow(self.num_layers as u32) as u64;
 
-        if entry >= alphabet_end {
+        // a tree without layers, like that of an empty layer's predicates, has no positions
+        if entry >= alphabet_end || width == 0 {
             return None;
         }
 
@@ -350,7 +356,8 @@ mod tests {
         let wavelet_blocks = block_on(wavelet_blocks_file.map()).unwrap();
         let wavelet_sblocks = block_on(wavelet_sblocks_file.map()).unwrap();
 
-        let wavelet_bitindex = BitIndex::from_maps(wavelet_bits, wavelet_blocks, wavelet_sblocks);
+        let wavelet_bitindex =
+            BitIndex::from_maps(wavelet_bits, wavelet_blocks, wavelet_sblocks).unwrap();
         let wavelet_tree = WaveletTree::from_parts(wavelet_bitindex, 5);
 
         assert_eq!(contents_len, wavelet_tree.len());
@@ -390,7 +397,8 @@ mod tests {
         let wavelet_blocks = block_on(wavelet_blocks_file.map()).unwrap();
         let wavelet_sblocks = block_on(wavelet_sblocks_file.map()).unwrap();
 
-        let wavelet_bitindex = BitIndex::from_maps(wavelet_bits, wavelet_blocks, wavelet_sblocks);
+        let wavelet_bitindex =
+            BitIndex::from_maps(wavelet_bits, wavelet_blocks, wavelet_sblocks).unwrap();
         let wavelet_tree = WaveletTree::from_parts(wavelet_bitindex, 5);
 
         assert_eq!(contents_len, wavelet_tree.len());
@@ -420,7 +428,8 @@ mod tests {
         let wavelet_blocks = block_on(wavelet_blocks_file.map()).unwrap();
         let wavelet_sblocks = block_on(wavelet_sblocks_file.map()).unwrap();
 
-        let wavelet_bitindex = BitIndex::from_maps(wavelet_bits, wavelet_blocks, wavelet_sblocks);
+        let wavelet_bitindex =
+            BitIndex::from_maps(wavelet_bits, wavelet_blocks, wavelet_sblocks).unwrap();
         let wavelet_tree = WaveletTree::from_parts(wavelet_bitindex, 4);
 
         let slice = wavelet_tree.lookup(8).unwrap();
@@ -455,10 +464,14 @@ mod tests {
         let wavelet_blocks = block_on(wavelet_blocks_file.map()).unwrap();
         let wavelet_sblocks = block_on(wavelet_sblocks_file.map()).unwrap();
 
-        let wavelet_bitindex = BitIndex::from_maps(wavelet_bits, wavelet_blocks, wavelet_sblocks);
-        let wavelet_tree = WaveletTree::from_parts(wavelet_bitindex, 4);
+        let wavelet_bitindex =
+            BitIndex::from_maps(wavelet_bits, wavelet_blocks, wavelet_sblocks).unwrap();
+        let wavelet_tree = WaveletTree::from_parts(wavelet_bitindex.clone(), 4);
 
         assert!(wavelet_tree.lookup(3).is_none());
+
+        let wavelet_tree = WaveletTree::from_parts(wavelet_bitindex, 0);
+        assert!(wavelet_tree.lookup(0).is_none());
     }
 
     #[test]
@@ -483,7 +496,8 @@ mod tests {
         let wavelet_blocks = block_on(wavelet_blocks_file.map()).unwrap();
         let wavelet_sblocks = block_on(wavelet_sblocks_file.map()).unwrap();
 
-        let wavelet_bitindex = BitIndex::from_maps(wavelet_bits, wavelet_blocks, wavelet_sblocks);
+        let wavelet_bitindex =
+            BitIndex::from_maps(wavelet_bits, wavelet_blocks, wavelet_sblocks).unwrap();
         let wavelet_tree = WaveletTree::from_parts(wavelet_bitindex, 4);
 
         assert!(wavelet_tree.lookup(100).is_none());
@@ -511,7 +525,8 @@ mod tests {
         let wavelet_blocks = block_on(wavelet_blocks_file.map()).unwrap();
         let wavelet_sblocks = block_on(wavelet_sblocks_file.map()).unwrap();
 
-        let wavelet_bitindex = BitIndex::from_maps(wavelet_bits, wavelet_blocks, wavelet_sblocks);
+        let wavelet_bitindex =
+            BitIndex::from_maps(wavelet_bits, wavelet_blocks, wavelet_sblocks).unwrap();
         let wavelet_tree = WaveletTree::from_parts(wavelet_bitindex, 4);
 
         assert_eq!(
@@ -544,7 +559,8 @@ mod tests {
         let wavelet_blocks = block_on(wavelet_blocks_file.map()).unwrap();
         let wavelet_sblocks = block_on(wavelet_sblocks_file.map()).unwrap();
 
-        let wavelet_bitindex = BitIndex::from_maps(wavelet_bits, wavelet_blocks, wavelet_sblocks);
+        let wavelet_bitindex =
+            BitIndex::from_maps(wavelet_bits, wavelet_blocks, wavelet_sblocks).unwrap();
         let wavelet_tree = WaveletTree::from_parts(wavelet_bitindex, 4);
 
         assert_eq!(Some(3), wavelet_tree.lookup_one(1));